@@ -7,8 +7,10 @@ use std::sync::{
 };
 use std::time::{Duration, Instant};
 
+use super::performance::EnginePerfShared;
 use super::process_thread::EngineProcessThread;
 use crate::graph::shared_pools::SharedProcessorSchedule;
+use crate::processor_schedule::parallel::WorkerPoolShared;
 
 /// Allocate enough for at-least 3 seconds of buffer time at the
 /// highest possible sample rate.
@@ -39,6 +41,8 @@ pub struct EngineAudioThread {
 
     sample_rate: u32,
     sample_rate_recip: f64,
+
+    perf: Arc<EnginePerfShared>,
 }
 
 impl Debug for EngineAudioThread {
@@ -55,6 +59,7 @@ impl Debug for EngineAudioThread {
 }
 
 impl EngineAudioThread {
+    #[allow(clippy::too_many_arguments)] // Fix this?
     pub(crate) fn new(
         schedule: SharedProcessorSchedule,
         sample_rate: u32,
@@ -62,6 +67,8 @@ impl EngineAudioThread {
         graph_audio_out_channels: usize,
         max_frames: usize,
         hard_clip_outputs: bool,
+        workers: Option<Arc<WorkerPoolShared>>,
+        perf: Arc<EnginePerfShared>,
         coll_handle: &basedrop::Handle,
     ) -> (Self, EngineProcessThread) {
         assert_ne!(sample_rate, 0);
@@ -102,6 +109,7 @@ impl EngineAudioThread {
                 graph_audio_out_channels,
                 sample_rate,
                 sample_rate_recip,
+                perf: Arc::clone(&perf),
             },
             EngineProcessThread::new(
                 audio_to_process_rx,
@@ -111,15 +119,50 @@ impl EngineAudioThread {
                 max_frames,
                 hard_clip_outputs,
                 schedule,
+                workers,
+                sample_rate,
+                perf,
                 coll_handle,
             ),
         )
     }
 
+    /// Process the audio graph when there is no audio input device. The graph
+    /// inputs will be filled with silence.
     pub fn process_cpal_interleaved_output_only<T: cpal::Sample>(
         &mut self,
         cpal_out_channels: usize,
         out: &mut [T],
+    ) {
+        self.process_cpal_interleaved::<T, T>(None, cpal_out_channels, out)
+    }
+
+    /// Process the audio graph with interleaved audio from an input device.
+    ///
+    /// `input` should contain the same number of frames as `out`. If it
+    /// contains less, then the remaining frames are filled with silence.
+    ///
+    /// If the device has a different number of input channels than the
+    /// audio graph, then:
+    /// * A mono device is copied to every graph input channel.
+    /// * Otherwise device channel `n` is sent to graph input channel `n`, any
+    /// extra device channels are discarded, and any extra graph channels are
+    /// filled with silence.
+    pub fn process_cpal_interleaved_duplex<I: cpal::Sample, T: cpal::Sample>(
+        &mut self,
+        cpal_in_channels: usize,
+        input: &[I],
+        cpal_out_channels: usize,
+        out: &mut [T],
+    ) {
+        self.process_cpal_interleaved(Some((cpal_in_channels, input)), cpal_out_channels, out)
+    }
+
+    fn process_cpal_interleaved<I: cpal::Sample, T: cpal::Sample>(
+        &mut self,
+        input: Option<(usize, &[I])>,
+        cpal_out_channels: usize,
+        out: &mut [T],
     ) {
         let clear_output = |out: &mut [T]| {
             for s in out.iter_mut() {
@@ -150,8 +193,37 @@ impl EngineAudioThread {
                     match audio_rb_tx.write_chunk(total_frames * self.graph_audio_in_channels) {
                         Ok(mut chunk) => {
                             let (slice_1, slice_2) = chunk.as_mut_slices();
-                            slice_1.fill(0.0);
-                            slice_2.fill(0.0);
+
+                            if let Some((cpal_in_channels, input)) = input {
+                                let graph_in_channels = self.graph_audio_in_channels;
+
+                                let read_input = |smp_i: usize| -> f32 {
+                                    let frame = smp_i / graph_in_channels;
+                                    let graph_ch = smp_i % graph_in_channels;
+
+                                    let cpal_ch = if cpal_in_channels == 1 { 0 } else { graph_ch };
+
+                                    if cpal_ch < cpal_in_channels {
+                                        input
+                                            .get((frame * cpal_in_channels) + cpal_ch)
+                                            .map(|s| s.to_f32())
+                                            .unwrap_or(0.0)
+                                    } else {
+                                        0.0
+                                    }
+                                };
+
+                                for (i, s) in slice_1.iter_mut().enumerate() {
+                                    *s = read_input(i);
+                                }
+                                let slice_1_len = slice_1.len();
+                                for (i, s) in slice_2.iter_mut().enumerate() {
+                                    *s = read_input(slice_1_len + i);
+                                }
+                            } else {
+                                slice_1.fill(0.0);
+                                slice_2.fill(0.0);
+                            }
 
                             chunk.commit_all();
                         }
@@ -249,6 +321,7 @@ impl EngineAudioThread {
 
         // The engine took too long to process.
         log::trace!("underrun");
+        self.perf.record_underrun();
         clear_output(out);
     }
 }
@@ -1,9 +1,11 @@
 use std::error::Error;
 
-use meadowlark_plugin_api::PluginFormat;
+use meadowlark_plugin_api::{PluginFormat, PluginInstanceID};
 
 use crate::graph::error::GraphCompilerError;
 
+use super::modify_request::EdgeReqPortID;
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum EngineCrashError {
@@ -22,6 +24,80 @@ impl std::fmt::Display for EngineCrashError {
     }
 }
 
+#[derive(Debug)]
+pub enum ModifyGraphError {
+    /// The engine is deactivated.
+    EngineDeactivated,
+}
+
+impl Error for ModifyGraphError {}
+
+impl std::fmt::Display for ModifyGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModifyGraphError::EngineDeactivated => {
+                write!(f, "Failed to modify audio graph: engine is deactivated")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OfflineRenderError {
+    /// The engine is deactivated.
+    EngineDeactivated,
+    /// The plugin to render does not exist or is not activated.
+    PluginNotActivated(PluginInstanceID),
+    /// The audio output port to render does not exist on the plugin.
+    PortDoesNotExist(PluginInstanceID, EdgeReqPortID),
+    /// The end frame is not greater than the start frame.
+    InvalidRange { start_frame: u64, end_frame: u64 },
+    /// The process thread did not hand over the processor schedule in time.
+    ProcessThreadTimedOut,
+    /// Another offline render is already in progress.
+    AlreadyRendering,
+    /// No offline render is in progress.
+    NotRendering,
+}
+
+impl Error for OfflineRenderError {}
+
+impl std::fmt::Display for OfflineRenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OfflineRenderError::EngineDeactivated => {
+                write!(f, "Failed to render offline: engine is deactivated")
+            }
+            OfflineRenderError::PluginNotActivated(id) => {
+                write!(f, "Failed to render offline: plugin {:?} is not activated", id)
+            }
+            OfflineRenderError::PortDoesNotExist(id, port_id) => {
+                write!(
+                    f,
+                    "Failed to render offline: plugin {:?} has no audio output port {:?}",
+                    id, port_id
+                )
+            }
+            OfflineRenderError::InvalidRange { start_frame, end_frame } => {
+                write!(
+                    f,
+                    "Failed to render offline: invalid range of frames [{}, {})",
+                    start_frame, end_frame
+                )
+            }
+            OfflineRenderError::ProcessThreadTimedOut => {
+                write!(f, "Failed to render offline: timed out while waiting for the process thread to park")
+            }
+            OfflineRenderError::AlreadyRendering => {
+                write!(f, "Failed to render offline: another offline render is already in progress")
+            }
+            OfflineRenderError::NotRendering => {
+                write!(f, "Failed to render offline: no offline render is in progress")
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum NewPluginInstanceError {
     FactoryFailedToCreateNewInstance(String, String),
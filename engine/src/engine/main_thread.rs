@@ -2,14 +2,14 @@ use basedrop::{Collector, Shared};
 use fnv::FnvHashSet;
 use meadowlark_plugin_api::transport::LoopState;
 use smallvec::SmallVec;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
 use std::thread::{self, JoinHandle};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use thread_priority::ThreadPriority;
 
 use meadowlark_plugin_api::ext::gui::{GuiResizeHints, GuiSize};
@@ -17,22 +17,54 @@ use meadowlark_plugin_api::{HostInfo, PluginFactory, PluginInstanceID};
 
 use crate::engine::audio_thread::EngineAudioThread;
 use crate::engine::EngineTempoMap;
-use crate::graph::{AudioGraph, Edge, EngineEdgeID};
+use crate::graph::error::GraphCompilerError;
+use crate::graph::{AudioGraph, Edge, EngineEdgeID, OfflineRender};
 use crate::plugin_host::error::{ActivatePluginError, RescanParamListError};
 use crate::plugin_host::{ParamModifiedInfo, PluginHostMainThread, PluginHostSaveState};
-use crate::plugin_scanner::{PluginScanner, ScanExternalPluginsRes, ScannedPluginKey};
+use crate::plugin_scanner::{
+    DuplicatePluginPolicy, PluginScanner, ScanExternalPluginsRes, ScannedPluginKey,
+};
+use crate::processor_schedule::parallel::WorkerPoolShared;
 use crate::processor_schedule::TransportHandle;
 use crate::utils::thread_id::SharedThreadIDs;
 
-use super::error::{EngineCrashError, NewPluginInstanceError};
-use super::modify_request::{ModifyGraphRequest, PluginIDReq};
+use super::error::{
+    EngineCrashError, ModifyGraphError, NewPluginInstanceError, OfflineRenderError,
+};
+use super::modify_request::{ConnectEdgeReq, ModifyGraphRequest, PluginIDReq};
+use super::performance::{EnginePerfShared, PerformanceReport};
+use super::process_thread::ProcessThreadPark;
 use super::timer_wheel::{EngineTimerWheel, TimerEntry, TimerEntryKey};
-use super::{ActivateEngineSettings, EngineSettings};
+use super::{
+    ActivateEngineSettings, AudioGraphSaveState, EngineSettings, NodeSaveStateID,
+    OfflineRenderSettings, OfflineRenderSource,
+};
+
+/// How long to wait for the process thread to hand over the schedule
+/// before giving up on an offline render.
+static PROCESS_THREAD_PARK_TIMEOUT: Duration = Duration::from_secs(3);
 
 struct ActivatedState {
     audio_graph: AudioGraph,
+    settings: ActivateEngineSettings,
     run_process_thread: Arc<AtomicBool>,
+    process_thread_park: ProcessThreadPark,
     process_thread_handle: Option<JoinHandle<()>>,
+    workers: Option<Arc<WorkerPoolShared>>,
+    worker_thread_handles: Vec<JoinHandle<()>>,
+    perf: Arc<EnginePerfShared>,
+    offline_render: Option<ActiveOfflineRender>,
+}
+
+/// An offline render started with `EngineMainThread::begin_offline_render()`.
+struct ActiveOfflineRender {
+    render: OfflineRender,
+    num_audio_out_channels: u16,
+    /// Whether the process thread was parked for this render, and thus needs
+    /// to be unparked once it ends.
+    parked_process_thread: bool,
+    /// The total time spent processing the render so far.
+    process_time: Duration,
 }
 
 impl Drop for ActivatedState {
@@ -45,6 +77,17 @@ impl Drop for ActivatedState {
                 log::error!("Failed to join process thread handle: {:?}", e);
             }
         }
+
+        // The worker threads can only be stopped once the process thread is no
+        // longer handing them blocks to process.
+        if let Some(workers) = &self.workers {
+            workers.stop();
+        }
+        for worker_thread_handle in self.worker_thread_handles.drain(..) {
+            if let Err(e) = worker_thread_handle.join() {
+                log::error!("Failed to join worker thread handle: {:?}", e);
+            }
+        }
     }
 }
 
@@ -86,8 +129,14 @@ impl EngineMainThread {
         let thread_ids =
             SharedThreadIDs::new(Some(thread::current().id()), None, &collector.handle());
 
-        let mut plugin_scanner =
-            PluginScanner::new(collector.handle(), Shared::clone(&host_info), thread_ids.clone());
+        let mut plugin_scanner = PluginScanner::new(
+            collector.handle(),
+            Shared::clone(&host_info),
+            thread_ids.clone(),
+            settings.plugin_scan_cache_path.clone(),
+            Duration::from_millis(u64::from(settings.plugin_scan_timeout_ms)),
+            settings.duplicate_plugin_policy.clone(),
+        );
 
         // Scan the user's internal plugins.
         let internal_plugins_res: Vec<Result<ScannedPluginKey, String>> =
@@ -160,13 +209,28 @@ impl EngineMainThread {
             match elapsed_entry.key {
                 TimerEntryKey::MainIdle => {
                     if let Some(activated_state) = &mut self.activated_state {
+                        // The main thread has taken over the schedule, so plugins
+                        // must not be restarted or reconnected until the render
+                        // has ended.
+                        if activated_state.offline_render.is_some() {
+                            continue;
+                        }
+
                         let recompile = activated_state
                             .audio_graph
                             .on_idle(&mut events_out, &mut self.timer_wheel);
 
                         if recompile {
-                            self.compile_audio_graph();
+                            activated_state.audio_graph.request_compile();
                         }
+
+                        activated_state.audio_graph.poll_compiler(&mut self.timer_wheel);
+                    }
+
+                    self.handle_compile_results(&mut events_out);
+
+                    if let Some(res) = self.plugin_scanner.poll_scan_result() {
+                        events_out.push(OnIdleEvent::ExternalPluginsScanned(res));
                     }
                 }
                 TimerEntryKey::GarbageCollect => {
@@ -206,11 +270,61 @@ impl EngineMainThread {
         self.plugin_scanner.remove_clap_scan_directory(path.into())
     }
 
-    /// (Re)scan all external plugins.
+    /// Start (re)scanning all external plugins in a separate thread.
     ///
-    /// This will a return a new list of all the external plugins.
-    pub fn scan_external_plugins(&mut self) -> ScanExternalPluginsRes {
-        self.plugin_scanner.scan_external_plugins()
+    /// Once the scan has finished, a new list of all the external plugins
+    /// will be sent in an `OnIdleEvent::ExternalPluginsScanned` event.
+    /// Binaries which have not changed since the last scan are not loaded
+    /// again, and binaries which crashed or hung while being scanned are
+    /// added to a blocklist and skipped from then on.
+    ///
+    /// This returns `false` if a scan is already in progress.
+    pub fn scan_external_plugins(&mut self) -> bool {
+        self.plugin_scanner.start_scanning_external_plugins()
+    }
+
+    /// Whether or not external plugins are currently being scanned.
+    pub fn is_scanning_external_plugins(&self) -> bool {
+        self.plugin_scanner.is_scanning_external_plugins()
+    }
+
+    /// Set how to choose which binary to load a plugin from when multiple
+    /// binaries contain a plugin with the same ID.
+    pub fn set_duplicate_plugin_policy(&mut self, policy: DuplicatePluginPolicy) {
+        self.plugin_scanner.set_duplicate_plugin_policy(policy);
+    }
+
+    /// Always load new instances of the plugin with the given key from this
+    /// binary, regardless of the `DuplicatePluginPolicy`.
+    ///
+    /// Plugins loaded from a save state will still use the binary they were
+    /// saved with if it still exists.
+    pub fn pin_plugin_binary<P: Into<PathBuf>>(&mut self, key: ScannedPluginKey, path: P) {
+        self.plugin_scanner.pin_binary(key, path.into());
+    }
+
+    /// Stop pinning the plugin with the given key to a specific binary.
+    ///
+    /// This returns `false` if the plugin was not pinned.
+    pub fn unpin_plugin_binary(&mut self, key: &ScannedPluginKey) -> bool {
+        self.plugin_scanner.unpin_binary(key)
+    }
+
+    /// The plugin binaries which are skipped while scanning, along with the
+    /// reason they were blocked.
+    ///
+    /// This will return `None` while a scan is in progress.
+    pub fn plugin_scan_blocklist(&self) -> Option<Vec<(PathBuf, String)>> {
+        self.plugin_scanner.scan_blocklist()
+    }
+
+    /// Remove a plugin binary from the blocklist so it will be scanned again
+    /// on the next call to `EngineMainThread::scan_external_plugins()`.
+    ///
+    /// This returns `false` if the binary was not in the blocklist or if a
+    /// scan is in progress.
+    pub fn unblock_plugin_binary<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        self.plugin_scanner.unblock_binary(path.as_ref())
     }
 
     /// Activate the engine.
@@ -238,6 +352,7 @@ impl EngineMainThread {
         let note_buffer_size = settings.note_buffer_size;
         let event_buffer_size = settings.event_buffer_size;
         let transport_declick_seconds = settings.transport_declick_seconds;
+        let num_worker_threads = settings.num_worker_threads;
 
         let (audio_graph, shared_schedule, transport_handle) = AudioGraph::new(
             self.collector.handle(),
@@ -249,6 +364,7 @@ impl EngineMainThread {
             note_buffer_size,
             event_buffer_size,
             self.thread_ids.clone(),
+            num_worker_threads,
             seek_to_frame,
             loop_state,
             tempo_map,
@@ -256,6 +372,40 @@ impl EngineMainThread {
             &mut self.timer_wheel,
         );
 
+        let workers = if num_worker_threads > 0 {
+            Some(Arc::new(WorkerPoolShared::new(num_worker_threads)))
+        } else {
+            None
+        };
+
+        let mut worker_thread_handles: Vec<JoinHandle<()>> = Vec::new();
+        if let Some(workers) = &workers {
+            for i in 0..workers.num_worker_threads() {
+                let workers = Arc::clone(workers);
+
+                worker_thread_handles.push(thread_priority::spawn(
+                    ThreadPriority::Max,
+                    move |priority_res| {
+                        if let Err(e) = priority_res {
+                            log::error!("Failed to set worker thread priority to max: {:?}", e);
+                        }
+
+                        // The process thread uses the first queue.
+                        workers.run_worker(i + 1);
+                    },
+                ));
+            }
+
+            self.thread_ids.set_worker_thread_ids(
+                worker_thread_handles.iter().map(|h| h.thread().id()).collect(),
+                &self.collector.handle(),
+            );
+
+            log::info!("Spawned {} realtime worker threads", num_worker_threads);
+        }
+
+        let perf = Arc::new(EnginePerfShared::new());
+
         let (audio_thread, mut process_thread) = EngineAudioThread::new(
             shared_schedule,
             sample_rate,
@@ -263,12 +413,17 @@ impl EngineMainThread {
             num_audio_out_channels as usize,
             max_frames as usize,
             settings.hard_clip_outputs,
+            workers.clone(),
+            Arc::clone(&perf),
             &self.collector.handle(),
         );
 
         let run_process_thread = Arc::new(AtomicBool::new(true));
         let run_process_thread_clone = Arc::clone(&run_process_thread);
 
+        let process_thread_park = ProcessThreadPark::new();
+        let process_thread_park_clone = process_thread_park.clone();
+
         let process_thread_handle =
             thread_priority::spawn(ThreadPriority::Max, move |priority_res| {
                 if let Err(e) = priority_res {
@@ -277,7 +432,7 @@ impl EngineMainThread {
                     log::info!("Successfully set process thread priority to max");
                 }
 
-                process_thread.run(run_process_thread_clone);
+                process_thread.run(run_process_thread_clone, process_thread_park_clone);
             });
 
         let info = ActivatedEngineInfo {
@@ -293,14 +448,21 @@ impl EngineMainThread {
 
         self.activated_state = Some(ActivatedState {
             audio_graph,
+            settings,
             run_process_thread,
+            process_thread_park,
             process_thread_handle: Some(process_thread_handle),
+            workers,
+            worker_thread_handles,
+            perf,
+            offline_render: None,
         });
 
-        self.compile_audio_graph();
-
-        if self.activated_state.is_none() {
-            panic!("Unexpected error: Empty audio graph failed to compile a schedule.");
+        let audio_graph = &mut self.activated_state.as_mut().unwrap().audio_graph;
+        audio_graph.request_compile();
+        audio_graph.flush_compiler(&mut self.timer_wheel);
+        if let Some(e) = audio_graph.drain_compile_results().find_map(|res| res.status.err()) {
+            panic!("Unexpected error: Empty audio graph failed to compile a schedule: {}", e);
         }
 
         log::info!("Successfully activated RustyDAW engine");
@@ -310,9 +472,19 @@ impl EngineMainThread {
 
     /// Modify the audio graph.
     ///
-    /// This will return `None` if the engine is deactivated.
-    pub fn modify_graph(&mut self, mut request: ModifyGraphRequest) -> Option<ModifyGraphRes> {
+    /// The modified graph is compiled in the background. If it fails to
+    /// compile, then the changes are rolled back, the last working schedule
+    /// keeps running, and the error is reported with
+    /// `OnIdleEvent::GraphCompiled` along with `ModifyGraphRes::request_id`.
+    pub fn modify_graph(
+        &mut self,
+        mut request: ModifyGraphRequest,
+    ) -> Result<ModifyGraphRes, ModifyGraphError> {
         if let Some(activated_state) = &mut self.activated_state {
+            // Record the changes so they can be rolled back if the modified graph
+            // fails to compile.
+            let request_id = activated_state.audio_graph.begin_modify();
+
             let mut removed_edges: FnvHashSet<EngineEdgeID> = FnvHashSet::default();
             let mut new_edges: Vec<Edge> = Vec::new();
 
@@ -385,23 +557,233 @@ impl EngineMainThread {
                 }
             }
 
-            let res = ModifyGraphRes {
+            // The new schedule is compiled in the compiler thread. The result is
+            // reported with `OnIdleEvent::GraphCompiled`.
+            activated_state.audio_graph.finish_modify();
+
+            Ok(ModifyGraphRes {
+                request_id,
                 new_plugins: new_plugins_res,
                 removed_plugins: removed_plugins.drain().collect(),
                 new_edges,
                 removed_edges,
-            };
-
-            // TODO: Compile audio graph in a separate thread?
-            self.compile_audio_graph();
-
-            Some(res)
+            })
         } else {
             log::warn!("Cannot modify audio graph: Engine is deactivated");
-            None
+            Err(ModifyGraphError::EngineDeactivated)
         }
     }
 
+    /// The number of interleaved channels in each frame of an offline render
+    /// of the given source.
+    pub fn offline_render_num_channels(
+        &self,
+        source: &OfflineRenderSource,
+    ) -> Result<u16, OfflineRenderError> {
+        let activated_state =
+            self.activated_state.as_ref().ok_or(OfflineRenderError::EngineDeactivated)?;
+
+        let source = activated_state.audio_graph.resolve_offline_render_source(source)?;
+
+        Ok(source.num_channels(usize::from(activated_state.settings.num_audio_out_channels)) as u16)
+    }
+
+    /// Render the output of the audio graph faster than realtime for the
+    /// range of frames given in `settings`.
+    ///
+    /// `on_block` is called with the interleaved output of each processed
+    /// block, where each frame has `OfflineRenderInfo::num_audio_out_channels`
+    /// samples (the number of channels in `settings.source`). If `on_block`
+    /// returns `false`, then the render is cancelled.
+    ///
+    /// This blocks the main thread until the render is finished. Use
+    /// `EngineMainThread::begin_offline_render()` instead to render in
+    /// smaller steps.
+    pub fn render_offline<F: FnMut(&[f32]) -> bool>(
+        &mut self,
+        settings: OfflineRenderSettings,
+        mut on_block: F,
+    ) -> Result<OfflineRenderInfo, OfflineRenderError> {
+        self.begin_offline_render(settings)?;
+
+        while !self.continue_offline_render(Duration::MAX, &mut on_block)?.finished {}
+
+        Ok(self.end_offline_render().unwrap())
+    }
+
+    /// Start rendering the output of the audio graph faster than realtime for
+    /// the range of frames given in `settings`.
+    ///
+    /// The render is processed in steps by calling
+    /// `EngineMainThread::continue_offline_render()` until it reports that it
+    /// has finished, and then it **MUST** be ended with
+    /// `EngineMainThread::end_offline_render()`. Ending it before it has
+    /// finished cancels the render.
+    ///
+    /// Once `settings.end_frame` is reached, the render keeps going for the
+    /// length of the longest plugin tail in the graph (up to
+    /// `settings.max_tail_ms`), so reverbs and delays can ring out.
+    ///
+    /// While rendering, the transport plays from `settings.start_frame` with
+    /// looping disabled, and the system audio output is silenced. Once done,
+    /// the transport is restored to its previous state. The audio graph input
+    /// is fed with silence. Plugins are not restarted and the audio graph does
+    /// not pick up modifications until the render has ended.
+    pub fn begin_offline_render(
+        &mut self,
+        settings: OfflineRenderSettings,
+    ) -> Result<(), OfflineRenderError> {
+        let activated_state =
+            self.activated_state.as_mut().ok_or(OfflineRenderError::EngineDeactivated)?;
+
+        if activated_state.offline_render.is_some() {
+            return Err(OfflineRenderError::AlreadyRendering);
+        }
+
+        if settings.end_frame <= settings.start_frame {
+            return Err(OfflineRenderError::InvalidRange {
+                start_frame: settings.start_frame,
+                end_frame: settings.end_frame,
+            });
+        }
+
+        let source = activated_state.audio_graph.resolve_offline_render_source(&settings.source)?;
+
+        // Make sure the latest modifications to the graph are rendered.
+        activated_state.audio_graph.flush_compiler(&mut self.timer_wheel);
+        let num_audio_out_channels =
+            source.num_channels(usize::from(activated_state.settings.num_audio_out_channels));
+
+        // There is no need to park the process thread if it is no longer running.
+        let park_process_thread = activated_state.run_process_thread.load(Ordering::Relaxed);
+        if park_process_thread
+            && !activated_state.process_thread_park.park(PROCESS_THREAD_PARK_TIMEOUT)
+        {
+            return Err(OfflineRenderError::ProcessThreadTimedOut);
+        }
+
+        log::info!(
+            "Rendering frames [{}, {}) offline...",
+            settings.start_frame,
+            settings.end_frame
+        );
+
+        let sample_rate = activated_state.settings.sample_rate;
+        let max_tail_frames = u64::from(settings.max_tail_ms) * u64::from(sample_rate) / 1_000;
+
+        let render = activated_state.audio_graph.begin_offline_render(
+            source,
+            settings.start_frame,
+            settings.end_frame,
+            max_tail_frames,
+            settings.hard_clip_outputs,
+        );
+
+        activated_state.offline_render = Some(ActiveOfflineRender {
+            render,
+            num_audio_out_channels: num_audio_out_channels as u16,
+            parked_process_thread: park_process_thread,
+            process_time: Duration::ZERO,
+        });
+
+        Ok(())
+    }
+
+    /// Process blocks of the offline render started with
+    /// `EngineMainThread::begin_offline_render()` until it has finished, or
+    /// until `max_duration` has passed.
+    ///
+    /// `on_block` is called with the interleaved output of each processed
+    /// block, where each frame has `OfflineRenderInfo::num_audio_out_channels`
+    /// samples (the number of channels in `settings.source`). If `on_block`
+    /// returns `false`, then the render is cancelled.
+    pub fn continue_offline_render<F: FnMut(&[f32]) -> bool>(
+        &mut self,
+        max_duration: Duration,
+        mut on_block: F,
+    ) -> Result<OfflineRenderProgress, OfflineRenderError> {
+        let activated_state =
+            self.activated_state.as_mut().ok_or(OfflineRenderError::EngineDeactivated)?;
+        let active =
+            activated_state.offline_render.as_mut().ok_or(OfflineRenderError::NotRendering)?;
+
+        let start_time = Instant::now();
+
+        while activated_state.audio_graph.render_offline_block(
+            &mut active.render,
+            &activated_state.perf,
+            |block| on_block(block),
+        ) {
+            if start_time.elapsed() >= max_duration {
+                break;
+            }
+        }
+
+        active.process_time += start_time.elapsed();
+
+        Ok(OfflineRenderProgress {
+            frames_rendered: active.render.frames_rendered(),
+            total_frames: active.render.total_frames(),
+            finished: active.render.is_finished(),
+        })
+    }
+
+    /// End the offline render started with
+    /// `EngineMainThread::begin_offline_render()`, and hand the audio graph
+    /// back to the system audio output.
+    ///
+    /// If the render has not finished yet, then it is cancelled.
+    ///
+    /// This returns `None` if no offline render is in progress.
+    pub fn end_offline_render(&mut self) -> Option<OfflineRenderInfo> {
+        let activated_state = self.activated_state.as_mut()?;
+        let active = activated_state.offline_render.take()?;
+
+        let cancelled = active.render.cancelled() || !active.render.is_finished();
+        let num_frames = activated_state.audio_graph.end_offline_render(active.render);
+
+        if active.parked_process_thread {
+            activated_state.process_thread_park.unpark();
+        }
+
+        log::info!("Rendered {} frames offline in {:?}", num_frames, active.process_time);
+
+        let sample_rate = activated_state.settings.sample_rate;
+
+        Some(OfflineRenderInfo {
+            num_frames,
+            num_audio_out_channels: active.num_audio_out_channels,
+            sample_rate,
+            cancelled,
+            realtime_factor: (num_frames as f64 / f64::from(sample_rate))
+                / active.process_time.as_secs_f64().max(f64::EPSILON),
+        })
+    }
+
+    /// Returns `true` if an offline render started with
+    /// `EngineMainThread::begin_offline_render()` has not been ended yet.
+    pub fn is_rendering_offline(&self) -> bool {
+        self.activated_state.as_ref().map(|a| a.offline_render.is_some()).unwrap_or(false)
+    }
+
+    /// Render the output of the audio graph faster than realtime for the
+    /// range of frames given in `settings`, and return the interleaved output.
+    ///
+    /// See `EngineMainThread::render_offline()` for more details.
+    pub fn render_offline_to_vec(
+        &mut self,
+        settings: OfflineRenderSettings,
+    ) -> Result<(Vec<f32>, OfflineRenderInfo), OfflineRenderError> {
+        let mut output = Vec::new();
+
+        let info = self.render_offline(settings, |block| {
+            output.extend_from_slice(block);
+            true
+        })?;
+
+        Ok((output, info))
+    }
+
     /// Gracefully deactivate the engine. This will also reset the audio
     /// graph and remove all plugins.
     ///
@@ -417,6 +799,10 @@ impl EngineMainThread {
 
         log::info!("Deactivating RustyDAW engine");
 
+        if self.end_offline_render().is_some() {
+            log::warn!("Cancelled offline render because the engine was deactivated");
+        }
+
         let mut activated_state = self.activated_state.take().unwrap();
 
         // Attempt to remove all plugins gracefully.
@@ -439,6 +825,15 @@ impl EngineMainThread {
         self.activated_state.is_some()
     }
 
+    /// The latency in frames between when a plugin with no latency of its own
+    /// processes a block and when that block reaches the graph output (this
+    /// includes the delay inserted by delay compensation).
+    ///
+    /// This will return `0` if the engine is deactivated.
+    pub fn graph_out_latency(&self) -> u64 {
+        self.activated_state.as_ref().map(|a| a.audio_graph.graph_out_latency()).unwrap_or(0)
+    }
+
     /// Collect the latest save states for all plugins.
     ///
     /// This will only return the save states of plugins which have
@@ -454,33 +849,116 @@ impl EngineMainThread {
         self.activated_state.as_mut().unwrap().audio_graph.collect_save_states()
     }
 
-    fn collect_garbage(&mut self) {
-        self.plugin_scanner.unload_unused_binaries();
-        self.collector.collect();
+    /// Collect a report on the performance of the engine and of each plugin
+    /// since the last call to this method.
+    ///
+    /// This will return `None` if the engine is deactivated.
+    pub fn performance_report(&mut self) -> Option<PerformanceReport> {
+        let activated_state = if let Some(activated_state) = &self.activated_state {
+            activated_state
+        } else {
+            log::warn!("Ignored request for the performance report: Engine is deactivated");
+            return None;
+        };
+
+        let plugins = activated_state.audio_graph.take_plugin_process_times();
+
+        Some(activated_state.perf.take_report(plugins))
     }
 
-    fn compile_audio_graph(&mut self) {
-        if let Some(mut activated_state) = self.activated_state.take() {
-            match activated_state.audio_graph.compile() {
-                Ok(_) => {
-                    self.activated_state = Some(activated_state);
-                }
-                Err(e) => {
-                    log::error!("{}", e);
+    /// Collect a snapshot of the entire audio graph (all plugins, the edges
+    /// between them, and the settings the engine was activated with).
+    ///
+    /// This will return `None` if the engine is deactivated.
+    pub fn collect_graph_save_state(&mut self) -> Option<AudioGraphSaveState> {
+        let activated_state = if let Some(activated_state) = &mut self.activated_state {
+            activated_state
+        } else {
+            log::warn!("Ignored request for the graph save state: Engine is deactivated");
+            return None;
+        };
+
+        log::trace!("Got request for audio graph save state");
+
+        let (plugins, edges) = activated_state.audio_graph.collect_graph_save_state();
 
-                    // Audio graph is in an invalid state. Drop it and have the user restore
-                    // from the last working save state.
+        Some(AudioGraphSaveState { settings: activated_state.settings, plugins, edges })
+    }
 
-                    // Attempt to remove all plugins gracefully.
-                    activated_state.audio_graph.reset(&mut self.timer_wheel);
+    /// Replace the entire audio graph with the one in the given save state.
+    ///
+    /// All existing plugins and edges are removed, and the new graph is
+    /// compiled once. The plugins in `ModifyGraphRes::new_plugins` are in the
+    /// same order as in `AudioGraphSaveState::plugins`.
+    ///
+    /// This does not apply the engine settings in the save state. To restore
+    /// those as well, activate the engine with `AudioGraphSaveState::settings`
+    /// before calling this.
+    pub fn restore_graph(
+        &mut self,
+        save_state: AudioGraphSaveState,
+    ) -> Result<ModifyGraphRes, ModifyGraphError> {
+        let audio_graph =
+            &self.activated_state.as_ref().ok_or(ModifyGraphError::EngineDeactivated)?.audio_graph;
+
+        log::trace!("Got request to restore audio graph from save state");
+
+        let graph_in_id = audio_graph.graph_in_id().clone();
+        let graph_out_id = audio_graph.graph_out_id().clone();
+        let plugin_id_req = |node: NodeSaveStateID| match node {
+            NodeSaveStateID::GraphIn => PluginIDReq::Existing(graph_in_id.clone()),
+            NodeSaveStateID::GraphOut => PluginIDReq::Existing(graph_out_id.clone()),
+            NodeSaveStateID::Plugin(index) => PluginIDReq::Added(index),
+        };
 
-                    // Attempt to stop the process thread gracefully.
-                    let _ = activated_state;
+        let connect_new_edges: Vec<ConnectEdgeReq> = save_state
+            .edges
+            .iter()
+            .map(|edge| ConnectEdgeReq {
+                edge_type: edge.edge_type,
+                src_plugin_id: plugin_id_req(edge.src_node),
+                dst_plugin_id: plugin_id_req(edge.dst_node),
+                src_port_id: edge.src_port_id.clone(),
+                src_port_channel: edge.src_port_channel,
+                dst_port_id: edge.dst_port_id.clone(),
+                dst_port_channel: edge.dst_port_channel,
+                // The save state was collected from a graph that compiled
+                // successfully. If it somehow contains a cycle anyway, then
+                // the whole restore is rolled back.
+                check_for_cycles: false,
+                log_error_on_fail: true,
+            })
+            .collect();
+
+        let request = ModifyGraphRequest {
+            add_plugin_instances: save_state.plugins,
+            remove_plugin_instances: audio_graph.plugin_ids(),
+            connect_new_edges,
+            disconnect_edges: audio_graph.edge_ids(),
+        };
 
-                    self.timer_wheel.reset();
+        self.modify_graph(request)
+    }
+
+    fn collect_garbage(&mut self) {
+        self.plugin_scanner.unload_unused_binaries();
+        self.collector.collect();
+    }
 
-                    self.crash_msg = Some(EngineCrashError::CompilerError(e));
+    fn handle_compile_results(&mut self, events_out: &mut SmallVec<[OnIdleEvent; 32]>) {
+        if let Some(activated_state) = &mut self.activated_state {
+            for res in activated_state.audio_graph.drain_compile_results() {
+                // The process thread keeps running the last working schedule, so
+                // there is no need to tear down the engine.
+                if let Err(e) = &res.status {
+                    log::error!("{}", e);
                 }
+
+                events_out.push(OnIdleEvent::GraphCompiled {
+                    schedule_version: res.schedule_version,
+                    status: res.status,
+                    rolled_back_requests: res.rolled_back_requests,
+                });
             }
         }
     }
@@ -542,6 +1020,40 @@ impl std::fmt::Debug for ActivatedEngineInfo {
     }
 }
 
+/// The progress of an offline render started with
+/// `EngineMainThread::begin_offline_render()`.
+#[derive(Debug, Clone, Copy)]
+pub struct OfflineRenderProgress {
+    /// The number of frames that have been rendered so far.
+    pub frames_rendered: u64,
+
+    /// The total number of frames to render. Once the end frame is reached,
+    /// this grows by the length of the longest plugin tail.
+    pub total_frames: u64,
+
+    /// `true` if the render has finished or was cancelled, in which case it
+    /// must be ended with `EngineMainThread::end_offline_render()`.
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OfflineRenderInfo {
+    /// The total number of frames that were rendered.
+    pub num_frames: u64,
+
+    /// The number of interleaved channels in the rendered output.
+    pub num_audio_out_channels: u16,
+
+    /// The sample rate of the rendered output.
+    pub sample_rate: u32,
+
+    /// `true` if the render was cancelled before reaching the end frame.
+    pub cancelled: bool,
+
+    /// How many times faster than realtime the render was.
+    pub realtime_factor: f64,
+}
+
 #[derive(Debug)]
 /// Sent whenever the engine has become deactivated, whether gracefully
 /// or because of a crash.
@@ -622,6 +1134,11 @@ pub struct NewPluginRes {
 
 #[derive(Debug)]
 pub struct ModifyGraphRes {
+    /// The ID of this modification. If the modified graph fails to compile
+    /// and this modification is rolled back, then this ID is included in
+    /// `OnIdleEvent::GraphCompiled::rolled_back_requests`.
+    pub request_id: u64,
+
     /// Any new plugins that were added to the graph.
     pub new_plugins: Vec<NewPluginRes>,
 
@@ -714,6 +1231,28 @@ pub enum OnIdleEvent {
         status: Result<(), ActivatePluginError>,
     },
 
+    /// Sent when the audio graph has finished compiling a new schedule in
+    /// the background.
+    ///
+    /// Modifications to the graph made in quick succession are compiled
+    /// together, so there is not necessarily one of these events for every
+    /// call to `EngineMainThread::modify_graph()`.
+    ///
+    /// If `status` is an error, then the process thread keeps running the
+    /// last schedule that compiled successfully. The modifications in the
+    /// failed compile and any later modifications which depend on them have
+    /// been rolled back, and `rolled_back_requests` contains their
+    /// `ModifyGraphRes::request_id`s. Other modifications are kept.
+    GraphCompiled {
+        schedule_version: u64,
+        status: Result<(), GraphCompilerError>,
+        rolled_back_requests: Vec<u64>,
+    },
+
+    /// Sent when a scan started with `EngineMainThread::scan_external_plugins()`
+    /// has finished.
+    ExternalPluginsScanned(ScanExternalPluginsRes),
+
     /// Sent whenever the engine has been deactivated, whether gracefully or
     /// because of a crash.
     EngineDeactivated(EngineDeactivatedStatus),
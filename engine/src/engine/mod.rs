@@ -1,8 +1,10 @@
 pub(crate) mod audio_thread;
+pub(crate) mod performance;
 pub(crate) mod timer_wheel;
 
 mod main_thread;
 mod process_thread;
+mod save_state;
 mod settings;
 mod tempo_map;
 
@@ -11,8 +13,10 @@ pub mod modify_request;
 
 pub use audio_thread::EngineAudioThread;
 pub use main_thread::*;
+pub use performance::{DspLoadStats, PerformanceReport, ProcessTimeStats};
+pub use save_state::{AudioGraphSaveState, EdgeSaveState, NodeSaveStateID};
 pub use settings::{
-    ActivateEngineSettings, EngineSettings, DEFAULT_GARBAGE_COLLECT_INTERVAL_MS,
-    DEFAULT_IDLE_INTERVAL_MS,
+    ActivateEngineSettings, EngineSettings, OfflineRenderSettings, OfflineRenderSource,
+    DEFAULT_GARBAGE_COLLECT_INTERVAL_MS, DEFAULT_IDLE_INTERVAL_MS, DEFAULT_PLUGIN_SCAN_TIMEOUT_MS,
 };
 pub use tempo_map::{DefaultTempoMap, EngineTempoMap, TransportInfoAtFrame};
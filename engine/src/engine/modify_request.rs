@@ -1,4 +1,5 @@
 use meadowlark_plugin_api::PluginInstanceID;
+use serde::{Deserialize, Serialize};
 
 use crate::graph::{EngineEdgeID, PortType};
 use crate::plugin_host::PluginHostSaveState;
@@ -27,7 +28,7 @@ pub enum PluginIDReq {
     Added(usize),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EdgeReqPortID {
     /// Use the main port.
     ///
@@ -0,0 +1,294 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use meadowlark_plugin_api::PluginInstanceID;
+
+/// Accumulates measurements from a realtime thread so they can be collected
+/// by the main thread.
+///
+/// Recording a measurement is realtime-safe (it only uses atomics).
+pub(crate) struct PerfMeter {
+    num_measurements: AtomicU64,
+    total: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl PerfMeter {
+    pub fn new() -> Self {
+        Self {
+            num_measurements: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        self.num_measurements.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(value, Ordering::Relaxed);
+        self.min.fetch_min(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    pub fn record_duration(&self, elapsed: Duration) {
+        self.record(elapsed.as_nanos().min(u128::from(u64::MAX)) as u64);
+    }
+
+    /// Take all of the measurements recorded since the last call to this method.
+    ///
+    /// Returns `(num_measurements, min, avg, max)`, or `None` if nothing was
+    /// recorded.
+    ///
+    /// The values are not taken as a single atomic operation, so a measurement
+    /// recorded at the same time may be counted in the next call instead.
+    fn take(&self) -> Option<(u64, u64, u64, u64)> {
+        let num_measurements = self.num_measurements.swap(0, Ordering::Relaxed);
+        let total = self.total.swap(0, Ordering::Relaxed);
+        let min = self.min.swap(u64::MAX, Ordering::Relaxed);
+        let max = self.max.swap(0, Ordering::Relaxed);
+
+        if num_measurements == 0 {
+            return None;
+        }
+
+        Some((num_measurements, min.min(max), total / num_measurements, max))
+    }
+
+    pub fn take_process_time(&self) -> ProcessTimeStats {
+        self.take()
+            .map(|(num_cycles, min, avg, max)| ProcessTimeStats {
+                num_cycles,
+                num_skipped_cycles: 0,
+                min: Duration::from_nanos(min),
+                avg: Duration::from_nanos(avg),
+                max: Duration::from_nanos(max),
+            })
+            .unwrap_or_default()
+    }
+
+    /// Take the recorded DSP load measurements, where each measurement is in
+    /// parts per million of the available time.
+    fn take_dsp_load(&self) -> DspLoadStats {
+        self.take()
+            .map(|(_, min, avg, max)| DspLoadStats {
+                min: min as f64 / 1_000_000.0,
+                avg: avg as f64 / 1_000_000.0,
+                max: max as f64 / 1_000_000.0,
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Accumulates the total time spent on one kind of task in a single block,
+/// and then records that total as a single measurement.
+///
+/// The tasks in a block may be processed by multiple threads at once, so the
+/// total is accumulated with atomics.
+struct BlockPerfMeter {
+    block_total: AtomicU64,
+    meter: PerfMeter,
+}
+
+impl BlockPerfMeter {
+    fn new() -> Self {
+        Self { block_total: AtomicU64::new(0), meter: PerfMeter::new() }
+    }
+
+    fn add(&self, elapsed: Duration) {
+        self.block_total
+            .fetch_add(elapsed.as_nanos().min(u128::from(u64::MAX)) as u64, Ordering::Relaxed);
+    }
+
+    fn finish_block(&self) {
+        self.meter.record(self.block_total.swap(0, Ordering::Relaxed));
+    }
+}
+
+/// The kinds of tasks in a schedule which are metered as a group (plugins
+/// are metered individually instead).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TaskKind {
+    /// Summing multiple edges into a single input buffer.
+    Sum,
+    /// Delaying a buffer to compensate for the latency of other plugins.
+    DelayComp,
+}
+
+/// The measurements shared between the realtime threads and the main thread
+/// for the engine as a whole.
+pub(crate) struct EnginePerfShared {
+    schedule_process_time: PerfMeter,
+    dsp_load: PerfMeter,
+    num_underruns: AtomicU64,
+
+    sum_tasks: BlockPerfMeter,
+    delay_comp_tasks: BlockPerfMeter,
+    graph_in_out: PerfMeter,
+
+    offline_render_process_time: PerfMeter,
+    offline_render_dsp_load: PerfMeter,
+}
+
+impl EnginePerfShared {
+    pub fn new() -> Self {
+        Self {
+            schedule_process_time: PerfMeter::new(),
+            dsp_load: PerfMeter::new(),
+            num_underruns: AtomicU64::new(0),
+            sum_tasks: BlockPerfMeter::new(),
+            delay_comp_tasks: BlockPerfMeter::new(),
+            graph_in_out: PerfMeter::new(),
+            offline_render_process_time: PerfMeter::new(),
+            offline_render_dsp_load: PerfMeter::new(),
+        }
+    }
+
+    /// Record the time it took to process a single task of the given kind.
+    ///
+    /// This is realtime-safe, and it may be called from any of the threads
+    /// processing the block.
+    pub fn record_task(&self, kind: TaskKind, elapsed: Duration) {
+        match kind {
+            TaskKind::Sum => self.sum_tasks.add(elapsed),
+            TaskKind::DelayComp => self.delay_comp_tasks.add(elapsed),
+        }
+    }
+
+    /// Record the time it took to copy the audio into the graph input and out
+    /// of the graph output, and finish recording the tasks in the block.
+    ///
+    /// This must be called once all of the tasks in the block have been
+    /// processed.
+    ///
+    /// This is realtime-safe.
+    pub fn finish_block(&self, graph_in_out_elapsed: Duration) {
+        self.graph_in_out.record_duration(graph_in_out_elapsed);
+        self.sum_tasks.finish_block();
+        self.delay_comp_tasks.finish_block();
+    }
+
+    /// Record the time it took to process the whole schedule for a block of
+    /// `num_frames` frames.
+    ///
+    /// This is realtime-safe.
+    pub fn record_schedule_cycle(&self, elapsed: Duration, num_frames: usize, sample_rate: u32) {
+        self.schedule_process_time.record_duration(elapsed);
+
+        if num_frames > 0 {
+            let available_secs = num_frames as f64 / f64::from(sample_rate);
+            let load = elapsed.as_secs_f64() / available_secs;
+
+            self.dsp_load.record((load * 1_000_000.0).round() as u64);
+        }
+    }
+
+    /// Record the time it took to process a block of `num_frames` frames in
+    /// an offline render.
+    ///
+    /// These are kept separate from the measurements of the process thread,
+    /// since offline renders are not bound by a realtime deadline.
+    pub fn record_offline_render_block(
+        &self,
+        elapsed: Duration,
+        num_frames: usize,
+        sample_rate: u32,
+    ) {
+        self.offline_render_process_time.record_duration(elapsed);
+
+        if num_frames > 0 {
+            let realtime_secs = num_frames as f64 / f64::from(sample_rate);
+            let load = elapsed.as_secs_f64() / realtime_secs;
+
+            self.offline_render_dsp_load.record((load * 1_000_000.0).round() as u64);
+        }
+    }
+
+    /// Record that the audio thread had to output silence because the engine
+    /// took too long to process.
+    ///
+    /// This is realtime-safe.
+    pub fn record_underrun(&self) {
+        self.num_underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn take_report(
+        &self,
+        plugins: Vec<(PluginInstanceID, ProcessTimeStats)>,
+    ) -> PerformanceReport {
+        PerformanceReport {
+            schedule: self.schedule_process_time.take_process_time(),
+            dsp_load: self.dsp_load.take_dsp_load(),
+            num_underruns: self.num_underruns.swap(0, Ordering::Relaxed),
+            sum_tasks: self.sum_tasks.meter.take_process_time(),
+            delay_comp_tasks: self.delay_comp_tasks.meter.take_process_time(),
+            graph_in_out: self.graph_in_out.take_process_time(),
+            offline_render: self.offline_render_process_time.take_process_time(),
+            offline_render_dsp_load: self.offline_render_dsp_load.take_dsp_load(),
+            plugins,
+        }
+    }
+}
+
+/// Statistics about how long something took to process.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessTimeStats {
+    /// The number of process cycles these statistics were collected over.
+    pub num_cycles: u64,
+
+    /// The number of those process cycles where a plugin was not processed
+    /// because all of its inputs were silent. This is always `0` for the
+    /// schedule as a whole.
+    pub num_skipped_cycles: u64,
+
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+}
+
+/// Statistics about how much of the available time the engine spent
+/// processing each block, where `1.0` means it took all of the available
+/// time.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DspLoadStats {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+/// A report on the performance of the engine since the last report was
+/// collected with `EngineMainThread::performance_report()`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PerformanceReport {
+    /// The time it took to process the whole schedule in each process cycle.
+    pub schedule: ProcessTimeStats,
+
+    /// The time it took to process the whole schedule in each process cycle,
+    /// relative to the duration of audio in that cycle.
+    pub dsp_load: DspLoadStats,
+
+    /// The number of times the audio thread had to output silence because
+    /// the engine took too long to process.
+    pub num_underruns: u64,
+
+    /// The total time spent summing edges into input buffers in each block.
+    pub sum_tasks: ProcessTimeStats,
+
+    /// The total time spent on delay compensation in each block.
+    pub delay_comp_tasks: ProcessTimeStats,
+
+    /// The time spent copying audio into the graph input and out of the
+    /// graph output in each block.
+    pub graph_in_out: ProcessTimeStats,
+
+    /// The time it took to process each block of an offline render.
+    pub offline_render: ProcessTimeStats,
+
+    /// The time it took to process each block of an offline render, relative
+    /// to the duration of audio in that block. Values below `1.0` mean the
+    /// render was faster than realtime.
+    pub offline_render_dsp_load: DspLoadStats,
+
+    /// The time each plugin took to process in each process cycle.
+    pub plugins: Vec<(PluginInstanceID, ProcessTimeStats)>,
+}
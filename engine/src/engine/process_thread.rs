@@ -1,14 +1,86 @@
 use basedrop::Owned;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
+use std::time::{Duration, Instant};
 
 use crate::graph::shared_pools::SharedProcessorSchedule;
+use crate::processor_schedule::parallel::WorkerPoolShared;
 
 use super::audio_thread::{
     AudioToProcessChannelRX, ProcessToAudioChannelTX, AUDIO_THREAD_POLL_INTERVAL,
 };
+use super::performance::EnginePerfShared;
+
+/// Used by the main thread to temporarily take exclusive ownership of the
+/// processor schedule (i.e. when rendering offline).
+///
+/// While parked, the process thread keeps feeding silence to the audio
+/// thread without touching the schedule.
+///
+/// Every call to `park()` starts a new generation, and it only returns once
+/// the process thread has acknowledged that exact generation. This way a
+/// `park()` which quickly follows an `unpark()` can never mistake the
+/// acknowledgement of the previous request for its own.
+#[derive(Clone)]
+pub(crate) struct ProcessThreadPark {
+    requested: Arc<AtomicBool>,
+    generation: Arc<AtomicU64>,
+    acked_generation: Arc<AtomicU64>,
+}
+
+impl ProcessThreadPark {
+    pub fn new() -> Self {
+        Self {
+            requested: Arc::new(AtomicBool::new(false)),
+            generation: Arc::new(AtomicU64::new(0)),
+            acked_generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Request the process thread to park, and wait until it has done so.
+    ///
+    /// This returns `false` if the process thread did not park within the
+    /// given timeout, in which case the request is withdrawn.
+    pub fn park(&self, timeout: Duration) -> bool {
+        // The request must be visible before the new generation is, so the
+        // process thread can never acknowledge this generation without also
+        // seeing the request.
+        self.requested.store(true, Ordering::SeqCst);
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let start_time = Instant::now();
+        while self.acked_generation.load(Ordering::SeqCst) != generation {
+            if start_time.elapsed() >= timeout {
+                self.requested.store(false, Ordering::SeqCst);
+                return false;
+            }
+
+            std::thread::sleep(AUDIO_THREAD_POLL_INTERVAL);
+        }
+
+        true
+    }
+
+    /// Allow the process thread to resume processing the schedule.
+    pub fn unpark(&self) {
+        self.requested.store(false, Ordering::SeqCst);
+    }
+
+    /// Called by the process thread before it touches the schedule. Returns
+    /// `true` if the process thread must not touch the schedule.
+    fn poll(&self) -> bool {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let is_parked = self.requested.load(Ordering::SeqCst);
+
+        if is_parked {
+            self.acked_generation.store(generation, Ordering::SeqCst);
+        }
+
+        is_parked
+    }
+}
 
 pub(crate) struct EngineProcessThread {
     audio_to_process_channel: Owned<AudioToProcessChannelRX>,
@@ -23,6 +95,12 @@ pub(crate) struct EngineProcessThread {
     hard_clip_outputs: bool,
 
     schedule: SharedProcessorSchedule,
+
+    /// The realtime worker threads which help process the schedule, if any.
+    workers: Option<Arc<WorkerPoolShared>>,
+
+    sample_rate: u32,
+    perf: Arc<EnginePerfShared>,
 }
 
 impl EngineProcessThread {
@@ -35,6 +113,9 @@ impl EngineProcessThread {
         max_frames: usize,
         hard_clip_outputs: bool,
         schedule: SharedProcessorSchedule,
+        workers: Option<Arc<WorkerPoolShared>>,
+        sample_rate: u32,
+        perf: Arc<EnginePerfShared>,
         coll_handle: &basedrop::Handle,
     ) -> Self {
         Self {
@@ -52,14 +133,19 @@ impl EngineProcessThread {
             ),
             hard_clip_outputs,
             schedule,
+            workers,
+            sample_rate,
+            perf,
         }
     }
 
-    pub fn run(&mut self, run: Arc<AtomicBool>) {
+    pub fn run(&mut self, run: Arc<AtomicBool>, park: ProcessThreadPark) {
         #[cfg(target_os = "windows")]
         let spin_sleeper = spin_sleep::SpinSleeper::default();
 
         while run.load(Ordering::Relaxed) {
+            let is_parked = park.poll();
+
             let num_frames = match &mut *self.audio_to_process_channel {
                 AudioToProcessChannelRX::HasInputAudio { audio_rb_rx } => {
                     if !audio_rb_rx.is_abandoned() {
@@ -116,8 +202,24 @@ impl EngineProcessThread {
             self.audio_out_temp_buffer.clear();
             self.audio_out_temp_buffer.resize(num_frames * self.graph_audio_out_channels, 0.0);
 
-            self.schedule
-                .process_interleaved(&self.audio_in_temp_buffer, &mut self.audio_out_temp_buffer);
+            // The main thread has exclusive ownership of the schedule while the
+            // process thread is parked, so just output silence.
+            if !is_parked {
+                let proc_start_time = Instant::now();
+
+                self.schedule.process_interleaved(
+                    self.workers.as_deref(),
+                    &self.perf,
+                    &self.audio_in_temp_buffer,
+                    &mut self.audio_out_temp_buffer,
+                );
+
+                self.perf.record_schedule_cycle(
+                    proc_start_time.elapsed(),
+                    num_frames,
+                    self.sample_rate,
+                );
+            }
 
             if self.hard_clip_outputs {
                 for smp in self.audio_out_temp_buffer.iter_mut() {
@@ -149,8 +251,62 @@ impl EngineProcessThread {
             }
         }
 
+        // Wait for the main thread to give back ownership of the schedule.
+        while park.poll() {
+            std::thread::sleep(AUDIO_THREAD_POLL_INTERVAL);
+        }
+
         // Make sure we drop all plugin processors in the process thread
         // when deactivating the engine.
         self.schedule.deactivate();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn park_waits_for_every_request() {
+        let park = ProcessThreadPark::new();
+        let run = Arc::new(AtomicBool::new(true));
+        let in_schedule = Arc::new(AtomicBool::new(false));
+
+        // Mimic the process thread, which touches the schedule whenever it
+        // is not parked.
+        let handle = {
+            let park = park.clone();
+            let run = Arc::clone(&run);
+            let in_schedule = Arc::clone(&in_schedule);
+            std::thread::spawn(move || {
+                while run.load(Ordering::SeqCst) {
+                    if !park.poll() {
+                        in_schedule.store(true, Ordering::SeqCst);
+                        std::thread::yield_now();
+                        in_schedule.store(false, Ordering::SeqCst);
+                    }
+                }
+            })
+        };
+
+        for _ in 0..200 {
+            assert!(park.park(Duration::from_secs(5)));
+            for _ in 0..10 {
+                assert!(!in_schedule.load(Ordering::SeqCst));
+                std::thread::yield_now();
+            }
+            park.unpark();
+        }
+
+        run.store(false, Ordering::SeqCst);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn park_times_out_without_a_process_thread() {
+        let park = ProcessThreadPark::new();
+
+        assert!(!park.park(Duration::from_millis(10)));
+        assert!(!park.requested.load(Ordering::SeqCst));
+    }
+}
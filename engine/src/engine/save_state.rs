@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use crate::graph::PortType;
+use crate::plugin_host::PluginHostSaveState;
+
+use super::modify_request::EdgeReqPortID;
+use super::ActivateEngineSettings;
+
+/// A snapshot of the entire audio graph (all plugin instances, the edges
+/// between them, and the settings the engine was activated with).
+///
+/// Collect one with `EngineMainThread::collect_graph_save_state()`, and
+/// rebuild the graph from it with `EngineMainThread::restore_graph()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioGraphSaveState {
+    /// The settings the engine was activated with. This also contains the
+    /// number of input/output channels on the graph in/out nodes.
+    pub settings: ActivateEngineSettings,
+
+    /// The save states of all plugin instances in the graph (including
+    /// whether they are active/bypassed).
+    pub plugins: Vec<PluginHostSaveState>,
+
+    /// All edges between the nodes in the graph.
+    pub edges: Vec<EdgeSaveState>,
+}
+
+/// A node in an `AudioGraphSaveState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NodeSaveStateID {
+    /// The graph input node.
+    GraphIn,
+    /// The graph output node.
+    GraphOut,
+    /// The plugin instance at this index in `AudioGraphSaveState::plugins`.
+    Plugin(usize),
+}
+
+/// An edge in an `AudioGraphSaveState`.
+///
+/// Ports on plugins are referred to by their stable IDs, so this stays
+/// valid across different versions of a plugin.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EdgeSaveState {
+    pub edge_type: PortType,
+
+    pub src_node: NodeSaveStateID,
+    pub dst_node: NodeSaveStateID,
+
+    pub src_port_id: EdgeReqPortID,
+    pub src_port_channel: u16,
+
+    pub dst_port_id: EdgeReqPortID,
+    pub dst_port_channel: u16,
+}
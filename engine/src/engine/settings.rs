@@ -1,11 +1,41 @@
+use meadowlark_plugin_api::PluginInstanceID;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::plugin_scanner::DuplicatePluginPolicy;
+
+use super::modify_request::EdgeReqPortID;
+
 pub static DEFAULT_IDLE_INTERVAL_MS: u32 = 16;
 pub static DEFAULT_GARBAGE_COLLECT_INTERVAL_MS: u32 = 3_000;
 pub static DEFAULT_TRANSPORT_DECLICK_SECONDS: f64 = 3.0 / 1_000.0;
+pub static DEFAULT_PLUGIN_SCAN_TIMEOUT_MS: u32 = 20_000;
+pub static DEFAULT_MAX_OFFLINE_RENDER_TAIL_MS: u32 = 10_000;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EngineSettings {
     pub main_idle_interval_ms: u32,
     pub garbage_collect_interval_ms: u32,
+
+    /// The file where the results of scanning external plugins (as well as
+    /// the blocklist of plugins which crashed or hung while being scanned)
+    /// are stored, so that rescans only need to load plugins which have
+    /// changed. Set this to `None` to always scan every plugin.
+    ///
+    /// By default this is a file in the user's cache directory.
+    pub plugin_scan_cache_path: Option<PathBuf>,
+
+    /// If a plugin binary takes longer than this to scan, then it will be
+    /// added to the blocklist.
+    ///
+    /// By default this is set to `20_000` (20 seconds).
+    pub plugin_scan_timeout_ms: u32,
+
+    /// How to choose which binary to load a plugin from when multiple
+    /// binaries contain a plugin with the same ID.
+    ///
+    /// By default this is set to `DuplicatePluginPolicy::NewestVersion`.
+    pub duplicate_plugin_policy: DuplicatePluginPolicy,
 }
 
 impl Default for EngineSettings {
@@ -13,11 +43,15 @@ impl Default for EngineSettings {
         Self {
             main_idle_interval_ms: DEFAULT_IDLE_INTERVAL_MS,
             garbage_collect_interval_ms: DEFAULT_GARBAGE_COLLECT_INTERVAL_MS,
+            plugin_scan_cache_path: dirs::cache_dir()
+                .map(|dir| dir.join("meadowlark").join("plugin_scan_cache.txt")),
+            plugin_scan_timeout_ms: DEFAULT_PLUGIN_SCAN_TIMEOUT_MS,
+            duplicate_plugin_policy: DuplicatePluginPolicy::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct ActivateEngineSettings {
     /// The sample rate of the project.
     pub sample_rate: u32,
@@ -56,6 +90,18 @@ pub struct ActivateEngineSettings {
     ///
     /// By default this is set to `false`.
     pub hard_clip_outputs: bool,
+
+    /// The number of realtime worker threads (not including the process
+    /// thread) used to process independent branches of the audio graph in
+    /// parallel. Set this to `0` to process the whole graph in the process
+    /// thread.
+    ///
+    /// Worker threads run at realtime priority and busy-wait for new blocks
+    /// while the engine is activated, so only enable them when the graph is
+    /// large enough to benefit from it.
+    ///
+    /// By default this is set to `0`.
+    pub num_worker_threads: usize,
 }
 
 impl Default for ActivateEngineSettings {
@@ -70,6 +116,60 @@ impl Default for ActivateEngineSettings {
             event_buffer_size: 256,
             transport_declick_seconds: DEFAULT_TRANSPORT_DECLICK_SECONDS,
             hard_clip_outputs: false,
+            num_worker_threads: 0,
+        }
+    }
+}
+
+/// Where the rendered output of an offline render is taken from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OfflineRenderSource {
+    /// Render the output of the audio graph.
+    GraphOutput,
+    /// Render the output of a single audio output port of a plugin.
+    ///
+    /// Note the plugin must still be connected (directly or indirectly) to
+    /// the graph output in order for it to be processed.
+    PluginAudioOutPort { plugin_id: PluginInstanceID, port_id: EdgeReqPortID },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineRenderSettings {
+    /// Where to take the rendered output from.
+    ///
+    /// By default this is set to `OfflineRenderSource::GraphOutput`.
+    pub source: OfflineRenderSource,
+
+    /// The frame on the timeline where rendering starts (inclusive).
+    pub start_frame: u64,
+
+    /// The frame on the timeline where rendering ends (exclusive).
+    ///
+    /// The render is extended past this frame by the longest tail of all
+    /// plugins in the graph (see `max_tail_ms`).
+    pub end_frame: u64,
+
+    /// The maximum amount of time to keep rendering after `end_frame` to let
+    /// the tails of plugins (i.e. reverbs and delays) ring out. Plugins with
+    /// an infinite tail are rendered for this long.
+    ///
+    /// By default this is set to `10_000` (10 seconds).
+    pub max_tail_ms: u32,
+
+    /// If true, all rendered output samples will be hard clipped at 0dB.
+    ///
+    /// By default this is set to `false`.
+    pub hard_clip_outputs: bool,
+}
+
+impl Default for OfflineRenderSettings {
+    fn default() -> Self {
+        Self {
+            source: OfflineRenderSource::GraphOutput,
+            start_frame: 0,
+            end_frame: 0,
+            max_tail_ms: DEFAULT_MAX_OFFLINE_RENDER_TAIL_MS,
+            hard_clip_outputs: false,
         }
     }
 }
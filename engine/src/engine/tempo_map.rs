@@ -9,7 +9,10 @@ pub trait EngineTempoMap: Send + Sync + 'static {
 
 #[derive(Debug, Clone, Copy)]
 pub struct TransportInfoAtFrame {
+    /// The tempo at the frame in beats per minute.
     pub tempo: f64,
+    /// How much the tempo changes with each frame after this frame (i.e.
+    /// while the tempo is ramping from one tempo to another).
     pub tempo_inc: f64,
 
     pub tsig_num: u16,
@@ -19,6 +22,10 @@ pub struct TransportInfoAtFrame {
     pub current_bar_start: BeatTime,
 }
 
+/// A tempo map with a single static tempo and time signature.
+///
+/// Hosts which support tempo and time signature changes should provide
+/// their own implementation of `EngineTempoMap`.
 #[derive(Debug, Clone)]
 pub struct DefaultTempoMap {
     pub tsig_num: u16,
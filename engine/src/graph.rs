@@ -1,38 +1,50 @@
 use std::hash::Hash;
 
-use audio_graph::{error::AddEdgeError, AudioGraphHelper, EdgeID, PortID, TypeIdx};
+use audio_graph::{error::AddEdgeError, EdgeID, NodeID, PortID, TypeIdx};
 use basedrop::Shared;
 use fnv::{FnvHashMap, FnvHashSet};
 use meadowlark_plugin_api::transport::LoopState;
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 
+mod abstract_graph;
 mod compiler;
 
 pub mod error;
 
 pub(crate) mod shared_pools;
 
-use meadowlark_plugin_api::{PluginInstanceID, PluginInstanceType};
+pub(crate) use abstract_graph::AbstractGraph;
+pub(crate) use compiler::compiler_thread::CompileResult;
 
+use meadowlark_plugin_api::ext::audio_ports::MainPortsLayout;
+use meadowlark_plugin_api::{PluginInstanceID, PluginInstanceType, TailLength};
+
+use crate::engine::error::OfflineRenderError;
 use crate::engine::modify_request::{ConnectEdgeReq, EdgeReqPortID};
+use crate::engine::performance::EnginePerfShared;
 use crate::engine::timer_wheel::EngineTimerWheel;
-use crate::engine::{EngineTempoMap, NewPluginRes, OnIdleEvent, PluginStatus};
+use crate::engine::{
+    EdgeSaveState, EngineTempoMap, NewPluginRes, NodeSaveStateID, OfflineRenderSource, OnIdleEvent,
+    PluginStatus, ProcessTimeStats,
+};
 use crate::plugin_host::{
     OnIdleResult, PluginHostMainThread, PluginHostProcessorWrapper, PluginHostSaveState,
 };
 use crate::plugin_scanner::PluginScanner;
-use crate::processor_schedule::tasks::{TransportHandle, TransportTask};
+use crate::processor_schedule::tasks::{SavedTransportState, TransportHandle, TransportTask};
 use crate::processor_schedule::ProcessorSchedule;
 use crate::utils::thread_id::SharedThreadIDs;
 
-use compiler::verifier::Verifier;
-use shared_pools::{GraphSharedPools, SharedProcessorSchedule};
+use compiler::compiler_thread::CompilerThread;
+use compiler::{CompileJob, CompilerPluginNode};
+use shared_pools::{CompilerPools, GraphSharedPools, SharedProcessorSchedule};
 
-use error::{ConnectEdgeError, ConnectEdgeErrorType, GraphCompilerError};
+use error::{ConnectEdgeError, ConnectEdgeErrorType};
 
 /// A default port type for general purpose applications
 #[repr(u32)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PortType {
     /// Audio ports
     Audio = 0,
@@ -97,9 +109,16 @@ pub struct PortChannelID {
 
 pub(crate) struct AudioGraph {
     shared_pools: GraphSharedPools,
-    verifier: Verifier,
+    compiler_thread: CompilerThread,
 
-    graph_helper: AudioGraphHelper,
+    /// Set when the graph has been modified since the last compile was
+    /// started. This lets multiple modifications be compiled together.
+    compile_requested: bool,
+    /// The results of compiles which have finished but have not yet been
+    /// collected with `AudioGraph::drain_compile_results()`.
+    compile_results: Vec<CompileResult>,
+
+    abstract_graph: AbstractGraph,
     coll_handle: basedrop::Handle,
 
     graph_in_id: PluginInstanceID,
@@ -107,9 +126,17 @@ pub(crate) struct AudioGraph {
     graph_in_num_audio_channels: usize,
     graph_out_num_audio_channels: usize,
 
-    edge_id_to_ds_edge_id: FnvHashMap<EdgeID, EngineEdgeID>,
+    edges: FnvHashMap<EdgeID, GraphEdge>,
     next_ds_edge_id: u64,
 
+    /// The changes made to the graph since `AudioGraph::begin_modify()` was
+    /// called.
+    modify_journal: Option<ModifyJournal>,
+    /// The finished modifications whose compile result has not arrived yet,
+    /// in the order they were made.
+    pending_journals: Vec<PendingJournal>,
+    next_modify_request_id: u64,
+
     sample_rate: u32,
     min_frames: u32,
     max_frames: u32,
@@ -120,9 +147,38 @@ pub(crate) struct AudioGraph {
 
     thread_ids: SharedThreadIDs,
 
+    /// The number of realtime worker threads (not including the process
+    /// thread) the compiled schedules will be processed with.
+    num_worker_threads: usize,
+
     schedule_version: u64,
 }
 
+/// A finished modification which is waiting for the result of the compile
+/// that includes it.
+struct PendingJournal {
+    /// The version of the schedule the modification is compiled into. This
+    /// is `None` until that compile is started.
+    schedule_version: Option<u64>,
+    journal: ModifyJournal,
+}
+
+/// The changes made to the graph by a single modification, so that they can
+/// be undone if the modified graph fails to compile.
+#[derive(Default)]
+struct ModifyJournal {
+    /// The ID returned by `AudioGraph::begin_modify()`.
+    request_id: u64,
+
+    new_plugins: Vec<PluginInstanceID>,
+    new_edges: Vec<EdgeID>,
+    disconnected_edges: Vec<GraphEdge>,
+
+    /// The plugins which are removed once the modified graph compiles
+    /// successfully. Until then they are only disconnected from the graph.
+    removed_plugins: Vec<PluginInstanceID>,
+}
+
 impl AudioGraph {
     #[allow(clippy::too_many_arguments)] // Fix this?
     pub fn new(
@@ -135,6 +191,7 @@ impl AudioGraph {
         note_buffer_size: usize,
         event_buffer_size: usize,
         thread_ids: SharedThreadIDs,
+        num_worker_threads: usize,
         seek_to_frame: u64,
         loop_state: LoopState,
         tempo_map: Box<dyn EngineTempoMap>,
@@ -144,8 +201,6 @@ impl AudioGraph {
         //assert!(graph_in_channels > 0);
         assert!(graph_out_channels > 0);
 
-        let graph_helper = AudioGraphHelper::new(PortType::NUM_TYPES);
-
         let (transport_task, transport_handle) = TransportTask::new(
             seek_to_frame,
             loop_state,
@@ -159,13 +214,22 @@ impl AudioGraph {
         let (shared_pools, shared_schedule) = GraphSharedPools::new(
             thread_ids.clone(),
             max_frames as usize,
-            note_buffer_size,
-            event_buffer_size,
             transport_task,
             0,
             coll_handle.clone(),
         );
 
+        let compiler_thread = CompilerThread::spawn(
+            CompilerPools::new(
+                max_frames as usize,
+                note_buffer_size,
+                event_buffer_size,
+                coll_handle.clone(),
+            ),
+            shared_pools.shared_schedule.new_handle(),
+            coll_handle.clone(),
+        );
+
         let graph_in_rdn = Shared::new(&coll_handle, String::from("app.meadowlark.graph_in_node"));
         let graph_out_rdn =
             Shared::new(&coll_handle, String::from("app.meadowlark.graph_out_node"));
@@ -177,20 +241,26 @@ impl AudioGraph {
 
         let mut new_self = Self {
             shared_pools,
-            verifier: Verifier::new(),
-            graph_helper,
+            compiler_thread,
+            compile_requested: false,
+            compile_results: Vec::new(),
+            abstract_graph: AbstractGraph::new(),
             coll_handle,
             graph_in_num_audio_channels: graph_in_channels,
             graph_out_num_audio_channels: graph_out_channels,
             graph_in_id,
             graph_out_id,
-            edge_id_to_ds_edge_id: FnvHashMap::default(),
+            edges: FnvHashMap::default(),
             next_ds_edge_id: 0,
+            modify_journal: None,
+            pending_journals: Vec::new(),
+            next_modify_request_id: 0,
             sample_rate,
             min_frames,
             max_frames,
             plugin_processors_to_drop: Vec::new(),
             thread_ids,
+            num_worker_threads,
             schedule_version: 0,
         };
 
@@ -207,7 +277,7 @@ impl AudioGraph {
     ) -> NewPluginRes {
         let do_activate_plugin = save_state.active;
 
-        let node_id = self.graph_helper.add_node(0.0);
+        let node_id = self.abstract_graph.add_node(0.0);
         let res = plugin_scanner.create_plugin(save_state, node_id, fallback_to_other_formats);
         let plugin_id = res.plugin_host.id().clone();
 
@@ -231,6 +301,10 @@ impl AudioGraph {
             panic!("Something went wrong when allocating a new slot for a plugin");
         }
 
+        if let Some(journal) = &mut self.modify_journal {
+            journal.new_plugins.push(plugin_id.clone());
+        }
+
         let activation_status = if do_activate_plugin {
             self.activate_plugin_instance(&plugin_id).unwrap()
         } else {
@@ -256,8 +330,8 @@ impl AudioGraph {
             self.sample_rate,
             self.min_frames,
             self.max_frames,
-            &mut self.graph_helper,
-            &mut self.edge_id_to_ds_edge_id,
+            &mut self.abstract_graph,
+            &mut self.edges,
             self.thread_ids.clone(),
             self.schedule_version,
             &self.coll_handle,
@@ -278,6 +352,10 @@ impl AudioGraph {
     /// `AudioGraph::graph_in_node_id()` and `AudioGraph::graph_out_node_id()` will be
     /// ignored.
     ///
+    /// If the graph is being modified (see `AudioGraph::begin_modify()`), then the
+    /// plugins are only disconnected from the graph until the modified graph has
+    /// compiled successfully.
+    ///
     /// This returns a list of all the plugins that were successfully removed, as well
     /// as a list of all edges that were removed as a result of the operation.
     pub fn remove_plugin_instances(
@@ -295,31 +373,42 @@ impl AudioGraph {
             }
 
             if removed_plugins.insert(id.clone()) {
-                if let Some(plugin_host) = self.shared_pools.plugin_hosts.get_mut(id) {
-                    if let Some(plugin_proc_to_drop) =
-                        plugin_host.schedule_remove(&self.coll_handle, engine_timer)
-                    {
-                        self.plugin_processors_to_drop.push(plugin_proc_to_drop);
-                    }
-
-                    let removed_edges_res =
-                        self.graph_helper.remove_node(id._node_id().into()).unwrap();
-                    for edge_id in removed_edges_res.iter() {
-                        if let Some(ds_edge_id) = self.edge_id_to_ds_edge_id.remove(edge_id) {
-                            removed_edges.push(ds_edge_id);
-                        } else {
+                if self.shared_pools.plugin_hosts.get(id).is_none()
+                    || self.is_queued_for_removal(id)
+                {
+                    removed_plugins.remove(id);
+                    log::warn!(
+                        "Ignored request to remove plugin instance {:?}: plugin is already removed",
+                        id
+                    );
+                } else if let Some(journal) = &mut self.modify_journal {
+                    let node_id: NodeID = id._node_id().into();
+
+                    let edge_ids: Vec<EdgeID> = self
+                        .edges
+                        .iter()
+                        .filter(|(_, edge)| {
+                            edge.src_node_id == node_id || edge.dst_node_id == node_id
+                        })
+                        .map(|(edge_id, _)| *edge_id)
+                        .collect();
+
+                    for edge_id in edge_ids {
+                        if self.abstract_graph.remove_edge(edge_id).is_err() {
                             panic!(
-                                "Helper disconnected an edge that doesn't exist in graph: {:?}",
+                                "Unexpected error while disconnecting edge in graph: {:?}",
                                 edge_id
                             );
                         }
+
+                        let edge = self.edges.remove(&edge_id).unwrap();
+                        removed_edges.push(edge.ds_edge_id);
+                        journal.disconnected_edges.push(edge);
                     }
+
+                    journal.removed_plugins.push(id.clone());
                 } else {
-                    removed_plugins.remove(id);
-                    log::warn!(
-                        "Ignored request to remove plugin instance {:?}: plugin is already removed",
-                        id
-                    );
+                    self.remove_plugin_instance(id, engine_timer, &mut removed_edges);
                 }
             } else {
                 log::warn!("Ignored duplicate request to remove plugin instance {:?}", id);
@@ -329,12 +418,63 @@ impl AudioGraph {
         (removed_plugins, removed_edges)
     }
 
+    fn remove_plugin_instance(
+        &mut self,
+        id: &PluginInstanceID,
+        engine_timer: &mut EngineTimerWheel,
+        removed_edges: &mut Vec<EngineEdgeID>,
+    ) {
+        if let Some(plugin_host) = self.shared_pools.plugin_hosts.get_mut(id) {
+            if let Some(plugin_proc_to_drop) =
+                plugin_host.schedule_remove(&self.coll_handle, engine_timer)
+            {
+                self.plugin_processors_to_drop.push(plugin_proc_to_drop);
+            }
+
+            let removed_edges_res = self.abstract_graph.remove_node(id._node_id().into()).unwrap();
+            for edge_id in removed_edges_res.iter() {
+                if let Some(edge) = self.edges.remove(edge_id) {
+                    removed_edges.push(edge.ds_edge_id);
+                } else {
+                    panic!(
+                        "Helper disconnected an edge that doesn't exist in graph: {:?}",
+                        edge_id
+                    );
+                }
+            }
+        }
+    }
+
+    /// Whether the plugin is queued to be removed once a modification to the
+    /// graph has compiled successfully.
+    fn is_queued_for_removal(&self, id: &PluginInstanceID) -> bool {
+        self.modify_journal.iter().any(|journal| journal.removed_plugins.contains(id))
+            || self
+                .pending_journals
+                .iter()
+                .any(|pending| pending.journal.removed_plugins.contains(id))
+    }
+
     pub fn connect_edge(
         &mut self,
         edge: &ConnectEdgeReq,
         src_plugin_id: &PluginInstanceID,
         dst_plugin_id: &PluginInstanceID,
     ) -> Result<Edge, ConnectEdgeError> {
+        // Plugins that are queued to be removed can no longer be connected.
+        if self.is_queued_for_removal(src_plugin_id) {
+            return Err(ConnectEdgeError {
+                error_type: ConnectEdgeErrorType::SrcPluginDoesNotExist,
+                edge: edge.clone(),
+            });
+        }
+        if self.is_queued_for_removal(dst_plugin_id) {
+            return Err(ConnectEdgeError {
+                error_type: ConnectEdgeErrorType::DstPluginDoesNotExist,
+                edge: edge.clone(),
+            });
+        }
+
         let (src_port_id, src_port_stable_id) = if src_plugin_id == &self.graph_in_id {
             match &edge.src_port_id {
                 EdgeReqPortID::Main => match edge.edge_type {
@@ -567,10 +707,13 @@ impl AudioGraph {
             });
         };
 
-        match self.graph_helper.add_edge(
-            src_plugin_id._node_id().into(),
+        let src_node_id: NodeID = src_plugin_id._node_id().into();
+        let dst_node_id: NodeID = dst_plugin_id._node_id().into();
+
+        match self.abstract_graph.add_edge(
+            src_node_id,
             src_port_id,
-            dst_plugin_id._node_id().into(),
+            dst_node_id,
             dst_port_id,
             edge.check_for_cycles,
         ) {
@@ -578,10 +721,22 @@ impl AudioGraph {
                 let ds_edge_id = EngineEdgeID { unique_id: self.next_ds_edge_id, edge_id };
                 self.next_ds_edge_id += 1;
 
-                if self.edge_id_to_ds_edge_id.insert(edge_id, ds_edge_id).is_some() {
+                let graph_edge = GraphEdge {
+                    ds_edge_id,
+                    edge_type: edge.edge_type,
+                    src_node_id,
+                    src_port_id,
+                    dst_node_id,
+                    dst_port_id,
+                };
+                if self.edges.insert(edge_id, graph_edge).is_some() {
                     panic!("Something went wrong while connecting edge {:?}", edge_id);
                 }
 
+                if let Some(journal) = &mut self.modify_journal {
+                    journal.new_edges.push(edge_id);
+                }
+
                 Ok(Edge {
                     id: ds_edge_id,
 
@@ -614,9 +769,16 @@ impl AudioGraph {
     }
 
     pub fn disconnect_edge(&mut self, ds_edge_id: EngineEdgeID) -> bool {
-        if self.edge_id_to_ds_edge_id.remove(&ds_edge_id.edge_id).is_some() {
-            if self.graph_helper.remove_edge(ds_edge_id.edge_id).is_ok() {
+        if let Some(edge_id) = self.find_edge_id(&ds_edge_id) {
+            let edge = self.edges.remove(&edge_id).unwrap();
+
+            if self.abstract_graph.remove_edge(edge_id).is_ok() {
                 log::trace!("Successfully disconnected edge: {:?}", ds_edge_id);
+
+                if let Some(journal) = &mut self.modify_journal {
+                    journal.disconnected_edges.push(edge);
+                }
+
                 true
             } else {
                 panic!("Unexpected error while disconnecting edge in graph: {:?}", ds_edge_id);
@@ -630,7 +792,96 @@ impl AudioGraph {
         }
     }
 
+    /// Find the ID the abstract graph has assigned to the given edge.
+    ///
+    /// Edges that were reconnected after a modification was undone are
+    /// assigned a new ID by the abstract graph, so the ID stored in
+    /// `ds_edge_id` may be out of date.
+    fn find_edge_id(&self, ds_edge_id: &EngineEdgeID) -> Option<EdgeID> {
+        match self.edges.get(&ds_edge_id.edge_id) {
+            Some(edge) if &edge.ds_edge_id == ds_edge_id => Some(ds_edge_id.edge_id),
+            _ => self
+                .edges
+                .iter()
+                .find(|(_, edge)| &edge.ds_edge_id == ds_edge_id)
+                .map(|(edge_id, _)| *edge_id),
+        }
+    }
+
+    /// Start recording the changes made to the graph, so that they can be
+    /// undone if the modified graph fails to compile.
+    ///
+    /// This must be followed by a call to `AudioGraph::finish_modify()`.
+    ///
+    /// Returns the ID of this modification. If it is undone, then this ID is
+    /// included in `CompileResult::rolled_back_requests`.
+    pub fn begin_modify(&mut self) -> u64 {
+        let request_id = self.next_modify_request_id;
+        self.next_modify_request_id += 1;
+
+        self.modify_journal = Some(ModifyJournal { request_id, ..Default::default() });
+
+        request_id
+    }
+
+    /// Stop recording changes to the graph and request a new schedule.
+    ///
+    /// The changes are kept until the result of the compile that includes them
+    /// arrives. If it succeeds, then the plugins queued for removal are removed.
+    /// If it fails, then the changes are undone so the graph matches the
+    /// schedule the process thread is still running (see
+    /// `AudioGraph::undo_failed_journals()`).
+    pub fn finish_modify(&mut self) {
+        if let Some(journal) = self.modify_journal.take() {
+            self.pending_journals.push(PendingJournal { schedule_version: None, journal });
+            self.request_compile();
+        }
+    }
+
+    fn undo_modify(&mut self, journal: ModifyJournal, engine_timer: &mut EngineTimerWheel) {
+        for edge_id in journal.new_edges.iter().rev() {
+            if self.edges.remove(edge_id).is_some()
+                && self.abstract_graph.remove_edge(*edge_id).is_err()
+            {
+                panic!("Unexpected error while disconnecting edge in graph: {:?}", edge_id);
+            }
+        }
+
+        let mut removed_edges: Vec<EngineEdgeID> = Vec::new();
+        for id in journal.new_plugins.iter() {
+            self.remove_plugin_instance(id, engine_timer, &mut removed_edges);
+        }
+
+        for edge in journal.disconnected_edges.iter().rev() {
+            match self.abstract_graph.add_edge(
+                edge.src_node_id,
+                edge.src_port_id,
+                edge.dst_node_id,
+                edge.dst_port_id,
+                false,
+            ) {
+                Ok(edge_id) => {
+                    // Keep the same unique ID so that the user's handle to this edge
+                    // stays valid.
+                    let ds_edge_id = EngineEdgeID { unique_id: edge.ds_edge_id.unique_id, edge_id };
+                    self.edges.insert(edge_id, GraphEdge { ds_edge_id, ..*edge });
+                }
+                Err(e) => {
+                    log::error!("Failed to reconnect edge {:?}: {}", edge.ds_edge_id, e);
+                }
+            }
+        }
+    }
+
     pub fn reset(&mut self, engine_timer: &mut EngineTimerWheel) {
+        // Make sure a schedule that is still being compiled doesn't replace the
+        // empty schedule below.
+        self.compile_requested = false;
+        while let Some(mut res) = self.compiler_thread.recv() {
+            self.plugin_processors_to_drop.append(&mut res.plugins_to_drop);
+        }
+        self.compile_results.clear();
+
         // Try to gracefully remove all existing plugins.
         for plugin_host in self.shared_pools.plugin_hosts.iter_mut() {
             if let Some(processor_to_drop) =
@@ -674,15 +925,17 @@ impl AudioGraph {
         }
 
         self.shared_pools.plugin_hosts.clear();
-        self.shared_pools.buffers.set_num_buffers(0, 0, 0);
-        self.edge_id_to_ds_edge_id.clear();
+        self.compiler_thread.reset_pools();
+        self.edges.clear();
+        self.modify_journal = None;
+        self.pending_journals.clear();
 
-        self.graph_helper = AudioGraphHelper::new(PortType::NUM_TYPES);
+        self.abstract_graph.reset();
 
         // ---  Add the graph input and graph output nodes to the graph  --------------------------
 
-        let graph_in_node_id = self.graph_helper.add_node(0.0);
-        let graph_out_node_id = self.graph_helper.add_node(0.0);
+        let graph_in_node_id = self.abstract_graph.add_node(0.0);
+        let graph_out_node_id = self.abstract_graph.add_node(0.0);
 
         self.graph_in_id = PluginInstanceID::_new(
             graph_in_node_id.into(),
@@ -698,56 +951,484 @@ impl AudioGraph {
         );
 
         for i in 0..self.graph_in_num_audio_channels as u16 {
-            self.graph_helper
+            self.abstract_graph
                 .add_port(graph_in_node_id, PortID(i as u32), PortType::Audio.as_type_idx(), false)
                 .unwrap();
         }
         for i in 0..self.graph_out_num_audio_channels as u16 {
-            self.graph_helper
+            self.abstract_graph
                 .add_port(graph_out_node_id, PortID(i as u32), PortType::Audio.as_type_idx(), true)
                 .unwrap();
         }
     }
 
-    /// Compile the audio graph into a schedule that is sent to the audio thread.
+    /// Request the audio graph to be compiled into a new schedule.
     ///
-    /// If an error is returned then the graph **MUST** be restored with the previous
-    /// working save state.
-    pub fn compile(&mut self) -> Result<(), GraphCompilerError> {
-        self.schedule_version += 1;
+    /// The compile is started on the next call to `AudioGraph::poll_compiler()`,
+    /// so multiple modifications made in quick succession are compiled together.
+    pub fn request_compile(&mut self) {
+        self.compile_requested = true;
+    }
 
-        match compiler::compile_graph(
-            &mut self.shared_pools,
-            &mut self.graph_helper,
-            &self.graph_in_id,
-            &self.graph_out_id,
-            self.graph_in_num_audio_channels,
-            self.graph_out_num_audio_channels,
-            self.plugin_processors_to_drop.drain(..).collect(),
-            &mut self.verifier,
-            self.schedule_version,
-            &self.coll_handle,
-        ) {
-            Ok(schedule) => {
-                log::debug!("Successfully compiled new schedule:\n{:?}", &schedule);
-                self.shared_pools.shared_schedule.set_new_schedule(schedule, &self.coll_handle);
-                Ok(())
+    /// Collect the results of any finished compiles, and start compiling a new
+    /// schedule in the compiler thread if one was requested and the compiler
+    /// thread is not already busy.
+    pub fn poll_compiler(&mut self, engine_timer: &mut EngineTimerWheel) {
+        while let Some(res) = self.compiler_thread.try_recv() {
+            self.push_compile_result(res, engine_timer);
+        }
+
+        if self.compile_requested && !self.compiler_thread.is_busy() {
+            self.compile_requested = false;
+            self.start_compile();
+        }
+    }
+
+    /// Block until all requested compiles have finished.
+    pub fn flush_compiler(&mut self, engine_timer: &mut EngineTimerWheel) {
+        loop {
+            while let Some(res) = self.compiler_thread.recv() {
+                self.push_compile_result(res, engine_timer);
             }
-            Err(e) => {
-                // Replace the current schedule with an emtpy one now that the graph
-                // is in an invalid state.
-                self.shared_pools.shared_schedule.set_new_schedule(
-                    ProcessorSchedule::new_empty(
-                        self.max_frames as usize,
-                        self.shared_pools.transports.transport.clone(),
-                        self.plugin_processors_to_drop.drain(..).collect(),
-                        self.schedule_version,
-                    ),
-                    &self.coll_handle,
+
+            if !self.compile_requested {
+                break;
+            }
+
+            self.poll_compiler(engine_timer);
+        }
+    }
+
+    /// Take the results of all compiles which have finished.
+    ///
+    /// If a result is an error, then the process thread keeps running the last
+    /// schedule that compiled successfully.
+    pub fn drain_compile_results(&mut self) -> std::vec::Drain<'_, CompileResult> {
+        self.compile_results.drain(..)
+    }
+
+    fn push_compile_result(&mut self, mut res: CompileResult, engine_timer: &mut EngineTimerWheel) {
+        // The processors of removed plugins did not make it to the process
+        // thread, so send them with the next schedule instead.
+        self.plugin_processors_to_drop.append(&mut res.plugins_to_drop);
+
+        if res.status.is_ok() {
+            self.finalize_journals(res.schedule_version, engine_timer);
+        } else {
+            res.rolled_back_requests =
+                self.undo_failed_journals(res.schedule_version, engine_timer);
+        }
+
+        self.compile_results.push(res);
+    }
+
+    /// Remove the plugins queued for removal by the modifications which were
+    /// compiled into the given schedule (or an earlier one).
+    fn finalize_journals(&mut self, schedule_version: u64, engine_timer: &mut EngineTimerWheel) {
+        let num_compiled = self
+            .pending_journals
+            .iter()
+            .take_while(|pending| {
+                pending.schedule_version.map(|v| v <= schedule_version).unwrap_or(false)
+            })
+            .count();
+
+        let compiled: Vec<PendingJournal> = self.pending_journals.drain(..num_compiled).collect();
+
+        let mut removed_edges: Vec<EngineEdgeID> = Vec::new();
+        let mut removed_any_plugins = false;
+        for pending in compiled.iter() {
+            for id in pending.journal.removed_plugins.iter() {
+                self.remove_plugin_instance(id, engine_timer, &mut removed_edges);
+                removed_any_plugins = true;
+            }
+        }
+
+        // All edges were already disconnected when the plugins were queued for
+        // removal.
+        debug_assert!(removed_edges.is_empty());
+
+        if removed_any_plugins {
+            self.request_compile();
+        }
+    }
+
+    /// Undo the modifications which were compiled into the failed schedule
+    /// with the given version, as well as any later modifications which
+    /// depend on them (i.e. an edge to a plugin that was added by a failed
+    /// modification). All other modifications are kept.
+    ///
+    /// Returns the IDs of the modifications which were undone, in the order
+    /// they were made.
+    fn undo_failed_journals(
+        &mut self,
+        schedule_version: u64,
+        engine_timer: &mut EngineTimerWheel,
+    ) -> Vec<u64> {
+        // Find which modifications to undo before undoing any of them, since
+        // undoing one can remove edges the others refer to.
+        let mut undone_nodes: FnvHashSet<NodeID> = FnvHashSet::default();
+        let mut undone_edges: FnvHashSet<u64> = FnvHashSet::default();
+        let mut reconnected_edges: FnvHashSet<(NodeID, PortID, NodeID, PortID)> =
+            FnvHashSet::default();
+
+        let mut do_undo: Vec<bool> = Vec::with_capacity(self.pending_journals.len());
+        for pending in self.pending_journals.iter() {
+            let journal = &pending.journal;
+
+            let failed = pending.schedule_version.map(|v| v <= schedule_version).unwrap_or(false);
+
+            // A new edge depends on an undone modification if it connects to a
+            // plugin which is removed again, or if it takes the place of an
+            // edge which is reconnected.
+            let new_edges_depend =
+                journal.new_edges.iter().filter_map(|edge_id| self.edges.get(edge_id)).any(
+                    |edge| {
+                        undone_nodes.contains(&edge.src_node_id)
+                            || undone_nodes.contains(&edge.dst_node_id)
+                            || reconnected_edges.contains(&edge_connection(edge))
+                    },
                 );
-                Err(e)
+            let disconnected_edges_depend = journal.disconnected_edges.iter().any(|edge| {
+                undone_edges.contains(&edge.ds_edge_id.unique_id)
+                    || undone_nodes.contains(&edge.src_node_id)
+                    || undone_nodes.contains(&edge.dst_node_id)
+            });
+            let removed_plugins_depend = journal.removed_plugins.iter().any(|id| {
+                let node_id: NodeID = id._node_id().into();
+                undone_nodes.contains(&node_id)
+            });
+
+            let undo =
+                failed || new_edges_depend || disconnected_edges_depend || removed_plugins_depend;
+            do_undo.push(undo);
+            if !undo {
+                continue;
             }
+
+            undone_nodes
+                .extend(journal.new_plugins.iter().map(|id| -> NodeID { id._node_id().into() }));
+            undone_edges.extend(
+                journal
+                    .new_edges
+                    .iter()
+                    .filter_map(|edge_id| self.edges.get(edge_id))
+                    .map(|edge| edge.ds_edge_id.unique_id),
+            );
+            reconnected_edges.extend(journal.disconnected_edges.iter().map(edge_connection));
         }
+
+        // Undo the modifications newest first.
+        let mut rolled_back_requests: Vec<u64> = Vec::new();
+        for (i, undo) in do_undo.iter().enumerate().rev() {
+            if *undo {
+                let pending = self.pending_journals.remove(i);
+                rolled_back_requests.push(pending.journal.request_id);
+                self.undo_modify(pending.journal, engine_timer);
+            }
+        }
+        rolled_back_requests.reverse();
+
+        if !rolled_back_requests.is_empty() {
+            // The processors of the plugins that were added still need to be
+            // dropped in the process thread.
+            self.request_compile();
+        }
+
+        rolled_back_requests
+    }
+
+    fn start_compile(&mut self) {
+        self.schedule_version += 1;
+
+        for pending in self.pending_journals.iter_mut() {
+            if pending.schedule_version.is_none() {
+                pending.schedule_version = Some(self.schedule_version);
+            }
+        }
+
+        // The `audio_graph` crate compiles a schedule for us in its purest
+        // "abstract" form (as a list of Node IDs with their corresponding
+        // list of assigned buffer IDs). This is done in the compiler thread
+        // on its own copy of the abstract graph, so only the modifications
+        // made since the last compile are sent.
+        //
+        // If the compile fails, then the process thread keeps running the last
+        // schedule that compiled successfully.
+        let graph_ops = self.abstract_graph.take_ops();
+
+        let plugin_nodes = self
+            .shared_pools
+            .plugin_hosts
+            .iter_by_node_id()
+            .map(|(node_id, plugin_host)| (*node_id, CompilerPluginNode::new(plugin_host)))
+            .collect();
+
+        self.compiler_thread.compile(CompileJob {
+            graph_ops,
+            plugin_nodes,
+            graph_in_id: self.graph_in_id.clone(),
+            graph_out_id: self.graph_out_id.clone(),
+            num_graph_in_audio_ports: self.graph_in_num_audio_channels,
+            num_graph_out_audio_ports: self.graph_out_num_audio_channels,
+            plugins_to_drop: self.plugin_processors_to_drop.drain(..).collect(),
+            transport: self.shared_pools.transports.transport.clone(),
+            num_worker_threads: self.num_worker_threads,
+            schedule_version: self.schedule_version,
+        });
+    }
+
+    /// Find the audio buffers to take the output of an offline render from.
+    pub fn resolve_offline_render_source(
+        &self,
+        source: &OfflineRenderSource,
+    ) -> Result<ResolvedRenderSource, OfflineRenderError> {
+        match source {
+            OfflineRenderSource::GraphOutput => Ok(ResolvedRenderSource::GraphOutput),
+            OfflineRenderSource::PluginAudioOutPort { plugin_id, port_id } => {
+                let audio_ports = self
+                    .shared_pools
+                    .plugin_hosts
+                    .get(plugin_id)
+                    .and_then(|plugin_host| plugin_host.audio_ports_ext())
+                    .ok_or_else(|| OfflineRenderError::PluginNotActivated(plugin_id.clone()))?;
+
+                let port_index = match port_id {
+                    EdgeReqPortID::Main => match audio_ports.main_ports_layout {
+                        MainPortsLayout::InOut | MainPortsLayout::OutOnly => {
+                            if audio_ports.outputs.is_empty() {
+                                None
+                            } else {
+                                Some(0)
+                            }
+                        }
+                        MainPortsLayout::InOnly | MainPortsLayout::NoMainPorts => None,
+                    },
+                    EdgeReqPortID::StableID(id) => {
+                        audio_ports.outputs.iter().position(|p| p.stable_id == *id)
+                    }
+                }
+                .ok_or_else(|| {
+                    OfflineRenderError::PortDoesNotExist(plugin_id.clone(), port_id.clone())
+                })?;
+
+                Ok(ResolvedRenderSource::PluginAudioOutPort {
+                    plugin_id: plugin_id.clone(),
+                    port_index,
+                    num_channels: usize::from(audio_ports.outputs[port_index].channels),
+                })
+            }
+        }
+    }
+
+    /// Start rendering the output of the graph for the frames in the range
+    /// `[start_frame, end_frame)` as fast as possible.
+    ///
+    /// The render is then processed one block at a time with
+    /// `AudioGraph::render_offline_block()`, and it **MUST** be finished with
+    /// `AudioGraph::end_offline_render()`, even if it was cancelled.
+    ///
+    /// The process thread **MUST** be parked before calling this, and it must
+    /// stay parked until the render has ended. Schedules compiled in the
+    /// meantime are not sent to the process thread until the render has
+    /// ended, so the whole render uses the same schedule.
+    pub fn begin_offline_render(
+        &mut self,
+        source: ResolvedRenderSource,
+        start_frame: u64,
+        end_frame: u64,
+        max_tail_frames: u64,
+        hard_clip_outputs: bool,
+    ) -> OfflineRender {
+        let max_frames = self.max_frames as usize;
+        let num_render_channels = source.num_channels(self.graph_out_num_audio_channels);
+
+        let plugin_out = match &source {
+            ResolvedRenderSource::GraphOutput => Vec::new(),
+            ResolvedRenderSource::PluginAudioOutPort { .. } => {
+                vec![0.0; max_frames * num_render_channels]
+            }
+        };
+
+        let prev_process_thread_id = self.thread_ids.process_thread_id();
+
+        // The main thread has taken over the role of the process thread, so
+        // the compiler thread must not swap out the schedule from under it.
+        self.compiler_thread.pause_publishing();
+
+        let saved_transport_state = self
+            .shared_pools
+            .transports
+            .transport
+            .borrow_mut()
+            .begin_offline_render(start_frame, &self.coll_handle);
+
+        // The plugin may not be in the schedule if it is not connected to
+        // anything, in which case it renders silence.
+        if let ResolvedRenderSource::PluginAudioOutPort { plugin_id, port_index, num_channels } =
+            &source
+        {
+            self.shared_pools.shared_schedule.begin_plugin_audio_out_capture(
+                plugin_id,
+                *port_index,
+                *num_channels,
+            );
+        }
+
+        OfflineRender {
+            source,
+            start_frame,
+            end_frame,
+            render_end_frame: end_frame,
+            frame: start_frame,
+            max_tail_frames,
+            tail_added: false,
+            hard_clip_outputs,
+            cancelled: false,
+            saved_transport_state: Some(saved_transport_state),
+            prev_process_thread_id,
+            audio_in: vec![0.0; max_frames * self.graph_in_num_audio_channels],
+            audio_out: vec![0.0; max_frames * self.graph_out_num_audio_channels],
+            plugin_out,
+        }
+    }
+
+    /// Process the next block of an offline render.
+    ///
+    /// `on_block` is called with the interleaved output of the block. If it
+    /// returns `false`, then the render is cancelled.
+    ///
+    /// This returns `false` once the render has finished or was cancelled.
+    /// The time it took to process the block is recorded in `perf`.
+    pub fn render_offline_block<F: FnOnce(&[f32]) -> bool>(
+        &mut self,
+        render: &mut OfflineRender,
+        perf: &EnginePerfShared,
+        on_block: F,
+    ) -> bool {
+        if render.is_finished() {
+            return false;
+        }
+
+        let frames =
+            ((render.render_end_frame - render.frame) as usize).min(self.max_frames as usize);
+        let num_render_channels = render.source.num_channels(self.graph_out_num_audio_channels);
+
+        let audio_in = &render.audio_in[0..frames * self.graph_in_num_audio_channels];
+        let audio_out = &mut render.audio_out[0..frames * self.graph_out_num_audio_channels];
+
+        let proc_start_time = std::time::Instant::now();
+
+        // Offline renders are always processed serially in the calling thread.
+        self.shared_pools.shared_schedule.process_interleaved(None, perf, audio_in, audio_out);
+
+        perf.record_offline_render_block(proc_start_time.elapsed(), frames, self.sample_rate);
+
+        let rendered = match &render.source {
+            ResolvedRenderSource::GraphOutput => audio_out,
+            ResolvedRenderSource::PluginAudioOutPort { .. } => {
+                let plugin_out = &mut render.plugin_out[0..frames * num_render_channels];
+
+                if !self.shared_pools.shared_schedule.take_plugin_audio_out_capture(plugin_out) {
+                    plugin_out.fill(0.0);
+                }
+
+                plugin_out
+            }
+        };
+
+        if render.hard_clip_outputs {
+            for smp in rendered.iter_mut() {
+                *smp = smp.clamp(-1.0, 1.0);
+            }
+        }
+
+        render.frame += frames as u64;
+
+        if !on_block(&rendered[..]) {
+            render.cancelled = true;
+            return false;
+        }
+
+        // Let the tails of the plugins ring out past the end of the render.
+        if render.frame == render.end_frame && !render.tail_added {
+            render.tail_added = true;
+
+            let tail_frames = self.longest_plugin_tail(&render.source, render.max_tail_frames);
+            if tail_frames > 0 {
+                log::debug!("Extending offline render by {} frames of plugin tail", tail_frames);
+                render.render_end_frame += tail_frames;
+            }
+        }
+
+        !render.is_finished()
+    }
+
+    /// Finish an offline render started with
+    /// `AudioGraph::begin_offline_render()`, and hand the schedule back to
+    /// the process thread.
+    ///
+    /// This returns the total number of frames that were rendered.
+    pub fn end_offline_render(&mut self, mut render: OfflineRender) -> u64 {
+        if let Some(saved_transport_state) = render.saved_transport_state.take() {
+            self.shared_pools
+                .transports
+                .transport
+                .borrow_mut()
+                .end_offline_render(saved_transport_state, &self.coll_handle);
+        }
+
+        if let ResolvedRenderSource::PluginAudioOutPort { .. } = &render.source {
+            self.shared_pools.shared_schedule.end_plugin_audio_out_capture();
+        }
+
+        // Hand the role of the process thread back to the actual process thread.
+        if let Some(id) = render.prev_process_thread_id {
+            self.thread_ids.set_process_thread_id(id, &self.coll_handle);
+        }
+
+        self.compiler_thread.resume_publishing();
+
+        render.frame - render.start_frame
+    }
+
+    /// The length in frames of the longest tail of all plugins which feed
+    /// into the given render source (including the source plugin itself),
+    /// clamped to `max_tail_frames`.
+    fn longest_plugin_tail(&self, source: &ResolvedRenderSource, max_tail_frames: u64) -> u64 {
+        let source_node_id: NodeID = match source {
+            ResolvedRenderSource::GraphOutput => self.graph_out_id._node_id().into(),
+            ResolvedRenderSource::PluginAudioOutPort { plugin_id, .. } => {
+                plugin_id._node_id().into()
+            }
+        };
+
+        // Note and automation edges are followed too, since those can make an
+        // upstream plugin (e.g. a synth) produce sound.
+        let mut in_edges: FnvHashMap<NodeID, SmallVec<[NodeID; 4]>> = FnvHashMap::default();
+        for edge in self.edges.values() {
+            in_edges.entry(edge.dst_node_id).or_default().push(edge.src_node_id);
+        }
+
+        let mut upstream_node_ids: FnvHashSet<NodeID> = FnvHashSet::default();
+        let mut stack = vec![source_node_id];
+        while let Some(node_id) = stack.pop() {
+            if upstream_node_ids.insert(node_id) {
+                if let Some(src_node_ids) = in_edges.get(&node_id) {
+                    stack.extend_from_slice(src_node_ids);
+                }
+            }
+        }
+
+        upstream_node_ids
+            .iter()
+            .filter_map(|node_id| self.shared_pools.plugin_hosts.get_by_node_id(node_id))
+            .map(|plugin_host| match plugin_host.tail() {
+                TailLength::Frames(frames) => u64::from(frames).min(max_tail_frames),
+                TailLength::Infinite => max_tail_frames,
+            })
+            .max()
+            .unwrap_or(0)
     }
 
     pub fn collect_save_states(&mut self) -> Vec<(PluginInstanceID, PluginHostSaveState)> {
@@ -764,6 +1445,185 @@ impl AudioGraph {
             .collect()
     }
 
+    /// The latency in frames between when a plugin with no latency of its own
+    /// processes a block and when that block reaches the graph output.
+    ///
+    /// Delay compensation aligns every path into the graph output to the path
+    /// with the most latency, so this is the largest sum of plugin latencies
+    /// along any audio path which ends at the graph output.
+    pub fn graph_out_latency(&self) -> u64 {
+        let mut audio_in_edges: FnvHashMap<NodeID, SmallVec<[NodeID; 4]>> = FnvHashMap::default();
+        for edge in self.edges.values().filter(|edge| edge.edge_type == PortType::Audio) {
+            audio_in_edges.entry(edge.dst_node_id).or_default().push(edge.src_node_id);
+        }
+
+        let mut node_out_latency: FnvHashMap<NodeID, u64> = FnvHashMap::default();
+        self.node_out_latency(
+            self.graph_out_id._node_id().into(),
+            &audio_in_edges,
+            &mut node_out_latency,
+        )
+    }
+
+    /// The latency at the output of the given node, including the latency of
+    /// the node itself.
+    fn node_out_latency(
+        &self,
+        node_id: NodeID,
+        audio_in_edges: &FnvHashMap<NodeID, SmallVec<[NodeID; 4]>>,
+        node_out_latency: &mut FnvHashMap<NodeID, u64>,
+    ) -> u64 {
+        if let Some(latency) = node_out_latency.get(&node_id) {
+            return *latency;
+        }
+
+        // The graph is acyclic, so this recursion always terminates.
+        let in_latency = audio_in_edges
+            .get(&node_id)
+            .map(|src_node_ids| {
+                src_node_ids
+                    .iter()
+                    .map(|src_node_id| {
+                        self.node_out_latency(*src_node_id, audio_in_edges, node_out_latency)
+                    })
+                    .max()
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0);
+
+        let latency = in_latency
+            + self
+                .shared_pools
+                .plugin_hosts
+                .get_by_node_id(&node_id)
+                .map(|plugin_host| plugin_host.latency().max(0) as u64)
+                .unwrap_or(0);
+
+        node_out_latency.insert(node_id, latency);
+        latency
+    }
+
+    /// Collect the save states of all plugins in the graph, along with all of
+    /// the edges between them.
+    ///
+    /// Plugins which are queued to be removed are not included.
+    pub fn collect_graph_save_state(&mut self) -> (Vec<PluginHostSaveState>, Vec<EdgeSaveState>) {
+        let queued_for_removal: FnvHashSet<PluginInstanceID> = self
+            .pending_journals
+            .iter()
+            .flat_map(|pending| pending.journal.removed_plugins.iter().cloned())
+            .collect();
+
+        let mut plugin_hosts: Vec<&mut PluginHostMainThread> = self
+            .shared_pools
+            .plugin_hosts
+            .iter_mut()
+            .filter(|plugin_host| {
+                !plugin_host.is_remove_requested() && !queued_for_removal.contains(plugin_host.id())
+            })
+            .collect();
+
+        // Keep the order stable so that the same graph always results in the
+        // same save state.
+        plugin_hosts.sort_by_key(|plugin_host| plugin_host.id().unique_id());
+
+        let mut node_id_to_index: FnvHashMap<NodeID, usize> = FnvHashMap::default();
+        let plugins: Vec<PluginHostSaveState> = plugin_hosts
+            .iter_mut()
+            .enumerate()
+            .map(|(i, plugin_host)| {
+                node_id_to_index.insert(plugin_host.id()._node_id().into(), i);
+                plugin_host.collect_save_state()
+            })
+            .collect();
+
+        let graph_in_node_id: NodeID = self.graph_in_id._node_id().into();
+        let graph_out_node_id: NodeID = self.graph_out_id._node_id().into();
+
+        let port_save_state = |node_id: NodeID,
+                               port_id: PortID,
+                               edge_type: PortType|
+         -> Option<(NodeSaveStateID, EdgeReqPortID, u16)> {
+            // The ports on the graph in/out nodes are always the main audio port.
+            if node_id == graph_in_node_id {
+                return Some((NodeSaveStateID::GraphIn, EdgeReqPortID::Main, port_id.0 as u16));
+            }
+            if node_id == graph_out_node_id {
+                return Some((NodeSaveStateID::GraphOut, EdgeReqPortID::Main, port_id.0 as u16));
+            }
+
+            let index = *node_id_to_index.get(&node_id)?;
+
+            // Plugins only have a single automation port.
+            if edge_type == PortType::Automation {
+                return Some((NodeSaveStateID::Plugin(index), EdgeReqPortID::Main, 0));
+            }
+
+            let channel_id = plugin_hosts[index].port_ids().port_id_to_channel_id.get(&port_id)?;
+
+            Some((
+                NodeSaveStateID::Plugin(index),
+                EdgeReqPortID::StableID(channel_id.stable_id),
+                channel_id.channel,
+            ))
+        };
+
+        let mut graph_edges: Vec<&GraphEdge> = self.edges.values().collect();
+        graph_edges.sort_by_key(|edge| edge.ds_edge_id.unique_id);
+
+        let edges: Vec<EdgeSaveState> = graph_edges
+            .iter()
+            .filter_map(|edge| {
+                let (src_node, src_port_id, src_port_channel) =
+                    port_save_state(edge.src_node_id, edge.src_port_id, edge.edge_type)?;
+                let (dst_node, dst_port_id, dst_port_channel) =
+                    port_save_state(edge.dst_node_id, edge.dst_port_id, edge.edge_type)?;
+
+                Some(EdgeSaveState {
+                    edge_type: edge.edge_type,
+                    src_node,
+                    dst_node,
+                    src_port_id,
+                    src_port_channel,
+                    dst_port_id,
+                    dst_port_channel,
+                })
+            })
+            .collect();
+
+        (plugins, edges)
+    }
+
+    /// The IDs of all plugins in the graph which are not queued to be removed.
+    pub fn plugin_ids(&self) -> Vec<PluginInstanceID> {
+        self.shared_pools
+            .plugin_hosts
+            .iter_by_node_id()
+            .filter(|(_, plugin_host)| {
+                !plugin_host.is_remove_requested() && !self.is_queued_for_removal(plugin_host.id())
+            })
+            .map(|(_, plugin_host)| plugin_host.id().clone())
+            .collect()
+    }
+
+    /// Take the statistics on how long each plugin took to process since the
+    /// last call to this method.
+    pub fn take_plugin_process_times(&self) -> Vec<(PluginInstanceID, ProcessTimeStats)> {
+        self.shared_pools
+            .plugin_hosts
+            .iter_by_node_id()
+            .filter(|(_, plugin_host)| !plugin_host.is_remove_requested())
+            .map(|(_, plugin_host)| {
+                (plugin_host.id().clone(), plugin_host.take_process_time_stats())
+            })
+            .collect()
+    }
+
+    /// The IDs of all edges in the graph.
+    pub fn edge_ids(&self) -> Vec<EngineEdgeID> {
+        self.edges.values().map(|edge| edge.ds_edge_id).collect()
+    }
+
     pub fn on_idle(
         &mut self,
         events_out: &mut SmallVec<[OnIdleEvent; 32]>,
@@ -778,9 +1638,9 @@ impl AudioGraph {
                 self.min_frames,
                 self.max_frames,
                 &self.coll_handle,
-                &mut self.graph_helper,
+                &mut self.abstract_graph,
                 events_out,
-                &mut self.edge_id_to_ds_edge_id,
+                &mut self.edges,
                 &self.thread_ids,
                 self.schedule_version,
                 engine_timer,
@@ -863,6 +1723,82 @@ impl AudioGraph {
     }
 }
 
+/// The buffers to take the output of an offline render from.
+pub(crate) enum ResolvedRenderSource {
+    GraphOutput,
+    PluginAudioOutPort { plugin_id: PluginInstanceID, port_index: usize, num_channels: usize },
+}
+
+impl ResolvedRenderSource {
+    pub fn num_channels(&self, graph_out_num_audio_channels: usize) -> usize {
+        match self {
+            ResolvedRenderSource::GraphOutput => graph_out_num_audio_channels,
+            ResolvedRenderSource::PluginAudioOutPort { num_channels, .. } => *num_channels,
+        }
+    }
+}
+
+/// An offline render in progress. See `AudioGraph::begin_offline_render()`.
+pub(crate) struct OfflineRender {
+    source: ResolvedRenderSource,
+    start_frame: u64,
+    end_frame: u64,
+    /// The end of the render including the tails of the plugins, once they
+    /// are known.
+    render_end_frame: u64,
+    /// The next frame to render.
+    frame: u64,
+    max_tail_frames: u64,
+    tail_added: bool,
+    hard_clip_outputs: bool,
+    cancelled: bool,
+
+    saved_transport_state: Option<SavedTransportState>,
+    prev_process_thread_id: Option<std::thread::ThreadId>,
+
+    audio_in: Vec<f32>,
+    audio_out: Vec<f32>,
+    plugin_out: Vec<f32>,
+}
+
+impl OfflineRender {
+    /// The number of frames that have been rendered so far.
+    pub fn frames_rendered(&self) -> u64 {
+        self.frame - self.start_frame
+    }
+
+    /// The total number of frames to render. This grows by the length of the
+    /// longest plugin tail once `end_frame` is reached.
+    pub fn total_frames(&self) -> u64 {
+        self.render_end_frame - self.start_frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cancelled || self.frame >= self.render_end_frame
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+/// An edge in the abstract graph, along with the ports it connects so that
+/// it can be reconnected if a modification to the graph is undone.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct GraphEdge {
+    pub ds_edge_id: EngineEdgeID,
+    pub edge_type: PortType,
+    pub src_node_id: NodeID,
+    pub src_port_id: PortID,
+    pub dst_node_id: NodeID,
+    pub dst_port_id: PortID,
+}
+
+/// The ports an edge connects.
+fn edge_connection(edge: &GraphEdge) -> (NodeID, PortID, NodeID, PortID) {
+    (edge.src_node_id, edge.src_port_id, edge.dst_node_id, edge.dst_port_id)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct EngineEdgeID {
     pub(crate) unique_id: u64,
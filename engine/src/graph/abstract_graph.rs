@@ -0,0 +1,306 @@
+use audio_graph::error::{AddEdgeError, CompileGraphError};
+use audio_graph::{AudioGraphHelper, CompiledSchedule, EdgeID, NodeID, PortID, TypeIdx};
+
+use super::error::GraphCompilerError;
+use super::PortType;
+
+/// A single modification made to the abstract graph.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum GraphOp {
+    /// Replace the graph with a new empty graph.
+    Reset,
+    AddNode {
+        node_id: NodeID,
+        latency: f64,
+    },
+    RemoveNode {
+        node_id: NodeID,
+    },
+    AddPort {
+        node_id: NodeID,
+        port_id: PortID,
+        type_idx: TypeIdx,
+        is_input: bool,
+    },
+    RemovePort {
+        node_id: NodeID,
+        port_id: PortID,
+    },
+    AddEdge {
+        edge_id: EdgeID,
+        src: (NodeID, PortID),
+        dst: (NodeID, PortID),
+    },
+    RemoveEdge {
+        edge_id: EdgeID,
+    },
+    SetNodeLatency {
+        node_id: NodeID,
+        latency: f64,
+    },
+}
+
+/// The abstract graph used in the main thread.
+///
+/// Compiling the abstract graph into a schedule can be expensive for large
+/// graphs, so that is done in the compiler thread on a mirror of this graph
+/// (see `MirrorGraph`). Every modification that is made to this graph is
+/// recorded so that it can be replayed on the mirror before the next compile.
+pub(crate) struct AbstractGraph {
+    helper: AudioGraphHelper,
+
+    /// The modifications which have not yet been sent to the compiler
+    /// thread.
+    ops: Vec<GraphOp>,
+}
+
+impl AbstractGraph {
+    pub fn new() -> Self {
+        Self { helper: AudioGraphHelper::new(PortType::NUM_TYPES), ops: vec![GraphOp::Reset] }
+    }
+
+    /// Replace the graph with a new empty graph.
+    pub fn reset(&mut self) {
+        self.helper = AudioGraphHelper::new(PortType::NUM_TYPES);
+
+        // None of the previous modifications matter anymore.
+        self.ops.clear();
+        self.ops.push(GraphOp::Reset);
+    }
+
+    /// Take all of the modifications made since the last call to this method.
+    pub fn take_ops(&mut self) -> Vec<GraphOp> {
+        std::mem::take(&mut self.ops)
+    }
+
+    pub fn add_node(&mut self, latency: f64) -> NodeID {
+        let node_id = self.helper.add_node(latency);
+        self.ops.push(GraphOp::AddNode { node_id, latency });
+        node_id
+    }
+
+    /// Remove the node and all of its ports from the graph, returning the
+    /// edges that were removed as a result.
+    pub fn remove_node(&mut self, node_id: NodeID) -> Result<Vec<EdgeID>, String> {
+        let removed_edges = self.helper.remove_node(node_id).map_err(|e| format!("{:?}", e))?;
+        self.ops.push(GraphOp::RemoveNode { node_id });
+        Ok(removed_edges.into_iter().collect())
+    }
+
+    pub fn add_port(
+        &mut self,
+        node_id: NodeID,
+        port_id: PortID,
+        type_idx: TypeIdx,
+        is_input: bool,
+    ) -> Result<(), String> {
+        self.helper
+            .add_port(node_id, port_id, type_idx, is_input)
+            .map_err(|e| format!("{:?}", e))?;
+        self.ops.push(GraphOp::AddPort { node_id, port_id, type_idx, is_input });
+        Ok(())
+    }
+
+    /// Remove the port from the graph, returning the edges that were removed
+    /// as a result.
+    pub fn remove_port(&mut self, node_id: NodeID, port_id: PortID) -> Result<Vec<EdgeID>, String> {
+        let removed_edges =
+            self.helper.remove_port(node_id, port_id).map_err(|e| format!("{:?}", e))?;
+        self.ops.push(GraphOp::RemovePort { node_id, port_id });
+        Ok(removed_edges.into_iter().collect())
+    }
+
+    pub fn add_edge(
+        &mut self,
+        src_node_id: NodeID,
+        src_port_id: PortID,
+        dst_node_id: NodeID,
+        dst_port_id: PortID,
+        check_for_cycles: bool,
+    ) -> Result<EdgeID, AddEdgeError> {
+        let edge_id = self.helper.add_edge(
+            src_node_id,
+            src_port_id,
+            dst_node_id,
+            dst_port_id,
+            check_for_cycles,
+        )?;
+        self.ops.push(GraphOp::AddEdge {
+            edge_id,
+            src: (src_node_id, src_port_id),
+            dst: (dst_node_id, dst_port_id),
+        });
+        Ok(edge_id)
+    }
+
+    pub fn remove_edge(&mut self, edge_id: EdgeID) -> Result<(), String> {
+        self.helper.remove_edge(edge_id).map_err(|e| format!("{:?}", e))?;
+        self.ops.push(GraphOp::RemoveEdge { edge_id });
+        Ok(())
+    }
+
+    pub fn set_node_latency(&mut self, node_id: NodeID, latency: f64) -> Result<(), String> {
+        self.helper.set_node_latency(node_id, latency).map_err(|e| format!("{:?}", e))?;
+        self.ops.push(GraphOp::SetNodeLatency { node_id, latency });
+        Ok(())
+    }
+}
+
+/// A mirror of the main thread's `AbstractGraph` which lives in the compiler
+/// thread.
+///
+/// The abstract graph assigns IDs deterministically, so replaying the exact
+/// same modifications in the exact same order results in the exact same
+/// IDs. This is checked as the modifications are replayed.
+pub(crate) struct MirrorGraph {
+    helper: AudioGraphHelper,
+}
+
+impl MirrorGraph {
+    pub fn new() -> Self {
+        Self { helper: AudioGraphHelper::new(PortType::NUM_TYPES) }
+    }
+
+    /// Replay the modifications made to the main thread's graph, and then
+    /// compile the resulting graph.
+    pub fn apply_and_compile(
+        &mut self,
+        ops: &[GraphOp],
+    ) -> Result<CompiledSchedule, GraphCompilerError> {
+        for op in ops.iter() {
+            self.apply(op).map_err(|e| {
+                GraphCompilerError::UnexpectedError(format!(
+                    "Failed to apply {:?} to the compiler's copy of the abstract graph: {}",
+                    op, e
+                ))
+            })?;
+        }
+
+        self.compile().map_err(GraphCompilerError::from)
+    }
+
+    fn apply(&mut self, op: &GraphOp) -> Result<(), String> {
+        match *op {
+            GraphOp::Reset => {
+                self.helper = AudioGraphHelper::new(PortType::NUM_TYPES);
+            }
+            GraphOp::AddNode { node_id, latency } => {
+                let new_node_id = self.helper.add_node(latency);
+                if new_node_id != node_id {
+                    return Err(format!("got node ID {:?}", new_node_id));
+                }
+            }
+            GraphOp::RemoveNode { node_id } => {
+                self.helper.remove_node(node_id).map_err(|e| format!("{:?}", e))?;
+            }
+            GraphOp::AddPort { node_id, port_id, type_idx, is_input } => {
+                self.helper
+                    .add_port(node_id, port_id, type_idx, is_input)
+                    .map_err(|e| format!("{:?}", e))?;
+            }
+            GraphOp::RemovePort { node_id, port_id } => {
+                self.helper.remove_port(node_id, port_id).map_err(|e| format!("{:?}", e))?;
+            }
+            GraphOp::AddEdge { edge_id, src, dst } => {
+                // Cycles were already checked for in the main thread.
+                let new_edge_id = self
+                    .helper
+                    .add_edge(src.0, src.1, dst.0, dst.1, false)
+                    .map_err(|e| format!("{:?}", e))?;
+                if new_edge_id != edge_id {
+                    return Err(format!("got edge ID {:?}", new_edge_id));
+                }
+            }
+            GraphOp::RemoveEdge { edge_id } => {
+                self.helper.remove_edge(edge_id).map_err(|e| format!("{:?}", e))?;
+            }
+            GraphOp::SetNodeLatency { node_id, latency } => {
+                self.helper.set_node_latency(node_id, latency).map_err(|e| format!("{:?}", e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile(&mut self) -> Result<CompiledSchedule, CompileGraphError> {
+        self.helper.compile()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_node_with_ports(graph: &mut AbstractGraph) -> NodeID {
+        let node_id = graph.add_node(0.0);
+        graph.add_port(node_id, PortID(0), PortType::AUDIO_TYPE_IDX, true).unwrap();
+        graph.add_port(node_id, PortID(1), PortType::AUDIO_TYPE_IDX, false).unwrap();
+        node_id
+    }
+
+    #[test]
+    fn mirror_matches_graph_after_replaying_ops() {
+        let mut graph = AbstractGraph::new();
+        let mut mirror = MirrorGraph::new();
+
+        let a = add_node_with_ports(&mut graph);
+        let b = add_node_with_ports(&mut graph);
+        let c = add_node_with_ports(&mut graph);
+        graph.add_edge(a, PortID(1), b, PortID(0), true).unwrap();
+        let b_to_c = graph.add_edge(b, PortID(1), c, PortID(0), true).unwrap();
+        graph.set_node_latency(b, 32.0).unwrap();
+
+        mirror.apply_and_compile(&graph.take_ops()).unwrap();
+
+        // Removing things and then adding new ones reuses the freed IDs.
+        graph.remove_edge(b_to_c).unwrap();
+        graph.remove_node(a).unwrap();
+        let d = add_node_with_ports(&mut graph);
+        graph.add_edge(d, PortID(1), c, PortID(0), true).unwrap();
+        graph.add_edge(b, PortID(1), c, PortID(0), true).unwrap();
+        graph.remove_port(c, PortID(1)).unwrap();
+
+        let mirror_schedule = mirror.apply_and_compile(&graph.take_ops()).unwrap();
+        let schedule = graph.helper.compile().unwrap();
+
+        assert_eq!(format!("{:?}", mirror_schedule), format!("{:?}", schedule));
+    }
+
+    #[test]
+    fn reset_discards_previous_ops() {
+        let mut graph = AbstractGraph::new();
+        let mut mirror = MirrorGraph::new();
+
+        add_node_with_ports(&mut graph);
+        mirror.apply_and_compile(&graph.take_ops()).unwrap();
+
+        add_node_with_ports(&mut graph);
+        graph.reset();
+        let a = add_node_with_ports(&mut graph);
+        let b = add_node_with_ports(&mut graph);
+        graph.add_edge(a, PortID(1), b, PortID(0), true).unwrap();
+
+        let mirror_schedule = mirror.apply_and_compile(&graph.take_ops()).unwrap();
+        let schedule = graph.helper.compile().unwrap();
+
+        assert_eq!(format!("{:?}", mirror_schedule), format!("{:?}", schedule));
+    }
+
+    #[test]
+    fn mismatched_ids_are_reported() {
+        let mut graph = AbstractGraph::new();
+        let mut mirror = MirrorGraph::new();
+
+        add_node_with_ports(&mut graph);
+        let ops = graph.take_ops();
+
+        mirror.apply_and_compile(&ops).unwrap();
+
+        // Replaying the same node being added again (without the reset at the
+        // start) gives the mirror a different ID than the one recorded.
+        assert!(matches!(
+            mirror.apply_and_compile(&ops[1..]),
+            Err(GraphCompilerError::UnexpectedError(_))
+        ));
+    }
+}
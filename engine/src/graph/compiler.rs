@@ -1,7 +1,12 @@
-use audio_graph::{AudioGraphHelper, ScheduleEntry};
+use audio_graph::{BufferAssignment, CompiledSchedule, NodeID, PortID, ScheduleEntry};
 use basedrop::Shared;
+use fnv::FnvHashMap;
+use meadowlark_plugin_api::ext::audio_ports::PluginAudioPortsExt;
+use meadowlark_plugin_api::ext::note_ports::PluginNotePortsExt;
 
-use crate::plugin_host::PluginHostProcessorWrapper;
+use crate::plugin_host::{
+    PluginHostMainThread, PluginHostProcessorWrapper, SharedPluginHostProcessor,
+};
 use crate::processor_schedule::tasks::{GraphInTask, GraphOutTask, Task};
 
 mod delay_comp_task;
@@ -9,37 +14,110 @@ mod graph_in_out_task;
 mod plugin_task;
 mod sum_task;
 
+pub(super) mod compiler_thread;
 pub(super) mod verifier;
 
 use verifier::Verifier;
 
+use super::abstract_graph::{GraphOp, MirrorGraph};
 use super::error::GraphCompilerError;
-use super::shared_pools::GraphSharedPools;
-use super::{PluginInstanceID, PortType, ProcessorSchedule};
+use super::shared_pools::{CompilerPools, SharedTransportTask};
+use super::{PluginInstanceID, PortChannelID, PortType, ProcessorSchedule};
+
+/// A snapshot of the parts of a plugin host that the compiler needs in order
+/// to construct a task for that plugin.
+pub(super) struct CompilerPluginNode {
+    pub plugin_id: PluginInstanceID,
+    pub port_id_to_channel_id: FnvHashMap<PortID, PortChannelID>,
+    pub shared_processor: SharedPluginHostProcessor,
+    pub audio_ports_ext: Option<PluginAudioPortsExt>,
+    pub note_ports_ext: Option<PluginNotePortsExt>,
+    pub is_loaded: bool,
+}
+
+impl CompilerPluginNode {
+    pub fn new(plugin_host: &PluginHostMainThread) -> Self {
+        Self {
+            plugin_id: plugin_host.id().clone(),
+            port_id_to_channel_id: plugin_host.port_ids().port_id_to_channel_id.clone(),
+            shared_processor: plugin_host.shared_processor().clone(),
+            audio_ports_ext: plugin_host.audio_ports_ext().cloned(),
+            note_ports_ext: plugin_host.note_ports_ext().cloned(),
+            is_loaded: plugin_host.is_loaded(),
+        }
+    }
+}
+
+/// Everything the compiler needs in order to turn an abstract schedule into
+/// a new `ProcessorSchedule`.
+///
+/// This is constructed in the main thread and then sent to the compiler
+/// thread.
+pub(super) struct CompileJob {
+    /// The modifications made to the abstract graph since the last job.
+    pub graph_ops: Vec<GraphOp>,
+    pub plugin_nodes: FnvHashMap<NodeID, CompilerPluginNode>,
+
+    pub graph_in_id: PluginInstanceID,
+    pub graph_out_id: PluginInstanceID,
+    pub num_graph_in_audio_ports: usize,
+    pub num_graph_out_audio_ports: usize,
+
+    /// For the plugins that are queued to be removed, make sure that
+    /// the plugin's processor part is dropped in the process thread.
+    ///
+    /// These are only added to the new schedule once it has been verified.
+    pub plugins_to_drop: Vec<Shared<PluginHostProcessorWrapper>>,
+    pub transport: SharedTransportTask,
+
+    pub num_worker_threads: usize,
+    pub schedule_version: u64,
+}
+
+// Required so we can send the job from the main thread to the compiler
+// thread.
+//
+// This is safe because the compiler thread never dereferences the plugin
+// processors or the transport. It only clones these pointers into the new
+// schedule.
+unsafe impl Send for CompileJob {}
 
-#[allow(clippy::too_many_arguments)] // Fix this?
 pub(super) fn compile_graph(
-    shared_pool: &mut GraphSharedPools,
-    graph_helper: &mut AudioGraphHelper,
-    graph_in_id: &PluginInstanceID,
-    graph_out_id: &PluginInstanceID,
-    num_graph_in_audio_ports: usize,
-    num_graph_out_audio_ports: usize,
-    // For the plugins that are queued to be removed, make sure that
-    // the plugin's processor part is dropped in the process thread.
-    plugins_to_drop: Vec<Shared<PluginHostProcessorWrapper>>,
+    job: CompileJob,
+    mirror_graph: &mut MirrorGraph,
+    shared_pool: &mut CompilerPools,
     verifier: &mut Verifier,
-    schedule_version: u64,
     coll_handle: &basedrop::Handle,
 ) -> Result<ProcessorSchedule, GraphCompilerError> {
-    let mut tasks: Vec<Task> = Vec::with_capacity(shared_pool.plugin_hosts.num_plugins() * 2);
+    let CompileJob {
+        graph_ops,
+        plugin_nodes,
+        graph_in_id,
+        graph_out_id,
+        num_graph_in_audio_ports,
+        num_graph_out_audio_ports,
+        transport,
+        num_worker_threads,
+        schedule_version,
+        ..
+    } = job;
+
+    // The `audio_graph` crate compiles a schedule for us in its purest
+    // "abstract" form (as a list of Node IDs with their corresponding list of
+    // assigned buffer IDs).
+    let mut abstract_schedule = mirror_graph.apply_and_compile(&graph_ops)?;
+
+    let mut tasks: Vec<Task> = Vec::with_capacity(plugin_nodes.len() * 2);
     let mut graph_in_task: Option<GraphInTask> = None;
     let mut graph_out_task: Option<GraphOutTask> = None;
 
-    // The `audio_graph` crate compiles a schedule for us in its purest
-    // "abstract" form (as a list of Node IDs with their corresponding
-    // list of assigned buffer IDs).
-    let abstract_schedule = graph_helper.compile()?;
+    // The abstract schedule reuses buffers as much as possible, which is only
+    // sound when every task is processed in order. Give every written buffer
+    // its own slot so that independent branches of the graph don't end up
+    // sharing buffers and can be processed in parallel.
+    if num_worker_threads > 0 {
+        assign_unique_buffers(&mut abstract_schedule);
+    }
 
     // We now take that "abstract" schedule and do a one-to-one translation
     // into a schedule with our desired tasks (a list of pointers to
@@ -88,7 +166,11 @@ pub(super) fn compile_graph(
                     )?);
                 } else {
                     // Construct a task for a plugin.
-                    tasks.push(plugin_task::construct_plugin_task(scheduled_node, shared_pool)?);
+                    tasks.push(plugin_task::construct_plugin_task(
+                        scheduled_node,
+                        &plugin_nodes,
+                        shared_pool,
+                    )?);
                 };
             }
             ScheduleEntry::Delay(inserted_delay) => {
@@ -144,9 +226,9 @@ pub(super) fn compile_graph(
         tasks,
         graph_in_task,
         graph_out_task,
-        shared_pool.transports.transport.clone(),
-        plugins_to_drop,
+        transport,
         shared_pool.buffers.audio_buffer_pool.buffer_size(),
+        num_worker_threads,
         schedule_version,
     );
 
@@ -161,7 +243,7 @@ pub(super) fn compile_graph(
     //
     // However, it is still very possible to have race condition bugs in the schedule, such as
     // the same buffer being assigned multiple times within the same task, or the same buffer
-    // appearing multiple times between parallel tasks (once we have multithreaded scheduling).
+    // appearing multiple times between parallel tasks.
     if let Err(e) = verifier.verify_schedule_for_race_conditions(&new_schedule) {
         return Err(GraphCompilerError::VerifierError(
             e,
@@ -172,3 +254,204 @@ pub(super) fn compile_graph(
 
     Ok(new_schedule)
 }
+
+/// Re-assign the buffers in the abstract schedule so that every buffer is
+/// written to by exactly one entry in the schedule.
+///
+/// This removes all of the false dependencies between entries that only
+/// exist because the abstract compiler reused a buffer that was no longer
+/// needed.
+fn assign_unique_buffers(abstract_schedule: &mut CompiledSchedule) {
+    let mut buffers = UniqueBufferAssigner::new(&abstract_schedule.num_buffers);
+
+    for schedule_entry in abstract_schedule.schedule.iter_mut() {
+        match schedule_entry {
+            ScheduleEntry::Node(scheduled_node) => {
+                for b in scheduled_node.input_buffers.iter_mut() {
+                    // Inputs that get cleared are written to by this node.
+                    if b.should_clear {
+                        buffers.write(b);
+                    } else {
+                        buffers.read(b);
+                    }
+                }
+                for b in scheduled_node.output_buffers.iter_mut() {
+                    buffers.write(b);
+                }
+            }
+            ScheduleEntry::Delay(inserted_delay) => {
+                buffers.read(&mut inserted_delay.input_buffer);
+                buffers.write(&mut inserted_delay.output_buffer);
+            }
+            ScheduleEntry::Sum(inserted_sum) => {
+                for b in inserted_sum.input_buffers.iter_mut() {
+                    buffers.read(b);
+                }
+
+                buffers.write(&mut inserted_sum.output_buffer);
+            }
+        }
+    }
+
+    for (type_i, n) in buffers.num_buffers.iter().enumerate() {
+        abstract_schedule.num_buffers[type_i] = *n;
+    }
+}
+
+struct UniqueBufferAssigner {
+    /// Maps the buffer index assigned by the abstract compiler to the most
+    /// recently assigned unique buffer index, for each port type.
+    current: [Vec<Option<usize>>; PortType::NUM_TYPES],
+    num_buffers: [usize; PortType::NUM_TYPES],
+}
+
+impl UniqueBufferAssigner {
+    fn new(abstract_num_buffers: &[usize]) -> Self {
+        let mut current: [Vec<Option<usize>>; PortType::NUM_TYPES] = Default::default();
+        for (type_i, map) in current.iter_mut().enumerate() {
+            map.resize(abstract_num_buffers[type_i], None);
+        }
+
+        Self { current, num_buffers: [0; PortType::NUM_TYPES] }
+    }
+
+    /// Assign a new unique buffer to a buffer that is written to.
+    fn write(&mut self, b: &mut BufferAssignment) {
+        let type_i = b.type_index.0;
+
+        let new_index = self.num_buffers[type_i];
+        self.num_buffers[type_i] += 1;
+
+        self.current[type_i][b.buffer_index.0] = Some(new_index);
+        b.buffer_index.0 = new_index;
+    }
+
+    /// Assign the most recently written unique buffer to a buffer that is
+    /// read from.
+    fn read(&mut self, b: &mut BufferAssignment) {
+        match self.current[b.type_index.0][b.buffer_index.0] {
+            Some(index) => b.buffer_index.0 = index,
+            // Nothing has written to this buffer yet, so give it its own buffer.
+            None => self.write(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use audio_graph::{AudioGraphHelper, NodeID, PortID};
+    use fnv::FnvHashSet;
+
+    use super::*;
+
+    /// Every buffer access in the schedule, in order, as
+    /// `(is_write, type_index, buffer_index)`.
+    fn buffer_accesses(schedule: &CompiledSchedule) -> Vec<(bool, usize, usize)> {
+        let mut accesses = Vec::new();
+        let mut access = |b: &BufferAssignment, is_write: bool| {
+            accesses.push((is_write, b.type_index.0, b.buffer_index.0));
+        };
+
+        for schedule_entry in schedule.schedule.iter() {
+            match schedule_entry {
+                ScheduleEntry::Node(scheduled_node) => {
+                    for b in scheduled_node.input_buffers.iter() {
+                        access(b, b.should_clear);
+                    }
+                    for b in scheduled_node.output_buffers.iter() {
+                        access(b, true);
+                    }
+                }
+                ScheduleEntry::Delay(inserted_delay) => {
+                    access(&inserted_delay.input_buffer, false);
+                    access(&inserted_delay.output_buffer, true);
+                }
+                ScheduleEntry::Sum(inserted_sum) => {
+                    for b in inserted_sum.input_buffers.iter() {
+                        access(b, false);
+                    }
+                    access(&inserted_sum.output_buffer, true);
+                }
+            }
+        }
+
+        accesses
+    }
+
+    /// graph_in -> a -> b -> graph_out
+    /// graph_in -> c ------> graph_out
+    /// graph_in -> d ------> graph_out
+    fn compile_test_graph() -> CompiledSchedule {
+        let mut graph = AudioGraphHelper::new(PortType::NUM_TYPES);
+
+        let add_node = |graph: &mut AudioGraphHelper, num_in: u32, num_out: u32| -> NodeID {
+            let node_id = graph.add_node(0.0);
+            for i in 0..num_in {
+                graph.add_port(node_id, PortID(i), PortType::AUDIO_TYPE_IDX, true).unwrap();
+            }
+            for i in 0..num_out {
+                graph
+                    .add_port(node_id, PortID(num_in + i), PortType::AUDIO_TYPE_IDX, false)
+                    .unwrap();
+            }
+            node_id
+        };
+
+        let graph_in = add_node(&mut graph, 0, 1);
+        let graph_out = add_node(&mut graph, 1, 0);
+        let a = add_node(&mut graph, 1, 1);
+        let b = add_node(&mut graph, 1, 1);
+        let c = add_node(&mut graph, 1, 1);
+        let d = add_node(&mut graph, 1, 1);
+
+        // The output port of `graph_in` is port 0, and the output port of
+        // every other node is port 1.
+        for dst in [a, c, d] {
+            graph.add_edge(graph_in, PortID(0), dst, PortID(0), true).unwrap();
+        }
+        graph.add_edge(a, PortID(1), b, PortID(0), true).unwrap();
+        for src in [b, c, d] {
+            graph.add_edge(src, PortID(1), graph_out, PortID(0), true).unwrap();
+        }
+
+        graph.compile().unwrap()
+    }
+
+    #[test]
+    fn unique_buffers_are_written_exactly_once() {
+        let mut schedule = compile_test_graph();
+        assign_unique_buffers(&mut schedule);
+
+        let mut written: FnvHashSet<(usize, usize)> = FnvHashSet::default();
+        for (is_write, type_i, index) in buffer_accesses(&schedule) {
+            assert!(index < schedule.num_buffers[type_i]);
+            if is_write {
+                assert!(written.insert((type_i, index)), "buffer {} written twice", index);
+            }
+        }
+    }
+
+    #[test]
+    fn unique_buffers_preserve_data_flow() {
+        let original = compile_test_graph();
+        let mut reassigned = compile_test_graph();
+        assign_unique_buffers(&mut reassigned);
+
+        let original = buffer_accesses(&original);
+        let reassigned = buffer_accesses(&reassigned);
+        assert_eq!(original.len(), reassigned.len());
+
+        // Every read must see the buffer written by the same entry that wrote
+        // to it in the original schedule.
+        let mut last_write: FnvHashMap<(usize, usize), usize> = FnvHashMap::default();
+        for ((is_write, type_i, original_i), (_, _, new_i)) in
+            original.iter().zip(reassigned.iter())
+        {
+            if *is_write {
+                last_write.insert((*type_i, *original_i), *new_i);
+            } else if let Some(expected_i) = last_write.get(&(*type_i, *original_i)) {
+                assert_eq!(new_i, expected_i);
+            }
+        }
+    }
+}
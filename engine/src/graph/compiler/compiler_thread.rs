@@ -0,0 +1,238 @@
+use std::sync::{mpsc, Arc, Condvar, Mutex, PoisonError};
+use std::thread::JoinHandle;
+
+use basedrop::Shared;
+
+use crate::plugin_host::PluginHostProcessorWrapper;
+
+use super::super::abstract_graph::MirrorGraph;
+use super::super::error::GraphCompilerError;
+use super::super::shared_pools::{CompilerPools, SharedProcessorSchedule};
+use super::verifier::Verifier;
+use super::{compile_graph, CompileJob};
+
+enum CompilerThreadMsg {
+    Compile(Box<CompileJob>),
+    /// Free all of the buffers allocated by the compiler.
+    ResetPools,
+}
+
+/// The result of compiling a new schedule in the compiler thread.
+pub(crate) struct CompileResult {
+    /// The version of the schedule that was compiled.
+    pub schedule_version: u64,
+
+    /// If this is `Ok(())`, then the new schedule has been sent to the
+    /// process thread.
+    ///
+    /// If this is `Err(e)`, then the process thread keeps running the last
+    /// schedule that compiled successfully.
+    pub status: Result<(), GraphCompilerError>,
+
+    /// If the compile failed, then this contains the processors of removed
+    /// plugins from the job, which still need to be dropped in the process
+    /// thread.
+    pub plugins_to_drop: Vec<Shared<PluginHostProcessorWrapper>>,
+
+    /// If the compile failed, then this contains the IDs of the modifications
+    /// to the graph which were rolled back (filled in by the `AudioGraph`).
+    pub rolled_back_requests: Vec<u64>,
+}
+
+// Required so we can send the result from the compiler thread back to the
+// main thread.
+//
+// This is safe for the same reasons that `CompileJob` is safe to send.
+unsafe impl Send for CompileResult {}
+
+/// A thread which constructs and verifies new schedules in the background,
+/// and then sends them to the process thread once they are ready.
+pub(crate) struct CompilerThread {
+    to_thread_tx: Option<mpsc::Sender<CompilerThreadMsg>>,
+    from_thread_rx: mpsc::Receiver<CompileResult>,
+
+    /// The number of jobs that have been sent to the compiler thread which
+    /// have not yet returned a result.
+    num_jobs_in_flight: usize,
+
+    /// Whether the main thread has paused the publishing of new schedules
+    /// (because it is processing the schedule itself), along with a condvar
+    /// which wakes up the compiler thread once publishing is resumed.
+    ///
+    /// The compiler thread holds the lock while it publishes a new schedule.
+    publish_paused: Arc<(Mutex<bool>, Condvar)>,
+
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl CompilerThread {
+    pub fn spawn(
+        mut pools: CompilerPools,
+        mut shared_schedule: SharedProcessorSchedule,
+        coll_handle: basedrop::Handle,
+    ) -> Self {
+        let (to_thread_tx, to_thread_rx) = mpsc::channel::<CompilerThreadMsg>();
+        let (from_thread_tx, from_thread_rx) = mpsc::channel::<CompileResult>();
+
+        let publish_paused = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_publish_paused = Arc::clone(&publish_paused);
+
+        let thread_handle = std::thread::Builder::new()
+            .name("graph_compiler".into())
+            .spawn(move || {
+                let mut verifier = Verifier::new();
+                let mut mirror_graph = MirrorGraph::new();
+
+                while let Ok(msg) = to_thread_rx.recv() {
+                    let mut job = match msg {
+                        CompilerThreadMsg::Compile(job) => job,
+                        CompilerThreadMsg::ResetPools => {
+                            pools.buffers.set_num_buffers(0, 0, 0);
+                            continue;
+                        }
+                    };
+
+                    let schedule_version = job.schedule_version;
+                    let mut plugins_to_drop = std::mem::take(&mut job.plugins_to_drop);
+
+                    // If the compile fails, then the last working schedule is
+                    // left in place.
+                    let status = compile_graph(
+                        *job,
+                        &mut mirror_graph,
+                        &mut pools,
+                        &mut verifier,
+                        &coll_handle,
+                    )
+                    .map(|mut schedule| {
+                        log::debug!("Successfully compiled new schedule:\n{:?}", &schedule);
+                        schedule
+                            .set_plugin_processors_to_stop(std::mem::take(&mut plugins_to_drop));
+
+                        let (paused, resumed) = &*thread_publish_paused;
+                        let mut paused = paused.lock().unwrap_or_else(PoisonError::into_inner);
+                        while *paused {
+                            paused = resumed.wait(paused).unwrap_or_else(PoisonError::into_inner);
+                        }
+
+                        shared_schedule.set_new_schedule(schedule, &coll_handle);
+                    });
+
+                    if from_thread_tx
+                        .send(CompileResult {
+                            schedule_version,
+                            status,
+                            plugins_to_drop,
+                            rolled_back_requests: Vec::new(),
+                        })
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to spawn graph compiler thread");
+
+        Self {
+            to_thread_tx: Some(to_thread_tx),
+            from_thread_rx,
+            num_jobs_in_flight: 0,
+            publish_paused,
+            thread_handle: Some(thread_handle),
+        }
+    }
+
+    /// Returns `true` if the compiler thread is currently compiling a
+    /// schedule.
+    pub fn is_busy(&self) -> bool {
+        self.num_jobs_in_flight > 0
+    }
+
+    /// Prevent the compiler thread from publishing new schedules until
+    /// `CompilerThread::resume_publishing()` is called.
+    ///
+    /// Jobs keep being compiled in the meantime, but a finished schedule is
+    /// only sent to the process thread once publishing is resumed. If the
+    /// compiler thread is in the middle of publishing a schedule, then this
+    /// blocks until it is done.
+    pub fn pause_publishing(&self) {
+        *self.publish_paused.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+    }
+
+    pub fn resume_publishing(&self) {
+        let (paused, resumed) = &*self.publish_paused;
+        *paused.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        resumed.notify_all();
+    }
+
+    pub fn compile(&mut self, job: CompileJob) {
+        if let Some(tx) = &self.to_thread_tx {
+            if tx.send(CompilerThreadMsg::Compile(Box::new(job))).is_ok() {
+                self.num_jobs_in_flight += 1;
+            } else {
+                log::error!("Graph compiler thread has unexpectedly exited");
+            }
+        }
+    }
+
+    pub fn reset_pools(&mut self) {
+        if let Some(tx) = &self.to_thread_tx {
+            let _ = tx.send(CompilerThreadMsg::ResetPools);
+        }
+    }
+
+    /// Returns the result of a finished compile without blocking.
+    pub fn try_recv(&mut self) -> Option<CompileResult> {
+        if self.num_jobs_in_flight == 0 {
+            return None;
+        }
+
+        match self.from_thread_rx.try_recv() {
+            Ok(res) => {
+                self.num_jobs_in_flight -= 1;
+                Some(res)
+            }
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                log::error!("Graph compiler thread has unexpectedly exited");
+                self.num_jobs_in_flight = 0;
+                None
+            }
+        }
+    }
+
+    /// Block until the next compile has finished and return its result.
+    ///
+    /// This returns `None` if the compiler thread is not busy.
+    pub fn recv(&mut self) -> Option<CompileResult> {
+        if self.num_jobs_in_flight == 0 {
+            return None;
+        }
+
+        match self.from_thread_rx.recv() {
+            Ok(res) => {
+                self.num_jobs_in_flight -= 1;
+                Some(res)
+            }
+            Err(_) => {
+                log::error!("Graph compiler thread has unexpectedly exited");
+                self.num_jobs_in_flight = 0;
+                None
+            }
+        }
+    }
+}
+
+impl Drop for CompilerThread {
+    fn drop(&mut self) {
+        // Dropping the sender causes the compiler thread to exit.
+        self.to_thread_tx = None;
+        self.resume_publishing();
+
+        if let Some(thread_handle) = self.thread_handle.take() {
+            if let Err(e) = thread_handle.join() {
+                log::error!("Failed to join graph compiler thread handle: {:?}", e);
+            }
+        }
+    }
+}
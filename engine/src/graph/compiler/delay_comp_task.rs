@@ -7,13 +7,13 @@ use crate::processor_schedule::tasks::{
 };
 
 use super::super::error::GraphCompilerError;
-use super::super::shared_pools::{DelayCompKey, GraphSharedPools};
+use super::super::shared_pools::{CompilerPools, DelayCompKey};
 use super::super::PortType;
 
 pub(super) fn construct_delay_comp_task(
     inserted_delay: &InsertedDelay,
     delay: i64,
-    shared_pool: &mut GraphSharedPools,
+    shared_pool: &mut CompilerPools,
     coll_handle: &basedrop::Handle,
 ) -> Result<Task, GraphCompilerError> {
     if delay < 0 {
@@ -5,12 +5,12 @@ use smallvec::{smallvec, SmallVec};
 use crate::processor_schedule::tasks::{GraphInTask, GraphOutTask};
 
 use super::super::error::GraphCompilerError;
-use super::super::shared_pools::GraphSharedPools;
+use super::super::shared_pools::CompilerPools;
 use super::super::PortType;
 
 pub(super) fn construct_graph_in_task(
     scheduled_node: &ScheduledNode,
-    shared_pool: &mut GraphSharedPools,
+    shared_pool: &mut CompilerPools,
     num_graph_in_audio_ports: usize,
 ) -> Result<GraphInTask, GraphCompilerError> {
     // --- Construct a map that maps the index (channel) of each port to its assigned buffer
@@ -67,7 +67,7 @@ pub(super) fn construct_graph_in_task(
 
 pub(super) fn construct_graph_out_task(
     scheduled_node: &ScheduledNode,
-    shared_pool: &mut GraphSharedPools,
+    shared_pool: &mut CompilerPools,
     num_graph_out_audio_ports: usize,
 ) -> Result<GraphOutTask, GraphCompilerError> {
     // --- Construct a map that maps the index (channel) of each port to its assigned buffer
@@ -1,4 +1,4 @@
-use audio_graph::ScheduledNode;
+use audio_graph::{NodeID, ScheduledNode};
 use fnv::FnvHashMap;
 use meadowlark_plugin_api::automation::AutomationIoEvent;
 use meadowlark_plugin_api::buffer::SharedBuffer;
@@ -7,31 +7,31 @@ use crate::plugin_host::event_io_buffers::NoteIoEvent;
 use crate::processor_schedule::tasks::Task;
 
 use super::super::error::GraphCompilerError;
-use super::super::shared_pools::GraphSharedPools;
+use super::super::shared_pools::CompilerPools;
 use super::super::{PortChannelID, PortType};
+use super::CompilerPluginNode;
 
 mod loaded_plugin_task;
 mod unloaded_plugin_task;
 
 pub(super) fn construct_plugin_task(
     scheduled_node: &ScheduledNode,
-    shared_pool: &mut GraphSharedPools,
+    plugin_nodes: &FnvHashMap<NodeID, CompilerPluginNode>,
+    shared_pool: &mut CompilerPools,
 ) -> Result<Task, GraphCompilerError> {
     // --- Get port info and processor from the plugin host ---------------------------------
 
-    let plugin_host =
-        shared_pool.plugin_hosts.get_by_node_id(&scheduled_node.id).ok_or_else(|| {
-            GraphCompilerError::UnexpectedError(format!(
-                "Abstract schedule assigned a node that doesn't exist: {:?}",
-                scheduled_node
-            ))
-        })?;
+    let plugin_node = plugin_nodes.get(&scheduled_node.id).ok_or_else(|| {
+        GraphCompilerError::UnexpectedError(format!(
+            "Abstract schedule assigned a node that doesn't exist: {:?}",
+            scheduled_node
+        ))
+    })?;
 
-    let plugin_id = plugin_host.id();
-    let port_ids = plugin_host.port_ids();
-    let shared_processor = plugin_host.shared_processor();
-    let maybe_audio_ports_ext = plugin_host.audio_ports_ext();
-    let maybe_note_ports_ext = plugin_host.note_ports_ext();
+    let plugin_id = &plugin_node.plugin_id;
+    let shared_processor = &plugin_node.shared_processor;
+    let maybe_audio_ports_ext = plugin_node.audio_ports_ext.as_ref();
+    let maybe_note_ports_ext = plugin_node.note_ports_ext.as_ref();
 
     // --- Construct a map that maps the PortChannelID of each port to its assigned buffer ------
 
@@ -46,7 +46,7 @@ pub(super) fn construct_plugin_task(
         scheduled_node.input_buffers.iter().chain(scheduled_node.output_buffers.iter())
     {
         let channel_id =
-            port_ids.port_id_to_channel_id.get(&assigned_buffer.port_id).ok_or_else(|| {
+            plugin_node.port_id_to_channel_id.get(&assigned_buffer.port_id).ok_or_else(|| {
                 GraphCompilerError::UnexpectedError(format!(
                     "Abstract schedule assigned a buffer for port that doesn't exist {:?}",
                     scheduled_node
@@ -122,7 +122,7 @@ pub(super) fn construct_plugin_task(
 
     // --- Construct the final task using the constructed map from above --------------------
 
-    if plugin_host.is_loaded() {
+    if plugin_node.is_loaded {
         loaded_plugin_task::construct_loaded_plugin_task(
             scheduled_node,
             shared_pool,
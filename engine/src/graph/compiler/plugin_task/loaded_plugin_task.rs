@@ -1,4 +1,5 @@
 use audio_graph::ScheduledNode;
+use clack_extensions::note_ports::NoteDialects;
 use fnv::FnvHashMap;
 use meadowlark_plugin_api::automation::AutomationIoEvent;
 use meadowlark_plugin_api::buffer::{AudioPortBuffer, AudioPortBufferMut, SharedBuffer};
@@ -12,13 +13,13 @@ use crate::plugin_host::SharedPluginHostProcessor;
 use crate::processor_schedule::tasks::{PluginTask, Task};
 
 use super::super::super::error::GraphCompilerError;
-use super::super::super::shared_pools::GraphSharedPools;
+use super::super::super::shared_pools::CompilerPools;
 use super::super::super::{PortChannelID, PortType};
 
 #[allow(clippy::too_many_arguments)] // Fix this?
 pub(super) fn construct_loaded_plugin_task(
     scheduled_node: &ScheduledNode,
-    shared_pool: &GraphSharedPools,
+    shared_pool: &CompilerPools,
     plugin_id: &PluginInstanceID,
     shared_processor: &SharedPluginHostProcessor,
     audio_ports_ext: &PluginAudioPortsExt,
@@ -32,6 +33,7 @@ pub(super) fn construct_loaded_plugin_task(
     let mut audio_out: SmallVec<[AudioPortBufferMut; 2]> = SmallVec::new();
     let mut note_in_buffers: SmallVec<[SharedBuffer<NoteIoEvent>; 2]> = SmallVec::new();
     let mut note_out_buffers: SmallVec<[SharedBuffer<NoteIoEvent>; 2]> = SmallVec::new();
+    let mut note_in_dialects: SmallVec<[NoteDialects; 2]> = SmallVec::new();
     let mut clear_audio_in_buffers: SmallVec<[SharedBuffer<f32>; 2]> = SmallVec::new();
     let mut clear_note_in_buffers: SmallVec<[SharedBuffer<NoteIoEvent>; 2]> = SmallVec::new();
 
@@ -118,6 +120,7 @@ pub(super) fn construct_loaded_plugin_task(
         })?;
 
         note_in_buffers.push(buffer.0.clone());
+        note_in_dialects.push(in_port.supported_dialects);
 
         if buffer.1 {
             clear_note_in_buffers.push(buffer.0.clone());
@@ -149,11 +152,13 @@ pub(super) fn construct_loaded_plugin_task(
         event_buffers: PluginEventIoBuffers {
             note_in_buffers,
             note_out_buffers,
+            note_in_dialects,
             clear_note_in_buffers,
             automation_in_buffer: assigned_automation_in_buffer,
             automation_out_buffer: assigned_automation_out_buffer,
             main_note_through_when_bypassed,
         },
         clear_audio_in_buffers,
+        audio_out_capture: None,
     }))
 }
@@ -7,12 +7,12 @@ use crate::plugin_host::event_io_buffers::NoteIoEvent;
 use crate::processor_schedule::tasks::{AudioSumTask, AutomationSumTask, NoteSumTask, Task};
 
 use super::super::error::GraphCompilerError;
-use super::super::shared_pools::GraphSharedPools;
+use super::super::shared_pools::CompilerPools;
 use super::super::PortType;
 
 pub(super) fn construct_sum_task(
     inserted_sum: &InsertedSum,
-    shared_pool: &mut GraphSharedPools,
+    shared_pool: &mut CompilerPools,
 ) -> Result<Task, GraphCompilerError> {
     let task = match inserted_sum.output_buffer.type_index {
         PortType::AUDIO_TYPE_IDX => {
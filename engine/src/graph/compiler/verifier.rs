@@ -1,6 +1,7 @@
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 use meadowlark_plugin_api::buffer::{DebugBufferID, RawAudioChannelBuffers};
 
+use crate::processor_schedule::parallel::{BufferAccesses, TaskGraph};
 use crate::processor_schedule::{tasks::Task, ProcessorSchedule};
 
 use super::super::error::VerifyScheduleError;
@@ -8,6 +9,9 @@ use super::super::error::VerifyScheduleError;
 pub(crate) struct Verifier {
     plugin_instances: FnvHashSet<u64>,
     buffer_instances: FnvHashSet<DebugBufferID>,
+    buffer_accesses: FnvHashMap<DebugBufferID, BufferAccesses>,
+    task_accesses: Vec<TaskAccesses>,
+    ancestors: Vec<Vec<u64>>,
 }
 
 impl Verifier {
@@ -17,7 +21,16 @@ impl Verifier {
         plugin_instances.reserve(1024);
         buffer_instances.reserve(1024);
 
-        Verifier { plugin_instances, buffer_instances }
+        let mut buffer_accesses: FnvHashMap<DebugBufferID, BufferAccesses> = FnvHashMap::default();
+        buffer_accesses.reserve(1024);
+
+        Verifier {
+            plugin_instances,
+            buffer_instances,
+            buffer_accesses,
+            task_accesses: Vec::new(),
+            ancestors: Vec::new(),
+        }
     }
 
     /// Verify that the schedule is sound (no race conditions).
@@ -31,17 +44,34 @@ impl Verifier {
     ///
     /// However, it is still very possible to have race condition bugs in the schedule, such as
     /// the same buffer being assigned multiple times within the same task, or the same buffer
-    /// appearing multiple times between parallel tasks (once we have multithreaded scheduling).
+    /// appearing multiple times between parallel tasks.
     pub fn verify_schedule_for_race_conditions(
         &mut self,
         schedule: &ProcessorSchedule,
     ) -> Result<(), VerifyScheduleError> {
-        // TODO: verifying that there are not data races between parallel threads once we
-        // have multithreaded scheduling.
+        self.collect_task_accesses(schedule.tasks())?;
+        self.verify_parallel_tasks(schedule.task_graph())
+    }
 
+    /// Collect the IDs of the buffers each task reads from and writes to,
+    /// taken directly from the buffers assigned to the task (and not from
+    /// `Task::collect_buffer_ids()`, which is what the task graph itself is
+    /// constructed from).
+    fn collect_task_accesses<'a>(
+        &mut self,
+        tasks: impl Iterator<Item = &'a Task>,
+    ) -> Result<(), VerifyScheduleError> {
         self.plugin_instances.clear();
 
-        for task in schedule.tasks().iter() {
+        let mut num_tasks = 0;
+        for (task_i, task) in tasks.enumerate() {
+            num_tasks = task_i + 1;
+            if self.task_accesses.len() < num_tasks {
+                self.task_accesses.push(TaskAccesses::default());
+            }
+            self.task_accesses[task_i].reads.clear();
+            self.task_accesses[task_i].writes.clear();
+
             self.buffer_instances.clear();
 
             match task {
@@ -53,62 +83,33 @@ impl Verifier {
                     }
 
                     for port_buffer in t.buffers.audio_in.iter() {
-                        match &port_buffer._raw_channels {
-                            RawAudioChannelBuffers::F32(buffers) => {
-                                for b in buffers.iter() {
-                                    if !self.buffer_instances.insert(b.id()) {
-                                        return Err(
-                                            VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                                buffer_id: b.id(),
-                                                task_info: format!("{:?}", &task),
-                                            },
-                                        );
-                                    }
-                                }
-                            }
-                            RawAudioChannelBuffers::F64(buffers) => {
-                                for b in buffers.iter() {
-                                    if !self.buffer_instances.insert(b.id()) {
-                                        return Err(
-                                            VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                                buffer_id: b.id(),
-                                                task_info: format!("{:?}", &task),
-                                            },
-                                        );
-                                    }
-                                }
-                            }
-                        }
+                        self.add_port(task_i, task, &port_buffer._raw_channels, false)?;
                     }
-
                     for port_buffer in t.buffers.audio_out.iter() {
-                        match &port_buffer._raw_channels {
-                            RawAudioChannelBuffers::F32(buffers) => {
-                                for b in buffers.iter() {
-                                    if !self.buffer_instances.insert(b.id()) {
-                                        return Err(
-                                            VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                                buffer_id: b.id(),
-                                                task_info: format!("{:?}", &task),
-                                            },
-                                        );
-                                    }
-                                }
-                            }
-                            RawAudioChannelBuffers::F64(buffers) => {
-                                for b in buffers.iter() {
-                                    if !self.buffer_instances.insert(b.id()) {
-                                        return Err(
-                                            VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                                buffer_id: b.id(),
-                                                task_info: format!("{:?}", &task),
-                                            },
-                                        );
-                                    }
-                                }
-                            }
+                        self.add_port(task_i, task, &port_buffer._raw_channels, true)?;
+                    }
+                    for b in t.clear_audio_in_buffers.iter() {
+                        self.add_clear(task_i, task, b.id())?;
+                    }
+
+                    for b in t.event_buffers.note_in_buffers.iter() {
+                        self.add(task_i, task, b.id(), false)?;
+                    }
+                    for b in t.event_buffers.note_out_buffers.iter() {
+                        self.add(task_i, task, b.id(), true)?;
+                    }
+                    for b in t.event_buffers.clear_note_in_buffers.iter() {
+                        self.add_clear(task_i, task, b.id())?;
+                    }
+                    if let Some((b, do_clear)) = &t.event_buffers.automation_in_buffer {
+                        self.add(task_i, task, b.id(), false)?;
+                        if *do_clear {
+                            self.add_clear(task_i, task, b.id())?;
                         }
                     }
+                    if let Some(b) = &t.event_buffers.automation_out_buffer {
+                        self.add(task_i, task, b.id(), true)?;
+                    }
                 }
                 Task::AudioSum(t) => {
                     // This could be made just a warning and not an error, but it's still not what
@@ -121,19 +122,9 @@ impl Verifier {
                     }
 
                     for b in t.audio_in.iter() {
-                        if !self.buffer_instances.insert(b.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
-                    }
-                    if !self.buffer_instances.insert(t.audio_out.id()) {
-                        return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                            buffer_id: t.audio_out.id(),
-                            task_info: format!("{:?}", &task),
-                        });
+                        self.add(task_i, task, b.id(), false)?;
                     }
+                    self.add(task_i, task, t.audio_out.id(), true)?;
                 }
                 Task::NoteSum(t) => {
                     // This could be made just a warning and not an error, but it's still not what
@@ -146,19 +137,9 @@ impl Verifier {
                     }
 
                     for b in t.note_in.iter() {
-                        if !self.buffer_instances.insert(b.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
-                    }
-                    if !self.buffer_instances.insert(t.note_out.id()) {
-                        return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                            buffer_id: t.note_out.id(),
-                            task_info: format!("{:?}", &task),
-                        });
+                        self.add(task_i, task, b.id(), false)?;
                     }
+                    self.add(task_i, task, t.note_out.id(), true)?;
                 }
                 Task::AutomationSum(t) => {
                     // This could be made just a warning and not an error, but it's still not what
@@ -171,102 +152,310 @@ impl Verifier {
                     }
 
                     for b in t.input.iter() {
-                        if !self.buffer_instances.insert(b.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
-                    }
-                    if !self.buffer_instances.insert(t.output.id()) {
-                        return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                            buffer_id: t.output.id(),
-                            task_info: format!("{:?}", &task),
-                        });
+                        self.add(task_i, task, b.id(), false)?;
                     }
+                    self.add(task_i, task, t.output.id(), true)?;
                 }
                 Task::AudioDelayComp(t) => {
-                    if t.audio_in.id() == t.audio_out.id() {
-                        return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                            buffer_id: t.audio_in.id(),
-                            task_info: format!("{:?}", &task),
-                        });
-                    }
+                    self.add(task_i, task, t.audio_in.id(), false)?;
+                    self.add(task_i, task, t.audio_out.id(), true)?;
                 }
                 Task::NoteDelayComp(t) => {
-                    if t.note_in.id() == t.note_out.id() {
-                        return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                            buffer_id: t.note_in.id(),
-                            task_info: format!("{:?}", &task),
-                        });
-                    }
+                    self.add(task_i, task, t.note_in.id(), false)?;
+                    self.add(task_i, task, t.note_out.id(), true)?;
                 }
                 Task::AutomationDelayComp(t) => {
-                    if t.input.id() == t.output.id() {
-                        return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                            buffer_id: t.input.id(),
-                            task_info: format!("{:?}", &task),
-                        });
-                    }
+                    self.add(task_i, task, t.input.id(), false)?;
+                    self.add(task_i, task, t.output.id(), true)?;
                 }
                 Task::UnloadedPlugin(t) => {
                     for (b_in, b_out) in t.audio_through.iter() {
-                        if !self.buffer_instances.insert(b_in.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b_in.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
-                        if !self.buffer_instances.insert(b_out.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b_out.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
+                        self.add(task_i, task, b_in.id(), false)?;
+                        self.add(task_i, task, b_out.id(), true)?;
                     }
                     if let Some((b_in, b_out)) = &t.note_through {
-                        if !self.buffer_instances.insert(b_in.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b_in.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
-                        if !self.buffer_instances.insert(b_out.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b_out.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
+                        self.add(task_i, task, b_in.id(), false)?;
+                        self.add(task_i, task, b_out.id(), true)?;
                     }
 
                     for b in t.clear_audio_out.iter() {
-                        if !self.buffer_instances.insert(b.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
+                        self.add(task_i, task, b.id(), true)?;
                     }
                     for b in t.clear_note_out.iter() {
-                        if !self.buffer_instances.insert(b.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
+                        self.add(task_i, task, b.id(), true)?;
                     }
                     if let Some(b) = &t.clear_automation_out {
-                        if !self.buffer_instances.insert(b.id()) {
-                            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
-                                buffer_id: b.id(),
-                                task_info: format!("{:?}", &task),
-                            });
-                        }
+                        self.add(task_i, task, b.id(), true)?;
+                    }
+                }
+            }
+        }
+
+        self.task_accesses.truncate(num_tasks);
+
+        Ok(())
+    }
+
+    fn add_port(
+        &mut self,
+        task_i: usize,
+        task: &Task,
+        raw_channels: &RawAudioChannelBuffers,
+        is_write: bool,
+    ) -> Result<(), VerifyScheduleError> {
+        match raw_channels {
+            RawAudioChannelBuffers::F32(buffers) => {
+                for b in buffers.iter() {
+                    self.add(task_i, task, b.id(), is_write)?;
+                }
+            }
+            RawAudioChannelBuffers::F64(buffers) => {
+                for b in buffers.iter() {
+                    self.add(task_i, task, b.id(), is_write)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a buffer which is assigned to the task. A buffer may only be
+    /// assigned once within the same task.
+    fn add(
+        &mut self,
+        task_i: usize,
+        task: &Task,
+        buffer_id: DebugBufferID,
+        is_write: bool,
+    ) -> Result<(), VerifyScheduleError> {
+        if !self.buffer_instances.insert(buffer_id) {
+            return Err(VerifyScheduleError::BufferAppearsTwiceInSameTask {
+                buffer_id,
+                task_info: format!("{:?}", task),
+            });
+        }
+
+        let accesses = &mut self.task_accesses[task_i];
+        if is_write {
+            accesses.writes.push(buffer_id);
+        } else {
+            accesses.reads.push(buffer_id);
+        }
+
+        Ok(())
+    }
+
+    /// Add a buffer which is cleared by the task. If the buffer is also one
+    /// of the task's inputs, then it is counted as written to instead of read
+    /// from.
+    fn add_clear(
+        &mut self,
+        task_i: usize,
+        task: &Task,
+        buffer_id: DebugBufferID,
+    ) -> Result<(), VerifyScheduleError> {
+        let accesses = &mut self.task_accesses[task_i];
+        if let Some(i) = accesses.reads.iter().position(|id| *id == buffer_id) {
+            accesses.reads.swap_remove(i);
+            accesses.writes.push(buffer_id);
+            Ok(())
+        } else if accesses.writes.contains(&buffer_id) {
+            Ok(())
+        } else {
+            self.add(task_i, task, buffer_id, true)
+        }
+    }
+
+    /// Verify that every pair of tasks which use the same buffer (where at
+    /// least one of them writes to it) are ordered by the task graph, meaning
+    /// they can never be processed at the same time by different threads.
+    fn verify_parallel_tasks(&mut self, task_graph: &TaskGraph) -> Result<(), VerifyScheduleError> {
+        let num_tasks = task_graph.num_tasks();
+        if num_tasks != self.task_accesses.len() {
+            return Err(VerifyScheduleError::TaskGraphMismatch {
+                num_tasks: self.task_accesses.len(),
+                num_graph_tasks: num_tasks,
+            });
+        }
+        let num_words = (num_tasks + 63) / 64;
+
+        // Edges in the task graph always point from an earlier task to a later
+        // task, so the ancestors of a task are complete by the time it is reached.
+        self.ancestors.clear();
+        self.ancestors.resize(num_tasks, vec![0; num_words]);
+        for i in 0..num_tasks {
+            for &dependent in task_graph.dependents(i) {
+                let dependent = dependent as usize;
+                debug_assert!(dependent > i);
+
+                let (before, after) = self.ancestors.split_at_mut(dependent);
+                let dependent_ancestors = &mut after[0];
+                for (word, ancestor_word) in dependent_ancestors.iter_mut().zip(before[i].iter()) {
+                    *word |= *ancestor_word;
+                }
+                dependent_ancestors[i / 64] |= 1 << (i % 64);
+            }
+        }
+
+        let is_ancestor = |ancestors: &[Vec<u64>], task_i: usize, other_i: usize| {
+            task_i == other_i || ancestors[task_i][other_i / 64] & (1 << (other_i % 64)) != 0
+        };
+
+        self.buffer_accesses.clear();
+        for (task_i, task_accesses) in self.task_accesses.iter().enumerate() {
+            for buffer_id in task_accesses.reads.iter() {
+                let accesses = self.buffer_accesses.entry(*buffer_id).or_default();
+                if let Some(writer) = accesses.last_writer {
+                    if !is_ancestor(&self.ancestors, task_i, writer) {
+                        return Err(VerifyScheduleError::BufferAppearsTwiceInParallelTasks {
+                            buffer_id: *buffer_id,
+                        });
                     }
                 }
+                accesses.readers.push(task_i);
+            }
+            for buffer_id in task_accesses.writes.iter() {
+                let accesses = self.buffer_accesses.entry(*buffer_id).or_default();
+                let mut prev_tasks = accesses.last_writer.iter().chain(accesses.readers.iter());
+                if prev_tasks.any(|prev_i| !is_ancestor(&self.ancestors, task_i, *prev_i)) {
+                    return Err(VerifyScheduleError::BufferAppearsTwiceInParallelTasks {
+                        buffer_id: *buffer_id,
+                    });
+                }
+                accesses.readers.clear();
+                accesses.last_writer = Some(task_i);
             }
         }
 
         Ok(())
     }
 }
+
+/// The buffers a single task reads from and writes to.
+#[derive(Default)]
+struct TaskAccesses {
+    reads: Vec<DebugBufferID>,
+    writes: Vec<DebugBufferID>,
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+    use meadowlark_plugin_api::buffer::{DebugBufferType, SharedBuffer};
+
+    use super::*;
+    use crate::processor_schedule::parallel::TaskCell;
+    use crate::processor_schedule::tasks::AudioSumTask;
+
+    fn audio_buffers(num: u32, coll_handle: &basedrop::Handle) -> Vec<SharedBuffer<f32>> {
+        (0..num)
+            .map(|index| {
+                SharedBuffer::new(
+                    16,
+                    DebugBufferID { index, buffer_type: DebugBufferType::Audio32 },
+                    coll_handle,
+                )
+            })
+            .collect()
+    }
+
+    fn sum_task(audio_in: &[&SharedBuffer<f32>], audio_out: &SharedBuffer<f32>) -> Task {
+        Task::AudioSum(AudioSumTask {
+            audio_in: audio_in.iter().map(|b| (*b).clone()).collect(),
+            audio_out: audio_out.clone(),
+        })
+    }
+
+    fn verify(tasks: &[Task], task_graph: &TaskGraph) -> Result<(), VerifyScheduleError> {
+        let mut verifier = Verifier::new();
+        verifier.collect_task_accesses(tasks.iter())?;
+        verifier.verify_parallel_tasks(task_graph)
+    }
+
+    fn task_graph(tasks: Vec<Task>) -> TaskGraph {
+        let tasks: Vec<TaskCell> = tasks.into_iter().map(TaskCell::new).collect();
+        TaskGraph::new(&tasks, 1)
+    }
+
+    #[test]
+    fn dependent_tasks_are_accepted() {
+        let collector = Collector::new();
+        let b = audio_buffers(5, &collector.handle());
+
+        let tasks = || vec![sum_task(&[&b[0], &b[1]], &b[2]), sum_task(&[&b[2], &b[3]], &b[4])];
+
+        assert!(verify(&tasks(), &task_graph(tasks())).is_ok());
+    }
+
+    #[test]
+    fn parallel_readers_of_the_same_buffer_are_accepted() {
+        let collector = Collector::new();
+        let b = audio_buffers(5, &collector.handle());
+
+        let tasks = || vec![sum_task(&[&b[0], &b[1]], &b[2]), sum_task(&[&b[0], &b[3]], &b[4])];
+
+        let graph = task_graph(tasks());
+        assert!(graph.has_parallel_tasks());
+        assert!(verify(&tasks(), &graph).is_ok());
+    }
+
+    #[test]
+    fn buffer_missing_from_task_graph_is_rejected() {
+        let collector = Collector::new();
+        let b = audio_buffers(6, &collector.handle());
+
+        // Both tasks write to the same buffer, but the task graph was built
+        // as if they did not, so it lets them run in parallel.
+        let tasks = vec![sum_task(&[&b[0], &b[1]], &b[4]), sum_task(&[&b[2], &b[3]], &b[4])];
+        let graph =
+            task_graph(vec![sum_task(&[&b[0], &b[1]], &b[4]), sum_task(&[&b[2], &b[3]], &b[5])]);
+
+        assert!(matches!(
+            verify(&tasks, &graph),
+            Err(VerifyScheduleError::BufferAppearsTwiceInParallelTasks { buffer_id })
+                if buffer_id == b[4].id()
+        ));
+    }
+
+    #[test]
+    fn reader_not_ordered_after_writer_is_rejected() {
+        let collector = Collector::new();
+        let b = audio_buffers(6, &collector.handle());
+
+        let tasks = vec![sum_task(&[&b[0], &b[1]], &b[2]), sum_task(&[&b[2], &b[3]], &b[4])];
+        let graph =
+            task_graph(vec![sum_task(&[&b[0], &b[1]], &b[2]), sum_task(&[&b[5], &b[3]], &b[4])]);
+
+        assert!(matches!(
+            verify(&tasks, &graph),
+            Err(VerifyScheduleError::BufferAppearsTwiceInParallelTasks { buffer_id })
+                if buffer_id == b[2].id()
+        ));
+    }
+
+    #[test]
+    fn buffer_twice_in_same_task_is_rejected() {
+        let collector = Collector::new();
+        let b = audio_buffers(2, &collector.handle());
+
+        let tasks = || vec![sum_task(&[&b[0], &b[0]], &b[1])];
+
+        assert!(matches!(
+            verify(&tasks(), &task_graph(tasks())),
+            Err(VerifyScheduleError::BufferAppearsTwiceInSameTask { .. })
+        ));
+    }
+
+    #[test]
+    fn task_graph_with_different_number_of_tasks_is_rejected() {
+        let collector = Collector::new();
+        let b = audio_buffers(3, &collector.handle());
+
+        let tasks = vec![sum_task(&[&b[0], &b[1]], &b[2])];
+
+        assert!(matches!(
+            verify(&tasks, &task_graph(Vec::new())),
+            Err(VerifyScheduleError::TaskGraphMismatch { num_tasks: 1, num_graph_tasks: 0 })
+        ));
+    }
+}
@@ -113,6 +113,10 @@ pub enum VerifyScheduleError {
     PluginInstanceAppearsTwiceInSchedule {
         plugin_id: PluginInstanceID,
     },
+    TaskGraphMismatch {
+        num_tasks: usize,
+        num_graph_tasks: usize,
+    },
     /// This could be made just a warning and not an error, but it's still not what
     /// we want to happen.
     SumNodeWithLessThanTwoInputs {
@@ -135,6 +139,9 @@ impl std::fmt::Display for VerifyScheduleError {
             VerifyScheduleError::PluginInstanceAppearsTwiceInSchedule { plugin_id } => {
                 write!(f, "Error detected in compiled audio graph: The plugin instance with ID {:?} appears more than once in the schedule", plugin_id)
             }
+            VerifyScheduleError::TaskGraphMismatch { num_tasks, num_graph_tasks } => {
+                write!(f, "Error detected in compiled audio graph: The schedule has {} tasks but its task graph has {} tasks", num_tasks, num_graph_tasks)
+            }
             VerifyScheduleError::SumNodeWithLessThanTwoInputs { num_inputs, task_info } => {
                 write!(f, "Error detected in compiled audio graph: A Sum node was created with {} inputs in the task {}", num_inputs, task_info)
             }
@@ -1,11 +1,12 @@
 use meadowlark_plugin_api::automation::AutomationIoEvent;
 use meadowlark_plugin_api::buffer::{DebugBufferID, DebugBufferType, SharedBuffer};
 
-use crate::plugin_host::event_io_buffers::NoteIoEvent;
+use crate::plugin_host::event_io_buffers::{NoteIoEvent, NOTE_BUFFER_SYSEX_CAPACITY};
 
 pub(crate) struct BufferPool<T: Clone + Copy + Send + Sync + 'static> {
     pool: Vec<SharedBuffer<T>>,
     buffer_size: usize,
+    bytes_capacity: usize,
     buffer_type: DebugBufferType,
     collection_handle: basedrop::Handle,
 }
@@ -13,12 +14,13 @@ pub(crate) struct BufferPool<T: Clone + Copy + Send + Sync + 'static> {
 impl<T: Clone + Copy + Send + Sync + 'static> BufferPool<T> {
     fn new(
         buffer_size: usize,
+        bytes_capacity: usize,
         buffer_type: DebugBufferType,
         collection_handle: basedrop::Handle,
     ) -> Self {
         assert_ne!(buffer_size, 0);
 
-        Self { pool: Vec::new(), buffer_size, collection_handle, buffer_type }
+        Self { pool: Vec::new(), buffer_size, bytes_capacity, collection_handle, buffer_type }
     }
 
     #[inline]
@@ -33,6 +35,7 @@ impl<T: Clone + Copy + Send + Sync + 'static> BufferPool<T> {
             self.pool.resize_with(index + 1, || {
                 let buf = SharedBuffer::with_capacity(
                     self.buffer_size,
+                    self.bytes_capacity,
                     DebugBufferID { index: current_generated_index, buffer_type: self.buffer_type },
                     &self.collection_handle,
                 );
@@ -81,16 +84,19 @@ impl SharedBufferPool {
         Self {
             audio_buffer_pool: BufferPool::new(
                 audio_buffer_size,
+                0,
                 DebugBufferType::Audio32,
                 coll_handle.clone(),
             ),
             note_buffer_pool: BufferPool::new(
                 note_buffer_size,
+                NOTE_BUFFER_SYSEX_CAPACITY,
                 DebugBufferType::Note,
                 coll_handle.clone(),
             ),
             automation_buffer_pool: BufferPool::new(
                 event_buffer_size,
+                0,
                 DebugBufferType::Event,
                 coll_handle,
             ),
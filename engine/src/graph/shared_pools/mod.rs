@@ -18,9 +18,7 @@ use crate::{
 pub(super) struct GraphSharedPools {
     pub shared_schedule: SharedProcessorSchedule,
 
-    pub buffers: SharedBufferPool,
     pub plugin_hosts: PluginHostPool,
-    pub delay_comp_nodes: DelayCompNodePool,
     pub transports: TransportPool,
 }
 
@@ -28,8 +26,6 @@ impl GraphSharedPools {
     pub fn new(
         thread_ids: SharedThreadIDs,
         audio_buffer_size: usize,
-        note_buffer_size: usize,
-        event_buffer_size: usize,
         transport: TransportTask,
         schedule_version: u64,
         coll_handle: basedrop::Handle,
@@ -49,17 +45,36 @@ impl GraphSharedPools {
         (
             Self {
                 shared_schedule,
-                buffers: SharedBufferPool::new(
-                    audio_buffer_size,
-                    note_buffer_size,
-                    event_buffer_size,
-                    coll_handle,
-                ),
                 plugin_hosts: PluginHostPool::new(),
-                delay_comp_nodes: DelayCompNodePool::new(),
                 transports: TransportPool { transport: shared_transport_task },
             },
             shared_schedule_clone,
         )
     }
 }
+
+/// The pools which are only ever accessed by the graph compiler. These are
+/// owned by the compiler thread.
+pub(super) struct CompilerPools {
+    pub buffers: SharedBufferPool,
+    pub delay_comp_nodes: DelayCompNodePool,
+}
+
+impl CompilerPools {
+    pub fn new(
+        audio_buffer_size: usize,
+        note_buffer_size: usize,
+        event_buffer_size: usize,
+        coll_handle: basedrop::Handle,
+    ) -> Self {
+        Self {
+            buffers: SharedBufferPool::new(
+                audio_buffer_size,
+                note_buffer_size,
+                event_buffer_size,
+                coll_handle,
+            ),
+            delay_comp_nodes: DelayCompNodePool::new(),
+        }
+    }
+}
@@ -37,16 +37,16 @@ impl PluginHostPool {
         self.pool.get_mut(&id.unique_id())
     }
 
-    pub fn get_by_node_id(&self, id: &NodeID) -> Option<&PluginHostMainThread> {
-        self.node_id_to_plugin_id.get(id).map(|id| self.pool.get(&id.unique_id()).unwrap())
+    pub fn get_by_node_id(&self, node_id: &NodeID) -> Option<&PluginHostMainThread> {
+        self.node_id_to_plugin_id.get(node_id).and_then(|id| self.pool.get(&id.unique_id()))
     }
 
     pub fn get_by_unique_id_mut(&mut self, id: u64) -> Option<&mut PluginHostMainThread> {
         self.pool.get_mut(&id)
     }
 
-    pub fn num_plugins(&self) -> usize {
-        self.pool.len()
+    pub fn iter_by_node_id(&self) -> impl Iterator<Item = (&'_ NodeID, &'_ PluginHostMainThread)> {
+        self.node_id_to_plugin_id.iter().map(|(node_id, id)| (node_id, &self.pool[&id.unique_id()]))
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &'_ mut PluginHostMainThread> {
@@ -1,25 +1,37 @@
 use atomic_refcell::AtomicRefCell;
 use basedrop::{Shared, SharedCell};
+use meadowlark_plugin_api::PluginInstanceID;
 
+use crate::engine::performance::EnginePerfShared;
+use crate::processor_schedule::parallel::WorkerPoolShared;
 use crate::processor_schedule::ProcessorSchedule;
 use crate::utils::thread_id::SharedThreadIDs;
 
-// Required so we can send the schedule from the main thread to the process
-// thread.
+// Required so we can send the schedule from the compiler thread to the
+// process thread.
 //
-// This is safe because the schedule is only ever dereferenced in the process
-// thread. The only reason why the main thread holds onto these shared
-// pointers of buffers and `PluginAudioThread`s is so it can construct new
-// schedules with them. The main thread never dereferences these pointers.
+// This is safe because only one thread ever dereferences the buffers and
+// `PluginAudioThread`s in a schedule at a time:
+//
+// * Normally this is the process thread (along with the worker threads it
+//   hands tasks to while it is blocked waiting for them).
+// * During an offline render, the main thread processes the schedule itself.
+//   It only does so after the process thread has acknowledged a
+//   `ProcessThreadPark` request, after which the process thread only outputs
+//   silence and no longer touches the schedule until it is unparked. The
+//   compiler thread is also blocked from publishing new schedules for the
+//   whole length of the render (see `CompilerThread::pause_publishing()`).
+//
+// The compiler thread and the main thread otherwise only hold onto shared
+// pointers of buffers and `PluginAudioThread`s so they can construct new
+// schedules with them. They never dereference these pointers.
 unsafe impl Send for ProcessorSchedule {}
-// Required so we can send the schedule from the main thread to the process
-// thread. The fact that the main thread holds onto shared pointers of
-// buffers and `PluginAudioThread`s requires this to be `Sync` as well.
+// Required so we can send the schedule from the compiler thread to the
+// process thread. The fact that the compiler thread holds onto shared
+// pointers of buffers and `PluginAudioThread`s requires this to be `Sync` as
+// well.
 //
-// This is safe because the schedule is only ever dereferenced in the process
-// thread. The only reason why the main thread holds onto these shared
-// pointers of buffers and `PluginAudioThread`s is so it can construct new
-// schedules with them. The main thread never dereferences these pointers.
+// This is safe for the same reasons as `Send` above.
 unsafe impl Sync for ProcessorSchedule {}
 
 pub(crate) struct SharedProcessorSchedule {
@@ -56,6 +68,15 @@ impl SharedProcessorSchedule {
         )
     }
 
+    /// Create another handle to the same shared schedule.
+    pub fn new_handle(&self) -> Self {
+        Self {
+            schedule: Shared::clone(&self.schedule),
+            thread_ids: self.thread_ids.clone(),
+            coll_handle: self.coll_handle.clone(),
+        }
+    }
+
     pub fn set_new_schedule(
         &mut self,
         schedule: ProcessorSchedule,
@@ -64,7 +85,13 @@ impl SharedProcessorSchedule {
         self.schedule.set(Shared::new(coll_handle, AtomicRefCell::new(schedule)));
     }
 
-    pub fn process_interleaved(&mut self, audio_in: &[f32], audio_out: &mut [f32]) {
+    pub fn process_interleaved(
+        &mut self,
+        workers: Option<&WorkerPoolShared>,
+        perf: &EnginePerfShared,
+        audio_in: &[f32],
+        audio_out: &mut [f32],
+    ) {
         let latest_schedule = self.schedule.get();
 
         let mut schedule = latest_schedule.borrow_mut();
@@ -78,7 +105,28 @@ impl SharedProcessorSchedule {
             self.thread_ids.set_process_thread_id(std::thread::current().id(), &self.coll_handle);
         }
 
-        schedule.process_interleaved(audio_in, audio_out);
+        schedule.process_interleaved(workers, perf, audio_in, audio_out);
+    }
+
+    pub fn begin_plugin_audio_out_capture(
+        &mut self,
+        plugin_id: &PluginInstanceID,
+        port_index: usize,
+        num_channels: usize,
+    ) -> bool {
+        self.schedule.get().borrow_mut().begin_plugin_audio_out_capture(
+            plugin_id,
+            port_index,
+            num_channels,
+        )
+    }
+
+    pub fn take_plugin_audio_out_capture(&mut self, out: &mut [f32]) -> bool {
+        self.schedule.get().borrow_mut().take_plugin_audio_out_capture(out)
+    }
+
+    pub fn end_plugin_audio_out_capture(&mut self) {
+        self.schedule.get().borrow_mut().end_plugin_audio_out_capture();
     }
 
     pub fn deactivate(&mut self) {
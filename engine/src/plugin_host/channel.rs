@@ -1,28 +1,41 @@
 use atomic_refcell::{AtomicRefCell, AtomicRefMut};
 use basedrop::{Shared, SharedCell};
-use clack_host::events::{Event, EventFlags, EventHeader};
+use clack_host::events::event_types::NoteExpressionType;
 use clack_host::utils::Cookie;
-use meadowlark_plugin_api::{
-    buffer::EventBuffer,
-    event::{ParamModEvent, ParamValueEvent},
-    ParamID, PluginProcessor,
-};
+use fnv::FnvHashMap;
+use meadowlark_plugin_api::ext::params::ParamInfoFlags;
+use meadowlark_plugin_api::{ParamID, PluginProcessor, TailLength};
 use std::sync::{
-    atomic::{AtomicBool, AtomicU32, Ordering},
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
     Arc,
 };
 
-use meadowlark_plugin_api::automation::AutomationIoEventType;
+use meadowlark_plugin_api::automation::{AutomationIoEvent, AutomationIoEventType, IoEventHeader};
+use meadowlark_plugin_api::transport::TransportInfo;
+use rtrb::{Consumer, Producer, RingBuffer};
 
+use crate::engine::performance::PerfMeter;
 use crate::utils::reducing_queue::{
     ReducFnvConsumer, ReducFnvProducer, ReducFnvValue, ReducingFnvQueue,
 };
 use crate::utils::thread_id::SharedThreadIDs;
 
+use super::event_io_buffers::{InputEventQueue, PluginIoEvent};
+use super::main_thread::ParamState;
 use super::processor::PluginHostProcessor;
+use super::voice_table::VoiceTarget;
+
+/// The maximum number of per-voice events which can be sent to the process
+/// thread in a single process cycle.
+const VOICE_EVENT_QUEUE_CAPACITY: usize = 256;
+
+/// The maximum number of timestamped parameter events (i.e. from a gesture
+/// on a knob in the UI) which can be queued for the process thread.
+pub(super) const PARAM_EVENT_QUEUE_CAPACITY: usize = 1024;
 
 pub(super) struct PlugHostChannelMainThread {
     pub param_queues: Option<ParamQueuesMainThread>,
+    pub voice_event_tx: Option<Producer<MainToProcVoiceEvent>>,
     pub shared_state: Arc<SharedPluginHostState>,
 
     shared_processor: SharedPluginHostProcessor,
@@ -32,6 +45,7 @@ impl PlugHostChannelMainThread {
     pub fn new(bypassed: bool, coll_handle: &basedrop::Handle) -> Self {
         Self {
             param_queues: None,
+            voice_event_tx: None,
             shared_processor: SharedPluginHostProcessor::new(None, coll_handle),
             shared_state: Arc::new(SharedPluginHostState::new(bypassed)),
         }
@@ -43,40 +57,28 @@ impl PlugHostChannelMainThread {
         &mut self,
         plugin_processor: Box<dyn PluginProcessor>,
         plugin_instance_id: u64,
-        num_params: usize,
+        params: ProcParams,
         thread_ids: SharedThreadIDs,
         schedule_version: u64,
         bypass_declick_frames: usize,
         coll_handle: &basedrop::Handle,
     ) {
+        let num_params = params.len();
         let (param_queues_main_thread, param_queues_proc_thread) = if num_params > 0 {
-            let (main_to_proc_param_value_tx, main_to_proc_param_value_rx) =
-                ReducingFnvQueue::new_channel(num_params, coll_handle);
-            let (main_to_proc_param_mod_tx, main_to_proc_param_mod_rx) =
-                ReducingFnvQueue::new_channel(num_params, coll_handle);
-            let (proc_to_main_param_value_tx, proc_to_main_param_value_rx) =
-                ReducingFnvQueue::new_channel(num_params, coll_handle);
-
-            (
-                Some(ParamQueuesMainThread {
-                    to_proc_param_value_tx: main_to_proc_param_value_tx,
-                    to_proc_param_mod_tx: main_to_proc_param_mod_tx,
-                    from_proc_param_value_rx: proc_to_main_param_value_rx,
-                }),
-                Some(ParamQueuesProcThread {
-                    from_main_param_value_rx: main_to_proc_param_value_rx,
-                    from_main_param_mod_rx: main_to_proc_param_mod_rx,
-                    to_main_param_value_tx: proc_to_main_param_value_tx,
-                }),
-            )
+            let (main_thread, proc_thread) = new_param_queues(params, coll_handle);
+            (Some(main_thread), Some(proc_thread))
         } else {
             (None, None)
         };
 
         self.param_queues = param_queues_main_thread;
 
+        let (voice_event_tx, voice_event_rx) = RingBuffer::new(VOICE_EVENT_QUEUE_CAPACITY);
+        self.voice_event_tx = Some(voice_event_tx);
+
         let proc_channel = PlugHostChannelProcThread {
             param_queues: param_queues_proc_thread,
+            voice_event_rx,
             shared_state: Arc::clone(&self.shared_state),
         };
 
@@ -99,6 +101,7 @@ impl PlugHostChannelMainThread {
         coll_handle: &basedrop::Handle,
     ) -> Shared<PluginHostProcessorWrapper> {
         self.param_queues = None;
+        self.voice_event_tx = None;
         self.shared_processor.remove(coll_handle)
     }
 
@@ -109,81 +112,357 @@ impl PlugHostChannelMainThread {
 
 pub(crate) struct PlugHostChannelProcThread {
     pub param_queues: Option<ParamQueuesProcThread>,
+    pub voice_event_rx: Consumer<MainToProcVoiceEvent>,
     pub shared_state: Arc<SharedPluginHostState>,
 }
 
 pub(super) struct ParamQueuesMainThread {
     pub to_proc_param_value_tx: ReducFnvProducer<ParamID, MainToProcParamValue>,
     pub to_proc_param_mod_tx: ReducFnvProducer<ParamID, MainToProcParamValue>,
+    pub to_proc_param_event_tx: Producer<MainToProcParamEvent>,
 
     pub from_proc_param_value_rx: ReducFnvConsumer<ParamID, ProcToMainParamValue>,
+
+    next_seq: u64,
+}
+
+fn new_param_queues(
+    params: ProcParams,
+    coll_handle: &basedrop::Handle,
+) -> (ParamQueuesMainThread, ParamQueuesProcThread) {
+    let num_params = params.len();
+
+    let (main_to_proc_param_value_tx, main_to_proc_param_value_rx) =
+        ReducingFnvQueue::new_channel(num_params, coll_handle);
+    let (main_to_proc_param_mod_tx, main_to_proc_param_mod_rx) =
+        ReducingFnvQueue::new_channel(num_params, coll_handle);
+    let (proc_to_main_param_value_tx, proc_to_main_param_value_rx) =
+        ReducingFnvQueue::new_channel(num_params, coll_handle);
+    let (main_to_proc_param_event_tx, main_to_proc_param_event_rx) =
+        RingBuffer::new(PARAM_EVENT_QUEUE_CAPACITY);
+
+    (
+        ParamQueuesMainThread {
+            to_proc_param_value_tx: main_to_proc_param_value_tx,
+            to_proc_param_mod_tx: main_to_proc_param_mod_tx,
+            to_proc_param_event_tx: main_to_proc_param_event_tx,
+            from_proc_param_value_rx: proc_to_main_param_value_rx,
+            next_seq: 0,
+        },
+        ParamQueuesProcThread {
+            from_main_param_value_rx: main_to_proc_param_value_rx,
+            from_main_param_mod_rx: main_to_proc_param_mod_rx,
+            from_main_param_event_rx: main_to_proc_param_event_rx,
+            to_main_param_value_tx: proc_to_main_param_value_tx,
+            params,
+            untimed_values: Vec::with_capacity(num_params),
+        },
+    )
+}
+
+impl ParamQueuesMainThread {
+    /// Returns the position of the next parameter event in the order in
+    /// which events are sent to the process thread.
+    pub fn next_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
 }
 
 pub(crate) struct ParamQueuesProcThread {
     pub from_main_param_value_rx: ReducFnvConsumer<ParamID, MainToProcParamValue>,
     pub from_main_param_mod_rx: ReducFnvConsumer<ParamID, MainToProcParamValue>,
+    pub from_main_param_event_rx: Consumer<MainToProcParamEvent>,
 
     pub to_main_param_value_tx: ReducFnvProducer<ParamID, ProcToMainParamValue>,
+
+    pub params: ProcParams,
+
+    /// Preallocated storage for the values received without a timestamp.
+    untimed_values: Vec<(ParamID, MainToProcParamValue)>,
 }
 
 impl ParamQueuesProcThread {
-    pub fn consume_into_event_buffer(&mut self, buffer: &mut EventBuffer) -> bool {
+    pub fn consume_into_event_queue(
+        &mut self,
+        input_events: &mut InputEventQueue,
+        transport: &TransportInfo,
+        frames: usize,
+    ) -> bool {
+        let block_start =
+            if transport.is_playing() { Some(transport.playhead_frame()) } else { None };
+
+        self.consume(input_events, block_start, frames)
+    }
+
+    /// `block_start` is the position of the playhead at the start of this
+    /// process block, or `None` if the transport is not playing.
+    fn consume(
+        &mut self,
+        input_events: &mut InputEventQueue,
+        block_start: Option<u64>,
+        frames: usize,
+    ) -> bool {
         let mut has_param_in_event = false;
-        self.from_main_param_value_rx.consume(|param_id, value| {
+
+        let params = &mut self.params;
+        self.from_main_param_mod_rx.consume(|param_id, value| {
+            // The plugin is sent the sum of this amount and the modulation
+            // received through its automation in port.
+            let (cookie, mod_amount) = match params.set_main_mod_amount(*param_id, value.value) {
+                Some(m) => m,
+                None => return,
+            };
             has_param_in_event = true;
 
-            let event = ParamValueEvent::new(
-                // TODO: Finer values for `time` instead of just setting it to the first frame?
-                EventHeader::new_core(0, EventFlags::empty()),
-                value.cookie,
-                // TODO: Note ID
-                -1,                // note_id
-                param_id.as_u32(), // param_id
-                // TODO: Port index
-                -1, // port_index
-                // TODO: Channel
-                -1, // channel
-                // TODO: Key
-                -1,          // key
-                value.value, // value
-            );
-
-            buffer.push(event.as_unknown())
+            // Changes without a timestamp are sent at the start of the block.
+            input_events.push(main_thread_event(
+                0,
+                *param_id,
+                cookie,
+                AutomationIoEventType::Modulation(mod_amount),
+            ));
         });
 
-        self.from_main_param_mod_rx.consume(|param_id, value| {
+        let untimed_values = &mut self.untimed_values;
+        untimed_values.clear();
+        self.from_main_param_value_rx.consume(|param_id, value| {
+            untimed_values.push((*param_id, *value));
+        });
+        untimed_values.sort_unstable_by_key(|(_, value)| value.seq);
+
+        // A value without a timestamp must not be overridden by a timestamped
+        // event which was sent before it, so those events are all sent in this
+        // block, and the value is sent right after the last of them.
+        let last_untimed_seq = untimed_values.last().map(|(_, value)| value.seq);
+        let mut untimed_values = self.untimed_values.iter().peekable();
+
+        // The time of the last event which was written, so that events stay
+        // sorted by time even when the playhead seeked or looped back
+        // between them.
+        let mut last_time = 0;
+
+        while let Ok(event) = self.from_main_param_event_rx.peek() {
+            let event = *event;
+
+            let time = match timestamped_event_time(event.playhead_frame, block_start, frames) {
+                Some(time) => time,
+                None if last_untimed_seq.map(|seq| event.seq < seq).unwrap_or(false) => {
+                    (frames as u32).saturating_sub(1)
+                }
+                // The event belongs in the next process block.
+                None => break,
+            };
+
+            while let Some((param_id, value)) =
+                untimed_values.next_if(|(_, value)| value.seq < event.seq)
+            {
+                input_events.push(main_thread_event(
+                    last_time,
+                    *param_id,
+                    value.cookie,
+                    AutomationIoEventType::Value(value.value),
+                ));
+                has_param_in_event = true;
+            }
+
+            last_time = time.max(last_time);
+
+            input_events.push(main_thread_event(
+                last_time,
+                event.param_id,
+                event.cookie,
+                event.event_type,
+            ));
             has_param_in_event = true;
 
-            let event = ParamModEvent::new(
-                // TODO: Finer values for `time` instead of just setting it to the first frame?
-                EventHeader::new_core(0, EventFlags::empty()),
+            let _ = self.from_main_param_event_rx.pop();
+        }
+
+        for (param_id, value) in untimed_values {
+            input_events.push(main_thread_event(
+                last_time,
+                *param_id,
                 value.cookie,
-                // TODO: Note ID
-                -1,                // note_id
-                param_id.as_u32(), // param_id
-                // TODO: Port index
-                -1, // port_index
-                // TODO: Channel
-                -1, // channel
-                // TODO: Key
-                -1,          // key
-                value.value, // value
-            );
-
-            buffer.push(event.as_unknown())
-        });
+                AutomationIoEventType::Value(value.value),
+            ));
+            has_param_in_event = true;
+        }
+
         has_param_in_event
     }
 }
 
+fn main_thread_event(
+    time: u32,
+    param_id: ParamID,
+    cookie: Cookie,
+    event_type: AutomationIoEventType,
+) -> PluginIoEvent {
+    PluginIoEvent::AutomationEvent {
+        event: AutomationIoEvent {
+            header: IoEventHeader { time },
+            parameter_id: param_id.as_u32(),
+            event_type,
+            plugin_instance_id: 0,
+            cookie: Some(cookie),
+        },
+    }
+}
+
+/// Returns the frame in the process block which corresponds to the position
+/// of the playhead at the time a timestamped event happened, or `None` if the
+/// event belongs in the next process block.
+///
+/// `block_start` is the position of the playhead at the start of the process
+/// block, or `None` if the transport is not playing.
+fn timestamped_event_time(
+    playhead_frame: u64,
+    block_start: Option<u64>,
+    frames: usize,
+) -> Option<u32> {
+    let block_start = match block_start {
+        Some(block_start) => block_start,
+        None => return Some(0),
+    };
+    let block_end = block_start + frames as u64;
+
+    if playhead_frame < block_start {
+        // The event happened before this block (i.e. it was sent late or
+        // the playhead seeked), so send it as soon as possible.
+        Some(0)
+    } else if playhead_frame < block_end {
+        Some((playhead_frame - block_start) as u32)
+    } else if playhead_frame < block_end + frames as u64 {
+        None
+    } else {
+        // The estimate of the playhead was too far ahead (i.e. the playhead
+        // was looped back), so don't hold up the queue.
+        Some((frames as u32).saturating_sub(1))
+    }
+}
+
+/// The parameters of a plugin, as seen by the process thread.
+///
+/// A parameter can be modulated both from the main thread (with
+/// `PluginHostMainThread::set_param_mod_amount()`) and through the plugin's
+/// automation in port (i.e. by a modulator plugin), so this keeps track of
+/// both amounts and the plugin is sent their sum.
+pub(crate) struct ProcParams {
+    params: FnvHashMap<ParamID, ProcParamState>,
+}
+
+#[derive(Clone, Copy)]
+struct ProcParamState {
+    cookie: Cookie,
+    is_modulatable: bool,
+    main_mod_amount: f64,
+    edge_mod_amount: f64,
+}
+
+impl ProcParams {
+    pub fn new(param_states: &FnvHashMap<ParamID, ParamState>) -> Self {
+        Self {
+            params: param_states
+                .iter()
+                .map(|(param_id, state)| {
+                    (
+                        *param_id,
+                        ProcParamState {
+                            cookie: state.info._cookie,
+                            is_modulatable: state
+                                .info
+                                .flags
+                                .contains(ParamInfoFlags::IS_MODULATABLE),
+                            main_mod_amount: state.mod_amount,
+                            edge_mod_amount: state.edge_mod_amount,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// The cookie of the given parameter, or `None` if the plugin has no
+    /// such parameter.
+    pub fn cookie(&self, param_id: ParamID) -> Option<Cookie> {
+        self.params.get(&param_id).map(|p| p.cookie)
+    }
+
+    pub fn is_modulatable(&self, param_id: ParamID) -> bool {
+        self.params.get(&param_id).map(|p| p.is_modulatable).unwrap_or(false)
+    }
+
+    /// Set the modulation amount from the main thread.
+    ///
+    /// This returns the cookie of the parameter and the total modulation
+    /// amount to send to the plugin, or `None` if the plugin has no such
+    /// modulatable parameter.
+    pub fn set_main_mod_amount(&mut self, param_id: ParamID, amount: f64) -> Option<(Cookie, f64)> {
+        let param = self.params.get_mut(&param_id).filter(|p| p.is_modulatable)?;
+        param.main_mod_amount = amount;
+        Some((param.cookie, param.main_mod_amount + param.edge_mod_amount))
+    }
+
+    /// Set the modulation amount received through the automation in port.
+    ///
+    /// This returns the cookie of the parameter and the total modulation
+    /// amount to send to the plugin, or `None` if the plugin has no such
+    /// modulatable parameter.
+    pub fn set_edge_mod_amount(&mut self, param_id: ParamID, amount: f64) -> Option<(Cookie, f64)> {
+        let param = self.params.get_mut(&param_id).filter(|p| p.is_modulatable)?;
+        param.edge_mod_amount = amount;
+        Some((param.cookie, param.main_mod_amount + param.edge_mod_amount))
+    }
+}
+
 #[derive(Clone, Copy)]
 pub(crate) struct MainToProcParamValue {
     pub value: f64,
     pub cookie: Cookie,
+    /// The position of this change in the order in which parameter events
+    /// were sent to the process thread (see `ParamQueuesMainThread::next_seq()`).
+    pub seq: u64,
 }
 
 impl ReducFnvValue for MainToProcParamValue {}
 
+/// A parameter event sent from the main thread, timestamped with the
+/// position of the playhead at the time the event happened.
+///
+/// Unlike `MainToProcParamValue`, these events are never coalesced, so every
+/// value in a gesture reaches the plugin at the frame where it was heard.
+#[derive(Clone, Copy)]
+pub(crate) struct MainToProcParamEvent {
+    pub param_id: ParamID,
+    pub cookie: Cookie,
+    pub playhead_frame: u64,
+    /// The position of this event in the order in which parameter events
+    /// were sent to the process thread (see `ParamQueuesMainThread::next_seq()`).
+    pub seq: u64,
+    /// This is only ever `Value`, `BeginGesture`, or `EndGesture`.
+    pub event_type: AutomationIoEventType,
+}
+
+/// An event sent from the main thread to all active voices of a plugin
+/// which are selected by `target`.
+#[derive(Clone, Copy)]
+pub(crate) struct MainToProcVoiceEvent {
+    pub target: VoiceTarget,
+    pub event_type: MainToProcVoiceEventType,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum MainToProcVoiceEventType {
+    ParamMod { param_id: ParamID, cookie: Cookie, amount: f64 },
+    Expression { expression_type: NoteExpressionType, value: f64 },
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct ParamGestureInfo {
     pub is_begin: bool,
@@ -192,6 +471,9 @@ pub(crate) struct ParamGestureInfo {
 #[derive(Clone, Copy)]
 pub(crate) struct ProcToMainParamValue {
     pub value: Option<f64>,
+    /// The modulation amount received through the plugin's automation in
+    /// port.
+    pub edge_mod_amount: Option<f64>,
     pub gesture: Option<ParamGestureInfo>,
 }
 
@@ -201,6 +483,10 @@ impl ReducFnvValue for ProcToMainParamValue {
             self.value = new_value.value;
         }
 
+        if new_value.edge_mod_amount.is_some() {
+            self.edge_mod_amount = new_value.edge_mod_amount;
+        }
+
         if new_value.gesture.is_some() {
             self.gesture = new_value.gesture;
         }
@@ -210,14 +496,21 @@ impl ReducFnvValue for ProcToMainParamValue {
 impl ProcToMainParamValue {
     pub fn from_param_event(event: AutomationIoEventType) -> Option<Self> {
         match event {
-            AutomationIoEventType::Value(value) => Some(Self { value: Some(value), gesture: None }),
-            AutomationIoEventType::Modulation(_) => None, // TODO: handle mod events
-            AutomationIoEventType::BeginGesture => {
-                Some(Self { value: None, gesture: Some(ParamGestureInfo { is_begin: true }) })
-            }
-            AutomationIoEventType::EndGesture => {
-                Some(Self { value: None, gesture: Some(ParamGestureInfo { is_begin: false }) })
+            AutomationIoEventType::Value(value) => {
+                Some(Self { value: Some(value), edge_mod_amount: None, gesture: None })
             }
+            // Modulation is only ever sent from the host to a plugin.
+            AutomationIoEventType::Modulation(_) => None,
+            AutomationIoEventType::BeginGesture => Some(Self {
+                value: None,
+                edge_mod_amount: None,
+                gesture: Some(ParamGestureInfo { is_begin: true }),
+            }),
+            AutomationIoEventType::EndGesture => Some(Self {
+                value: None,
+                edge_mod_amount: None,
+                gesture: Some(ParamGestureInfo { is_begin: false }),
+            }),
         }
     }
 }
@@ -227,6 +520,13 @@ pub(crate) struct SharedPluginHostState {
     process_requested: AtomicBool,
     param_flush_requested: AtomicBool,
     bypassed: AtomicBool,
+    active_voices: AtomicU32,
+    tail_frames: AtomicU32,
+    skipped_cycles: AtomicU64,
+    discarded_sysex_events: AtomicU64,
+
+    /// The time the plugin's processor takes to process each block.
+    pub process_time: PerfMeter,
 }
 
 impl SharedPluginHostState {
@@ -236,6 +536,11 @@ impl SharedPluginHostState {
             process_requested: AtomicBool::new(false),
             param_flush_requested: AtomicBool::new(false),
             bypassed: AtomicBool::new(bypassed),
+            active_voices: AtomicU32::new(0),
+            tail_frames: AtomicU32::new(0),
+            skipped_cycles: AtomicU64::new(0),
+            discarded_sysex_events: AtomicU64::new(0),
+            process_time: PerfMeter::new(),
         }
     }
 
@@ -271,6 +576,49 @@ impl SharedPluginHostState {
     pub fn set_bypassed(&self, bypassed: bool) {
         self.bypassed.store(bypassed, Ordering::SeqCst);
     }
+
+    pub fn active_voices(&self) -> u32 {
+        self.active_voices.load(Ordering::Relaxed)
+    }
+
+    pub fn set_active_voices(&self, active_voices: u32) {
+        self.active_voices.store(active_voices, Ordering::Relaxed);
+    }
+
+    /// Record that the processor was not processed in this cycle because
+    /// all of its inputs were silent.
+    pub fn record_skipped_cycle(&self) {
+        self.skipped_cycles.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn take_skipped_cycles(&self) -> u64 {
+        self.skipped_cycles.swap(0, Ordering::Relaxed)
+    }
+
+    /// Record that MIDI SysEx events outputted by the plugin were discarded
+    /// because they didn't fit in the note out buffers.
+    pub fn record_discarded_sysex_events(&self, num_events: u32) {
+        self.discarded_sysex_events.fetch_add(u64::from(num_events), Ordering::Relaxed);
+    }
+
+    pub fn take_discarded_sysex_events(&self) -> u64 {
+        self.discarded_sysex_events.swap(0, Ordering::Relaxed)
+    }
+
+    pub fn tail(&self) -> TailLength {
+        match self.tail_frames.load(Ordering::Relaxed) {
+            u32::MAX => TailLength::Infinite,
+            frames => TailLength::Frames(frames),
+        }
+    }
+
+    pub fn set_tail(&self, tail: TailLength) {
+        let frames = match tail {
+            TailLength::Frames(frames) => frames.min(u32::MAX - 1),
+            TailLength::Infinite => u32::MAX,
+        };
+        self.tail_frames.store(frames, Ordering::Relaxed);
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -362,3 +710,216 @@ impl SharedPluginHostProcessor {
         self.shared.get()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+    use meadowlark_plugin_api::ext::params::ParamInfo;
+
+    use super::*;
+
+    fn param_states(params: &[(u32, ParamInfoFlags)]) -> FnvHashMap<ParamID, ParamState> {
+        params
+            .iter()
+            .map(|(id, flags)| {
+                let info = ParamInfo::new(
+                    ParamID::new(*id),
+                    *flags,
+                    String::new(),
+                    String::new(),
+                    0.0,
+                    1.0,
+                    0.0,
+                );
+                let state = ParamState {
+                    info,
+                    value: 0.0,
+                    mod_amount: 0.0,
+                    edge_mod_amount: 0.0,
+                    is_gesturing: false,
+                };
+                (ParamID::new(*id), state)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn main_and_edge_modulation_are_summed() {
+        let mut params = ProcParams::new(&param_states(&[(0, ParamInfoFlags::default_float())]));
+        let id = ParamID::new(0);
+
+        assert_eq!(params.set_main_mod_amount(id, 0.5).map(|(_, m)| m), Some(0.5));
+        assert_eq!(params.set_edge_mod_amount(id, 0.25).map(|(_, m)| m), Some(0.75));
+
+        // Each source only replaces its own amount.
+        assert_eq!(params.set_edge_mod_amount(id, -0.25).map(|(_, m)| m), Some(0.25));
+        assert_eq!(params.set_main_mod_amount(id, 0.0).map(|(_, m)| m), Some(-0.25));
+    }
+
+    #[test]
+    fn modulation_of_unknown_or_unmodulatable_params_is_rejected() {
+        let mut params = ProcParams::new(&param_states(&[
+            (0, ParamInfoFlags::default_float()),
+            (1, ParamInfoFlags::IS_AUTOMATABLE),
+        ]));
+
+        assert!(params.is_modulatable(ParamID::new(0)));
+        assert!(!params.is_modulatable(ParamID::new(1)));
+        assert!(params.set_main_mod_amount(ParamID::new(1), 0.5).is_none());
+        assert!(params.set_edge_mod_amount(ParamID::new(1), 0.5).is_none());
+
+        // Values of unknown parameters are discarded as well.
+        assert!(params.cookie(ParamID::new(1)).is_some());
+        assert!(params.cookie(ParamID::new(2)).is_none());
+        assert!(!params.is_modulatable(ParamID::new(2)));
+        assert!(params.set_edge_mod_amount(ParamID::new(2), 0.5).is_none());
+    }
+
+    #[test]
+    fn initial_amounts_are_taken_from_main_thread() {
+        let mut states = param_states(&[(0, ParamInfoFlags::default_float())]);
+        states.get_mut(&ParamID::new(0)).unwrap().mod_amount = 0.5;
+        let mut params = ProcParams::new(&states);
+
+        assert_eq!(params.set_edge_mod_amount(ParamID::new(0), 0.25).map(|(_, m)| m), Some(0.75));
+    }
+
+    const FRAMES: usize = 256;
+
+    fn param_queues(
+        coll_handle: &basedrop::Handle,
+    ) -> (ParamQueuesMainThread, ParamQueuesProcThread) {
+        let params = ProcParams::new(&param_states(&[(0, ParamInfoFlags::default_float())]));
+        new_param_queues(params, coll_handle)
+    }
+
+    fn set_value(main_thread: &mut ParamQueuesMainThread, value: f64) {
+        let seq = main_thread.next_seq();
+        main_thread
+            .to_proc_param_value_tx
+            .set(ParamID::new(0), MainToProcParamValue { value, cookie: Cookie::empty(), seq });
+        main_thread.to_proc_param_value_tx.producer_done();
+    }
+
+    fn set_value_at_frame(
+        main_thread: &mut ParamQueuesMainThread,
+        value: f64,
+        playhead_frame: u64,
+    ) {
+        let seq = main_thread.next_seq();
+        main_thread
+            .to_proc_param_event_tx
+            .push(MainToProcParamEvent {
+                param_id: ParamID::new(0),
+                cookie: Cookie::empty(),
+                playhead_frame,
+                seq,
+                event_type: AutomationIoEventType::Value(value),
+            })
+            .unwrap();
+    }
+
+    /// Returns the `(time, value)` of each value event sent to the plugin.
+    fn consume_values(
+        proc_thread: &mut ParamQueuesProcThread,
+        block_start: Option<u64>,
+    ) -> Vec<(u32, f64)> {
+        let mut input_events = InputEventQueue::with_capacity(16);
+        proc_thread.consume(&mut input_events, block_start, FRAMES);
+
+        input_events
+            .take_sorted()
+            .into_iter()
+            .filter_map(|event| match event {
+                PluginIoEvent::AutomationEvent {
+                    event:
+                        AutomationIoEvent {
+                            header, event_type: AutomationIoEventType::Value(v), ..
+                        },
+                } => Some((header.time, v)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn timestamped_events_are_placed_at_their_frame() {
+        // Not playing.
+        assert_eq!(timestamped_event_time(5_000, None, FRAMES), Some(0));
+
+        // Inside the block.
+        assert_eq!(timestamped_event_time(1_000, Some(1_000), FRAMES), Some(0));
+        assert_eq!(timestamped_event_time(1_100, Some(1_000), FRAMES), Some(100));
+
+        // Sent late.
+        assert_eq!(timestamped_event_time(900, Some(1_000), FRAMES), Some(0));
+
+        // In the next block.
+        assert_eq!(timestamped_event_time(1_256, Some(1_000), FRAMES), None);
+        assert_eq!(timestamped_event_time(1_511, Some(1_000), FRAMES), None);
+
+        // Too far ahead, so it goes at the end of this block.
+        assert_eq!(timestamped_event_time(1_512, Some(1_000), FRAMES), Some(255));
+    }
+
+    #[test]
+    fn timestamped_events_wait_for_their_block() {
+        let collector = Collector::new();
+        let (mut main_thread, mut proc_thread) = param_queues(&collector.handle());
+
+        set_value_at_frame(&mut main_thread, 0.25, 1_100);
+        set_value_at_frame(&mut main_thread, 0.5, 1_300);
+
+        assert_eq!(consume_values(&mut proc_thread, Some(1_000)), vec![(100, 0.25)]);
+        assert_eq!(consume_values(&mut proc_thread, Some(1_256)), vec![(44, 0.5)]);
+    }
+
+    #[test]
+    fn timestamped_events_stay_sorted_when_the_playhead_moves_back() {
+        let collector = Collector::new();
+        let (mut main_thread, mut proc_thread) = param_queues(&collector.handle());
+
+        set_value_at_frame(&mut main_thread, 0.25, 1_100);
+        set_value_at_frame(&mut main_thread, 0.5, 1_050);
+
+        assert_eq!(consume_values(&mut proc_thread, Some(1_000)), vec![(100, 0.25), (100, 0.5)]);
+    }
+
+    #[test]
+    fn untimed_value_is_sent_after_earlier_timestamped_events() {
+        let collector = Collector::new();
+        let (mut main_thread, mut proc_thread) = param_queues(&collector.handle());
+
+        set_value_at_frame(&mut main_thread, 0.25, 1_100);
+        set_value(&mut main_thread, 0.75);
+
+        assert_eq!(consume_values(&mut proc_thread, Some(1_000)), vec![(100, 0.25), (100, 0.75)]);
+    }
+
+    #[test]
+    fn untimed_value_flushes_earlier_timestamped_events_of_later_blocks() {
+        let collector = Collector::new();
+        let (mut main_thread, mut proc_thread) = param_queues(&collector.handle());
+
+        // The first event belongs in the next block, but the untimed value
+        // must still win over it.
+        set_value_at_frame(&mut main_thread, 0.25, 1_300);
+        set_value(&mut main_thread, 0.75);
+
+        assert_eq!(consume_values(&mut proc_thread, Some(1_000)), vec![(255, 0.25), (255, 0.75)]);
+        assert!(consume_values(&mut proc_thread, Some(1_256)).is_empty());
+    }
+
+    #[test]
+    fn timestamped_events_sent_after_untimed_value_are_kept() {
+        let collector = Collector::new();
+        let (mut main_thread, mut proc_thread) = param_queues(&collector.handle());
+
+        set_value(&mut main_thread, 0.75);
+        set_value_at_frame(&mut main_thread, 0.25, 1_100);
+        set_value_at_frame(&mut main_thread, 0.5, 1_300);
+
+        assert_eq!(consume_values(&mut proc_thread, Some(1_000)), vec![(0, 0.75), (100, 0.25)]);
+        assert_eq!(consume_values(&mut proc_thread, Some(1_256)), vec![(44, 0.5)]);
+    }
+}
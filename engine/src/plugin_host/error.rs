@@ -129,6 +129,43 @@ impl std::fmt::Display for SetParamValueError {
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum VoiceEventError {
+    ParamDoesNotExist(ParamID),
+    ParamIsNotModulatablePerNote(ParamID),
+    PluginNotActive,
+    QueueFull,
+}
+
+impl Error for VoiceEventError {}
+
+impl std::fmt::Display for VoiceEventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoiceEventError::ParamDoesNotExist(id) => {
+                write!(
+                    f,
+                    "failed to send event to plugin voices: parameter with id {:?} does not exist",
+                    id
+                )
+            }
+            VoiceEventError::ParamIsNotModulatablePerNote(id) => {
+                write!(
+                    f,
+                    "failed to send event to plugin voices: parameter with id {:?} is not marked as modulatable per note ID",
+                    id
+                )
+            }
+            VoiceEventError::PluginNotActive => {
+                write!(f, "failed to send event to plugin voices: plugin is not active")
+            }
+            VoiceEventError::QueueFull => {
+                write!(f, "failed to send event to plugin voices: too many events were sent in this process cycle")
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ShowGuiError {
     HostError(GuiError),
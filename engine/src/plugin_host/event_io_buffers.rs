@@ -1,27 +1,39 @@
+use clack_extensions::note_ports::NoteDialects;
 use clack_host::events::event_types::NoteEvent as ClackNoteEvent;
 use clack_host::events::event_types::*;
 use clack_host::events::io::EventBuffer;
 use clack_host::events::spaces::CoreEventSpace;
 use clack_host::events::{Event, EventHeader as ClackEventHeader, UnknownEvent};
+use clack_host::utils::Cookie;
 use smallvec::SmallVec;
 
 use meadowlark_plugin_api::automation::{AutomationIoEvent, AutomationIoEventType, IoEventHeader};
 use meadowlark_plugin_api::buffer::SharedBuffer;
 use meadowlark_plugin_api::ParamID;
 
+mod input_queue;
+mod midi;
 mod sanitizer;
 
+pub(crate) use input_queue::InputEventQueue;
+pub use midi::{MidiSysExRef, NOTE_BUFFER_SYSEX_CAPACITY};
 pub(crate) use sanitizer::PluginEventOutputSanitizer;
 
 use crate::utils::reducing_queue::ReducFnvProducerRefMut;
 
-use super::channel::ProcToMainParamValue;
+use super::channel::{ProcParams, ProcToMainParamValue};
+use super::voice_table::VoiceTable;
 
 // TODO: remove pubs
 pub(crate) struct PluginEventIoBuffers {
     pub note_in_buffers: SmallVec<[SharedBuffer<NoteIoEvent>; 2]>,
     pub note_out_buffers: SmallVec<[SharedBuffer<NoteIoEvent>; 2]>,
 
+    /// The note dialects supported by each of the plugin's note input ports.
+    /// Events are translated into one of these dialects before they are sent
+    /// to the plugin.
+    pub note_in_dialects: SmallVec<[NoteDialects; 2]>,
+
     pub clear_note_in_buffers: SmallVec<[SharedBuffer<NoteIoEvent>; 2]>,
 
     pub automation_in_buffer: Option<(SharedBuffer<AutomationIoEvent>, bool)>,
@@ -47,28 +59,66 @@ impl PluginEventIoBuffers {
         }
     }
 
+    /// Push the events from the note in ports and the automation in port
+    /// onto `input_events`.
     pub fn write_input_events(
         &self,
-        raw_event_buffer: &mut EventBuffer,
+        input_events: &mut InputEventQueue,
         plugin_instance_id: u64,
+        voices: &mut VoiceTable,
+        params: Option<&mut ProcParams>,
     ) -> (bool, bool) {
-        let wrote_note_event = self.write_input_note_events(raw_event_buffer);
-        let wrote_param_event =
-            self.write_input_automation_events(raw_event_buffer, plugin_instance_id);
+        let wrote_note_event = self.write_input_note_events(input_events, voices);
+        let wrote_param_event = match params {
+            Some(params) => {
+                self.write_input_automation_events(input_events, plugin_instance_id, params)
+            }
+            None => false,
+        };
 
         (wrote_note_event, wrote_param_event)
     }
 
-    fn write_input_note_events(&self, raw_event_buffer: &mut EventBuffer) -> bool {
+    fn write_input_note_events(
+        &self,
+        input_events: &mut InputEventQueue,
+        voices: &mut VoiceTable,
+    ) -> bool {
         let mut wrote_note_event = false;
 
         for (note_port_index, buffer) in self.note_in_buffers.iter().enumerate() {
+            let dialects = self
+                .note_in_dialects
+                .get(note_port_index)
+                .copied()
+                .unwrap_or(NoteDialects::empty());
+
             for event in buffer.borrow().data.iter() {
-                let event = PluginIoEvent::NoteEvent {
-                    note_port_index: note_port_index as i16,
-                    event: *event,
+                let mut event = match midi::translate_note_event(event, dialects) {
+                    Some(event) => event,
+                    None => continue,
                 };
-                event.write_to_clap_buffer(raw_event_buffer);
+
+                match event.event_type {
+                    NoteIoEventType::On { .. } => {
+                        let note_id = voices.note_on(
+                            note_port_index as i16,
+                            event.channel,
+                            event.key,
+                            event.note_id,
+                        );
+                        event.to_mut().note_id = note_id;
+                    }
+                    NoteIoEventType::End => continue,
+                    _ => {}
+                }
+
+                // The data of a SysEx event stays in the note buffer, which
+                // the queue reads it from when the event is written.
+                input_events.push(PluginIoEvent::NoteEvent {
+                    note_port_index: note_port_index as i16,
+                    event: event.into_owned(),
+                });
                 wrote_note_event = true;
             }
         }
@@ -76,26 +126,83 @@ impl PluginEventIoBuffers {
         wrote_note_event
     }
 
+    /// Events which target a parameter the plugin doesn't have (or modulate
+    /// a parameter which isn't modulatable) are discarded.
     fn write_input_automation_events(
         &self,
-        raw_event_buffer: &mut EventBuffer,
+        input_events: &mut InputEventQueue,
         plugin_instance_id: u64,
+        params: &mut ProcParams,
     ) -> bool {
         let mut wrote_event = false;
 
         if let Some((in_buf, _)) = &self.automation_in_buffer {
             for event in in_buf.borrow().data.iter() {
-                if event.plugin_instance_id == plugin_instance_id {
-                    let event = PluginIoEvent::AutomationEvent { event: *event };
-                    event.write_to_clap_buffer(raw_event_buffer);
-                    wrote_event = true;
+                if event.plugin_instance_id != plugin_instance_id {
+                    continue;
+                }
+
+                let param_id = ParamID::new(event.parameter_id);
+                let mut event = *event;
+                if let AutomationIoEventType::Modulation(amount) = event.event_type {
+                    // The plugin is sent the sum of this amount and the
+                    // modulation set from the main thread.
+                    match params.set_edge_mod_amount(param_id, amount) {
+                        Some((cookie, mod_amount)) => {
+                            event.event_type = AutomationIoEventType::Modulation(mod_amount);
+                            event.cookie = Some(cookie);
+                        }
+                        None => continue,
+                    }
+                } else {
+                    match params.cookie(param_id) {
+                        Some(cookie) => event.cookie = Some(cookie),
+                        None => continue,
+                    }
                 }
+
+                input_events.push(PluginIoEvent::AutomationEvent { event });
+                wrote_event = true;
             }
         }
 
         wrote_event
     }
 
+    /// Report the modulation amounts received through the automation in port
+    /// (i.e. from an internal modulator plugin) to the main thread, so that
+    /// the current modulation of each parameter can be displayed.
+    pub fn report_input_modulation(
+        &self,
+        plugin_instance_id: u64,
+        params: &ProcParams,
+        external_parameter_queue: &mut ReducFnvProducerRefMut<ParamID, ProcToMainParamValue>,
+    ) {
+        if let Some((in_buf, _)) = &self.automation_in_buffer {
+            for event in in_buf.borrow().data.iter() {
+                let param_id = ParamID::new(event.parameter_id);
+                if event.plugin_instance_id != plugin_instance_id
+                    || !params.is_modulatable(param_id)
+                {
+                    continue;
+                }
+
+                if let AutomationIoEventType::Modulation(mod_amount) = event.event_type {
+                    external_parameter_queue.set_or_update(
+                        param_id,
+                        ProcToMainParamValue {
+                            value: None,
+                            edge_mod_amount: Some(mod_amount),
+                            gesture: None,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// This returns the number of events which were discarded because they
+    /// didn't fit in the note out buffers.
     pub fn read_output_events(
         &mut self,
         raw_event_buffer: &EventBuffer,
@@ -104,14 +211,39 @@ impl PluginEventIoBuffers {
         >,
         sanitizer: &mut PluginEventOutputSanitizer,
         frames: u32,
-    ) {
-        let events_iter = raw_event_buffer.iter().filter_map(PluginIoEvent::read_from_clap);
+        voices: &mut VoiceTable,
+    ) -> u32 {
+        let mut num_discarded_events = 0;
+
+        let note_out_buffers = &self.note_out_buffers;
+        let events_iter = raw_event_buffer.iter().filter_map(|clap_event| {
+            // The data of a SysEx message is copied into the byte arena of the
+            // note buffer the event is sent to.
+            if let Some(CoreEventSpace::MidiSysEx(e)) = clap_event.as_core_event() {
+                let buffer = note_out_buffers.get(usize::from(e.port_index()))?;
+                let sysex = MidiSysExRef::push(&mut buffer.borrow_mut().bytes, e.data());
+                if sysex.is_none() {
+                    num_discarded_events += 1;
+                }
+
+                return Some(PluginIoEvent::NoteEvent {
+                    note_port_index: e.port_index() as i16,
+                    event: NoteIoEvent::midi(e.header().time(), NoteIoEventType::MidiSysEx(sysex?)),
+                });
+            }
+
+            PluginIoEvent::read_from_clap(clap_event)
+        });
         let events_iter = sanitizer.sanitize(events_iter, Some(frames));
 
         for event in events_iter {
             match event {
                 PluginIoEvent::NoteEvent { note_port_index, event } => {
-                    if let Some(b) = self.note_out_buffers.get(note_port_index as usize) {
+                    if let NoteIoEventType::End = event.event_type {
+                        voices.note_end(note_port_index, event.channel, event.key, event.note_id);
+                    }
+
+                    if let Some(b) = note_out_buffers.get(note_port_index as usize) {
                         b.borrow_mut().data.push(event)
                     }
                 }
@@ -140,10 +272,10 @@ impl PluginEventIoBuffers {
         // TODO: More note through ports when bypassed?
         if self.main_note_through_when_bypassed {
             let in_buf = self.note_in_buffers[0].borrow();
-            let mut out_buf = self.note_out_buffers[0].borrow_mut();
+            let out_buf = &mut *self.note_out_buffers[0].borrow_mut();
 
             for event in in_buf.data.iter() {
-                out_buf.data.push(*event);
+                event.copy_to(&in_buf.bytes, &mut out_buf.data, &mut out_buf.bytes);
             }
         }
     }
@@ -155,15 +287,158 @@ pub struct NoteIoEvent {
     pub header: IoEventHeader,
     pub channel: i16,
     pub key: i16,
+    /// The ID of the voice this event belongs to, or `-1` if it has none.
+    pub note_id: i32,
     pub event_type: NoteIoEventType,
 }
 
 #[derive(Copy, Clone)]
 pub enum NoteIoEventType {
-    On { velocity: f64 },
-    Expression { expression_type: NoteExpressionType, value: f64 },
+    On {
+        velocity: f64,
+    },
+    Expression {
+        expression_type: NoteExpressionType,
+        value: f64,
+    },
     Choke,
-    Off { velocity: f64 },
+    Off {
+        velocity: f64,
+    },
+    /// Sent by a plugin once it has finished playing a voice.
+    End,
+
+    /// A raw MIDI 1.0 message. The `channel` and `key` fields of the event
+    /// are set to `-1`.
+    Midi {
+        data: [u8; 3],
+    },
+    /// A MIDI 1.0 SysEx message. The `channel` and `key` fields of the event
+    /// are set to `-1`.
+    ///
+    /// The data of the message is stored in the byte arena of the note
+    /// buffer which contains this event.
+    MidiSysEx(MidiSysExRef),
+    /// A MIDI 2.0 Universal MIDI Packet. The `channel` and `key` fields of
+    /// the event are set to `-1`.
+    Midi2 {
+        data: [u32; 4],
+    },
+}
+
+impl NoteIoEvent {
+    fn midi(time: u32, event_type: NoteIoEventType) -> Self {
+        Self { header: IoEventHeader { time }, channel: -1, key: -1, note_id: -1, event_type }
+    }
+
+    /// Push this event onto the end of `events`, copying the data of a SysEx
+    /// event from `src_bytes` into `dst_bytes`.
+    ///
+    /// If `dst_bytes` does not have enough capacity left for the data, then
+    /// the event is discarded and `false` is returned.
+    pub fn copy_to(
+        &self,
+        src_bytes: &[u8],
+        events: &mut Vec<NoteIoEvent>,
+        dst_bytes: &mut Vec<u8>,
+    ) -> bool {
+        let mut event = *self;
+
+        if let NoteIoEventType::MidiSysEx(sysex) = self.event_type {
+            match MidiSysExRef::push(dst_bytes, sysex.get(src_bytes)) {
+                Some(sysex) => event.event_type = NoteIoEventType::MidiSysEx(sysex),
+                None => return false,
+            }
+        }
+
+        events.push(event);
+        true
+    }
+
+    /// `sysex_bytes` is the byte arena of the note buffer which contains this
+    /// event.
+    ///
+    /// Note that the buffer only stores a pointer to the data of a SysEx
+    /// event, so `sysex_bytes` must outlive any use of `buffer`.
+    pub fn write_to_clap_buffer(
+        &self,
+        note_port_index: i16,
+        sysex_bytes: &[u8],
+        buffer: &mut EventBuffer,
+    ) {
+        let NoteIoEvent { event_type, key, channel, note_id, header: IoEventHeader { time } } =
+            self;
+
+        match event_type {
+            NoteIoEventType::On { velocity } => buffer.push(
+                NoteOnEvent(ClackNoteEvent::new(
+                    ClackEventHeader::new(*time),
+                    *note_id,
+                    note_port_index,
+                    *key,
+                    *channel,
+                    *velocity,
+                ))
+                .as_unknown(),
+            ),
+            NoteIoEventType::Expression { expression_type, value } => buffer.push(
+                NoteExpressionEvent::new(
+                    ClackEventHeader::new(*time),
+                    *note_id,
+                    note_port_index,
+                    *key,
+                    *channel,
+                    *value,
+                    *expression_type,
+                )
+                .as_unknown(),
+            ),
+
+            NoteIoEventType::Choke => buffer.push(
+                NoteChokeEvent(ClackNoteEvent::new(
+                    ClackEventHeader::new(*time),
+                    *note_id,
+                    note_port_index,
+                    *key,
+                    *channel,
+                    0.0,
+                ))
+                .as_unknown(),
+            ),
+
+            NoteIoEventType::Off { velocity } => buffer.push(
+                NoteOffEvent(ClackNoteEvent::new(
+                    ClackEventHeader::new(*time),
+                    *note_id,
+                    note_port_index,
+                    *key,
+                    *channel,
+                    *velocity,
+                ))
+                .as_unknown(),
+            ),
+
+            // Note end events are only ever sent from a plugin to the host.
+            NoteIoEventType::End => {}
+
+            NoteIoEventType::Midi { data } => buffer.push(
+                MidiEvent::new(ClackEventHeader::new(*time), note_port_index as u16, *data)
+                    .as_unknown(),
+            ),
+            NoteIoEventType::MidiSysEx(sysex) => buffer.push(
+                MidiSysExEvent::new(
+                    ClackEventHeader::new(*time),
+                    note_port_index as u16,
+                    sysex.get(sysex_bytes),
+                )
+                .as_unknown(),
+            ),
+            NoteIoEventType::Midi2 { data } => buffer.push(
+                Midi2Event::new(ClackEventHeader::new(*time), note_port_index as u16, *data)
+                    .as_unknown(),
+            ),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -173,12 +448,20 @@ pub enum PluginIoEvent {
 }
 
 impl PluginIoEvent {
+    pub fn time(&self) -> u32 {
+        match self {
+            PluginIoEvent::NoteEvent { event, .. } => event.header.time,
+            PluginIoEvent::AutomationEvent { event } => event.header.time,
+        }
+    }
+
     pub fn read_from_clap(clap_event: &UnknownEvent) -> Option<Self> {
         match clap_event.as_core_event()? {
             CoreEventSpace::NoteOn(NoteOnEvent(e)) => Some(PluginIoEvent::NoteEvent {
                 note_port_index: e.port_index(),
                 event: NoteIoEvent {
                     channel: e.channel(),
+                    note_id: e.note_id(),
                     key: e.key(),
                     header: IoEventHeader { time: e.header().time() },
                     event_type: NoteIoEventType::On { velocity: e.velocity() },
@@ -188,6 +471,7 @@ impl PluginIoEvent {
                 note_port_index: e.port_index(),
                 event: NoteIoEvent {
                     channel: e.channel(),
+                    note_id: e.note_id(),
                     key: e.key(),
                     header: IoEventHeader { time: e.header().time() },
                     event_type: NoteIoEventType::Off { velocity: e.velocity() },
@@ -197,15 +481,27 @@ impl PluginIoEvent {
                 note_port_index: e.port_index(),
                 event: NoteIoEvent {
                     channel: e.channel(),
+                    note_id: e.note_id(),
                     key: e.key(),
                     header: IoEventHeader { time: e.header().time() },
                     event_type: NoteIoEventType::Choke,
                 },
             }),
+            CoreEventSpace::NoteEnd(NoteEndEvent(e)) => Some(PluginIoEvent::NoteEvent {
+                note_port_index: e.port_index(),
+                event: NoteIoEvent {
+                    channel: e.channel(),
+                    key: e.key(),
+                    note_id: e.note_id(),
+                    header: IoEventHeader { time: e.header().time() },
+                    event_type: NoteIoEventType::End,
+                },
+            }),
             CoreEventSpace::NoteExpression(e) => Some(PluginIoEvent::NoteEvent {
                 note_port_index: e.port_index(),
                 event: NoteIoEvent {
                     channel: e.channel(),
+                    note_id: e.note_id(),
                     key: e.key(),
                     header: IoEventHeader { time: e.header().time() },
                     event_type: NoteIoEventType::Expression {
@@ -252,7 +548,24 @@ impl PluginIoEvent {
                 },
             }),
 
-            // TODO: handle MIDI events & note end events
+            CoreEventSpace::Midi(e) => Some(PluginIoEvent::NoteEvent {
+                note_port_index: e.port_index() as i16,
+                event: NoteIoEvent::midi(
+                    e.header().time(),
+                    NoteIoEventType::Midi { data: e.data() },
+                ),
+            }),
+            // The data of a SysEx message has to be stored in a note buffer,
+            // so these are read in `PluginEventIoBuffers::read_output_events`.
+            CoreEventSpace::MidiSysEx(_) => None,
+            CoreEventSpace::Midi2(e) => Some(PluginIoEvent::NoteEvent {
+                note_port_index: e.port_index() as i16,
+                event: NoteIoEvent::midi(
+                    e.header().time(),
+                    NoteIoEventType::Midi2 { data: e.data() },
+                ),
+            }),
+
             CoreEventSpace::Transport(_) => {
                 log::warn!("Plugin outputted a `CLAP_EVENT_TRANSPORT` event. Event was discarded.");
                 None
@@ -264,117 +577,56 @@ impl PluginIoEvent {
 
     pub fn write_to_clap_buffer(&self, buffer: &mut EventBuffer) {
         match self {
-            PluginIoEvent::NoteEvent {
-                note_port_index,
-                event: NoteIoEvent { event_type, key, channel, header: IoEventHeader { time } },
+            // SysEx events must be written with `NoteIoEvent::write_to_clap_buffer`
+            // along with the byte arena of their note buffer.
+            PluginIoEvent::NoteEvent { note_port_index, event } => {
+                event.write_to_clap_buffer(*note_port_index, &[], buffer)
+            }
+            PluginIoEvent::AutomationEvent {
+                event:
+                    AutomationIoEvent {
+                        header: IoEventHeader { time },
+                        parameter_id,
+                        event_type,
+                        plugin_instance_id: _,
+                        cookie,
+                    },
             } => match event_type {
-                NoteIoEventType::On { velocity } => buffer.push(
-                    NoteOnEvent(ClackNoteEvent::new(
+                AutomationIoEventType::Value(value) => buffer.push(
+                    ParamValueEvent::new(
                         ClackEventHeader::new(*time),
+                        cookie.unwrap_or_else(Cookie::empty),
+                        -1,
+                        *parameter_id,
+                        -1,
                         -1,
-                        *note_port_index,
-                        *key,
-                        *channel,
-                        *velocity,
-                    ))
-                    .as_unknown(),
-                ),
-                NoteIoEventType::Expression { expression_type, value } => buffer.push(
-                    NoteExpressionEvent::new(
-                        ClackEventHeader::new(*time),
                         -1,
-                        *note_port_index,
-                        *key,
-                        *channel,
                         *value,
-                        *expression_type,
                     )
                     .as_unknown(),
                 ),
-
-                NoteIoEventType::Choke => buffer.push(
-                    NoteChokeEvent(ClackNoteEvent::new(
+                AutomationIoEventType::Modulation(modulation_amount) => buffer.push(
+                    ParamModEvent::new(
                         ClackEventHeader::new(*time),
+                        cookie.unwrap_or_else(Cookie::empty),
                         -1,
-                        *note_port_index,
-                        *key,
-                        *channel,
-                        0.0,
-                    ))
-                    .as_unknown(),
-                ),
-
-                NoteIoEventType::Off { velocity } => buffer.push(
-                    NoteOffEvent(ClackNoteEvent::new(
-                        ClackEventHeader::new(*time),
+                        *parameter_id,
                         -1,
-                        *note_port_index,
-                        *key,
-                        *channel,
-                        *velocity,
-                    ))
+                        -1,
+                        -1,
+                        *modulation_amount,
+                    )
                     .as_unknown(),
                 ),
+                AutomationIoEventType::BeginGesture => buffer.push(
+                    ParamGestureBeginEvent::new(ClackEventHeader::new(*time), *parameter_id)
+                        .as_unknown(),
+                ),
+                AutomationIoEventType::EndGesture => buffer.push(
+                    ParamGestureEndEvent::new(ClackEventHeader::new(*time), *parameter_id)
+                        .as_unknown(),
+                ),
             },
-            PluginIoEvent::AutomationEvent {
-                event:
-                    AutomationIoEvent {
-                        header: IoEventHeader { time },
-                        parameter_id,
-                        event_type,
-                        plugin_instance_id: _,
-                        cookie,
-                    },
-            } => {
-                match event_type {
-                    AutomationIoEventType::Value(value) => {
-                        if let Some(cookie) = cookie {
-                            buffer.push(
-                                ParamValueEvent::new(
-                                    ClackEventHeader::new(*time),
-                                    *cookie,
-                                    -1,
-                                    *parameter_id,
-                                    -1,
-                                    -1,
-                                    -1,
-                                    *value,
-                                )
-                                .as_unknown(),
-                            )
-                        } else {
-                            log::error!("Could not write automation event to CLAP buffer: event had no cookie");
-                        }
-                    }
-                    AutomationIoEventType::Modulation(modulation_amount) => {
-                        if let Some(cookie) = cookie {
-                            buffer.push(
-                                ParamModEvent::new(
-                                    ClackEventHeader::new(*time),
-                                    *cookie,
-                                    -1,
-                                    *parameter_id,
-                                    -1,
-                                    -1,
-                                    -1,
-                                    *modulation_amount,
-                                )
-                                .as_unknown(),
-                            )
-                        } else {
-                            log::error!("Could not write automation event to CLAP buffer: event had no cookie");
-                        }
-                    }
-                    AutomationIoEventType::BeginGesture => buffer.push(
-                        ParamGestureBeginEvent::new(ClackEventHeader::new(*time), *parameter_id)
-                            .as_unknown(),
-                    ),
-                    AutomationIoEventType::EndGesture => buffer.push(
-                        ParamGestureEndEvent::new(ClackEventHeader::new(*time), *parameter_id)
-                            .as_unknown(),
-                    ),
-                }
-            }
         }
     }
 }
@@ -0,0 +1,127 @@
+use clack_host::events::io::EventBuffer;
+use meadowlark_plugin_api::buffer::SharedBuffer;
+
+use super::{NoteIoEvent, PluginIoEvent};
+
+/// The input events of a plugin from all of its sources (the main thread,
+/// the note in ports, and the automation in port).
+///
+/// Each source is only sorted by time on its own, but the plugin must
+/// receive all of its events sorted by time, so events are collected here
+/// and sorted before they are written to the plugin's event buffer.
+pub(crate) struct InputEventQueue {
+    events: Vec<QueuedInputEvent>,
+}
+
+#[derive(Copy, Clone)]
+struct QueuedInputEvent {
+    /// The order in which this event was pushed. Events at the same time
+    /// are sent in the order they were pushed.
+    order: u32,
+    event: PluginIoEvent,
+}
+
+impl InputEventQueue {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { events: Vec::with_capacity(capacity) }
+    }
+
+    pub fn push(&mut self, event: PluginIoEvent) {
+        let order = self.events.len() as u32;
+        self.events.push(QueuedInputEvent { order, event });
+    }
+
+    /// Sort the queued events by time and write them to the end of `buffer`,
+    /// leaving this queue empty.
+    ///
+    /// `note_in_buffers` are the note in buffers the note events were read
+    /// from (which hold the data of any SysEx events).
+    pub fn write_to_clap_buffer(
+        &mut self,
+        note_in_buffers: &[SharedBuffer<NoteIoEvent>],
+        buffer: &mut EventBuffer,
+    ) {
+        self.sort();
+
+        for queued in self.events.iter() {
+            match &queued.event {
+                PluginIoEvent::NoteEvent { note_port_index, event } => {
+                    let note_buffer = note_in_buffers[*note_port_index as usize].borrow();
+                    event.write_to_clap_buffer(*note_port_index, &note_buffer.bytes, buffer);
+                }
+                event => event.write_to_clap_buffer(buffer),
+            }
+        }
+
+        self.events.clear();
+    }
+
+    fn sort(&mut self) {
+        // An unstable sort is used since it doesn't allocate. The order of
+        // events at the same time is kept by sorting on `order` as well.
+        self.events.sort_unstable_by_key(|queued| (queued.event.time(), queued.order));
+    }
+
+    #[cfg(test)]
+    pub fn take_sorted(&mut self) -> Vec<PluginIoEvent> {
+        self.sort();
+        self.events.drain(..).map(|queued| queued.event).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use meadowlark_plugin_api::automation::{
+        AutomationIoEvent, AutomationIoEventType, IoEventHeader,
+    };
+
+    use super::*;
+
+    fn automation_event(time: u32, parameter_id: u32) -> PluginIoEvent {
+        PluginIoEvent::AutomationEvent {
+            event: AutomationIoEvent {
+                header: IoEventHeader { time },
+                parameter_id,
+                event_type: AutomationIoEventType::Value(0.0),
+                plugin_instance_id: 0,
+                cookie: None,
+            },
+        }
+    }
+
+    fn sorted_ids(queue: &mut InputEventQueue) -> Vec<(u32, u32)> {
+        queue
+            .take_sorted()
+            .into_iter()
+            .map(|event| match event {
+                PluginIoEvent::AutomationEvent { event } => (event.header.time, event.parameter_id),
+                PluginIoEvent::NoteEvent { .. } => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn events_are_sorted_by_time() {
+        let mut queue = InputEventQueue::with_capacity(8);
+
+        // Events from two sources which are each sorted on their own.
+        queue.push(automation_event(0, 0));
+        queue.push(automation_event(64, 1));
+        queue.push(automation_event(128, 2));
+        queue.push(automation_event(32, 3));
+        queue.push(automation_event(96, 4));
+
+        assert_eq!(sorted_ids(&mut queue), vec![(0, 0), (32, 3), (64, 1), (96, 4), (128, 2)]);
+    }
+
+    #[test]
+    fn events_at_the_same_time_keep_their_order() {
+        let mut queue = InputEventQueue::with_capacity(8);
+
+        for parameter_id in 0..6 {
+            queue.push(automation_event(if parameter_id % 2 == 0 { 10 } else { 0 }, parameter_id));
+        }
+
+        assert_eq!(sorted_ids(&mut queue), vec![(0, 1), (0, 3), (0, 5), (10, 0), (10, 2), (10, 4)]);
+    }
+}
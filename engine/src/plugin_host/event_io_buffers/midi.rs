@@ -0,0 +1,392 @@
+use clack_extensions::note_ports::NoteDialects;
+use clack_host::events::event_types::NoteExpressionType;
+use std::borrow::Cow;
+
+use super::{NoteIoEvent, NoteIoEventType};
+
+/// The number of bytes of MIDI SysEx messages which can be stored in a
+/// single note buffer per process cycle. Messages which don't fit are
+/// discarded.
+pub const NOTE_BUFFER_SYSEX_CAPACITY: usize = 4096;
+
+/// A MIDI SysEx message, stored out of line in the byte arena of the buffer
+/// the event is in (`BufferInner::bytes`), so that note events stay small and
+/// can be copied around in the realtime thread without allocating.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MidiSysExRef {
+    offset: u32,
+    len: u32,
+}
+
+impl MidiSysExRef {
+    /// Copy the message into the byte arena.
+    ///
+    /// This returns `None` (and leaves the arena untouched) if the arena
+    /// doesn't have enough capacity left for the message, so this never
+    /// allocates.
+    pub fn push(bytes: &mut Vec<u8>, message: &[u8]) -> Option<Self> {
+        if bytes.capacity() - bytes.len() < message.len() {
+            return None;
+        }
+
+        let offset = bytes.len() as u32;
+        bytes.extend_from_slice(message);
+
+        Some(Self { offset, len: message.len() as u32 })
+    }
+
+    /// The message in the byte arena this was pushed to.
+    pub fn get<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        let start = self.offset as usize;
+        bytes.get(start..start + self.len as usize).unwrap_or(&[])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    Clap,
+    Midi,
+    Midi2,
+}
+
+/// Translate a note event into a dialect supported by the note port it is
+/// being sent to.
+///
+/// This returns `None` if the event can't be represented in any of the
+/// supported dialects.
+pub(crate) fn translate_note_event(
+    event: &NoteIoEvent,
+    supported_dialects: NoteDialects,
+) -> Option<Cow<'_, NoteIoEvent>> {
+    let supports_clap = supported_dialects.contains(NoteDialects::CLAP);
+    let supports_midi = supported_dialects.intersects(NoteDialects::MIDI | NoteDialects::MIDI_MPE);
+    let supports_midi2 = supported_dialects.contains(NoteDialects::MIDI2);
+
+    let dialect = match &event.event_type {
+        NoteIoEventType::On { .. }
+        | NoteIoEventType::Expression { .. }
+        | NoteIoEventType::Choke
+        | NoteIoEventType::Off { .. }
+        | NoteIoEventType::End => Dialect::Clap,
+        NoteIoEventType::Midi { .. } | NoteIoEventType::MidiSysEx(_) => Dialect::Midi,
+        NoteIoEventType::Midi2 { .. } => Dialect::Midi2,
+    };
+
+    // If the port didn't declare any dialects, then just pass everything
+    // through unchanged.
+    let is_supported = match dialect {
+        Dialect::Clap => supports_clap,
+        Dialect::Midi => supports_midi,
+        Dialect::Midi2 => supports_midi2,
+    };
+    if supported_dialects.is_empty() || is_supported {
+        return Some(Cow::Borrowed(event));
+    }
+
+    // Translate everything through MIDI 1.0 messages.
+    let midi = to_midi1(event)?;
+
+    let (channel, key, event_type) = if supports_midi {
+        (-1, -1, NoteIoEventType::Midi { data: midi })
+    } else if supports_clap {
+        midi1_to_clap(midi)?
+    } else if supports_midi2 {
+        (-1, -1, NoteIoEventType::Midi2 { data: midi1_to_midi2(midi)? })
+    } else {
+        return None;
+    };
+
+    Some(Cow::Owned(NoteIoEvent { header: event.header, channel, key, note_id: -1, event_type }))
+}
+
+fn to_midi1(event: &NoteIoEvent) -> Option<[u8; 3]> {
+    let to_7_bit = |value: f64| (value.clamp(0.0, 1.0) * 127.0).round() as u8;
+
+    // CLAP uses `-1` as a wildcard for the channel.
+    let channel = event.channel.clamp(0, 15) as u8;
+    let key = || u8::try_from(event.key).ok().filter(|key| *key < 128);
+
+    match &event.event_type {
+        // A note on with a velocity of `0` means note off in MIDI 1.0.
+        NoteIoEventType::On { velocity } => {
+            Some([0x90 | channel, key()?, to_7_bit(*velocity).max(1)])
+        }
+        NoteIoEventType::Off { velocity } => Some([0x80 | channel, key()?, to_7_bit(*velocity)]),
+        NoteIoEventType::Choke => Some([0x80 | channel, key()?, 0]),
+        NoteIoEventType::End => None,
+        NoteIoEventType::Expression { expression_type, value } => match expression_type {
+            NoteExpressionType::Pressure => Some([0xA0 | channel, key()?, to_7_bit(*value)]),
+            _ => None,
+        },
+        NoteIoEventType::Midi { data } => Some(*data),
+        NoteIoEventType::MidiSysEx(_) => None,
+        NoteIoEventType::Midi2 { data } => midi2_to_midi1(*data),
+    }
+}
+
+fn midi1_to_clap(data: [u8; 3]) -> Option<(i16, i16, NoteIoEventType)> {
+    let channel = i16::from(data[0] & 0x0F);
+    let key = i16::from(data[1] & 0x7F);
+    let value = f64::from(data[2] & 0x7F) / 127.0;
+
+    let event_type = match data[0] & 0xF0 {
+        0x90 if value > 0.0 => NoteIoEventType::On { velocity: value },
+        0x80 | 0x90 => NoteIoEventType::Off { velocity: value },
+        0xA0 => {
+            NoteIoEventType::Expression { expression_type: NoteExpressionType::Pressure, value }
+        }
+        _ => return None,
+    };
+
+    Some((channel, key, event_type))
+}
+
+/// Convert a MIDI 1.0 channel voice message into a MIDI 2.0 channel voice
+/// message (a Universal MIDI Packet) in group 0.
+fn midi1_to_midi2(data: [u8; 3]) -> Option<[u32; 4]> {
+    let status = data[0];
+    let d1 = u32::from(data[1] & 0x7F);
+    let d2 = u32::from(data[2] & 0x7F);
+
+    let (status, index, payload) = match status & 0xF0 {
+        // A note on with a velocity of `0` means note off in MIDI 1.0, but
+        // not in MIDI 2.0.
+        0x90 if d2 == 0 => (0x80 | (status & 0x0F), d1, 0),
+        0x80 | 0x90 => (status, d1, upscale(d2, 7, 16) << 16),
+        0xA0 | 0xB0 => (status, d1, upscale(d2, 7, 32)),
+        0xC0 => (status, 0, d1 << 24),
+        0xD0 => (status, 0, upscale(d1, 7, 32)),
+        0xE0 => (status, 0, upscale(d1 | (d2 << 7), 14, 32)),
+        _ => return None,
+    };
+
+    Some([(0x4 << 28) | (u32::from(status) << 16) | (index << 8), payload, 0, 0])
+}
+
+/// Convert a Universal MIDI Packet into a MIDI 1.0 channel voice message.
+fn midi2_to_midi1(data: [u32; 4]) -> Option<[u8; 3]> {
+    let message_type = data[0] >> 28;
+    let status = ((data[0] >> 16) & 0xFF) as u8;
+    let index = ((data[0] >> 8) & 0x7F) as u8;
+
+    match message_type {
+        // MIDI 1.0 channel voice messages wrapped in a UMP.
+        0x2 => Some([status, index, (data[0] & 0x7F) as u8]),
+        // MIDI 2.0 channel voice messages.
+        0x4 => match status & 0xF0 {
+            0x80 => Some([status, index, (data[1] >> 25) as u8]),
+            // A velocity of `0` would mean note off in MIDI 1.0.
+            0x90 => Some([status, index, ((data[1] >> 25) as u8).max(1)]),
+            0xA0 | 0xB0 => Some([status, index, (data[1] >> 25) as u8]),
+            0xC0 => Some([status, ((data[1] >> 24) & 0x7F) as u8, 0]),
+            0xD0 => Some([status, (data[1] >> 25) as u8, 0]),
+            0xE0 => {
+                let value = data[1] >> 18;
+                Some([status, (value & 0x7F) as u8, ((value >> 7) & 0x7F) as u8])
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Scale a value up to a higher resolution using the "min-center-max"
+/// algorithm from the MIDI 2.0 specification, so that the minimum, center,
+/// and maximum values are preserved.
+fn upscale(value: u32, src_bits: u32, dst_bits: u32) -> u32 {
+    let scale_bits = dst_bits - src_bits;
+    let center = 1 << (src_bits - 1);
+
+    let mut scaled = value << scale_bits;
+    if value <= center {
+        return scaled;
+    }
+
+    let repeat_bits = src_bits - 1;
+    let mut repeat_value = value & ((1 << repeat_bits) - 1);
+    if scale_bits > repeat_bits {
+        repeat_value <<= scale_bits - repeat_bits;
+    } else {
+        repeat_value >>= repeat_bits - scale_bits;
+    }
+
+    while repeat_value != 0 {
+        scaled |= repeat_value;
+        repeat_value >>= repeat_bits;
+    }
+
+    scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::IoEventHeader;
+    use super::*;
+
+    fn note_event(channel: i16, key: i16, event_type: NoteIoEventType) -> NoteIoEvent {
+        NoteIoEvent { header: IoEventHeader { time: 0 }, channel, key, note_id: -1, event_type }
+    }
+
+    fn translate(event: &NoteIoEvent, supported_dialects: NoteDialects) -> NoteIoEvent {
+        translate_note_event(event, supported_dialects).unwrap().into_owned()
+    }
+
+    #[test]
+    fn midi1_round_trips_through_midi2() {
+        let messages: [[u8; 3]; 10] = [
+            [0x91, 60, 100],
+            [0x91, 60, 127],
+            [0x82, 61, 0],
+            [0x82, 61, 64],
+            [0xA3, 62, 33],
+            [0xB4, 7, 127],
+            [0xC5, 12, 0],
+            [0xD6, 90, 0],
+            [0xE7, 0x00, 0x40],
+            [0xE7, 0x7F, 0x7F],
+        ];
+
+        for message in messages {
+            let midi2 = midi1_to_midi2(message).unwrap();
+            assert_eq!(midi2[0] >> 28, 0x4, "{:x?} is not a MIDI 2.0 channel voice message", midi2);
+            assert_eq!(midi2_to_midi1(midi2), Some(message), "{:x?}", message);
+        }
+    }
+
+    #[test]
+    fn midi1_is_upscaled_to_midi2_resolution() {
+        // Velocity is 16 bits, and the minimum, center, and maximum are kept.
+        let velocity = |v: u8| midi1_to_midi2([0x90, 60, v]).unwrap()[1] >> 16;
+        assert_eq!(velocity(1), 1 << 9);
+        assert_eq!(velocity(64), 0x8000);
+        assert_eq!(velocity(127), 0xFFFF);
+
+        // Control changes are 32 bits.
+        let cc = |v: u8| midi1_to_midi2([0xB0, 1, v]).unwrap()[1];
+        assert_eq!(cc(0), 0);
+        assert_eq!(cc(64), 0x8000_0000);
+        assert_eq!(cc(127), 0xFFFF_FFFF);
+
+        // Pitch bend is 32 bits, and the center stays in the center.
+        let bend = |lsb: u8, msb: u8| midi1_to_midi2([0xE0, lsb, msb]).unwrap()[1];
+        assert_eq!(bend(0x00, 0x40), 0x8000_0000);
+        assert_eq!(bend(0x7F, 0x7F), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn midi1_wrapped_in_ump_is_unwrapped() {
+        let ump = [(0x2 << 28) | (0x93 << 16) | (64 << 8) | 99, 0, 0, 0];
+        assert_eq!(midi2_to_midi1(ump), Some([0x93, 64, 99]));
+    }
+
+    #[test]
+    fn unsupported_messages_are_dropped() {
+        // System messages have no equivalent channel voice message.
+        assert_eq!(midi1_to_midi2([0xF8, 0, 0]), None);
+        // Neither do MIDI 2.0 per-note controllers.
+        assert_eq!(midi2_to_midi1([(0x4 << 28) | (0x00 << 16), 0, 0, 0]), None);
+        // Utility messages are not channel voice messages.
+        assert_eq!(midi2_to_midi1([0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn midi1_note_on_with_zero_velocity_is_note_off() {
+        // In MIDI 2.0 a velocity of `0` is a valid note on.
+        let midi2 = midi1_to_midi2([0x95, 60, 0]).unwrap();
+        assert_eq!((midi2[0] >> 16) & 0xFF, 0x85);
+        assert_eq!(midi2[1], 0);
+
+        let event = note_event(-1, -1, NoteIoEventType::Midi { data: [0x95, 60, 0] });
+        let translated = translate(&event, NoteDialects::CLAP);
+        assert_eq!((translated.channel, translated.key), (5, 60));
+        assert!(
+            matches!(translated.event_type, NoteIoEventType::Off { velocity } if velocity == 0.0)
+        );
+    }
+
+    #[test]
+    fn note_on_never_has_zero_velocity_in_midi1() {
+        // Otherwise the note on would be read as a note off.
+        let event = note_event(2, 60, NoteIoEventType::On { velocity: 0.0 });
+        let translated = translate(&event, NoteDialects::MIDI);
+        assert!(matches!(translated.event_type, NoteIoEventType::Midi { data: [0x92, 60, 1] }));
+
+        let midi2_note_on = [(0x4 << 28) | (0x92 << 16) | (60 << 8), 0, 0, 0];
+        assert_eq!(midi2_to_midi1(midi2_note_on), Some([0x92, 60, 1]));
+    }
+
+    #[test]
+    fn clap_notes_are_translated_to_midi() {
+        let event = note_event(3, 64, NoteIoEventType::On { velocity: 1.0 });
+
+        let translated = translate(&event, NoteDialects::MIDI);
+        assert_eq!((translated.channel, translated.key), (-1, -1));
+        assert!(matches!(translated.event_type, NoteIoEventType::Midi { data: [0x93, 64, 127] }));
+
+        let translated = translate(&event, NoteDialects::MIDI2);
+        let data = match translated.event_type {
+            NoteIoEventType::Midi2 { data } => data,
+            _ => panic!("expected a MIDI 2.0 event"),
+        };
+        assert_eq!(midi2_to_midi1(data), Some([0x93, 64, 127]));
+
+        // The wildcard channel is sent on the first channel.
+        let event = note_event(-1, 64, NoteIoEventType::Off { velocity: 0.0 });
+        let translated = translate(&event, NoteDialects::MIDI);
+        assert!(matches!(translated.event_type, NoteIoEventType::Midi { data: [0x80, 64, 0] }));
+    }
+
+    #[test]
+    fn supported_dialects_pass_through_unchanged() {
+        let event = note_event(-1, -1, NoteIoEventType::Midi { data: [0x90, 60, 0] });
+        assert!(matches!(
+            translate_note_event(&event, NoteDialects::MIDI | NoteDialects::CLAP),
+            Some(Cow::Borrowed(_))
+        ));
+        assert!(matches!(
+            translate_note_event(&event, NoteDialects::empty()),
+            Some(Cow::Borrowed(_))
+        ));
+
+        // Events which can't be represented in any supported dialect are
+        // dropped.
+        let event = note_event(0, 60, NoteIoEventType::End);
+        assert!(translate_note_event(&event, NoteDialects::MIDI).is_none());
+    }
+
+    #[test]
+    fn sysex_is_stored_in_byte_arena() {
+        let mut bytes = Vec::with_capacity(8);
+
+        let a = MidiSysExRef::push(&mut bytes, &[0xF0, 1, 2, 0xF7]).unwrap();
+        let b = MidiSysExRef::push(&mut bytes, &[0xF0, 3, 0xF7]).unwrap();
+        assert_eq!(a.get(&bytes), &[0xF0, 1, 2, 0xF7]);
+        assert_eq!(b.get(&bytes), &[0xF0, 3, 0xF7]);
+
+        // Messages which don't fit are discarded without allocating.
+        assert!(MidiSysExRef::push(&mut bytes, &[0xF0, 4, 0xF7]).is_none());
+        assert_eq!(bytes.len(), 7);
+        assert_eq!(bytes.capacity(), 8);
+    }
+
+    #[test]
+    fn copying_sysex_event_relocates_its_data() {
+        let mut src_bytes = Vec::with_capacity(8);
+        src_bytes.push(0);
+        let sysex = MidiSysExRef::push(&mut src_bytes, &[0xF0, 1, 0xF7]).unwrap();
+        let event = note_event(-1, -1, NoteIoEventType::MidiSysEx(sysex));
+
+        let mut events = Vec::new();
+        let mut dst_bytes = Vec::with_capacity(4);
+        assert!(event.copy_to(&src_bytes, &mut events, &mut dst_bytes));
+        assert!(!event.copy_to(&src_bytes, &mut events, &mut dst_bytes));
+
+        assert_eq!(events.len(), 1);
+        match events[0].event_type {
+            NoteIoEventType::MidiSysEx(sysex) => {
+                assert_eq!(sysex.get(&dst_bytes), &[0xF0, 1, 0xF7])
+            }
+            _ => panic!("expected a SysEx event"),
+        }
+    }
+}
@@ -6,6 +6,7 @@ use clack_extensions::latency::{HostLatency, PluginLatency};
 use clack_extensions::log::Log;
 use clack_extensions::params::{HostParams, PluginParams};
 use clack_extensions::state::PluginState;
+use clack_extensions::tail::{HostTail, PluginTail};
 use clack_extensions::thread_check::ThreadCheck;
 use clack_extensions::timer::{HostTimer, PluginTimer};
 use clack_host::events::io::{EventBuffer, InputEvents, OutputEvents};
@@ -13,7 +14,7 @@ use clack_host::extensions::HostExtensions;
 use clack_host::host::{Host, HostAudioProcessor, HostMainThread, HostShared};
 use clack_host::plugin::{PluginAudioProcessorHandle, PluginMainThreadHandle, PluginSharedHandle};
 use meadowlark_plugin_api::HostRequestChannelSender;
-use meadowlark_plugin_api::{HostRequestFlags, PluginInstanceID};
+use meadowlark_plugin_api::{HostRequestFlags, PluginInstanceID, TailLength};
 
 pub struct ClapHost;
 
@@ -30,7 +31,8 @@ impl<'a> Host<'a> for ClapHost {
             .register::<HostParams>()
             .register::<HostTimer>()
             .register::<HostGui>()
-            .register::<HostLatency>();
+            .register::<HostLatency>()
+            .register::<HostTail>();
     }
 }
 
@@ -87,6 +89,22 @@ impl<'a> ClapHostAudioProcessor<'a> {
 
         params_ext.flush_active(&mut self.plugin, &clap_in_events, &mut clap_out_events);
     }
+
+    pub fn tail(&mut self) -> TailLength {
+        let tail_ext = match self.shared.tail_ext {
+            None => return TailLength::Frames(0),
+            Some(t) => t,
+        };
+
+        // CLAP treats any value greater than or equal to `INT32_MAX` as an
+        // infinite tail.
+        let frames = tail_ext.get(&mut self.plugin);
+        if frames >= i32::MAX as u32 {
+            TailLength::Infinite
+        } else {
+            TailLength::Frames(frames)
+        }
+    }
 }
 
 pub struct ClapHostShared<'a> {
@@ -97,6 +115,7 @@ pub struct ClapHostShared<'a> {
     pub gui_ext: Option<&'a PluginGui>,
     pub latency_ext: Option<&'a PluginLatency>,
     pub timer_ext: Option<&'a PluginTimer>,
+    pub tail_ext: Option<&'a PluginTail>,
 
     host_request: HostRequestChannelSender,
     plugin_log_name: Shared<String>,
@@ -121,6 +140,7 @@ impl<'a> ClapHostShared<'a> {
             gui_ext: None,
             latency_ext: None,
             timer_ext: None,
+            tail_ext: None,
             plugin_log_name,
             thread_ids,
         }
@@ -134,6 +154,7 @@ impl<'a> HostShared<'a> for ClapHostShared<'a> {
         self.timer_ext = instance.get_extension();
         self.gui_ext = instance.get_extension();
         self.latency_ext = instance.get_extension();
+        self.tail_ext = instance.get_extension();
     }
 
     fn request_restart(&self) {
@@ -9,11 +9,12 @@ use clack_extensions::note_ports::{
 use clack_extensions::params::{
     HostParamsImplementation, HostParamsImplementationMainThread, ParamClearFlags, ParamRescanFlags,
 };
+use clack_extensions::tail::HostTailImpl;
 use clack_extensions::thread_check::host::ThreadCheckImplementation;
 use clack_extensions::timer::{HostTimerImpl, TimerError, TimerId};
 use meadowlark_plugin_api::HostRequestFlags;
 
-use super::{ClapHostMainThread, ClapHostShared};
+use super::{ClapHostAudioProcessor, ClapHostMainThread, ClapHostShared};
 
 impl<'a> HostLog for ClapHostShared<'a> {
     fn log(&self, severity: LogSeverity, message: &str) {
@@ -79,8 +80,7 @@ impl<'a> HostAudioPortsImplementation for ClapHostMainThread<'a> {
 
 impl<'a> HostNotePortsImplementation for ClapHostMainThread<'a> {
     fn supported_dialects(&self) -> NoteDialects {
-        // TODO: Support MIDI2
-        NoteDialects::CLAP | NoteDialects::MIDI
+        NoteDialects::CLAP | NoteDialects::MIDI | NoteDialects::MIDI2
     }
 
     fn rescan(&self, flags: NotePortRescanFlags) {
@@ -197,3 +197,10 @@ impl<'a> HostLatencyImpl for ClapHostMainThread<'a> {
         self.shared.host_request.request(HostRequestFlags::RESTART);
     }
 }
+
+impl<'a> HostTailImpl for ClapHostAudioProcessor<'a> {
+    fn changed(&mut self) {
+        // The tail is queried again in every process cycle, so there is
+        // nothing to do here.
+    }
+}
@@ -15,7 +15,7 @@ use meadowlark_plugin_api::ext::note_ports::{NotePortInfo, PluginNotePortsExt};
 use meadowlark_plugin_api::ext::params::{ParamID, ParamInfo, ParamInfoFlags};
 use meadowlark_plugin_api::{
     buffer::EventBuffer, ext, PluginActivatedInfo, PluginMainThread, PluginProcessor, ProcBuffers,
-    ProcInfo, ProcessStatus,
+    ProcInfo, ProcessStatus, TailLength,
 };
 use raw_window_handle::RawWindowHandle;
 use smallvec::SmallVec;
@@ -24,7 +24,7 @@ use std::ffi::{CStr, CString};
 use std::io::Cursor;
 use std::mem::MaybeUninit;
 
-use super::process::ClapProcess;
+use super::process::{sync_output_constant_masks, ClapProcess};
 use super::*;
 
 #[derive(Default)]
@@ -744,7 +744,6 @@ impl PluginProcessor for ClapPluginProcessor {
                 (input_refs_f32, input_refs_f64, output_refs_f32, output_refs_f64)
             };
 
-            // TODO: handle transport & timer
             let res = self
                 .audio_processor
                 .as_started_mut()
@@ -756,11 +755,9 @@ impl PluginProcessor for ClapPluginProcessor {
                     &mut out_events,
                     proc_info.steady_time,
                     Some(proc_info.frames),
-                    None,
+                    Some(proc_info.transport.event()),
                 );
 
-            // TODO: Sync audio output constant flags.
-
             //#[cfg(debug_assertions)]
             {
                 input_refs_f32.clear();
@@ -769,6 +766,10 @@ impl PluginProcessor for ClapPluginProcessor {
                 output_refs_f64.clear();
             }
 
+            if res.is_ok() {
+                sync_output_constant_masks(&audio_out, buffers);
+            }
+
             res
         };
 
@@ -782,6 +783,10 @@ impl PluginProcessor for ClapPluginProcessor {
         }
     }
 
+    fn tail(&mut self) -> TailLength {
+        self.audio_processor.audio_processor_host_data_mut().tail()
+    }
+
     fn param_flush(&mut self, in_events: &EventBuffer, out_events: &mut EventBuffer) {
         self.audio_processor.audio_processor_host_data_mut().param_flush(in_events, out_events)
     }
@@ -81,23 +81,27 @@ impl ClapProcess {
             },
         });
 
+        // The constant flags of the outputs are left over from the previous
+        // cycle, so they are cleared here. The plugin sets the constant mask
+        // of each output itself, and the engine's hints are only taken from
+        // that (see `sync_output_constant_masks()`).
         let outputs = buffers.audio_out.iter().map(|port| ClapAudioPortBuffer {
             latency: port.latency(),
             channels: match &port._raw_channels {
                 RawAudioChannelBuffers::F32(channels) => {
                     AudioPortBufferType::F32(channels.iter().map(|channel| {
-                        let buf = channel.borrow_mut();
-                        let is_constant = buf.is_constant;
+                        let mut buf = channel.borrow_mut();
+                        buf.is_constant = false;
 
-                        ChannelBuffer { data: BorrowedBuffer(buf), is_constant }
+                        ChannelBuffer { data: BorrowedBuffer(buf), is_constant: false }
                     }))
                 }
                 RawAudioChannelBuffers::F64(channels) => {
                     AudioPortBufferType::F64(channels.iter().map(|channel| {
-                        let buf = channel.borrow_mut();
-                        let is_constant = buf.is_constant;
+                        let mut buf = channel.borrow_mut();
+                        buf.is_constant = false;
 
-                        ChannelBuffer { data: BorrowedBuffer(buf), is_constant }
+                        ChannelBuffer { data: BorrowedBuffer(buf), is_constant: false }
                     }))
                 }
             },
@@ -106,3 +110,84 @@ impl ClapProcess {
         (self.input_buffer_slots.with_data(inputs), self.output_buffer_slots.with_data(outputs))
     }
 }
+
+/// Copy the constant masks the plugin set on its output buffers to the
+/// constant hints of the engine's buffers, so that silence can propagate
+/// through the rest of the graph.
+pub(super) fn sync_output_constant_masks(audio_out: &AudioBuffers<'_>, buffers: &ProcBuffers) {
+    let is_constant =
+        |constant_mask: u64, channel: usize| channel < 64 && constant_mask & (1 << channel) != 0;
+
+    for (raw_port, port) in audio_out.as_raw_buffers().iter().zip(buffers.audio_out.iter()) {
+        match &port._raw_channels {
+            RawAudioChannelBuffers::F32(channels) => {
+                for (i, channel) in channels.iter().enumerate() {
+                    channel.borrow_mut().is_constant = is_constant(raw_port.constant_mask, i);
+                }
+            }
+            RawAudioChannelBuffers::F64(channels) => {
+                for (i, channel) in channels.iter().enumerate() {
+                    channel.borrow_mut().is_constant = is_constant(raw_port.constant_mask, i);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+    use meadowlark_plugin_api::buffer::{
+        AudioPortBufferMut, DebugBufferID, DebugBufferType, SharedBuffer,
+    };
+    use smallvec::{smallvec, SmallVec};
+
+    use super::*;
+
+    #[test]
+    fn stale_output_constant_hints_are_cleared() {
+        let collector = Collector::new();
+        let coll_handle = collector.handle();
+
+        let channels: SmallVec<[SharedBuffer<f32>; 2]> = (0..2)
+            .map(|index| {
+                SharedBuffer::new(
+                    64,
+                    DebugBufferID { index, buffer_type: DebugBufferType::Audio32 },
+                    &coll_handle,
+                )
+            })
+            .collect();
+        let buffers = ProcBuffers::_new(
+            SmallVec::new(),
+            smallvec![AudioPortBufferMut::_new(channels.clone(), 0)],
+            false,
+        );
+
+        let mut process = ClapProcess::new(&AudioPortChannels {
+            num_input_ports: 0,
+            num_output_ports: 1,
+            max_input_channels: 0,
+            max_output_channels: 2,
+        });
+
+        // The output was silent in the previous cycle.
+        for channel in channels.iter() {
+            channel.borrow_mut().is_constant = true;
+        }
+
+        {
+            let (_, audio_out) = process.update_buffers(&buffers);
+
+            // The plugin doesn't see the hints from the previous cycle, so
+            // if it outputs sound without touching the constant mask, the
+            // output is no longer marked as silent.
+            assert_eq!(audio_out.as_raw_buffers()[0].constant_mask, 0);
+            sync_output_constant_masks(&audio_out, &buffers);
+        }
+
+        for channel in channels.iter() {
+            assert!(!channel.borrow().is_constant);
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use audio_graph::{AudioGraphHelper, EdgeID, PortID};
+use audio_graph::{EdgeID, PortID};
 use basedrop::Shared;
+use clack_host::events::event_types::NoteExpressionType;
 use clack_host::events::{Event, EventFlags, EventHeader};
 use clack_host::utils::Cookie;
+use meadowlark_plugin_api::automation::AutomationIoEventType;
 use meadowlark_plugin_api::event::{ParamModEvent, ParamValueEvent};
 use meadowlark_plugin_api::ext::audio_ports::PluginAudioPortsExt;
 use meadowlark_plugin_api::ext::gui::{EmbeddedGuiInfo, GuiResizeHints, GuiSize};
@@ -9,7 +11,7 @@ use meadowlark_plugin_api::ext::note_ports::PluginNotePortsExt;
 use meadowlark_plugin_api::ext::params::{ParamID, ParamInfo, ParamInfoFlags};
 use meadowlark_plugin_api::ext::timer::TimerID;
 use meadowlark_plugin_api::{
-    HostRequestChannelReceiver, HostRequestFlags, PluginInstanceID, PluginMainThread,
+    HostRequestChannelReceiver, HostRequestFlags, PluginInstanceID, PluginMainThread, TailLength,
 };
 
 use fnv::{FnvHashMap, FnvHashSet};
@@ -18,17 +20,23 @@ use raw_window_handle::RawWindowHandle;
 use smallvec::SmallVec;
 use std::error::Error;
 
-use crate::engine::{timer_wheel::EngineTimerWheel, OnIdleEvent, PluginActivatedStatus};
-use crate::graph::{EngineEdgeID, PortChannelID};
+use crate::engine::{
+    timer_wheel::EngineTimerWheel, OnIdleEvent, PluginActivatedStatus, ProcessTimeStats,
+};
+use crate::graph::{AbstractGraph, GraphEdge, PortChannelID};
 use crate::utils::thread_id::SharedThreadIDs;
 
 use super::channel::{
-    MainToProcParamValue, PlugHostChannelMainThread, PluginActiveState, ProcToMainParamValue,
+    MainToProcParamEvent, MainToProcParamValue, MainToProcVoiceEvent, MainToProcVoiceEventType,
+    PlugHostChannelMainThread, PluginActiveState, ProcParams, ProcToMainParamValue,
     SharedPluginHostProcessor,
 };
-use super::error::{ActivatePluginError, RescanParamListError, SetParamValueError};
+use super::error::{
+    ActivatePluginError, RescanParamListError, SetParamValueError, VoiceEventError,
+};
 use super::event_io_buffers::{PluginEventOutputSanitizer, PluginIoEvent};
 use super::processor::BYPASS_DECLICK_SECS;
+use super::voice_table::VoiceTarget;
 use super::{PluginHostProcessorWrapper, PluginHostSaveState};
 
 mod sync_ports;
@@ -46,13 +54,25 @@ pub struct ParamState {
     pub info: ParamInfo,
     /// The current value of this parameter.
     pub value: f64,
-    /// The current modulation amount on this parameter.
+    /// The current modulation amount on this parameter, set with
+    /// `PluginHostMainThread::set_param_mod_amount()`.
     pub mod_amount: f64,
+    /// The current modulation amount on this parameter received through the
+    /// plugin's automation in port (i.e. from a modulator plugin).
+    pub edge_mod_amount: f64,
     /// If this is `true`, then the user is currently directly modifying
     /// this parameter.
     pub is_gesturing: bool,
 }
 
+impl ParamState {
+    /// The total modulation amount on this parameter, which is the amount
+    /// the plugin receives.
+    pub fn total_mod_amount(&self) -> f64 {
+        self.mod_amount + self.edge_mod_amount
+    }
+}
+
 pub struct PluginHostMainThread {
     id: PluginInstanceID,
 
@@ -185,6 +205,23 @@ impl PluginHostMainThread {
         self.is_loaded
     }
 
+    /// Returns `true` if this plugin has been scheduled to be removed from
+    /// the audio graph.
+    pub(crate) fn is_remove_requested(&self) -> bool {
+        self.remove_requested
+    }
+
+    /// Take the statistics on how long the plugin's processor took to
+    /// process each block since the last call to this method.
+    pub(crate) fn take_process_time_stats(&self) -> ProcessTimeStats {
+        let shared_state = &self.channel.shared_state;
+
+        ProcessTimeStats {
+            num_skipped_cycles: shared_state.take_skipped_cycles(),
+            ..shared_state.process_time.take_process_time()
+        }
+    }
+
     /// Returns `true` if this plugin is currently being bypassed.
     pub fn is_bypassed(&self) -> bool {
         self.save_state.bypassed
@@ -257,6 +294,9 @@ impl PluginHostMainThread {
 
     /// Set the value of the given parameter.
     ///
+    /// The change is sent at the start of the next process block, or right
+    /// after any changes sent before it with `set_param_value_at_frame()`.
+    ///
     /// If successful, this returns the actual (clamped) value that the
     /// plugin accepted.
     pub fn set_param_value(
@@ -272,9 +312,10 @@ impl PluginHostMainThread {
                 let value = value.clamp(param_state.info.min_value, param_state.info.max_value);
 
                 if let Some(param_queues) = &mut self.channel.param_queues {
+                    let seq = param_queues.next_seq();
                     param_queues.to_proc_param_value_tx.set(
                         param_id,
-                        MainToProcParamValue { value, cookie: param_state.info._cookie },
+                        MainToProcParamValue { value, cookie: param_state.info._cookie, seq },
                     );
                     param_queues.to_proc_param_value_tx.producer_done();
                 } else {
@@ -310,17 +351,23 @@ impl PluginHostMainThread {
     ) -> Result<f64, SetParamValueError> {
         let mut flush_on_main_thread = None;
         let res = if let Some(param_state) = self.param_states.get_mut(&param_id) {
-            if param_state.info.flags.contains(ParamInfoFlags::IS_MODULATABLE) {
+            if !param_state.info.flags.contains(ParamInfoFlags::IS_MODULATABLE) {
                 Err(SetParamValueError::ParamIsNotModulatable(param_id))
             } else {
-                // TODO: Clamp mod amount?
+                // The modulation amount is an offset which is added to the value
+                // of the parameter, so it can never need to be larger than the
+                // parameter's range.
+                let range = param_state.info.max_value - param_state.info.min_value;
+                let mod_amount = mod_amount.clamp(-range, range);
 
                 if let Some(param_queues) = &mut self.channel.param_queues {
+                    let seq = param_queues.next_seq();
                     param_queues.to_proc_param_mod_tx.set(
                         param_id,
                         MainToProcParamValue {
                             value: mod_amount,
                             cookie: param_state.info._cookie,
+                            seq,
                         },
                     );
                     param_queues.to_proc_param_mod_tx.producer_done();
@@ -345,6 +392,183 @@ impl PluginHostMainThread {
         res
     }
 
+    /// Begin a gesture on the given parameter (i.e. the user has started
+    /// dragging a knob in the UI).
+    ///
+    /// `playhead_frame` is the position of the playhead at the time of the
+    /// gesture (see `TransportHandle::playhead_frame_estimate()`). It is used
+    /// to place the event at the corresponding frame in the process block.
+    pub fn begin_param_gesture(
+        &mut self,
+        param_id: ParamID,
+        playhead_frame: u64,
+    ) -> Result<(), SetParamValueError> {
+        self.send_param_gesture(param_id, playhead_frame, true)
+    }
+
+    /// End a gesture on the given parameter (i.e. the user has let go of
+    /// a knob in the UI).
+    ///
+    /// `playhead_frame` is the position of the playhead at the time of the
+    /// gesture (see `TransportHandle::playhead_frame_estimate()`).
+    pub fn end_param_gesture(
+        &mut self,
+        param_id: ParamID,
+        playhead_frame: u64,
+    ) -> Result<(), SetParamValueError> {
+        self.send_param_gesture(param_id, playhead_frame, false)
+    }
+
+    /// Set the value of the given parameter at the given position of the
+    /// playhead (see `TransportHandle::playhead_frame_estimate()`).
+    ///
+    /// Unlike `set_param_value()`, the change is placed at the corresponding
+    /// frame in the process block, and changes are never coalesced, so every
+    /// value in a gesture reaches the plugin at the frame where it was heard.
+    ///
+    /// If successful, this returns the actual (clamped) value that the
+    /// plugin accepted.
+    pub fn set_param_value_at_frame(
+        &mut self,
+        param_id: ParamID,
+        value: f64,
+        playhead_frame: u64,
+    ) -> Result<f64, SetParamValueError> {
+        let param_state = self
+            .param_states
+            .get_mut(&param_id)
+            .ok_or(SetParamValueError::ParamDoesNotExist(param_id))?;
+
+        if param_state.info.flags.contains(ParamInfoFlags::IS_READONLY) {
+            return Err(SetParamValueError::ParamIsReadOnly(param_id));
+        }
+
+        let value = value.clamp(param_state.info.min_value, param_state.info.max_value);
+
+        let sent = if let Some(param_queues) = &mut self.channel.param_queues {
+            let seq = param_queues.next_seq();
+            param_queues
+                .to_proc_param_event_tx
+                .push(MainToProcParamEvent {
+                    param_id,
+                    cookie: param_state.info._cookie,
+                    playhead_frame,
+                    seq,
+                    event_type: AutomationIoEventType::Value(value),
+                })
+                .is_ok()
+        } else {
+            false
+        };
+
+        if !sent {
+            // The plugin is not active or the queue is full, so fall back to
+            // setting the value without a timestamp. The process thread sends
+            // it after any timestamped events which are still queued, so this
+            // newer value is not overridden by them.
+            return self.set_param_value(param_id, value);
+        }
+
+        param_state.value = value;
+        self.save_state_dirty = true;
+
+        Ok(value)
+    }
+
+    fn send_param_gesture(
+        &mut self,
+        param_id: ParamID,
+        playhead_frame: u64,
+        is_begin: bool,
+    ) -> Result<(), SetParamValueError> {
+        let param_state = self
+            .param_states
+            .get_mut(&param_id)
+            .ok_or(SetParamValueError::ParamDoesNotExist(param_id))?;
+
+        param_state.is_gesturing = is_begin;
+
+        if let Some(param_queues) = &mut self.channel.param_queues {
+            let event_type = if is_begin {
+                AutomationIoEventType::BeginGesture
+            } else {
+                AutomationIoEventType::EndGesture
+            };
+
+            // Gesture markers don't change the state of the plugin, so it is
+            // fine to drop them if the queue is full.
+            let seq = param_queues.next_seq();
+            let _ = param_queues.to_proc_param_event_tx.push(MainToProcParamEvent {
+                param_id,
+                cookie: param_state.info._cookie,
+                playhead_frame,
+                seq,
+                event_type,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Set the modulation amount on the given parameter for all of the
+    /// plugin's currently active voices selected by `target`.
+    ///
+    /// Voices which are started after this is called are not affected.
+    pub fn set_voice_param_mod_amount(
+        &mut self,
+        param_id: ParamID,
+        target: VoiceTarget,
+        mod_amount: f64,
+    ) -> Result<(), VoiceEventError> {
+        let param_state =
+            self.param_states.get(&param_id).ok_or(VoiceEventError::ParamDoesNotExist(param_id))?;
+
+        if !param_state.info.flags.contains(ParamInfoFlags::IS_MODULATABLE_PER_NOTE_ID) {
+            return Err(VoiceEventError::ParamIsNotModulatablePerNote(param_id));
+        }
+
+        let event_type = MainToProcVoiceEventType::ParamMod {
+            param_id,
+            cookie: param_state.info._cookie,
+            amount: mod_amount,
+        };
+
+        self.send_voice_event(MainToProcVoiceEvent { target, event_type })
+    }
+
+    /// Send a note expression to all of the plugin's currently active voices
+    /// selected by `target`.
+    pub fn set_voice_note_expression(
+        &mut self,
+        target: VoiceTarget,
+        expression_type: NoteExpressionType,
+        value: f64,
+    ) -> Result<(), VoiceEventError> {
+        let event_type = MainToProcVoiceEventType::Expression { expression_type, value };
+
+        self.send_voice_event(MainToProcVoiceEvent { target, event_type })
+    }
+
+    fn send_voice_event(&mut self, event: MainToProcVoiceEvent) -> Result<(), VoiceEventError> {
+        let voice_event_tx =
+            self.channel.voice_event_tx.as_mut().ok_or(VoiceEventError::PluginNotActive)?;
+
+        voice_event_tx.push(event).map_err(|_| VoiceEventError::QueueFull)
+    }
+
+    /// The length of the plugin's tail, as last reported by the plugin's
+    /// processor.
+    pub fn tail(&self) -> TailLength {
+        self.channel.shared_state.tail()
+    }
+
+    /// The number of voices the plugin is currently playing.
+    ///
+    /// This is only accurate for plugins which send note end events.
+    pub fn num_active_voices(&self) -> u32 {
+        self.channel.shared_state.active_voices()
+    }
+
     /// Get the display text for the given parameter with the given
     /// value.
     pub fn param_value_to_text(
@@ -690,8 +914,8 @@ impl PluginHostMainThread {
         sample_rate: u32,
         min_frames: u32,
         max_frames: u32,
-        graph_helper: &mut AudioGraphHelper,
-        edge_id_to_ds_edge_id: &mut FnvHashMap<EdgeID, EngineEdgeID>,
+        abstract_graph: &mut AbstractGraph,
+        edges: &mut FnvHashMap<EdgeID, GraphEdge>,
         thread_ids: SharedThreadIDs,
         schedule_version: u64,
         coll_handle: &basedrop::Handle,
@@ -738,7 +962,7 @@ impl PluginHostMainThread {
         let has_new_latency = if self.latency != latency {
             self.latency = latency;
             // Updates the new latency for the node in the abstract graph.
-            sync_ports::sync_latency_in_graph(self, graph_helper, latency);
+            sync_ports::sync_latency_in_graph(self, abstract_graph, latency);
             true
         } else {
             false
@@ -758,8 +982,8 @@ impl PluginHostMainThread {
                 // plugin adding/removing ports.
                 let (removed_edges, recompile) = match sync_ports::sync_ports_in_graph(
                     self,
-                    graph_helper,
-                    edge_id_to_ds_edge_id,
+                    abstract_graph,
+                    edges,
                     &new_audio_ports,
                     &new_note_ports,
                     coll_handle,
@@ -835,7 +1059,7 @@ impl PluginHostMainThread {
                 self.channel.new_processor(
                     info.processor,
                     self.id.unique_id(),
-                    self.param_list.len(),
+                    ProcParams::new(&self.param_states),
                     thread_ids,
                     sched_version,
                     bypass_declick_frames,
@@ -916,7 +1140,13 @@ impl PluginHostMainThread {
                             .get(&info.stable_id)
                             .map(|i| (i.is_gesturing, i.mod_amount))
                             .unwrap_or((false, 0.0));
-                        let param_state = ParamState { info, value, is_gesturing, mod_amount };
+                        let param_state = ParamState {
+                            info,
+                            value,
+                            is_gesturing,
+                            mod_amount,
+                            edge_mod_amount: 0.0,
+                        };
 
                         if self.param_states.insert(id, param_state).is_some() {
                             error_clear(self);
@@ -1022,17 +1252,23 @@ impl PluginHostMainThread {
                                     // the parameter.
                                     self.save_state_dirty = true;
                                 }
-                            } else {
+                            } else if new_value.value.is_some() {
                                 self.save_state_dirty = true;
                             };
 
                             if let Some(v) = new_value.value {
                                 param_state.value = v;
                             }
+                            if let Some(m) = new_value.edge_mod_amount {
+                                param_state.edge_mod_amount = m;
+                            }
 
                             modified_params.push(ParamModifiedInfo {
                                 param_id,
                                 new_value: new_value.value,
+                                new_mod_amount: new_value
+                                    .edge_mod_amount
+                                    .map(|_| param_state.total_mod_amount()),
                                 is_gesturing: param_state.is_gesturing,
                             })
                         }
@@ -1056,9 +1292,9 @@ impl PluginHostMainThread {
         min_frames: u32,
         max_frames: u32,
         coll_handle: &basedrop::Handle,
-        graph_helper: &mut AudioGraphHelper,
+        abstract_graph: &mut AbstractGraph,
         events_out: &mut SmallVec<[OnIdleEvent; 32]>,
-        edge_id_to_ds_edge_id: &mut FnvHashMap<EdgeID, EngineEdgeID>,
+        edges: &mut FnvHashMap<EdgeID, GraphEdge>,
         thread_ids: &SharedThreadIDs,
         schedule_version: u64,
         engine_timer: &mut EngineTimerWheel,
@@ -1071,6 +1307,15 @@ impl PluginHostMainThread {
         let request_flags = self.host_request_rx.fetch_requests();
         let mut active_state = self.channel.shared_state.get_active_state();
 
+        let num_discarded_sysex_events = self.channel.shared_state.take_discarded_sysex_events();
+        if num_discarded_sysex_events > 0 {
+            log::warn!(
+                "Plugin {:?} outputted {} MIDI SysEx messages which didn't fit in its note out buffers. Events were discarded.",
+                &self.id,
+                num_discarded_sysex_events
+            );
+        }
+
         // Collect any parameter updates that happened in previous calls to
         // `flush_params_on_main_thread()`.
         for event in self.modified_params.drain(..) {
@@ -1089,17 +1334,23 @@ impl PluginHostMainThread {
                             // the parameter.
                             self.save_state_dirty = true;
                         }
-                    } else {
+                    } else if new_value.value.is_some() {
                         self.save_state_dirty = true;
                     };
 
                     if let Some(v) = new_value.value {
                         param_state.value = v;
                     }
+                    if let Some(m) = new_value.edge_mod_amount {
+                        param_state.edge_mod_amount = m;
+                    }
 
                     modified_params.push(ParamModifiedInfo {
                         param_id: *param_id,
                         new_value: new_value.value,
+                        new_mod_amount: new_value
+                            .edge_mod_amount
+                            .map(|_| param_state.total_mod_amount()),
                         is_gesturing: param_state.is_gesturing,
                     });
                 }
@@ -1298,8 +1549,8 @@ impl PluginHostMainThread {
                         sample_rate,
                         min_frames,
                         max_frames,
-                        graph_helper,
-                        edge_id_to_ds_edge_id,
+                        abstract_graph,
+                        edges,
                         thread_ids.clone(),
                         schedule_version,
                         coll_handle,
@@ -1335,8 +1586,8 @@ impl PluginHostMainThread {
                         sample_rate,
                         min_frames,
                         max_frames,
-                        graph_helper,
-                        edge_id_to_ds_edge_id,
+                        abstract_graph,
+                        edges,
                         thread_ids.clone(),
                         schedule_version,
                         coll_handle,
@@ -1412,6 +1663,10 @@ impl PluginHostPortIDs {
 pub struct ParamModifiedInfo {
     pub param_id: ParamID,
     pub new_value: Option<f64>,
+    /// The new total modulation amount on this parameter (see
+    /// `ParamState::total_mod_amount()`), if the modulation received through
+    /// the plugin's automation in port has changed.
+    pub new_mod_amount: Option<f64>,
     pub is_gesturing: bool,
 }
 
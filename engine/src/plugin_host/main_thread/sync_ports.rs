@@ -1,20 +1,20 @@
-use audio_graph::{AudioGraphHelper, EdgeID, PortID};
+use audio_graph::{EdgeID, PortID};
 use fnv::{FnvHashMap, FnvHashSet};
 use meadowlark_plugin_api::ext::audio_ports::{MainPortsLayout, PluginAudioPortsExt};
 use meadowlark_plugin_api::ext::note_ports::PluginNotePortsExt;
 
-use crate::graph::{EngineEdgeID, PortChannelID, PortType};
+use crate::graph::{AbstractGraph, EngineEdgeID, GraphEdge, PortChannelID, PortType};
 
 use super::super::error::ActivatePluginError;
 use super::PluginHostMainThread;
 
 pub(super) fn sync_latency_in_graph(
     plugin_host: &mut PluginHostMainThread,
-    graph_helper: &mut AudioGraphHelper,
+    abstract_graph: &mut AbstractGraph,
     new_latency: i64,
 ) {
     // Update the latency on the node assigned to this plugin.
-    graph_helper.set_node_latency(plugin_host.id._node_id().into(), new_latency as f64).unwrap();
+    abstract_graph.set_node_latency(plugin_host.id._node_id().into(), new_latency as f64).unwrap();
 }
 
 /// Adds/removes ports from the abstract graph according to the plugin's new
@@ -28,8 +28,8 @@ pub(super) fn sync_latency_in_graph(
 /// plugin adding/removing ports.
 pub(super) fn sync_ports_in_graph(
     plugin_host: &mut PluginHostMainThread,
-    graph_helper: &mut AudioGraphHelper,
-    edge_id_to_ds_edge_id: &mut FnvHashMap<EdgeID, EngineEdgeID>,
+    abstract_graph: &mut AbstractGraph,
+    edges: &mut FnvHashMap<EdgeID, GraphEdge>,
     new_audio_ports: &Option<PluginAudioPortsExt>,
     new_note_ports: &Option<PluginNotePortsExt>,
     coll_handle: &basedrop::Handle,
@@ -129,7 +129,7 @@ pub(super) fn sync_ports_in_graph(
                         PortID(plugin_host.next_port_id - 1)
                     });
 
-                    graph_helper
+                    abstract_graph
                         .add_port(
                             plugin_host.id._node_id().into(),
                             new_port_id,
@@ -175,7 +175,7 @@ pub(super) fn sync_ports_in_graph(
                         PortID(plugin_host.next_port_id - 1)
                     });
 
-                    graph_helper
+                    abstract_graph
                         .add_port(
                             plugin_host.id._node_id().into(),
                             new_port_id,
@@ -232,7 +232,7 @@ pub(super) fn sync_ports_in_graph(
                 PortID(plugin_host.next_port_id - 1)
             });
 
-            graph_helper
+            abstract_graph
                 .add_port(
                     plugin_host.id._node_id().into(),
                     new_port_id,
@@ -265,7 +265,7 @@ pub(super) fn sync_ports_in_graph(
                     PortID(plugin_host.next_port_id - 1)
                 });
 
-                graph_helper
+                abstract_graph
                     .add_port(
                         plugin_host.id._node_id().into(),
                         new_port_id,
@@ -309,7 +309,7 @@ pub(super) fn sync_ports_in_graph(
                     PortID(plugin_host.next_port_id - 1)
                 });
 
-                graph_helper
+                abstract_graph
                     .add_port(
                         plugin_host.id._node_id().into(),
                         new_port_id,
@@ -348,7 +348,7 @@ pub(super) fn sync_ports_in_graph(
                     PortID(plugin_host.next_port_id - 1)
                 });
 
-                graph_helper
+                abstract_graph
                     .add_port(
                         plugin_host.id._node_id().into(),
                         new_port_id,
@@ -391,12 +391,13 @@ pub(super) fn sync_ports_in_graph(
         // removed any of its ports.
         needs_recompile = true;
 
-        let removed_edges_res =
-            graph_helper.remove_port(plugin_host.id._node_id().into(), port_to_remove_id).unwrap();
+        let removed_edges_res = abstract_graph
+            .remove_port(plugin_host.id._node_id().into(), port_to_remove_id)
+            .unwrap();
 
         for edge_id in removed_edges_res.iter() {
-            if let Some(ds_edge_id) = edge_id_to_ds_edge_id.remove(edge_id) {
-                removed_edges.push(ds_edge_id);
+            if let Some(edge) = edges.remove(edge_id) {
+                removed_edges.push(edge.ds_edge_id);
             } else {
                 panic!("Helper disconnected an edge that doesn't exist in graph: {:?}", edge_id);
             }
@@ -4,12 +4,14 @@ mod channel;
 mod main_thread;
 mod processor;
 mod save_state;
+mod voice_table;
 
 pub(crate) mod event_io_buffers;
 pub(crate) mod external;
 
 pub use main_thread::{ParamModifiedInfo, ParamState, PluginHostMainThread};
 pub use save_state::PluginHostSaveState;
+pub use voice_table::VoiceTarget;
 
 pub(crate) use channel::{PluginHostProcessorWrapper, SharedPluginHostProcessor};
 pub(crate) use main_thread::OnIdleResult;
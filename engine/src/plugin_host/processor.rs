@@ -1,11 +1,18 @@
-use clack_host::events::Event;
+use clack_host::events::event_types::NoteExpressionEvent;
+use clack_host::events::{Event, EventFlags, EventHeader};
 use meadowlark_plugin_api::buffer::EventBuffer;
-use meadowlark_plugin_api::{PluginProcessor, ProcBuffers, ProcInfo, ProcessStatus};
+use meadowlark_plugin_api::event::ParamModEvent;
+use meadowlark_plugin_api::{PluginProcessor, ProcBuffers, ProcInfo, ProcessStatus, TailLength};
+use std::time::Instant;
 
 use crate::utils::thread_id::SharedThreadIDs;
 
-use super::channel::{PlugHostChannelProcThread, PluginActiveState};
-use super::event_io_buffers::{PluginEventIoBuffers, PluginEventOutputSanitizer};
+use super::channel::{
+    MainToProcVoiceEventType, PlugHostChannelProcThread, PluginActiveState,
+    PARAM_EVENT_QUEUE_CAPACITY,
+};
+use super::event_io_buffers::{InputEventQueue, PluginEventIoBuffers, PluginEventOutputSanitizer};
+use super::voice_table::VoiceTable;
 
 // The amount of time to smooth/declick the audio outputs when
 // bypassing/unbypassing the plugin.
@@ -25,11 +32,19 @@ pub(crate) struct PluginHostProcessor {
 
     channel: PlugHostChannelProcThread,
 
+    /// The events from the main thread and the input ports, which are sorted
+    /// by time before they are written to `in_events`.
+    input_events: InputEventQueue,
     in_events: EventBuffer,
     out_events: EventBuffer,
 
     event_output_sanitizer: PluginEventOutputSanitizer,
 
+    voices: VoiceTable,
+
+    /// The number of frames since the plugin's inputs have gone silent.
+    silent_input_frames: u64,
+
     processing_state: ProcessingState,
 
     thread_ids: SharedThreadIDs,
@@ -63,9 +78,14 @@ impl PluginHostProcessor {
             plugin_processor,
             plugin_instance_id,
             channel,
+            input_events: InputEventQueue::with_capacity(
+                num_params * 3 + PARAM_EVENT_QUEUE_CAPACITY,
+            ),
             in_events: EventBuffer::with_capacity(num_params * 3),
             out_events: EventBuffer::with_capacity(num_params * 3),
             event_output_sanitizer: PluginEventOutputSanitizer::new(num_params),
+            voices: VoiceTable::new(),
+            silent_input_frames: 0,
             processing_state: ProcessingState::WaitingForStart,
             thread_ids,
             schedule_version,
@@ -84,6 +104,8 @@ impl PluginHostProcessor {
         buffers: &mut ProcBuffers,
         event_buffers: &mut PluginEventIoBuffers,
     ) -> bool {
+        let proc_start_time = Instant::now();
+
         let mut do_process = true;
 
         // Always clear event and note output buffers.
@@ -95,23 +117,44 @@ impl PluginHostProcessor {
 
         self.in_events.clear();
 
+        // Read the transport event from the schedule's transport. This is at
+        // the very start of the block, so it goes before all other events to
+        // keep the buffer sorted by time.
+        self.in_events.push(proc_info.transport.event().as_unknown());
+
+        // Read per-voice events from the main thread. These are also at the
+        // start of the block, so they only target the voices which were
+        // already active before this block.
+        let (mut has_note_in_event, mut has_param_in_event) = self.write_voice_events();
+
         // Read parameter updates from the main thread.
-        let mut has_param_in_event = self
+        has_param_in_event |= self
             .channel
             .param_queues
             .as_mut()
-            .map(|q| q.consume_into_event_buffer(&mut self.in_events))
+            .map(|q| {
+                q.consume_into_event_queue(
+                    &mut self.input_events,
+                    &proc_info.transport,
+                    proc_info.frames,
+                )
+            })
             .unwrap_or(false);
 
-        // Read parameter automation events from the automation in port.
-        let (has_note_in_event, wrote_param_in_event) =
-            event_buffers.write_input_events(&mut self.in_events, self.plugin_instance_id);
+        // Read note events from the note in ports and parameter automation
+        // events from the automation in port.
+        let (wrote_note_in_event, wrote_param_in_event) = event_buffers.write_input_events(
+            &mut self.input_events,
+            self.plugin_instance_id,
+            &mut self.voices,
+            self.channel.param_queues.as_mut().map(|q| &mut q.params),
+        );
+        has_note_in_event |= wrote_note_in_event;
         has_param_in_event |= wrote_param_in_event;
 
-        // Read transport events from the schedule's transport.
-        if let Some(transport_in_event) = proc_info.transport.event() {
-            self.in_events.push(transport_in_event.as_unknown());
-        }
+        // Each of these sources is only sorted by time on its own, so merge
+        // them into a single list sorted by time.
+        self.input_events.write_to_clap_buffer(&event_buffers.note_in_buffers, &mut self.in_events);
 
         // --- Check for requests to drop or start processing ------------------------------------
 
@@ -159,21 +202,12 @@ impl PluginHostProcessor {
             if let ProcessingState::Stopped | ProcessingState::WaitingForStart =
                 self.processing_state
             {
-                if self.processing_state == ProcessingState::Stopped && !has_note_in_event {
-                    // Check if all audio inputs are silent.
-
-                    // First do a quick check using the constant flags.
-                    if buffers.audio_inputs_have_silent_hint() {
-                        do_process = false;
-                    }
-
-                    if do_process {
-                        // If quick check didn't tell us that the buffer was silent,
-                        // then do a slow thorough check.
-                        if buffers.audio_inputs_silent(proc_info.frames) {
-                            do_process = false;
-                        }
-                    }
+                if self.processing_state == ProcessingState::Stopped
+                    && !has_note_in_event
+                    && audio_inputs_silent(buffers, proc_info.frames)
+                {
+                    do_process = false;
+                    self.channel.shared_state.record_skipped_cycle();
                 } else if let Err(e) = self.plugin_processor.start_processing() {
                     log::error!("Plugin has failed to start processing: {}", e);
 
@@ -183,6 +217,7 @@ impl PluginHostProcessor {
                     do_process = false;
                 } else {
                     self.channel.shared_state.set_active_state(PluginActiveState::Active);
+                    self.silent_input_frames = 0;
                 }
             }
         }
@@ -215,6 +250,9 @@ impl PluginHostProcessor {
                     )
                 };
 
+            let tail = self.plugin_processor.tail();
+            self.channel.shared_state.set_tail(tail);
+
             // --- Update the processing state -------------------------------------------------------
 
             self.processing_state = match new_status {
@@ -223,23 +261,42 @@ impl PluginHostProcessor {
                     // Check if the plugin should be put to sleep.
                     if buffers.audio_outputs_have_silent_hint() {
                         self.plugin_processor.stop_processing();
+                        self.voices.clear();
                         ProcessingState::Stopped
                     } else {
                         ProcessingState::Started(ProcessStatus::ContinueIfNotQuiet)
                     }
                 }
                 ProcessStatus::Tail => {
-                    // TODO: handle tail by reading from the tail extension
-                    ProcessingState::Started(ProcessStatus::Tail)
+                    // Let the plugin ring out for as long as its tail once
+                    // its inputs have gone silent, then put it to sleep.
+                    if has_note_in_event || !audio_inputs_silent(buffers, proc_info.frames) {
+                        self.silent_input_frames = 0;
+                    } else {
+                        self.silent_input_frames += proc_info.frames as u64;
+                    }
+
+                    match tail {
+                        TailLength::Frames(frames)
+                            if self.silent_input_frames >= u64::from(frames) =>
+                        {
+                            self.plugin_processor.stop_processing();
+                            self.voices.clear();
+                            ProcessingState::Stopped
+                        }
+                        _ => ProcessingState::Started(ProcessStatus::Tail),
+                    }
                 }
                 ProcessStatus::Sleep => {
                     self.plugin_processor.stop_processing();
+                    self.voices.clear();
 
                     ProcessingState::Stopped
                 }
                 ProcessStatus::Error => {
                     // Discard all output buffers.
                     buffers.clear_all_outputs_and_set_constant_hint(proc_info);
+                    self.voices.clear();
                     ProcessingState::Errored
                 }
             };
@@ -255,24 +312,38 @@ impl PluginHostProcessor {
         // Read output events from the plugin and store them in the automation out port
         // buffer (if this plugin has one).
 
+        let mut num_discarded_events = 0;
         if let Some(params_queue) = &mut self.channel.param_queues {
             // If this plugin has parameters, send parameter updates to the main thread.
             params_queue.to_main_param_value_tx.produce(|mut producer| {
-                event_buffers.read_output_events(
+                event_buffers.report_input_modulation(
+                    self.plugin_instance_id,
+                    &params_queue.params,
+                    &mut producer,
+                );
+                num_discarded_events = event_buffers.read_output_events(
                     &self.out_events,
                     Some(&mut producer),
                     &mut self.event_output_sanitizer,
                     proc_info.frames as u32,
-                )
+                    &mut self.voices,
+                );
             });
         } else {
-            event_buffers.read_output_events(
+            num_discarded_events = event_buffers.read_output_events(
                 &self.out_events,
                 None,
                 &mut self.event_output_sanitizer,
                 proc_info.frames as u32,
+                &mut self.voices,
             );
         }
+        if num_discarded_events > 0 {
+            // Logging is not realtime-safe, so this is logged by the main thread.
+            self.channel.shared_state.record_discarded_sysex_events(num_discarded_events);
+        }
+
+        self.channel.shared_state.set_active_voices(self.voices.num_voices() as u32);
 
         // --- Process bypassing/unbypassing the plugin ------------------------------------------
 
@@ -307,9 +378,58 @@ impl PluginHostProcessor {
             self.bypass(proc_info, buffers);
         }
 
+        self.channel.shared_state.process_time.record_duration(proc_start_time.elapsed());
+
         do_drop
     }
 
+    /// Send the per-voice events from the main thread to every active voice
+    /// they target.
+    ///
+    /// Returns whether a note event and a parameter event were written.
+    fn write_voice_events(&mut self) -> (bool, bool) {
+        let mut wrote_note_event = false;
+        let mut wrote_param_event = false;
+
+        while let Ok(event) = self.channel.voice_event_rx.pop() {
+            for voice in self.voices.targeted(&event.target) {
+                match event.event_type {
+                    MainToProcVoiceEventType::ParamMod { param_id, cookie, amount } => {
+                        let event = ParamModEvent::new(
+                            EventHeader::new_core(0, EventFlags::empty()),
+                            cookie,
+                            voice.note_id,
+                            param_id.as_u32(),
+                            voice.port_index,
+                            voice.channel,
+                            voice.key,
+                            amount,
+                        );
+
+                        self.in_events.push(event.as_unknown());
+                        wrote_param_event = true;
+                    }
+                    MainToProcVoiceEventType::Expression { expression_type, value } => {
+                        let event = NoteExpressionEvent::new(
+                            EventHeader::new_core(0, EventFlags::empty()),
+                            voice.note_id,
+                            voice.port_index,
+                            voice.key,
+                            voice.channel,
+                            value,
+                            expression_type,
+                        );
+
+                        self.in_events.push(event.as_unknown());
+                        wrote_note_event = true;
+                    }
+                }
+            }
+        }
+
+        (wrote_note_event, wrote_param_event)
+    }
+
     /// The plugin is currently in the process of smoothing/declicking the audio
     /// output buffers as a result of bypassing/unbypassing the plugin.
     fn bypass_declick(&mut self, proc_info: &ProcInfo, buffers: &mut ProcBuffers) {
@@ -432,6 +552,13 @@ impl PluginHostProcessor {
     }
 }
 
+/// Returns `true` if all of the audio inputs are silent.
+fn audio_inputs_silent(buffers: &ProcBuffers, frames: usize) -> bool {
+    // First do a quick check using the constant flags. If that didn't tell us
+    // that the buffers are silent, then do a slow thorough check.
+    buffers.audio_inputs_have_silent_hint() || buffers.audio_inputs_silent(frames)
+}
+
 impl Drop for PluginHostProcessor {
     fn drop(&mut self) {
         if self.thread_ids.is_process_thread() {
@@ -1,14 +1,29 @@
 use std::fmt::Debug;
+use std::path::PathBuf;
 
 use crate::plugin_scanner::ScannedPluginKey;
 use clack_extensions::gui::GuiSize;
 use meadowlark_plugin_api::ext::audio_ports::PluginAudioPortsExt;
 use meadowlark_plugin_api::ext::note_ports::PluginNotePortsExt;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PluginHostSaveState {
     pub key: ScannedPluginKey,
 
+    /// The path to the binary this plugin was loaded from. This is `None`
+    /// for internal plugins.
+    ///
+    /// When this plugin is loaded again, this binary will be used if it still
+    /// exists (even if other binaries contain the same plugin).
+    pub binary_path: Option<PathBuf>,
+
+    /// The version of the plugin that was loaded.
+    ///
+    /// If the binary this plugin was saved with no longer exists, then a
+    /// binary with the same version of this plugin will be preferred.
+    pub plugin_version: Option<String>,
+
     /// If this is `false` when receiving a save state, then it means that
     /// the plugin was manually deactivated at the time of collecting the
     /// save state of the plugin/project.
@@ -32,6 +47,7 @@ pub struct PluginHostSaveState {
     pub backup_note_ports_ext: Option<PluginNotePortsExt>,
 
     /// The latest recorded size of the plugin's GUI.
+    #[serde(with = "serde_gui_size")]
     pub gui_size: Option<GuiSize>,
 
     /// The plugin's state/preset as raw bytes.
@@ -45,6 +61,8 @@ impl PluginHostSaveState {
     pub fn new_with_default_state(key: ScannedPluginKey) -> Self {
         Self {
             key,
+            binary_path: None,
+            plugin_version: None,
             active: true,
             bypassed: false,
             backup_audio_ports_ext: None,
@@ -60,6 +78,8 @@ impl Debug for PluginHostSaveState {
         let mut f = f.debug_struct("PluginHostSaveState");
 
         f.field("key", &self.key);
+        f.field("binary_path", &self.binary_path);
+        f.field("plugin_version", &self.plugin_version);
         f.field("active", &self.active);
         f.field("bypassed", &self.bypassed);
         f.field("backup_audio_ports_ext", &self.backup_audio_ports_ext);
@@ -75,3 +95,18 @@ impl Debug for PluginHostSaveState {
         f.finish()
     }
 }
+
+/// `GuiSize` is defined by `clack_extensions`, so it is (de)serialized as a
+/// `(width, height)` tuple.
+mod serde_gui_size {
+    use clack_extensions::gui::GuiSize;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(size: &Option<GuiSize>, s: S) -> Result<S::Ok, S::Error> {
+        size.map(|size| (size.width, size.height)).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<GuiSize>, D::Error> {
+        Ok(Option::<(u32, u32)>::deserialize(d)?.map(|(width, height)| GuiSize { width, height }))
+    }
+}
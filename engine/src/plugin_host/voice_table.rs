@@ -0,0 +1,159 @@
+/// The maximum number of voices tracked per plugin. If a plugin has more
+/// voices than this, the oldest ones are forgotten.
+pub(super) const MAX_TRACKED_VOICES: usize = 512;
+
+/// Selects which active voices of a plugin an event is sent to.
+///
+/// A value of `-1` in any of the fields acts as a wildcard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoiceTarget {
+    pub port_index: i16,
+    pub channel: i16,
+    pub key: i16,
+}
+
+impl VoiceTarget {
+    /// Target all active voices.
+    pub const ALL: Self = Self { port_index: -1, channel: -1, key: -1 };
+
+    /// Target all active voices playing the given key.
+    pub fn key(key: i16) -> Self {
+        Self { key, ..Self::ALL }
+    }
+}
+
+/// A voice which a plugin is currently playing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Voice {
+    pub note_id: i32,
+    pub port_index: i16,
+    pub channel: i16,
+    pub key: i16,
+}
+
+impl Voice {
+    fn matches(&self, note_id: i32, port_index: i16, channel: i16, key: i16) -> bool {
+        (note_id == -1 || note_id == self.note_id)
+            && (port_index == -1 || port_index == self.port_index)
+            && (channel == -1 || channel == self.channel)
+            && (key == -1 || key == self.key)
+    }
+}
+
+/// Keeps track of the voices a plugin is currently playing, so the host can
+/// send per-voice events to them.
+///
+/// A voice is added when a note on event is sent to the plugin, and it is
+/// removed once the plugin sends back a note end event for it.
+pub(crate) struct VoiceTable {
+    voices: Vec<Voice>,
+    next_note_id: i32,
+}
+
+impl VoiceTable {
+    pub fn new() -> Self {
+        Self { voices: Vec::with_capacity(MAX_TRACKED_VOICES), next_note_id: 0 }
+    }
+
+    /// Add a new voice to the table.
+    ///
+    /// If the note on event has no note ID (`-1`), then a new one is assigned
+    /// by the host. This returns the note ID of the new voice.
+    pub fn note_on(&mut self, port_index: i16, channel: i16, key: i16, note_id: i32) -> i32 {
+        let note_id = if note_id == -1 {
+            let note_id = self.next_note_id;
+            self.next_note_id = self.next_note_id.checked_add(1).unwrap_or(0);
+            note_id
+        } else {
+            note_id
+        };
+
+        // Don't allocate in the process thread.
+        if self.voices.len() == MAX_TRACKED_VOICES {
+            self.voices.remove(0);
+        }
+
+        self.voices.push(Voice { note_id, port_index, channel, key });
+
+        note_id
+    }
+
+    /// Remove all voices matching the note end event sent by the plugin.
+    pub fn note_end(&mut self, port_index: i16, channel: i16, key: i16, note_id: i32) {
+        self.voices.retain(|v| !v.matches(note_id, port_index, channel, key));
+    }
+
+    /// Remove all voices (i.e. when the plugin stops processing).
+    pub fn clear(&mut self) {
+        self.voices.clear();
+    }
+
+    pub fn num_voices(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// All active voices selected by the given target.
+    pub fn targeted<'a>(&'a self, target: &'a VoiceTarget) -> impl Iterator<Item = &'a Voice> {
+        self.voices.iter().filter(|v| v.matches(-1, target.port_index, target.channel, target.key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_ids<'a>(voices: impl Iterator<Item = &'a Voice>) -> Vec<i32> {
+        voices.map(|v| v.note_id).collect()
+    }
+
+    #[test]
+    fn host_assigns_missing_note_ids() {
+        let mut table = VoiceTable::new();
+
+        assert_eq!(table.note_on(0, 0, 60, -1), 0);
+        assert_eq!(table.note_on(0, 0, 62, 42), 42);
+        assert_eq!(table.note_on(0, 0, 64, -1), 1);
+        assert_eq!(table.num_voices(), 3);
+    }
+
+    #[test]
+    fn note_end_removes_matching_voices() {
+        let mut table = VoiceTable::new();
+        table.note_on(0, 0, 60, -1);
+        table.note_on(0, 1, 60, -1);
+        table.note_on(1, 0, 62, -1);
+
+        // Only the voice with the given note ID ends.
+        table.note_end(0, 0, 60, 1);
+        assert_eq!(note_ids(table.targeted(&VoiceTarget::ALL)), vec![0, 2]);
+
+        // Wildcards end every matching voice.
+        table.note_end(-1, -1, -1, -1);
+        assert_eq!(table.num_voices(), 0);
+    }
+
+    #[test]
+    fn targets_select_voices() {
+        let mut table = VoiceTable::new();
+        table.note_on(0, 0, 60, -1);
+        table.note_on(0, 1, 60, -1);
+        table.note_on(1, 0, 62, -1);
+
+        assert_eq!(note_ids(table.targeted(&VoiceTarget::ALL)), vec![0, 1, 2]);
+        assert_eq!(note_ids(table.targeted(&VoiceTarget::key(60))), vec![0, 1]);
+
+        let target = VoiceTarget { port_index: 1, ..VoiceTarget::ALL };
+        assert_eq!(note_ids(table.targeted(&target)), vec![2]);
+    }
+
+    #[test]
+    fn oldest_voices_are_forgotten() {
+        let mut table = VoiceTable::new();
+        for key in 0..MAX_TRACKED_VOICES + 1 {
+            table.note_on(0, 0, (key % 128) as i16, -1);
+        }
+
+        assert_eq!(table.num_voices(), MAX_TRACKED_VOICES);
+        assert_eq!(table.targeted(&VoiceTarget::ALL).next().map(|v| v.note_id), Some(1));
+    }
+}
@@ -1,10 +1,13 @@
 use audio_graph::NodeID;
 use basedrop::Shared;
 use fnv::FnvHashMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use walkdir::WalkDir;
+use std::sync::mpsc;
+use std::time::Duration;
 
 use meadowlark_plugin_api::{
     HostInfo, HostRequestChannelReceiver, PluginDescriptor, PluginFactory, PluginFormat,
@@ -16,7 +19,12 @@ use crate::plugin_host::{PluginHostMainThread, PluginHostSaveState};
 use crate::utils::thread_id::SharedThreadIDs;
 
 mod missing_plugin;
+mod scan_cache;
+mod scan_thread;
+
 use missing_plugin::MissingPluginMainThread;
+use scan_cache::ScanCache;
+use scan_thread::{spawn_scan_thread, ScanJob, ScanJobResult};
 
 #[cfg(any(
     target_os = "linux",
@@ -33,9 +41,7 @@ const DEFAULT_CLAP_SCAN_DIRECTORIES: [&str; 1] = ["/Library/Audio/Plug-Ins/CLAP"
 // TODO: Find the proper "Common Files" folder at runtime.
 const DEFAULT_CLAP_SCAN_DIRECTORIES: [&str; 1] = ["C:/Program Files/Common Files/CLAP"];
 
-const MAX_SCAN_DEPTH: usize = 10;
-
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ScannedPluginKey {
     pub rdn: String,
     pub format: PluginFormat,
@@ -47,12 +53,49 @@ pub struct ScannedPluginInfo {
     pub format: PluginFormat,
     pub format_version: String,
     pub key: ScannedPluginKey,
+
+    /// The path to the binary which contains this plugin. This is `None`
+    /// for internal plugins.
+    ///
+    /// Note that multiple binaries may contain a plugin with the same key
+    /// (i.e. different versions of the same plugin), in which case there
+    /// will be one `ScannedPluginInfo` for each of them.
+    pub binary_path: Option<PathBuf>,
 }
 
 impl ScannedPluginInfo {
     pub fn rdn(&self) -> &str {
         self.key.rdn.as_str()
     }
+
+    /// The version of this plugin.
+    pub fn version(&self) -> &str {
+        self.description.version.as_str()
+    }
+}
+
+/// How to choose which binary to load a plugin from when multiple binaries
+/// contain a plugin with the same ID.
+///
+/// This is only used when the plugin is not pinned to a specific binary
+/// with `EngineMainThread::pin_plugin_binary()`, and the binary the plugin
+/// was saved with (if any) can't be found.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum DuplicatePluginPolicy {
+    /// Use the binary with the newest version of the plugin.
+    #[default]
+    NewestVersion,
+
+    /// Prefer binaries inside of this directory, and fall back to the binary
+    /// with the newest version of the plugin.
+    PreferDirectory(PathBuf),
+}
+
+/// A binary which contains a plugin with a given key.
+struct PluginCandidate {
+    bundle_key: u32,
+    binary_path: PathBuf,
+    version: String,
 }
 
 struct LoadedPluginFactory {
@@ -70,11 +113,21 @@ struct ScannedPluginBundle {
 pub(crate) struct PluginScanner {
     scanned_internal_plugins: HashMap<ScannedPluginKey, ScannedPluginBundle>,
 
-    scanned_external_plugins: HashMap<ScannedPluginKey, u32>,
+    scanned_external_plugins: HashMap<ScannedPluginKey, Vec<PluginCandidate>>,
     external_plugin_bundles: FnvHashMap<u32, ScannedPluginBundle>,
 
+    duplicate_plugin_policy: DuplicatePluginPolicy,
+    pinned_binaries: HashMap<ScannedPluginKey, PathBuf>,
+
     clap_scan_directories: Vec<PathBuf>,
 
+    /// This is `None` while a scan is in progress, in which case the cache
+    /// is owned by the scanner thread.
+    scan_cache: Option<ScanCache>,
+    scan_cache_path: Option<PathBuf>,
+    scan_timeout: Duration,
+    scan_result_rx: Option<mpsc::Receiver<ScanJobResult>>,
+
     host_info: Shared<HostInfo>,
 
     thread_ids: SharedThreadIDs,
@@ -89,14 +142,25 @@ impl PluginScanner {
         coll_handle: basedrop::Handle,
         host_info: Shared<HostInfo>,
         thread_ids: SharedThreadIDs,
+        scan_cache_path: Option<PathBuf>,
+        scan_timeout: Duration,
+        duplicate_plugin_policy: DuplicatePluginPolicy,
     ) -> Self {
         Self {
             scanned_internal_plugins: HashMap::default(),
             scanned_external_plugins: HashMap::default(),
             external_plugin_bundles: FnvHashMap::default(),
 
+            duplicate_plugin_policy,
+            pinned_binaries: HashMap::default(),
+
             clap_scan_directories: Vec::new(),
 
+            scan_cache: Some(ScanCache::load(scan_cache_path.clone())),
+            scan_cache_path,
+            scan_timeout,
+            scan_result_rx: None,
+
             host_info,
 
             thread_ids,
@@ -189,139 +253,218 @@ impl PluginScanner {
         Ok(key)
     }
 
-    pub fn scan_external_plugins(&mut self) -> ScanExternalPluginsRes {
+    /// Start (re)scanning all external plugins in a separate thread.
+    ///
+    /// This returns `false` if a scan is already in progress.
+    pub fn start_scanning_external_plugins(&mut self) -> bool {
+        let cache = if let Some(cache) = self.scan_cache.take() {
+            cache
+        } else {
+            log::warn!("Ignored request to scan plugins: A scan is already in progress");
+            return false;
+        };
+
         log::info!("(Re)scanning plugin directories...");
 
-        // TODO: Detect duplicate plugins (both duplicates with different versions and with different formats)
+        let mut scan_directories: Vec<PathBuf> =
+            DEFAULT_CLAP_SCAN_DIRECTORIES.iter().map(|s| PathBuf::from_str(s).unwrap()).collect();
+
+        if let Some(mut dir) = dirs::home_dir() {
+            dir.push(".clap");
+            scan_directories.push(dir);
+        } else {
+            log::warn!(
+                "Could not search local clap plugin directory: Could not get user's home directory"
+            );
+        }
+
+        scan_directories.extend(self.clap_scan_directories.iter().cloned());
+
+        match spawn_scan_thread(ScanJob {
+            scan_directories,
+            cache,
+            timeout: self.scan_timeout,
+            thread_ids: self.thread_ids.clone(),
+            coll_handle: self.coll_handle.clone(),
+        }) {
+            Ok(scan_result_rx) => {
+                self.scan_result_rx = Some(scan_result_rx);
+                true
+            }
+            Err(e) => {
+                log::error!("Failed to spawn plugin scanner thread: {}", e);
+                self.scan_cache = Some(ScanCache::load(self.scan_cache_path.clone()));
+                false
+            }
+        }
+    }
+
+    pub fn is_scanning_external_plugins(&self) -> bool {
+        self.scan_result_rx.is_some()
+    }
+
+    /// Poll for the result of the scan started with
+    /// `PluginScanner::start_scanning_external_plugins()`.
+    ///
+    /// This returns `None` if no scan has finished since the last call.
+    pub fn poll_scan_result(&mut self) -> Option<ScanExternalPluginsRes> {
+        let res = match self.scan_result_rx.as_ref()?.try_recv() {
+            Ok(res) => res,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                log::error!("Plugin scanner thread panicked");
+
+                self.scan_result_rx = None;
+                self.scan_cache = Some(ScanCache::load(self.scan_cache_path.clone()));
+
+                return None;
+            }
+        };
+
+        self.scan_result_rx = None;
 
-        // TODO: Scan plugins in a separate thread?
+        let ScanJobResult { cache, mut scanned_binaries, failed_binaries, blocked_binaries } = res;
+        self.scan_cache = Some(cache);
 
         self.scanned_external_plugins.clear();
         self.external_plugin_bundles.clear();
         let mut scanned_plugins: Vec<ScannedPluginInfo> = Vec::new();
-        let mut failed_plugins: Vec<(PathBuf, String)> = Vec::new();
 
-        let mut next_external_factory_key: u32 = 0;
+        for (next_external_factory_key, (binary_path, mut plugins)) in
+            (0u32..).zip(scanned_binaries.drain(..))
+        {
+            let _ = self.external_plugin_bundles.insert(
+                next_external_factory_key,
+                ScannedPluginBundle {
+                    binary_path: Some(binary_path.clone()),
+                    // We will reload the factories once a plugin is added to the graph.
+                    loaded_factories: None,
+                },
+            );
 
-        /*
-        for (key, f) in self.scanned_internal_plugins.iter() {
-            scanned_plugins.push(ScannedPlugin {
-                description: f.factory.description(),
-                format: PluginFormat::Internal,
-                format_version: env!("CARGO_PKG_VERSION").into(),
-                key: key.clone(),
-            })
+            for plugin in plugins.drain(..) {
+                let id = plugin.descriptor.id.clone();
+
+                log::debug!(
+                    "Successfully scanned CLAP plugin with ID: {}, version {}, and CLAP version {}",
+                    &id,
+                    &plugin.descriptor.version,
+                    &plugin.format_version,
+                );
+                log::trace!("Full plugin descriptor: {:?}", &plugin.descriptor);
+
+                let key = ScannedPluginKey { rdn: id.clone(), format: PluginFormat::Clap };
+
+                let candidates = self.scanned_external_plugins.entry(key.clone()).or_default();
+                if !candidates.is_empty() {
+                    log::info!(
+                        "Found duplicate CLAP plugin with ID: {}, version {} in binary {:?}",
+                        &id,
+                        &plugin.descriptor.version,
+                        &binary_path
+                    );
+                }
+                candidates.push(PluginCandidate {
+                    bundle_key: next_external_factory_key,
+                    binary_path: binary_path.clone(),
+                    version: plugin.descriptor.version.clone(),
+                });
+
+                scanned_plugins.push(ScannedPluginInfo {
+                    description: plugin.descriptor,
+                    format: PluginFormat::Clap,
+                    format_version: plugin.format_version,
+                    key,
+                    binary_path: Some(binary_path.clone()),
+                });
+            }
         }
-        */
 
-        {
-            let mut found_binaries: Vec<PathBuf> = Vec::new();
+        Some(ScanExternalPluginsRes {
+            scanned_plugins,
+            failed_plugins: failed_binaries,
+            blocked_plugins: blocked_binaries,
+        })
+    }
 
-            let mut scan_directories: Vec<PathBuf> = DEFAULT_CLAP_SCAN_DIRECTORIES
-                .iter()
-                .map(|s| PathBuf::from_str(s).unwrap())
-                .collect();
+    pub fn set_duplicate_plugin_policy(&mut self, policy: DuplicatePluginPolicy) {
+        self.duplicate_plugin_policy = policy;
+    }
 
-            if let Some(mut dir) = dirs::home_dir() {
-                dir.push(".clap");
-                scan_directories.push(dir);
-            } else {
-                log::warn!("Could not search local clap plugin directory: Could not get user's home directory");
-            }
+    /// Always load the plugin with the given key from this binary (as long as
+    /// the binary was found in the latest scan).
+    pub fn pin_binary(&mut self, key: ScannedPluginKey, binary_path: PathBuf) {
+        log::info!("Pinned plugin {:?} to binary {:?}", &key, &binary_path);
+        self.pinned_binaries.insert(key, binary_path);
+    }
 
-            for dir in scan_directories.iter().chain(self.clap_scan_directories.iter()) {
-                let walker = WalkDir::new(dir).max_depth(MAX_SCAN_DEPTH).follow_links(true);
+    pub fn unpin_binary(&mut self, key: &ScannedPluginKey) -> bool {
+        self.pinned_binaries.remove(key).is_some()
+    }
 
-                for item in walker {
-                    match item {
-                        Ok(binary) => {
-                            if !binary.file_type().is_file() {
-                                continue;
-                            }
+    /// Choose which binary to load an external plugin from.
+    ///
+    /// In order of priority, this picks:
+    /// * The binary the plugin was saved with
+    /// * The binary the user pinned this plugin to
+    /// * A binary with the same version of the plugin the plugin was saved with
+    /// * The binary chosen by the `DuplicatePluginPolicy`
+    fn select_candidate(
+        candidates: &[PluginCandidate],
+        save_state: &PluginHostSaveState,
+        pinned_binary: Option<&PathBuf>,
+        policy: &DuplicatePluginPolicy,
+    ) -> Option<u32> {
+        let find_by_path = |path: &PathBuf| candidates.iter().find(|c| &c.binary_path == path);
+
+        if let Some(c) = save_state.binary_path.as_ref().and_then(find_by_path) {
+            return Some(c.bundle_key);
+        }
 
-                            match binary.path().extension().and_then(|e| e.to_str()) {
-                                Some(ext) if ext == "clap" => {}
-                                _ => continue,
-                            };
+        if let Some(c) = pinned_binary.and_then(find_by_path) {
+            return Some(c.bundle_key);
+        }
 
-                            let binary_path = binary.into_path();
-                            log::trace!("Found CLAP binary: {:?}", &binary_path);
-                            found_binaries.push(binary_path);
-                        }
-                        Err(e) => {
-                            log::warn!("Failed to scan binary for potential CLAP plugin: {}", e);
-                        }
-                    }
-                }
+        if let Some(version) = &save_state.plugin_version {
+            if let Some(c) = candidates.iter().find(|c| &c.version == version) {
+                return Some(c.bundle_key);
             }
+        }
 
-            for binary_path in found_binaries.iter() {
-                match crate::plugin_host::external::clap::factory::entry_init(
-                    binary_path,
-                    self.thread_ids.clone(),
-                    &self.coll_handle,
-                ) {
-                    Ok(mut factories) => {
-                        let _ = self.external_plugin_bundles.insert(
-                            next_external_factory_key,
-                            ScannedPluginBundle {
-                                binary_path: Some(binary_path.clone()),
-                                // We will reload the factories once a plugin is added to the graph.
-                                loaded_factories: None,
-                            },
-                        );
+        if let DuplicatePluginPolicy::PreferDirectory(dir) = policy {
+            if let Some(c) =
+                newest_candidate(candidates.iter().filter(|c| c.binary_path.starts_with(dir)))
+            {
+                return Some(c.bundle_key);
+            }
+        }
 
-                        for f in factories.drain(..) {
-                            let id: String = f.description().id.clone();
-                            let v = f.clap_version;
-                            let format_version = format!("{}.{}.{}", v.major, v.minor, v.revision);
+        newest_candidate(candidates.iter()).map(|c| c.bundle_key)
+    }
 
-                            log::debug!(
-                                "Successfully scanned CLAP plugin with ID: {}, version {}, and CLAP version {}",
-                                &id,
-                                &f.description().version,
-                                &format_version,
-                            );
-                            log::trace!("Full plugin descriptor: {:?}", f.description());
-
-                            let key =
-                                ScannedPluginKey { rdn: id.clone(), format: PluginFormat::Clap };
-
-                            let description = f.description();
-
-                            scanned_plugins.push(ScannedPluginInfo {
-                                description,
-                                format: PluginFormat::Clap,
-                                format_version,
-                                key: key.clone(),
-                            });
-
-                            if self
-                                .scanned_external_plugins
-                                .insert(key, next_external_factory_key)
-                                .is_some()
-                            {
-                                // TODO: Handle this better
-                                log::warn!("Found duplicate CLAP plugins with ID: {}", &id);
-                                let _ = scanned_plugins.pop();
-                            }
-                        }
+    /// The binaries which will not be loaded while scanning, along with the
+    /// reason they were blocked.
+    ///
+    /// This returns `None` while a scan is in progress.
+    pub fn scan_blocklist(&self) -> Option<Vec<(PathBuf, String)>> {
+        self.scan_cache.as_ref().map(|cache| cache.blocklist())
+    }
 
-                        next_external_factory_key += 1;
-                    }
-                    Err(e) => {
-                        log::error!(
-                            "Failed to scan CLAP plugin binary at {:?}: {}",
-                            binary_path,
-                            e
-                        );
-                        failed_plugins.push((binary_path.clone(), e));
-                    }
-                }
+    /// Remove a binary from the blocklist so it will be loaded again on the
+    /// next scan.
+    ///
+    /// This returns `false` if the binary was not in the blocklist or if a scan
+    /// is in progress.
+    pub fn unblock_binary(&mut self, binary_path: &Path) -> bool {
+        if let Some(cache) = &mut self.scan_cache {
+            if cache.unblock(binary_path) {
+                log::info!("Removed plugin binary {:?} from the blocklist", binary_path);
+                cache.save();
+                return true;
             }
         }
 
-        ScanExternalPluginsRes { scanned_plugins, failed_plugins }
+        false
     }
 
     pub(crate) fn create_plugin(
@@ -361,16 +504,21 @@ impl PluginScanner {
         if plugin_bundle.is_none()
             && (save_state.key.format == PluginFormat::Clap || fallback_to_other_formats)
         {
-            let pb = if save_state.key.format == PluginFormat::Clap {
-                self.scanned_external_plugins.get(&save_state.key)
+            let key = if save_state.key.format == PluginFormat::Clap {
+                save_state.key.clone()
             } else {
-                let new_key = ScannedPluginKey {
-                    rdn: save_state.key.rdn.clone(),
-                    format: PluginFormat::Clap,
-                };
-                self.scanned_external_plugins.get(&new_key)
+                ScannedPluginKey { rdn: save_state.key.rdn.clone(), format: PluginFormat::Clap }
             };
 
+            let pb = self.scanned_external_plugins.get(&key).and_then(|candidates| {
+                Self::select_candidate(
+                    candidates,
+                    &save_state,
+                    self.pinned_binaries.get(&key),
+                    &self.duplicate_plugin_policy,
+                )
+            });
+
             if let Some(plugin_bundle_key) = &pb {
                 plugin_bundle = self.external_plugin_bundles.get_mut(plugin_bundle_key);
             } else {
@@ -386,6 +534,8 @@ impl PluginScanner {
         let (host_request_rx, channel_send) =
             HostRequestChannelReceiver::new_channel(self.thread_ids.main_thread_id().unwrap());
 
+        let binary_path = plugin_bundle.as_ref().and_then(|pb| pb.binary_path.clone());
+
         let plugin_factory = if let Some(plugin_bundle) = plugin_bundle {
             let loaded_factories =
                 if let Some(loaded_factories) = plugin_bundle.loaded_factories.as_mut() {
@@ -457,6 +607,11 @@ impl PluginScanner {
                 };
             }
 
+            // Remember exactly which binary was loaded, so that the same one
+            // gets picked when this plugin is loaded again.
+            save_state.binary_path = binary_path;
+            save_state.plugin_version = Some(plugin_factory.factory.description().version);
+
             let id = PluginInstanceID::_new(
                 node_id.into(),
                 self.next_plug_unique_id,
@@ -535,6 +690,30 @@ impl PluginScanner {
     }
 }
 
+/// The candidate with the newest version of the plugin. If multiple
+/// candidates have the same version, then the first one is used.
+fn newest_candidate<'a>(
+    candidates: impl Iterator<Item = &'a PluginCandidate>,
+) -> Option<&'a PluginCandidate> {
+    candidates.fold(None, |newest, c| match newest {
+        Some(n) if compare_versions(&c.version, &n.version) != Ordering::Greater => Some(n),
+        _ => Some(c),
+    })
+}
+
+/// Compare two plugin versions (i.e. "1.4.4" or "1.1.2_beta") by their
+/// numeric components.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let components = |v: &str| -> Vec<u64> {
+        v.split(|c: char| !c.is_ascii_digit())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().unwrap_or(u64::MAX))
+            .collect()
+    };
+
+    components(a).cmp(&components(b))
+}
+
 pub(crate) struct CreatePluginResult {
     pub plugin_host: PluginHostMainThread,
     pub status: Result<(), NewPluginInstanceError>,
@@ -543,5 +722,166 @@ pub(crate) struct CreatePluginResult {
 #[derive(Debug)]
 pub struct ScanExternalPluginsRes {
     pub scanned_plugins: Vec<ScannedPluginInfo>,
+
+    /// The binaries which failed to load, along with the error.
     pub failed_plugins: Vec<(PathBuf, String)>,
+
+    /// The binaries which were skipped because they are in the blocklist
+    /// (because they crashed or hung while being scanned), along with the
+    /// reason they were blocked.
+    pub blocked_plugins: Vec<(PathBuf, String)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(bundle_key: u32, binary_path: &str, version: &str) -> PluginCandidate {
+        PluginCandidate {
+            bundle_key,
+            binary_path: PathBuf::from(binary_path),
+            version: version.into(),
+        }
+    }
+
+    fn save_state(binary_path: Option<&str>, plugin_version: Option<&str>) -> PluginHostSaveState {
+        let mut save_state = PluginHostSaveState::new_with_default_state(ScannedPluginKey {
+            rdn: "org.example.plugin".into(),
+            format: PluginFormat::Clap,
+        });
+        save_state.binary_path = binary_path.map(PathBuf::from);
+        save_state.plugin_version = plugin_version.map(String::from);
+        save_state
+    }
+
+    fn candidates() -> Vec<PluginCandidate> {
+        vec![
+            candidate(0, "/usr/lib/clap/plugin.clap", "1.9"),
+            candidate(1, "/home/user/.clap/plugin.clap", "1.10"),
+            candidate(2, "/opt/clap/plugin.clap", "1.2"),
+        ]
+    }
+
+    #[test]
+    fn select_candidate_prefers_saved_binary() {
+        let pinned = PathBuf::from("/opt/clap/plugin.clap");
+
+        let selected = PluginScanner::select_candidate(
+            &candidates(),
+            &save_state(Some("/usr/lib/clap/plugin.clap"), Some("1.2")),
+            Some(&pinned),
+            &DuplicatePluginPolicy::PreferDirectory("/opt/clap".into()),
+        );
+
+        assert_eq!(selected, Some(0));
+    }
+
+    #[test]
+    fn select_candidate_prefers_pinned_binary_over_version() {
+        let pinned = PathBuf::from("/usr/lib/clap/plugin.clap");
+
+        // The saved binary no longer exists.
+        let selected = PluginScanner::select_candidate(
+            &candidates(),
+            &save_state(Some("/removed/plugin.clap"), Some("1.2")),
+            Some(&pinned),
+            &DuplicatePluginPolicy::NewestVersion,
+        );
+
+        assert_eq!(selected, Some(0));
+    }
+
+    #[test]
+    fn select_candidate_prefers_saved_version_over_policy() {
+        let pinned = PathBuf::from("/removed/plugin.clap");
+
+        let selected = PluginScanner::select_candidate(
+            &candidates(),
+            &save_state(None, Some("1.2")),
+            Some(&pinned),
+            &DuplicatePluginPolicy::PreferDirectory("/usr/lib/clap".into()),
+        );
+
+        assert_eq!(selected, Some(2));
+    }
+
+    #[test]
+    fn select_candidate_falls_back_to_policy() {
+        let save_state = save_state(None, Some("0.1"));
+
+        let selected = PluginScanner::select_candidate(
+            &candidates(),
+            &save_state,
+            None,
+            &DuplicatePluginPolicy::PreferDirectory("/usr/lib/clap".into()),
+        );
+        assert_eq!(selected, Some(0));
+
+        // No candidate in the preferred directory.
+        let selected = PluginScanner::select_candidate(
+            &candidates(),
+            &save_state,
+            None,
+            &DuplicatePluginPolicy::PreferDirectory("/nowhere".into()),
+        );
+        assert_eq!(selected, Some(1));
+
+        let selected = PluginScanner::select_candidate(
+            &candidates(),
+            &save_state,
+            None,
+            &DuplicatePluginPolicy::NewestVersion,
+        );
+        assert_eq!(selected, Some(1));
+    }
+
+    #[test]
+    fn select_candidate_uses_first_of_equal_versions() {
+        let candidates = vec![
+            candidate(4, "/a/plugin.clap", "2.0"),
+            candidate(5, "/b/plugin.clap", "2.0"),
+            candidate(6, "/c/plugin.clap", "1.0"),
+        ];
+
+        let selected = PluginScanner::select_candidate(
+            &candidates,
+            &save_state(None, None),
+            None,
+            &DuplicatePluginPolicy::NewestVersion,
+        );
+        assert_eq!(selected, Some(4));
+
+        let selected = PluginScanner::select_candidate(
+            &[],
+            &save_state(None, None),
+            None,
+            &DuplicatePluginPolicy::NewestVersion,
+        );
+        assert_eq!(selected, None);
+    }
+
+    #[test]
+    fn compare_versions_numerically() {
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.9", "1.10"), Ordering::Less);
+        assert_eq!(compare_versions("1.4.4", "1.4.4"), Ordering::Equal);
+        assert_eq!(compare_versions("2", "1.99.99"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0.1", "1.0"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compare_versions_ignores_suffixes() {
+        assert_eq!(compare_versions("1.1.2_beta", "1.1.2"), Ordering::Equal);
+        assert_eq!(compare_versions("v1.2", "1.2"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2_beta", "1.1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.1.2 (build 3)", "1.1.2"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compare_versions_with_empty_strings() {
+        assert_eq!(compare_versions("", ""), Ordering::Equal);
+        assert_eq!(compare_versions("", "0.1"), Ordering::Less);
+        assert_eq!(compare_versions("1.0", ""), Ordering::Greater);
+        assert_eq!(compare_versions("beta", ""), Ordering::Equal);
+    }
 }
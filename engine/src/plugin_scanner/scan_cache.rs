@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use meadowlark_plugin_api::PluginDescriptor;
+
+use crate::utils::fs::write_atomic;
+
+const CACHE_FILE_HEADER: &str = "meadowlark-plugin-scan-cache 1";
+
+/// The extension of the marker file which holds the path of the binary that
+/// is currently being scanned.
+const SCAN_MARKER_EXTENSION: &str = "scanning";
+
+/// A plugin found inside of a scanned CLAP binary.
+#[derive(Debug, Clone)]
+pub(super) struct ScannedClapPlugin {
+    pub descriptor: PluginDescriptor,
+    pub format_version: String,
+}
+
+/// Used to detect whether a binary has changed since it was last scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct BinaryFingerprint {
+    modified_secs: u64,
+    modified_nanos: u32,
+    size: u64,
+}
+
+impl BinaryFingerprint {
+    pub fn of(binary_path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(binary_path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(Self {
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            size: metadata.len(),
+        })
+    }
+}
+
+struct CachedBinary {
+    fingerprint: BinaryFingerprint,
+    result: Result<Vec<ScannedClapPlugin>, String>,
+}
+
+/// The results of previous plugin scans, along with the list of binaries
+/// which should never be scanned again.
+///
+/// If a cache path is given, then this is persisted to disk so rescans only
+/// need to load binaries which have changed.
+pub(super) struct ScanCache {
+    path: Option<PathBuf>,
+
+    binaries: HashMap<PathBuf, CachedBinary>,
+    blocklist: HashMap<PathBuf, String>,
+}
+
+impl ScanCache {
+    /// Load the cache from the given file.
+    ///
+    /// If the file doesn't exist or can't be parsed, then this will start with
+    /// an empty cache.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut cache = Self { path, binaries: HashMap::new(), blocklist: HashMap::new() };
+
+        let path = if let Some(path) = &cache.path {
+            path
+        } else {
+            return cache;
+        };
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                if e.kind() != ErrorKind::NotFound {
+                    log::warn!("Failed to read plugin scan cache at {:?}: {}", path, e);
+                }
+                return cache;
+            }
+        };
+
+        if let Err(e) = cache.parse(&contents) {
+            log::warn!("Ignoring invalid plugin scan cache at {:?}: {}", path, e);
+            cache.binaries.clear();
+            cache.blocklist.clear();
+        }
+
+        cache
+    }
+
+    fn parse(&mut self, contents: &str) -> Result<(), String> {
+        let mut lines = contents.lines();
+
+        if lines.next() != Some(CACHE_FILE_HEADER) {
+            return Err("unrecognized header".into());
+        }
+
+        let mut current_binary: Option<PathBuf> = None;
+
+        for line in lines {
+            let fields: Vec<String> = line.split('\t').map(unescape).collect();
+
+            match fields.first().map(|s| s.as_str()) {
+                Some("binary") if fields.len() == 5 => {
+                    let path = PathBuf::from(&fields[1]);
+                    let fingerprint = BinaryFingerprint {
+                        modified_secs: parse_number(&fields[2])?,
+                        modified_nanos: parse_number(&fields[3])?,
+                        size: parse_number(&fields[4])?,
+                    };
+
+                    self.binaries
+                        .insert(path.clone(), CachedBinary { fingerprint, result: Ok(Vec::new()) });
+                    current_binary = Some(path);
+                }
+                Some("plugin") if fields.len() == 11 => {
+                    let binary = current_binary
+                        .as_ref()
+                        .and_then(|path| self.binaries.get_mut(path))
+                        .ok_or("plugin entry without a binary")?;
+
+                    let mut fields = fields.into_iter().skip(1);
+                    let mut next = || fields.next().unwrap_or_default();
+
+                    let plugin = ScannedClapPlugin {
+                        format_version: next(),
+                        descriptor: PluginDescriptor {
+                            id: next(),
+                            version: next(),
+                            name: next(),
+                            vendor: next(),
+                            description: next(),
+                            features: next(),
+                            url: next(),
+                            manual_url: next(),
+                            support_url: next(),
+                        },
+                    };
+
+                    if let Ok(plugins) = &mut binary.result {
+                        plugins.push(plugin);
+                    }
+                }
+                Some("error") if fields.len() == 2 => {
+                    let binary = current_binary
+                        .as_ref()
+                        .and_then(|path| self.binaries.get_mut(path))
+                        .ok_or("error entry without a binary")?;
+
+                    binary.result = Err(fields[1].clone());
+                }
+                Some("blocked") if fields.len() == 3 => {
+                    self.blocklist.insert(PathBuf::from(&fields[1]), fields[2].clone());
+                }
+                _ => return Err(format!("invalid line: {:?}", line)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the cache to disk.
+    pub fn save(&self) {
+        let path = if let Some(path) = &self.path {
+            path
+        } else {
+            return;
+        };
+
+        let mut contents = String::from(CACHE_FILE_HEADER);
+        contents.push('\n');
+
+        for (binary_path, binary) in self.binaries.iter() {
+            // Paths which are not valid UTF-8 can't be stored, so they will just
+            // be rescanned every time.
+            let binary_path = if let Some(p) = binary_path.to_str() {
+                p
+            } else {
+                continue;
+            };
+
+            write_line(
+                &mut contents,
+                &[
+                    "binary",
+                    binary_path,
+                    &binary.fingerprint.modified_secs.to_string(),
+                    &binary.fingerprint.modified_nanos.to_string(),
+                    &binary.fingerprint.size.to_string(),
+                ],
+            );
+
+            match &binary.result {
+                Ok(plugins) => {
+                    for plugin in plugins.iter() {
+                        let d = &plugin.descriptor;
+                        write_line(
+                            &mut contents,
+                            &[
+                                "plugin",
+                                &plugin.format_version,
+                                &d.id,
+                                &d.version,
+                                &d.name,
+                                &d.vendor,
+                                &d.description,
+                                &d.features,
+                                &d.url,
+                                &d.manual_url,
+                                &d.support_url,
+                            ],
+                        );
+                    }
+                }
+                Err(e) => write_line(&mut contents, &["error", e]),
+            }
+        }
+
+        for (binary_path, reason) in self.blocklist.iter() {
+            if let Some(binary_path) = binary_path.to_str() {
+                write_line(&mut contents, &["blocked", binary_path, reason]);
+            }
+        }
+
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                log::error!("Failed to create directory for plugin scan cache {:?}: {}", dir, e);
+                return;
+            }
+        }
+
+        if let Err(e) = write_atomic(path, contents) {
+            log::error!("Failed to write plugin scan cache to {:?}: {}", path, e);
+        }
+    }
+
+    /// Get the results of the last scan of this binary, if the binary has not
+    /// changed since then.
+    pub fn get(
+        &self,
+        binary_path: &Path,
+        fingerprint: BinaryFingerprint,
+    ) -> Option<&Result<Vec<ScannedClapPlugin>, String>> {
+        self.binaries
+            .get(binary_path)
+            .filter(|binary| binary.fingerprint == fingerprint)
+            .map(|binary| &binary.result)
+    }
+
+    pub fn insert(
+        &mut self,
+        binary_path: PathBuf,
+        fingerprint: BinaryFingerprint,
+        result: Result<Vec<ScannedClapPlugin>, String>,
+    ) {
+        self.binaries.insert(binary_path, CachedBinary { fingerprint, result });
+    }
+
+    /// Forget about all binaries which were not found in the latest scan.
+    pub fn retain_binaries(&mut self, found_binaries: &[PathBuf]) {
+        self.binaries.retain(|path, _| found_binaries.contains(path));
+    }
+
+    /// Returns the reason the binary was blocked, or `None` if the binary is
+    /// not in the blocklist.
+    pub fn blocked_reason(&self, binary_path: &Path) -> Option<&str> {
+        self.blocklist.get(binary_path).map(|s| s.as_str())
+    }
+
+    pub fn block(&mut self, binary_path: PathBuf, reason: String) {
+        log::warn!("Added plugin binary {:?} to the blocklist: {}", &binary_path, &reason);
+
+        self.binaries.remove(&binary_path);
+        self.blocklist.insert(binary_path, reason);
+    }
+
+    pub fn unblock(&mut self, binary_path: &Path) -> bool {
+        self.blocklist.remove(binary_path).is_some()
+    }
+
+    pub fn blocklist(&self) -> Vec<(PathBuf, String)> {
+        self.blocklist.iter().map(|(path, reason)| (path.clone(), reason.clone())).collect()
+    }
+
+    fn marker_path(&self) -> Option<PathBuf> {
+        self.path.as_ref().map(|path| path.with_extension(SCAN_MARKER_EXTENSION))
+    }
+
+    /// Record on disk that this binary is about to be scanned, so that it can be
+    /// blocked if it brings down the whole process.
+    pub fn begin_scanning_binary(&self, binary_path: &Path) {
+        if let (Some(marker_path), Some(binary_path)) = (self.marker_path(), binary_path.to_str()) {
+            if let Some(dir) = marker_path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+
+            if let Err(e) = fs::write(&marker_path, binary_path) {
+                log::warn!("Failed to write plugin scan marker {:?}: {}", &marker_path, e);
+            }
+        }
+    }
+
+    pub fn end_scanning_binary(&self) {
+        if let Some(marker_path) = self.marker_path() {
+            let _ = fs::remove_file(marker_path);
+        }
+    }
+
+    /// If the last scan never finished scanning a binary (because that binary
+    /// crashed the process), then add that binary to the blocklist.
+    pub fn block_crashed_binary(&mut self) {
+        let marker_path = if let Some(marker_path) = self.marker_path() {
+            marker_path
+        } else {
+            return;
+        };
+
+        if let Ok(binary_path) = fs::read_to_string(&marker_path) {
+            self.block(PathBuf::from(binary_path), "Crashed while being scanned".into());
+        }
+
+        let _ = fs::remove_file(marker_path);
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number: {:?}", s))
+}
+
+fn write_line(contents: &mut String, fields: &[&str]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i != 0 {
+            contents.push('\t');
+        }
+        escape_into(contents, field);
+    }
+    contents.push('\n');
+}
+
+fn escape_into(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => out.push('\t'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some(c) => out.push(c),
+                None => out.push('\\'),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fs::unique_temp_dir;
+
+    /// A path in the temporary directory which is unique to this test.
+    fn temp_cache_path(name: &str) -> PathBuf {
+        unique_temp_dir(&format!("scan-cache-test-{}", name)).join("scan_cache.txt")
+    }
+
+    fn test_plugin() -> ScannedClapPlugin {
+        ScannedClapPlugin {
+            descriptor: PluginDescriptor {
+                id: "app.meadowlark.test".into(),
+                version: "1.0".into(),
+                name: "Test\tPlugin".into(),
+                vendor: "Meadowlark \\ Vendor".into(),
+                description: "First line\nSecond line\r\n".into(),
+                url: String::new(),
+                manual_url: "https://meadowlark.app/manual".into(),
+                support_url: String::new(),
+                features: "audio-effect delay".into(),
+            },
+            format_version: "1.1.6".into(),
+        }
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        for s in
+            ["", "plain", "a\tb", "a\nb\r\n", "back\\slash", "\\t is not a tab", "ends with \\"]
+        {
+            let mut escaped = String::new();
+            escape_into(&mut escaped, s);
+
+            assert!(!escaped.contains('\t') && !escaped.contains('\n') && !escaped.contains('\r'));
+            assert_eq!(unescape(&escaped), s);
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_cache_path("round-trip");
+
+        let fingerprint = BinaryFingerprint { modified_secs: 1_234, modified_nanos: 56, size: 789 };
+        let binary_path = PathBuf::from("/plugins/with\ttab/and\nnewline\\Test.clap");
+        let failed_binary_path = PathBuf::from("/plugins/Failed.clap");
+        let blocked_binary_path = PathBuf::from("/plugins/blocked\\Crashy.clap");
+
+        let mut cache = ScanCache::load(Some(path.clone()));
+        cache.insert(binary_path.clone(), fingerprint, Ok(vec![test_plugin()]));
+        cache.insert(failed_binary_path.clone(), fingerprint, Err("Failed\tto\nload".into()));
+        cache.block(blocked_binary_path.clone(), "Crashed while\tbeing scanned".into());
+        cache.save();
+
+        let loaded = ScanCache::load(Some(path.clone()));
+
+        let plugins = loaded.get(&binary_path, fingerprint).unwrap().as_ref().unwrap();
+        assert_eq!(plugins.len(), 1);
+        assert_eq!(format!("{:?}", plugins[0]), format!("{:?}", test_plugin()));
+
+        assert_eq!(
+            loaded.get(&failed_binary_path, fingerprint).unwrap().as_ref().unwrap_err(),
+            "Failed\tto\nload"
+        );
+        assert_eq!(
+            loaded.blocked_reason(&blocked_binary_path),
+            Some("Crashed while\tbeing scanned")
+        );
+
+        // A binary which has changed since it was scanned has to be rescanned.
+        let changed = BinaryFingerprint { size: 790, ..fingerprint };
+        assert!(loaded.get(&binary_path, changed).is_none());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn invalid_cache_is_ignored() {
+        let path = temp_cache_path("invalid");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let fingerprint = BinaryFingerprint { modified_secs: 1, modified_nanos: 2, size: 3 };
+
+        // A valid binary entry followed by a plugin entry with missing fields.
+        let contents =
+            format!("{}\nbinary\t/plugins/Test.clap\t1\t2\t3\nplugin\t1.1.6\n", CACHE_FILE_HEADER);
+        fs::write(&path, contents).unwrap();
+        let loaded = ScanCache::load(Some(path.clone()));
+        assert!(loaded.get(Path::new("/plugins/Test.clap"), fingerprint).is_none());
+
+        fs::write(&path, "meadowlark-plugin-scan-cache 1\n").unwrap();
+        let loaded = ScanCache::load(Some(path.clone()));
+        assert!(loaded.binaries.is_empty());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn crashed_binary_is_blocked() {
+        let path = temp_cache_path("crashed");
+        let binary_path = PathBuf::from("/plugins/Crashy.clap");
+
+        let cache = ScanCache::load(Some(path.clone()));
+        cache.begin_scanning_binary(&binary_path);
+
+        // The process "crashed" before `end_scanning_binary()` was called.
+        let mut cache = ScanCache::load(Some(path.clone()));
+        cache.block_crashed_binary();
+        assert!(cache.blocked_reason(&binary_path).is_some());
+
+        // The marker is removed, so the binary is only blocked once.
+        let mut cache = ScanCache::load(Some(path.clone()));
+        cache.block_crashed_binary();
+        assert!(cache.blocked_reason(&binary_path).is_none());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,198 @@
+use meadowlark_plugin_api::PluginFactory;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use walkdir::WalkDir;
+
+use crate::utils::thread_id::SharedThreadIDs;
+
+use super::scan_cache::{BinaryFingerprint, ScanCache, ScannedClapPlugin};
+
+const MAX_SCAN_DEPTH: usize = 10;
+
+pub(super) struct ScanJob {
+    pub scan_directories: Vec<PathBuf>,
+    pub cache: ScanCache,
+    pub timeout: Duration,
+    pub thread_ids: SharedThreadIDs,
+    pub coll_handle: basedrop::Handle,
+}
+
+pub(super) struct ScanJobResult {
+    pub cache: ScanCache,
+
+    /// All binaries which were successfully scanned (including the ones
+    /// loaded from the cache), along with the plugins they contain.
+    pub scanned_binaries: Vec<(PathBuf, Vec<ScannedClapPlugin>)>,
+
+    pub failed_binaries: Vec<(PathBuf, String)>,
+    pub blocked_binaries: Vec<(PathBuf, String)>,
+}
+
+/// Scan all of the CLAP binaries in the given directories in a separate
+/// thread.
+///
+/// The result is sent back once all binaries have been scanned.
+pub(super) fn spawn_scan_thread(
+    job: ScanJob,
+) -> Result<mpsc::Receiver<ScanJobResult>, std::io::Error> {
+    let (result_tx, result_rx) = mpsc::channel();
+
+    thread::Builder::new().name("plugin-scanner".into()).spawn(move || {
+        let _ = result_tx.send(run_scan_job(job));
+    })?;
+
+    Ok(result_rx)
+}
+
+fn run_scan_job(job: ScanJob) -> ScanJobResult {
+    let ScanJob { scan_directories, mut cache, timeout, thread_ids, coll_handle } = job;
+
+    // If the last scan brought down the whole process, make sure the binary
+    // that caused it never gets loaded again.
+    cache.block_crashed_binary();
+
+    let found_binaries = find_clap_binaries(&scan_directories);
+
+    let mut scanned_binaries: Vec<(PathBuf, Vec<ScannedClapPlugin>)> = Vec::new();
+    let mut failed_binaries: Vec<(PathBuf, String)> = Vec::new();
+    let mut blocked_binaries: Vec<(PathBuf, String)> = Vec::new();
+
+    cache.retain_binaries(&found_binaries);
+
+    for binary_path in found_binaries.into_iter() {
+        if let Some(reason) = cache.blocked_reason(&binary_path) {
+            log::debug!("Skipped blocked CLAP binary {:?}: {}", &binary_path, reason);
+            blocked_binaries.push((binary_path, reason.to_string()));
+            continue;
+        }
+
+        let fingerprint = BinaryFingerprint::of(&binary_path);
+
+        let cached_res = fingerprint.and_then(|f| cache.get(&binary_path, f)).cloned();
+        let res = if let Some(res) = cached_res {
+            log::trace!("Using cached scan result for CLAP binary {:?}", &binary_path);
+            res
+        } else {
+            cache.begin_scanning_binary(&binary_path);
+            let res = scan_binary_with_timeout(&binary_path, timeout, &thread_ids, &coll_handle);
+            cache.end_scanning_binary();
+
+            match res {
+                Some(res) => {
+                    if let Some(fingerprint) = fingerprint {
+                        cache.insert(binary_path.clone(), fingerprint, res.clone());
+                    }
+                    res
+                }
+                None => {
+                    let reason = format!("Timed out after {:?} while being scanned", timeout);
+                    cache.block(binary_path.clone(), reason.clone());
+                    blocked_binaries.push((binary_path, reason));
+                    continue;
+                }
+            }
+        };
+
+        match res {
+            Ok(plugins) => scanned_binaries.push((binary_path, plugins)),
+            Err(e) => {
+                log::error!("Failed to scan CLAP plugin binary at {:?}: {}", &binary_path, &e);
+                failed_binaries.push((binary_path, e));
+            }
+        }
+    }
+
+    cache.save();
+
+    ScanJobResult { cache, scanned_binaries, failed_binaries, blocked_binaries }
+}
+
+fn find_clap_binaries(scan_directories: &[PathBuf]) -> Vec<PathBuf> {
+    let mut found_binaries: Vec<PathBuf> = Vec::new();
+
+    for dir in scan_directories.iter() {
+        let walker = WalkDir::new(dir).max_depth(MAX_SCAN_DEPTH).follow_links(true);
+
+        for item in walker {
+            match item {
+                Ok(binary) => {
+                    if !binary.file_type().is_file() {
+                        continue;
+                    }
+
+                    match binary.path().extension().and_then(|e| e.to_str()) {
+                        Some(ext) if ext == "clap" => {}
+                        _ => continue,
+                    };
+
+                    let binary_path = binary.into_path();
+                    log::trace!("Found CLAP binary: {:?}", &binary_path);
+                    found_binaries.push(binary_path);
+                }
+                Err(e) => {
+                    log::warn!("Failed to scan binary for potential CLAP plugin: {}", e);
+                }
+            }
+        }
+    }
+
+    found_binaries
+}
+
+/// Load the binary in its own thread and read the descriptors of all the
+/// plugins inside it.
+///
+/// This returns `None` if the binary took longer than `timeout`. In that case
+/// the thread which is stuck loading the binary is abandoned.
+fn scan_binary_with_timeout(
+    binary_path: &Path,
+    timeout: Duration,
+    thread_ids: &SharedThreadIDs,
+    coll_handle: &basedrop::Handle,
+) -> Option<Result<Vec<ScannedClapPlugin>, String>> {
+    let (res_tx, res_rx) = mpsc::channel();
+
+    let binary_path = binary_path.to_path_buf();
+    let thread_ids = thread_ids.clone();
+    let coll_handle = coll_handle.clone();
+
+    if let Err(e) = thread::Builder::new().name("plugin-scanner-binary".into()).spawn(move || {
+        let _ = res_tx.send(scan_binary(binary_path, thread_ids, &coll_handle));
+    }) {
+        return Some(Err(format!("Failed to spawn plugin scanner thread: {}", e)));
+    }
+
+    match res_rx.recv_timeout(timeout) {
+        Ok(res) => Some(res),
+        Err(mpsc::RecvTimeoutError::Timeout) => None,
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Some(Err("Plugin panicked while being scanned".into()))
+        }
+    }
+}
+
+fn scan_binary(
+    binary_path: PathBuf,
+    thread_ids: SharedThreadIDs,
+    coll_handle: &basedrop::Handle,
+) -> Result<Vec<ScannedClapPlugin>, String> {
+    let factories = crate::plugin_host::external::clap::factory::entry_init(
+        &binary_path,
+        thread_ids,
+        coll_handle,
+    )?;
+
+    Ok(factories
+        .iter()
+        .map(|f| {
+            let v = f.clap_version;
+
+            ScannedClapPlugin {
+                descriptor: f.description(),
+                format_version: format!("{}.{}.{}", v.major, v.minor, v.revision),
+            }
+        })
+        .collect())
+}
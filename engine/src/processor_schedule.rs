@@ -1,17 +1,22 @@
 use basedrop::Shared;
-use meadowlark_plugin_api::ProcInfo;
+use meadowlark_plugin_api::{PluginInstanceID, ProcInfo};
 use std::fmt::Write;
+use std::time::Instant;
 
+pub(crate) mod parallel;
 pub(crate) mod tasks;
 
 pub use tasks::TransportHandle;
 
+use crate::engine::performance::EnginePerfShared;
 use crate::{graph::shared_pools::SharedTransportTask, plugin_host::PluginHostProcessorWrapper};
 
-use tasks::{GraphInTask, GraphOutTask, Task};
+use parallel::{ParallelBlock, TaskCell, TaskGraph, WorkerPoolShared};
+use tasks::{AudioOutCapture, GraphInTask, GraphOutTask, Task};
 
 pub struct ProcessorSchedule {
-    tasks: Vec<Task>,
+    tasks: Vec<TaskCell>,
+    task_graph: TaskGraph,
 
     graph_in_task: GraphInTask,
     graph_out_task: GraphOutTask,
@@ -27,28 +32,40 @@ pub struct ProcessorSchedule {
 }
 
 impl ProcessorSchedule {
+    #[allow(clippy::too_many_arguments)] // Fix this?
     pub(crate) fn new(
         tasks: Vec<Task>,
         graph_in_task: GraphInTask,
         graph_out_task: GraphOutTask,
         transport_task: SharedTransportTask,
-        // For the plugins that are queued to be removed, make sure that
-        // the plugin's processor part is dropped in the process thread.
-        plugin_processors_to_stop: Vec<Shared<PluginHostProcessorWrapper>>,
         max_block_size: usize,
+        num_worker_threads: usize,
         version: u64,
     ) -> Self {
+        let tasks: Vec<TaskCell> = tasks.into_iter().map(TaskCell::new).collect();
+        let task_graph = TaskGraph::new(&tasks, num_worker_threads);
+
         Self {
             tasks,
+            task_graph,
             graph_in_task,
             graph_out_task,
             transport_task,
-            plugin_processors_to_stop,
+            plugin_processors_to_stop: Vec::new(),
             max_block_size,
             version,
         }
     }
 
+    /// For the plugins that are queued to be removed, make sure that
+    /// the plugin's processor part is dropped in the process thread.
+    pub(crate) fn set_plugin_processors_to_stop(
+        &mut self,
+        plugin_processors_to_stop: Vec<Shared<PluginHostProcessorWrapper>>,
+    ) {
+        self.plugin_processors_to_stop = plugin_processors_to_stop;
+    }
+
     pub(crate) fn new_empty(
         max_block_size: usize,
         transport_task: SharedTransportTask,
@@ -57,6 +74,7 @@ impl ProcessorSchedule {
     ) -> Self {
         Self {
             tasks: Vec::new(),
+            task_graph: TaskGraph::new(&[], 0),
             graph_in_task: GraphInTask::default(),
             graph_out_task: GraphOutTask::default(),
             transport_task,
@@ -66,8 +84,68 @@ impl ProcessorSchedule {
         }
     }
 
-    pub(crate) fn tasks(&self) -> &[Task] {
-        &self.tasks
+    pub(crate) fn tasks(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.iter().map(|t| t.get())
+    }
+
+    pub(crate) fn task_graph(&self) -> &TaskGraph {
+        &self.task_graph
+    }
+
+    /// Start copying the contents of an audio output port of a plugin every
+    /// time the plugin is processed.
+    ///
+    /// This returns `false` if the plugin does not exist in this schedule.
+    pub(crate) fn begin_plugin_audio_out_capture(
+        &mut self,
+        plugin_id: &PluginInstanceID,
+        port_index: usize,
+        num_channels: usize,
+    ) -> bool {
+        let max_block_size = self.max_block_size;
+
+        self.tasks
+            .iter_mut()
+            .find_map(|task| match task.get_mut() {
+                Task::Plugin(t) if &t.plugin_id == plugin_id => Some(t),
+                _ => None,
+            })
+            .map(|t| {
+                t.audio_out_capture =
+                    Some(AudioOutCapture::new(port_index, num_channels, max_block_size));
+            })
+            .is_some()
+    }
+
+    /// Move the interleaved contents of the captured audio output port since
+    /// the last call to this method into `out`.
+    ///
+    /// This returns `false` if no port is being captured.
+    pub(crate) fn take_plugin_audio_out_capture(&mut self, out: &mut [f32]) -> bool {
+        for task in self.tasks.iter_mut() {
+            if let Task::Plugin(t) = task.get_mut() {
+                if let Some(capture) = &mut t.audio_out_capture {
+                    let len = capture.data().len().min(out.len());
+                    out[0..len].copy_from_slice(&capture.data()[0..len]);
+                    out[len..].fill(0.0);
+
+                    capture.clear();
+
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Stop capturing the audio output port of a plugin.
+    pub(crate) fn end_plugin_audio_out_capture(&mut self) {
+        for task in self.tasks.iter_mut() {
+            if let Task::Plugin(t) = task.get_mut() {
+                t.audio_out_capture = None;
+            }
+        }
     }
 }
 
@@ -86,7 +164,7 @@ impl std::fmt::Debug for ProcessorSchedule {
             let _ = writeln!(s, "    graph_audio_in: {},", s2);
         }
 
-        for t in self.tasks.iter() {
+        for t in self.tasks() {
             let _ = writeln!(s, "    {:?},", t);
         }
 
@@ -104,7 +182,19 @@ impl std::fmt::Debug for ProcessorSchedule {
 }
 
 impl ProcessorSchedule {
-    pub fn process_interleaved(&mut self, audio_in: &[f32], audio_out: &mut [f32]) {
+    /// Process the schedule.
+    ///
+    /// If `workers` is `Some`, then independent tasks will be processed in
+    /// parallel by the given pool of worker threads.
+    ///
+    /// The time spent on each kind of task is recorded in `perf`.
+    pub(crate) fn process_interleaved(
+        &mut self,
+        workers: Option<&WorkerPoolShared>,
+        perf: &EnginePerfShared,
+        audio_in: &[f32],
+        audio_out: &mut [f32],
+    ) {
         // For the plugins that are queued to be removed, make sure that
         // their processors are dropped on the process thread.
         for plugin_proc in self.plugin_processors_to_stop.drain(..) {
@@ -139,6 +229,8 @@ impl ProcessorSchedule {
         while processed_frames < total_frames {
             let frames = (total_frames - processed_frames).min(self.max_block_size);
 
+            let graph_in_start_time = Instant::now();
+
             // De-interlace the audio in stream to the graph input buffers.
             for (channel_i, buffer) in self.graph_in_task.audio_in.iter().enumerate() {
                 let buffer = &mut buffer.borrow_mut().data[0..frames];
@@ -149,19 +241,35 @@ impl ProcessorSchedule {
                 }
             }
 
-            let transport = self.transport_task.borrow_mut().process(frames);
+            let mut graph_in_out_elapsed = graph_in_start_time.elapsed();
 
-            let proc_info = ProcInfo {
-                steady_time: -1, // TODO
-                frames,
-                transport,
-                schedule_version: self.version,
+            let (transport, steady_time) = {
+                let mut transport_task = self.transport_task.borrow_mut();
+                let steady_time = transport_task.steady_time();
+                (transport_task.process(frames), steady_time)
             };
 
-            for task in self.tasks.iter_mut() {
-                task.process(&proc_info)
+            let proc_info =
+                ProcInfo { steady_time, frames, transport, schedule_version: self.version };
+
+            match workers {
+                Some(workers) if self.task_graph.has_parallel_tasks() => {
+                    workers.process_block(&ParallelBlock {
+                        tasks: &self.tasks,
+                        graph: &self.task_graph,
+                        proc_info: &proc_info,
+                        perf,
+                    });
+                }
+                _ => {
+                    for task in self.tasks.iter_mut() {
+                        task.get_mut().process_metered(&proc_info, perf)
+                    }
+                }
             }
 
+            let graph_out_start_time = Instant::now();
+
             // Interlace the graph output buffers to the audio out stream.
             for (channel_i, buffer) in self.graph_out_task.audio_out.iter().enumerate() {
                 let buffer = &buffer.borrow().data[0..frames];
@@ -173,6 +281,9 @@ impl ProcessorSchedule {
                 }
             }
 
+            graph_in_out_elapsed += graph_out_start_time.elapsed();
+            perf.finish_block(graph_in_out_elapsed);
+
             processed_frames += frames;
         }
     }
@@ -0,0 +1,435 @@
+use fnv::{FnvHashMap, FnvHashSet};
+use meadowlark_plugin_api::buffer::DebugBufferID;
+use meadowlark_plugin_api::ProcInfo;
+use smallvec::SmallVec;
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use super::tasks::Task;
+use crate::engine::performance::EnginePerfShared;
+
+/// The number of times an idle worker thread polls for a new block before
+/// yielding its timeslice.
+const WORKER_SPIN_ITERATIONS: u32 = 1_024;
+
+/// After a worker thread has gone this long without receiving a new block, it
+/// will start sleeping between polls instead of spinning.
+static WORKER_IDLE_TIMEOUT: Duration = Duration::from_millis(50);
+static WORKER_IDLE_SLEEP_INTERVAL: Duration = Duration::from_micros(500);
+
+/// The bit in `WorkerPoolShared::active` that signifies that a block is
+/// currently open for worker threads to join.
+const BLOCK_OPEN_BIT: usize = 1 << (usize::BITS - 1);
+
+/// A task in the schedule that can be processed by any one of the worker
+/// threads.
+pub(crate) struct TaskCell(UnsafeCell<Task>);
+
+impl TaskCell {
+    pub fn new(task: Task) -> Self {
+        Self(UnsafeCell::new(task))
+    }
+
+    pub fn get(&self) -> &Task {
+        // Safe because the only time a mutable reference is created is when
+        // processing a block, and the schedule itself is mutably borrowed for
+        // the entire duration of the block.
+        unsafe { &*self.0.get() }
+    }
+
+    pub fn get_mut(&mut self) -> &mut Task {
+        self.0.get_mut()
+    }
+
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread is accessing this task
+    /// at the same time.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn get_mut_unchecked(&self) -> &mut Task {
+        &mut *self.0.get()
+    }
+}
+
+/// A fixed-capacity lock-free queue of task indexes.
+///
+/// Only the worker thread that owns this queue is allowed to push to it, but
+/// any worker thread is allowed to pop (steal) from it.
+///
+/// Because each task is pushed at most once per block, the queue is given
+/// enough capacity to hold every task in the schedule, so it never wraps
+/// around within a block. The queue is reset before the start of every block
+/// while no worker threads are accessing it.
+struct TaskQueue {
+    slots: Box<[AtomicU32]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl TaskQueue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn reset(&self) {
+        self.head.store(0, Ordering::Relaxed);
+        self.tail.store(0, Ordering::Relaxed);
+    }
+
+    /// Only the owner of this queue may call this.
+    fn push(&self, task_index: u32) {
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        // This can't happen since each task is only pushed once per block.
+        debug_assert!(tail < self.slots.len());
+
+        self.slots[tail].store(task_index, Ordering::Relaxed);
+        self.tail.store(tail + 1, Ordering::Release);
+    }
+
+    fn pop(&self) -> Option<u32> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            if head >= self.tail.load(Ordering::Acquire) {
+                return None;
+            }
+
+            let task_index = self.slots[head].load(Ordering::Relaxed);
+
+            if self
+                .head
+                .compare_exchange_weak(head, head + 1, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Some(task_index);
+            }
+        }
+    }
+}
+
+/// The tasks which have accessed a particular buffer so far while building a
+/// `TaskGraph`.
+#[derive(Default)]
+pub(crate) struct BufferAccesses {
+    pub last_writer: Option<usize>,
+    /// The tasks which have read from the buffer since `last_writer`.
+    pub readers: SmallVec<[usize; 4]>,
+}
+
+/// The dependencies between the tasks in a schedule.
+///
+/// Two tasks which share a buffer (where at least one of them writes to it)
+/// are always ordered by this graph, so any tasks which are not ordered may
+/// safely be processed in parallel.
+pub(crate) struct TaskGraph {
+    /// The tasks which must wait for each task to complete.
+    dependents: Vec<SmallVec<[u32; 4]>>,
+    /// The number of tasks which each task must wait for.
+    num_dependencies: Vec<u32>,
+    /// The tasks which don't depend on any other task.
+    roots: Vec<u32>,
+
+    // --- The state used while processing a block ---------------------------
+    remaining_dependencies: Box<[AtomicU32]>,
+    tasks_left: AtomicUsize,
+    queues: Box<[TaskQueue]>,
+}
+
+impl TaskGraph {
+    /// Construct the dependency graph for the given list of tasks in serial
+    /// order.
+    ///
+    /// * `num_worker_threads` - The number of threads that will be processing
+    /// this schedule (not including the process thread).
+    pub fn new(tasks: &[TaskCell], num_worker_threads: usize) -> Self {
+        let num_tasks = tasks.len();
+
+        let mut dependents: Vec<SmallVec<[u32; 4]>> = vec![SmallVec::new(); num_tasks];
+        let mut num_dependencies: Vec<u32> = vec![0; num_tasks];
+
+        let mut buffer_accesses: FnvHashMap<DebugBufferID, BufferAccesses> = FnvHashMap::default();
+        let mut task_dependencies: FnvHashSet<usize> = FnvHashSet::default();
+        let mut reads: Vec<DebugBufferID> = Vec::new();
+        let mut writes: Vec<DebugBufferID> = Vec::new();
+
+        for (task_i, task) in tasks.iter().enumerate() {
+            reads.clear();
+            writes.clear();
+            task.get().collect_buffer_ids(&mut reads, &mut writes);
+
+            // A task that reads from a buffer must wait for the last task that
+            // wrote to it, and a task that writes to a buffer must also wait
+            // for every task that read from it since then.
+            task_dependencies.clear();
+            for buffer_id in reads.iter() {
+                let accesses = buffer_accesses.entry(*buffer_id).or_default();
+                if let Some(writer) = accesses.last_writer {
+                    task_dependencies.insert(writer);
+                }
+                accesses.readers.push(task_i);
+            }
+            for buffer_id in writes.iter() {
+                let accesses = buffer_accesses.entry(*buffer_id).or_default();
+                if let Some(writer) = accesses.last_writer {
+                    task_dependencies.insert(writer);
+                }
+                task_dependencies.extend(accesses.readers.drain(..));
+                accesses.last_writer = Some(task_i);
+            }
+            task_dependencies.remove(&task_i);
+
+            for prev_task_i in task_dependencies.iter() {
+                dependents[*prev_task_i].push(task_i as u32);
+            }
+            num_dependencies[task_i] = task_dependencies.len() as u32;
+        }
+
+        let roots: Vec<u32> = num_dependencies
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n == 0)
+            .map(|(i, _)| i as u32)
+            .collect();
+
+        Self {
+            dependents,
+            num_dependencies,
+            roots,
+            remaining_dependencies: (0..num_tasks).map(|_| AtomicU32::new(0)).collect(),
+            tasks_left: AtomicUsize::new(0),
+            queues: (0..num_worker_threads + 1).map(|_| TaskQueue::new(num_tasks)).collect(),
+        }
+    }
+
+    pub fn num_tasks(&self) -> usize {
+        self.num_dependencies.len()
+    }
+
+    /// The tasks which must wait for the task at the given index to complete.
+    pub fn dependents(&self, task_index: usize) -> &[u32] {
+        &self.dependents[task_index]
+    }
+
+    /// The maximum number of worker threads (including the process thread)
+    /// this graph can be processed with.
+    fn max_threads(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Whether or not there is any opportunity for tasks to be processed in
+    /// parallel.
+    pub fn has_parallel_tasks(&self) -> bool {
+        self.roots.len() > 1 || self.dependents.iter().any(|d| d.len() > 1)
+    }
+
+    /// Reset the state for a new block. No worker threads may be accessing
+    /// this graph while this is called.
+    fn reset(&self) {
+        for (remaining, num) in self.remaining_dependencies.iter().zip(self.num_dependencies.iter())
+        {
+            remaining.store(*num, Ordering::Relaxed);
+        }
+        self.tasks_left.store(self.num_tasks(), Ordering::Relaxed);
+
+        for queue in self.queues.iter() {
+            queue.reset();
+        }
+
+        // The process thread owns the first queue.
+        for root in self.roots.iter() {
+            self.queues[0].push(*root);
+        }
+    }
+}
+
+/// Everything the worker threads need to process a single block.
+pub(crate) struct ParallelBlock<'a> {
+    pub tasks: &'a [TaskCell],
+    pub graph: &'a TaskGraph,
+    pub proc_info: &'a ProcInfo,
+    pub perf: &'a EnginePerfShared,
+}
+
+impl<'a> ParallelBlock<'a> {
+    /// Process tasks until every task in the block has been completed.
+    fn work(&self, queue_index: usize) {
+        let queues = &self.graph.queues;
+        let num_queues = queues.len();
+
+        loop {
+            // Try our own queue first, and then try to steal from the other
+            // threads.
+            let mut next_task = queues[queue_index].pop();
+            if next_task.is_none() {
+                for i in 1..num_queues {
+                    next_task = queues[(queue_index + i) % num_queues].pop();
+                    if next_task.is_some() {
+                        break;
+                    }
+                }
+            }
+
+            if let Some(task_index) = next_task {
+                let task_index = task_index as usize;
+
+                // Safe because each task is pushed to a queue exactly once per block
+                // (once all of its dependencies have completed), and each queue slot
+                // can only be popped by a single thread.
+                unsafe {
+                    self.tasks[task_index]
+                        .get_mut_unchecked()
+                        .process_metered(self.proc_info, self.perf);
+                }
+
+                for dependent in self.graph.dependents[task_index].iter() {
+                    if self.graph.remaining_dependencies[*dependent as usize]
+                        .fetch_sub(1, Ordering::AcqRel)
+                        == 1
+                    {
+                        queues[queue_index].push(*dependent);
+                    }
+                }
+
+                self.graph.tasks_left.fetch_sub(1, Ordering::AcqRel);
+            } else if self.graph.tasks_left.load(Ordering::Acquire) == 0 {
+                return;
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// The state shared between the process thread and the realtime worker
+/// threads.
+pub(crate) struct WorkerPoolShared {
+    block: AtomicPtr<ParallelBlock<'static>>,
+    generation: AtomicU64,
+    /// The number of worker threads currently working on the block, plus
+    /// `BLOCK_OPEN_BIT` if worker threads are allowed to join the block.
+    active: AtomicUsize,
+    running: AtomicBool,
+    num_worker_threads: usize,
+}
+
+impl WorkerPoolShared {
+    pub fn new(num_worker_threads: usize) -> Self {
+        Self {
+            block: AtomicPtr::new(std::ptr::null_mut()),
+            generation: AtomicU64::new(0),
+            active: AtomicUsize::new(0),
+            running: AtomicBool::new(true),
+            num_worker_threads,
+        }
+    }
+
+    pub fn num_worker_threads(&self) -> usize {
+        self.num_worker_threads
+    }
+
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// Process all tasks in the given block, using the worker threads to
+    /// process independent tasks in parallel.
+    ///
+    /// This must only be called from the process thread.
+    pub fn process_block(&self, block: &ParallelBlock) {
+        block.graph.reset();
+
+        if block.graph.max_threads() > 1 {
+            // Safe because we wait for all worker threads to leave the block
+            // before returning, so the pointer never outlives the block.
+            let block_ptr = block as *const ParallelBlock as *mut ParallelBlock<'static>;
+            self.block.store(block_ptr, Ordering::SeqCst);
+            self.active.store(BLOCK_OPEN_BIT, Ordering::SeqCst);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+
+        block.work(0);
+
+        if block.graph.max_threads() > 1 {
+            // Stop any more worker threads from joining this block, and wait
+            // for the ones that did join to finish.
+            self.active.fetch_and(!BLOCK_OPEN_BIT, Ordering::SeqCst);
+            while self.active.load(Ordering::SeqCst) != 0 {
+                std::hint::spin_loop();
+            }
+
+            self.block.store(std::ptr::null_mut(), Ordering::SeqCst);
+        }
+    }
+
+    /// The main loop of a worker thread.
+    ///
+    /// * `queue_index` - The index of this worker thread, starting from `1`
+    /// (the process thread uses index `0`).
+    pub fn run_worker(&self, queue_index: usize) {
+        let mut last_generation = self.generation.load(Ordering::SeqCst);
+        let mut last_block_instant = Instant::now();
+        let mut spins = 0;
+
+        while self.running.load(Ordering::Relaxed) {
+            let generation = self.generation.load(Ordering::SeqCst);
+            if generation == last_generation {
+                spins += 1;
+                if spins < WORKER_SPIN_ITERATIONS {
+                    std::hint::spin_loop();
+                } else if last_block_instant.elapsed() < WORKER_IDLE_TIMEOUT {
+                    spins = 0;
+                    std::thread::yield_now();
+                } else {
+                    std::thread::sleep(WORKER_IDLE_SLEEP_INTERVAL);
+                }
+
+                continue;
+            }
+            last_generation = generation;
+            last_block_instant = Instant::now();
+            spins = 0;
+
+            if !self.try_join_block() {
+                continue;
+            }
+
+            let block_ptr = self.block.load(Ordering::SeqCst);
+            if !block_ptr.is_null() {
+                // Safe because the process thread waits for all worker threads to
+                // leave the block before invalidating this pointer.
+                let block = unsafe { &*block_ptr };
+
+                // The schedule may have been compiled for fewer worker threads.
+                if queue_index < block.graph.max_threads() {
+                    block.work(queue_index);
+                }
+            }
+
+            self.active.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn try_join_block(&self) -> bool {
+        let mut active = self.active.load(Ordering::SeqCst);
+        loop {
+            if active & BLOCK_OPEN_BIT == 0 {
+                return false;
+            }
+
+            match self.active.compare_exchange_weak(
+                active,
+                active + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return true,
+                Err(a) => active = a,
+            }
+        }
+    }
+}
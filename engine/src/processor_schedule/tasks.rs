@@ -1,5 +1,9 @@
+use meadowlark_plugin_api::buffer::{DebugBufferID, RawAudioChannelBuffers};
 use meadowlark_plugin_api::ProcInfo;
 use std::fmt::{Debug, Error, Formatter, Write};
+use std::time::Instant;
+
+use crate::engine::performance::{EnginePerfShared, TaskKind};
 
 mod delay_comp_task;
 mod graph_in_out_task;
@@ -16,9 +20,9 @@ pub(crate) use delay_comp_task::{
     SharedNoteDelayCompNode,
 };
 pub(crate) use graph_in_out_task::{GraphInTask, GraphOutTask};
-pub(crate) use plugin_task::PluginTask;
+pub(crate) use plugin_task::{AudioOutCapture, PluginTask};
 pub(crate) use sum_task::{AudioSumTask, AutomationSumTask, NoteSumTask};
-pub(crate) use transport_task::TransportTask;
+pub(crate) use transport_task::{SavedTransportState, TransportTask};
 pub(crate) use unloaded_plugin_task::UnloadedPluginTask;
 
 pub(crate) enum Task {
@@ -227,4 +231,123 @@ impl Task {
             Task::UnloadedPlugin(task) => task.process(proc_info),
         }
     }
+
+    /// Process this task, and record how long it took if it is metered as
+    /// part of a group of tasks.
+    pub fn process_metered(&mut self, proc_info: &ProcInfo, perf: &EnginePerfShared) {
+        if let Some(kind) = self.metered_kind() {
+            let start_time = Instant::now();
+            self.process(proc_info);
+            perf.record_task(kind, start_time.elapsed());
+        } else {
+            // Plugins record their own process times.
+            self.process(proc_info);
+        }
+    }
+
+    fn metered_kind(&self) -> Option<TaskKind> {
+        match self {
+            Task::AudioSum(_) | Task::NoteSum(_) | Task::AutomationSum(_) => Some(TaskKind::Sum),
+            Task::AudioDelayComp(_) | Task::NoteDelayComp(_) | Task::AutomationDelayComp(_) => {
+                Some(TaskKind::DelayComp)
+            }
+            Task::Plugin(_) | Task::UnloadedPlugin(_) => None,
+        }
+    }
+
+    /// Append the IDs of every buffer this task reads from to `reads`, and
+    /// the IDs of every buffer this task writes to to `writes`.
+    ///
+    /// A buffer that is both read from and written to (i.e. an input buffer
+    /// that is cleared by this task) is only appended to `writes`.
+    pub fn collect_buffer_ids(
+        &self,
+        reads: &mut Vec<DebugBufferID>,
+        writes: &mut Vec<DebugBufferID>,
+    ) {
+        fn port_ids(raw_channels: &RawAudioChannelBuffers, out: &mut Vec<DebugBufferID>) {
+            match raw_channels {
+                RawAudioChannelBuffers::F32(buffers) => out.extend(buffers.iter().map(|b| b.id())),
+                RawAudioChannelBuffers::F64(buffers) => out.extend(buffers.iter().map(|b| b.id())),
+            }
+        }
+
+        let reads_start = reads.len();
+
+        match self {
+            Task::Plugin(t) => {
+                for port_buffer in t.buffers.audio_in.iter() {
+                    port_ids(&port_buffer._raw_channels, reads);
+                }
+                for port_buffer in t.buffers.audio_out.iter() {
+                    port_ids(&port_buffer._raw_channels, writes);
+                }
+                writes.extend(t.clear_audio_in_buffers.iter().map(|b| b.id()));
+
+                reads.extend(t.event_buffers.note_in_buffers.iter().map(|b| b.id()));
+                writes.extend(t.event_buffers.note_out_buffers.iter().map(|b| b.id()));
+                writes.extend(t.event_buffers.clear_note_in_buffers.iter().map(|b| b.id()));
+                if let Some((b, do_clear)) = &t.event_buffers.automation_in_buffer {
+                    // The automation input buffer is cleared before processing
+                    // if nothing else reads from it.
+                    if *do_clear {
+                        writes.push(b.id());
+                    } else {
+                        reads.push(b.id());
+                    }
+                }
+                if let Some(b) = &t.event_buffers.automation_out_buffer {
+                    writes.push(b.id());
+                }
+            }
+            Task::AudioSum(t) => {
+                reads.extend(t.audio_in.iter().map(|b| b.id()));
+                writes.push(t.audio_out.id());
+            }
+            Task::NoteSum(t) => {
+                reads.extend(t.note_in.iter().map(|b| b.id()));
+                writes.push(t.note_out.id());
+            }
+            Task::AutomationSum(t) => {
+                reads.extend(t.input.iter().map(|b| b.id()));
+                writes.push(t.output.id());
+            }
+            Task::AudioDelayComp(t) => {
+                reads.push(t.audio_in.id());
+                writes.push(t.audio_out.id());
+            }
+            Task::NoteDelayComp(t) => {
+                reads.push(t.note_in.id());
+                writes.push(t.note_out.id());
+            }
+            Task::AutomationDelayComp(t) => {
+                reads.push(t.input.id());
+                writes.push(t.output.id());
+            }
+            Task::UnloadedPlugin(t) => {
+                for (b_in, b_out) in t.audio_through.iter() {
+                    reads.push(b_in.id());
+                    writes.push(b_out.id());
+                }
+                if let Some((b_in, b_out)) = &t.note_through {
+                    reads.push(b_in.id());
+                    writes.push(b_out.id());
+                }
+                writes.extend(t.clear_audio_out.iter().map(|b| b.id()));
+                writes.extend(t.clear_note_out.iter().map(|b| b.id()));
+                if let Some(b) = &t.clear_automation_out {
+                    writes.push(b.id());
+                }
+            }
+        }
+
+        let mut i = reads_start;
+        while i < reads.len() {
+            if writes.contains(&reads[i]) {
+                reads.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
 }
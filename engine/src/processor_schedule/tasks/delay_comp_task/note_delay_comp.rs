@@ -3,7 +3,7 @@ use basedrop::Shared;
 use meadowlark_plugin_api::buffer::SharedBuffer;
 use meadowlark_plugin_api::ProcInfo;
 
-use crate::plugin_host::event_io_buffers::NoteIoEvent;
+use crate::plugin_host::event_io_buffers::{NoteIoEvent, NOTE_BUFFER_SYSEX_CAPACITY};
 
 pub(crate) struct NoteDelayCompTask {
     pub shared_node: SharedNoteDelayCompNode,
@@ -45,6 +45,9 @@ impl SharedNoteDelayCompNode {
 pub(crate) struct NoteDelayCompNode {
     buf: Vec<NoteIoEvent>,
     temp_buf: Vec<NoteIoEvent>,
+    /// The data of the SysEx events in `buf`.
+    bytes: Vec<u8>,
+    temp_bytes: Vec<u8>,
     delay: u32,
 }
 
@@ -53,6 +56,8 @@ impl NoteDelayCompNode {
         Self {
             buf: Vec::with_capacity(note_buffer_size),
             temp_buf: Vec::with_capacity(note_buffer_size),
+            bytes: Vec::with_capacity(NOTE_BUFFER_SYSEX_CAPACITY),
+            temp_bytes: Vec::with_capacity(NOTE_BUFFER_SYSEX_CAPACITY),
             delay,
         }
     }
@@ -64,31 +69,38 @@ impl NoteDelayCompNode {
         output: &SharedBuffer<NoteIoEvent>,
     ) {
         let input_buf = input.borrow();
-        let mut output_buf = output.borrow_mut();
+        let output_buf = &mut *output.borrow_mut();
         output_buf.data.clear();
+        output_buf.bytes.clear();
 
         self.temp_buf.clear();
+        self.temp_bytes.clear();
 
         for mut event in self.buf.drain(..) {
             if event.header.time < proc_info.frames as u32 {
-                output_buf.data.push(event);
+                event.copy_to(&self.bytes, &mut output_buf.data, &mut output_buf.bytes);
             } else {
                 event.header.time -= proc_info.frames as u32;
-                self.temp_buf.push(event);
+                event.copy_to(&self.bytes, &mut self.temp_buf, &mut self.temp_bytes);
             }
         }
 
-        self.buf.append(&mut self.temp_buf);
+        std::mem::swap(&mut self.buf, &mut self.temp_buf);
+        std::mem::swap(&mut self.bytes, &mut self.temp_bytes);
 
         for event in input_buf.data.iter() {
             let mut event_delayed = *event;
             event_delayed.header.time += self.delay;
 
             if event_delayed.header.time < proc_info.frames as u32 {
-                output_buf.data.push(event_delayed);
+                event_delayed.copy_to(
+                    &input_buf.bytes,
+                    &mut output_buf.data,
+                    &mut output_buf.bytes,
+                );
             } else {
                 event_delayed.header.time -= proc_info.frames as u32;
-                self.buf.push(event_delayed);
+                event_delayed.copy_to(&input_buf.bytes, &mut self.buf, &mut self.bytes);
             }
         }
     }
@@ -15,6 +15,11 @@ pub(crate) struct PluginTask {
     pub buffers: ProcBuffers,
     pub event_buffers: PluginEventIoBuffers,
     pub clear_audio_in_buffers: SmallVec<[SharedBuffer<f32>; 2]>,
+
+    /// If this is `Some`, then the contents of one of the audio output ports
+    /// are copied right after the plugin is processed (used by offline
+    /// renders of a single plugin).
+    pub audio_out_capture: Option<AudioOutCapture>,
 }
 
 impl PluginTask {
@@ -33,6 +38,10 @@ impl PluginTask {
             false
         };
 
+        if let Some(capture) = &mut self.audio_out_capture {
+            capture.capture(&self.buffers, proc_info.frames);
+        }
+
         if drop_old_processor {
             // Drop the old processor if we got a request to deactivate or
             // remove the plugin.
@@ -42,3 +51,47 @@ impl PluginTask {
         }
     }
 }
+
+/// The interleaved contents of an audio output port of a plugin.
+///
+/// The contents are copied inside the plugin's task because the buffers of
+/// the port may be reused by tasks which are processed later in the schedule.
+pub(crate) struct AudioOutCapture {
+    port_index: usize,
+    num_channels: usize,
+    data: Vec<f32>,
+}
+
+impl AudioOutCapture {
+    pub fn new(port_index: usize, num_channels: usize, max_frames: usize) -> Self {
+        Self { port_index, num_channels, data: Vec::with_capacity(max_frames * num_channels) }
+    }
+
+    fn capture(&mut self, buffers: &ProcBuffers, frames: usize) {
+        let start = self.data.len();
+        self.data.resize(start + (frames * self.num_channels), 0.0);
+
+        if let Some(port) = buffers.audio_out.get(self.port_index) {
+            let out = &mut self.data[start..];
+
+            for channel_i in 0..port.channels().min(self.num_channels) {
+                if let Some(buffer) = port.channel_f32(channel_i) {
+                    let buffer = &buffer.data[0..frames];
+
+                    for i in 0..frames {
+                        out[(i * self.num_channels) + channel_i] = buffer[i];
+                    }
+                }
+            }
+        }
+    }
+
+    /// The samples captured since the last call to `AudioOutCapture::clear()`.
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
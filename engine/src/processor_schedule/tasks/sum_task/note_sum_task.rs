@@ -11,12 +11,15 @@ pub(crate) struct NoteSumTask {
 
 impl NoteSumTask {
     pub fn process(&mut self) {
-        let mut out_buf = self.note_out.borrow_mut();
+        let out_buf = &mut *self.note_out.borrow_mut();
         out_buf.data.clear();
+        out_buf.bytes.clear();
 
         for in_buf in self.note_in.iter() {
             let in_buf = in_buf.borrow();
-            out_buf.data.extend_from_slice(in_buf.data.as_slice());
+            for event in in_buf.data.iter() {
+                event.copy_to(&in_buf.bytes, &mut out_buf.data, &mut out_buf.bytes);
+            }
         }
     }
 }
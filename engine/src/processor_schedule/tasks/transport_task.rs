@@ -1,5 +1,6 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 use basedrop::{Shared, SharedCell};
 use clack_host::events::event_types::{TransportEvent, TransportEventFlags};
@@ -23,6 +24,12 @@ pub struct TransportHandle {
     playhead_frame_shared: Arc<AtomicU64>,
     latest_playhead_frame: u64,
 
+    /// The time (in nanoseconds since `clock_epoch`) at which the process
+    /// thread last updated `playhead_frame_shared`.
+    playhead_updated_at: Arc<AtomicU64>,
+    clock_epoch: Instant,
+    sample_rate: u32,
+
     last_seeked_frame: u64,
 
     coll_handle: basedrop::Handle,
@@ -76,6 +83,26 @@ impl TransportHandle {
         }
     }
 
+    /// An estimate of the frame the playhead is at right now, used to
+    /// timestamp events from the UI (i.e. a gesture on a knob) so that they
+    /// can be placed at the corresponding frame within a process block.
+    ///
+    /// The position of the playhead is only updated once per process cycle,
+    /// so this adds the time which has passed since the last update while
+    /// the transport is playing.
+    pub fn playhead_frame_estimate(&self) -> u64 {
+        let playhead_frame = self.playhead_frame_shared.load(Ordering::Relaxed);
+
+        if !self.parameters.get().is_playing {
+            return playhead_frame;
+        }
+
+        let now = self.clock_epoch.elapsed().as_nanos() as u64;
+        let elapsed_nanos = now.saturating_sub(self.playhead_updated_at.load(Ordering::Relaxed));
+
+        playhead_frame + elapsed_nanos.saturating_mul(u64::from(self.sample_rate)) / 1_000_000_000
+    }
+
     pub fn last_seeked_frame(&self) -> u64 {
         self.last_seeked_frame
     }
@@ -96,6 +123,13 @@ struct Parameters {
     loop_state: (LoopState, u64),
 }
 
+/// The state of the transport before an offline render, used to restore
+/// the transport once the render has finished.
+pub(crate) struct SavedTransportState {
+    parameters: Parameters,
+    playhead_frame: u64,
+}
+
 pub struct TransportTask {
     parameters: Shared<SharedCell<Parameters>>,
 
@@ -124,6 +158,12 @@ pub struct TransportTask {
     transport_info_at_frame: TransportInfoAtFrame,
 
     playhead_frame_shared: Arc<AtomicU64>,
+    playhead_updated_at: Arc<AtomicU64>,
+    clock_epoch: Instant,
+
+    /// A sample counter which is advanced on every process cycle and is never
+    /// reset, even when the playhead seeks or loops back.
+    steady_time: i64,
 
     declick: TransportDeclick,
 }
@@ -152,6 +192,8 @@ impl TransportTask {
 
         let playhead_frame = seek_to_frame;
         let playhead_frame_shared = Arc::new(AtomicU64::new(playhead_frame));
+        let playhead_updated_at = Arc::new(AtomicU64::new(0));
+        let clock_epoch = Instant::now();
 
         let (loop_start_beats, loop_end_beats, loop_start_seconds, loop_end_seconds) =
             if let LoopState::Active { loop_start_frame, loop_end_frame } = &loop_state {
@@ -192,6 +234,9 @@ impl TransportTask {
                 loop_end_seconds,
                 transport_info_at_frame,
                 playhead_frame_shared: Arc::clone(&playhead_frame_shared),
+                playhead_updated_at: Arc::clone(&playhead_updated_at),
+                clock_epoch,
+                steady_time: 0,
                 declick,
             },
             TransportHandle {
@@ -200,11 +245,64 @@ impl TransportTask {
                 coll_handle,
                 playhead_frame_shared,
                 latest_playhead_frame: playhead_frame,
+                playhead_updated_at,
+                clock_epoch,
+                sample_rate,
                 last_seeked_frame: playhead_frame,
             },
         )
     }
 
+    /// Start playing from `start_frame` with looping disabled, ignoring any
+    /// parameters set by the `TransportHandle` until
+    /// `TransportTask::end_offline_render()` is called.
+    ///
+    /// This must only be called while the process thread is parked.
+    pub(crate) fn begin_offline_render(
+        &mut self,
+        start_frame: u64,
+        coll_handle: &basedrop::Handle,
+    ) -> SavedTransportState {
+        let parameters = *self.parameters.get();
+
+        self.parameters.set(Shared::new(
+            coll_handle,
+            Parameters {
+                seek_to_frame: (start_frame, parameters.seek_to_frame.1 + 1),
+                is_playing: true,
+                loop_state: (LoopState::Inactive, parameters.loop_state.1 + 1),
+            },
+        ));
+
+        SavedTransportState { parameters, playhead_frame: self.next_playhead_frame }
+    }
+
+    /// Restore the state of the transport from before the offline render.
+    ///
+    /// This must only be called while the process thread is parked.
+    pub(crate) fn end_offline_render(
+        &mut self,
+        saved: SavedTransportState,
+        coll_handle: &basedrop::Handle,
+    ) {
+        let current = *self.parameters.get();
+
+        self.parameters.set(Shared::new(
+            coll_handle,
+            Parameters {
+                seek_to_frame: (saved.playhead_frame, current.seek_to_frame.1 + 1),
+                is_playing: saved.parameters.is_playing,
+                loop_state: (saved.parameters.loop_state.0, current.loop_state.1 + 1),
+            },
+        ));
+    }
+
+    /// The steady sample time at the start of the next call to
+    /// `TransportTask::process()`.
+    pub fn steady_time(&self) -> i64 {
+        self.steady_time
+    }
+
     /// Update the state of this transport.
     pub fn process(&mut self, frames: usize) -> TransportInfo {
         let Parameters { seek_to_frame, is_playing, loop_state } = *self.parameters.get();
@@ -263,11 +361,6 @@ impl TransportTask {
             self.loop_end_seconds = loop_end_seconds;
         }
 
-        // We don't need to return a new transport event if nothing has changed and
-        // we are not currently playing.
-        let do_return_event =
-            self.is_playing || is_playing || tempo_map_changed || loop_state_changed;
-
         self.is_playing = is_playing;
         self.loop_back_info = None;
         self.playhead_frame = self.next_playhead_frame;
@@ -305,11 +398,20 @@ impl TransportTask {
             self.transport_info_at_frame = tempo_map.transport_info_at_frame(self.playhead_frame);
         } else {
             self.range_checker = RangeChecker::Paused;
+
+            // Make sure the position reported to plugins is still correct when
+            // seeking or changing the tempo map while paused.
+            if self.seek_info.is_some() || tempo_map_changed {
+                self.transport_info_at_frame =
+                    tempo_map.transport_info_at_frame(self.playhead_frame);
+            }
         }
 
         self.playhead_frame_shared.store(self.next_playhead_frame, Ordering::Relaxed);
+        self.playhead_updated_at
+            .store(self.clock_epoch.elapsed().as_nanos() as u64, Ordering::Relaxed);
 
-        let event: Option<TransportEvent> = if do_return_event {
+        let event = {
             let song_pos_beats = tempo_map.frame_to_beat(self.playhead_frame);
             let song_pos_seconds = tempo_map.frame_to_seconds(self.playhead_frame);
 
@@ -328,7 +430,7 @@ impl TransportTask {
                 transport_flags |= TransportEventFlags::IS_LOOP_ACTIVE
             }
 
-            Some(TransportEvent {
+            TransportEvent {
                 header: EventHeader::new_core(0, EventFlags::empty()),
 
                 flags: transport_flags,
@@ -349,9 +451,7 @@ impl TransportTask {
 
                 time_signature_numerator: self.transport_info_at_frame.tsig_num as i16,
                 time_signature_denominator: self.transport_info_at_frame.tsig_denom as i16,
-            })
-        } else {
-            None
+            }
         };
 
         let jump_info = if let Some(info) = &self.loop_back_info {
@@ -365,6 +465,8 @@ impl TransportTask {
         self.declick.process(self.playhead_frame, frames, self.is_playing, jump_info);
         let declick_info = self.declick.get_info();
 
+        self.steady_time += proc_frames as i64;
+
         TransportInfo::_new(
             self.playhead_frame,
             self.is_playing,
@@ -35,8 +35,8 @@ impl UnloadedPluginTask {
             let in_buf_ref = in_buf.borrow();
             let mut out_buf_ref = out_buf.borrow_mut();
 
-            out_buf_ref.data.clear();
             out_buf_ref.data.clone_from(&in_buf_ref.data);
+            out_buf_ref.bytes.clone_from(&in_buf_ref.bytes);
         }
 
         // Make sure all output buffers are cleared.
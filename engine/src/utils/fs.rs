@@ -0,0 +1,44 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Write `contents` to the file at `path`, replacing the file if it exists.
+///
+/// The contents are written to a temporary file next to it first, which is
+/// then renamed, so a failed write never leaves behind a half-written file.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Create a directory in the platform's temporary directory which is unique
+/// to this process and `name`, for use by tests which read and write files.
+#[cfg(test)]
+pub(crate) fn unique_temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("meadowlark-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomic_replaces_file() {
+        let dir = unique_temp_dir("fs-write-atomic");
+        let path = dir.join("file.txt");
+
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,2 +1,3 @@
+pub mod fs;
 pub mod reducing_queue;
 pub mod thread_id;
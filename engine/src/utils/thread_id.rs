@@ -5,6 +5,9 @@ pub(crate) struct SharedThreadIDs {
     // TODO: Use AtomicU64 instead once ThreadId::as_u64() becomes stable?
     main_thread_id: Shared<SharedCell<Option<ThreadId>>>,
     process_thread_id: Shared<SharedCell<Option<ThreadId>>>,
+    /// The realtime worker threads which help the process thread process the
+    /// schedule.
+    worker_thread_ids: Shared<SharedCell<Vec<ThreadId>>>,
 }
 
 impl Clone for SharedThreadIDs {
@@ -12,6 +15,7 @@ impl Clone for SharedThreadIDs {
         Self {
             main_thread_id: Shared::clone(&self.main_thread_id),
             process_thread_id: Shared::clone(&self.process_thread_id),
+            worker_thread_ids: Shared::clone(&self.worker_thread_ids),
         }
     }
 }
@@ -31,6 +35,10 @@ impl SharedThreadIDs {
                 coll_handle,
                 SharedCell::new(Shared::new(coll_handle, process_thread_id)),
             ),
+            worker_thread_ids: Shared::new(
+                coll_handle,
+                SharedCell::new(Shared::new(coll_handle, Vec::new())),
+            ),
         }
     }
 
@@ -50,15 +58,25 @@ impl SharedThreadIDs {
         }
     }
 
+    /// Returns `true` if the current thread is either the process thread or
+    /// one of the realtime worker threads.
     pub fn is_process_thread(&self) -> bool {
+        let current_id = std::thread::current().id();
+
         if let Some(process_thread_id) = *self.process_thread_id.get() {
-            std::thread::current().id() == process_thread_id
-        } else {
-            false
+            if current_id == process_thread_id {
+                return true;
+            }
         }
+
+        self.worker_thread_ids.get().contains(&current_id)
     }
 
     pub fn set_process_thread_id(&self, id: ThreadId, coll_handle: &basedrop::Handle) {
         self.process_thread_id.set(Shared::new(coll_handle, Some(id)));
     }
+
+    pub fn set_worker_thread_ids(&self, ids: Vec<ThreadId>, coll_handle: &basedrop::Handle) {
+        self.worker_thread_ids.set(Shared::new(coll_handle, ids));
+    }
 }
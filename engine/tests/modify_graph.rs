@@ -0,0 +1,431 @@
+use basedrop::Shared;
+use std::time::{Duration, Instant};
+
+use meadowlark_engine::engine::modify_request::{
+    ConnectEdgeReq, EdgeReqPortID, ModifyGraphRequest, PluginIDReq,
+};
+use meadowlark_engine::engine::{
+    ActivateEngineSettings, AudioGraphSaveState, DefaultTempoMap, EngineMainThread, EngineSettings,
+    OnIdleEvent,
+};
+use meadowlark_engine::graph::error::GraphCompilerError;
+use meadowlark_engine::graph::PortType;
+use meadowlark_engine::plugin_host::PluginHostSaveState;
+use meadowlark_plugin_api::transport::LoopState;
+use meadowlark_plugin_api::{
+    buffer::EventBuffer, ext, HostInfo, HostRequestChannelSender, PluginActivatedInfo,
+    PluginDescriptor, PluginFactory, PluginInstanceID, PluginMainThread, PluginProcessor,
+    ProcBuffers, ProcInfo, ProcessStatus,
+};
+
+static THRU_PLUG_RDN: &str = "app.meadowlark.test-thru";
+
+const COMPILE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A plugin with a mono input and a mono output which does nothing.
+struct ThruPlugFactory;
+
+impl PluginFactory for ThruPlugFactory {
+    fn description(&self) -> PluginDescriptor {
+        PluginDescriptor {
+            id: THRU_PLUG_RDN.into(),
+            version: "0.1".into(),
+            name: "Thru".into(),
+            vendor: "Meadowlark".into(),
+            description: String::new(),
+            url: String::new(),
+            manual_url: String::new(),
+            support_url: String::new(),
+            features: String::new(),
+        }
+    }
+
+    fn instantiate(
+        &mut self,
+        _host_request_channel: HostRequestChannelSender,
+        _host_info: Shared<HostInfo>,
+        _plugin_id: PluginInstanceID,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<Box<dyn PluginMainThread>, String> {
+        Ok(Box::new(ThruPlugMainThread))
+    }
+}
+
+struct ThruPlugMainThread;
+
+impl PluginMainThread for ThruPlugMainThread {
+    fn activate(
+        &mut self,
+        _sample_rate: u32,
+        _min_frames: u32,
+        _max_frames: u32,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<PluginActivatedInfo, String> {
+        Ok(PluginActivatedInfo { processor: Box::new(ThruPlugProcessor), internal_handle: None })
+    }
+
+    fn audio_ports_ext(&mut self) -> Result<ext::audio_ports::PluginAudioPortsExt, String> {
+        Ok(ext::audio_ports::PluginAudioPortsExt::mono_in_out())
+    }
+}
+
+struct ThruPlugProcessor;
+
+impl PluginProcessor for ThruPlugProcessor {
+    fn process(
+        &mut self,
+        _proc_info: &ProcInfo,
+        _buffers: &mut ProcBuffers,
+        _in_events: &EventBuffer,
+        _out_events: &mut EventBuffer,
+    ) -> ProcessStatus {
+        ProcessStatus::Continue
+    }
+}
+
+fn connect_mono_edge(
+    src_plugin_id: PluginIDReq,
+    dst_plugin_id: PluginIDReq,
+    check_for_cycles: bool,
+) -> ConnectEdgeReq {
+    ConnectEdgeReq {
+        edge_type: PortType::Audio,
+        src_plugin_id,
+        dst_plugin_id,
+        src_port_id: EdgeReqPortID::Main,
+        src_port_channel: 0,
+        dst_port_id: EdgeReqPortID::Main,
+        dst_port_channel: 0,
+        check_for_cycles,
+        log_error_on_fail: true,
+    }
+}
+
+/// Keep polling the engine until the next compile has finished.
+fn wait_for_compile(engine: &mut EngineMainThread) -> Result<(), GraphCompilerError> {
+    wait_for_compile_result(engine).0
+}
+
+/// Keep polling the engine until the next compile has finished, and return
+/// its status along with the IDs of the modifications that were rolled back.
+fn wait_for_compile_result(
+    engine: &mut EngineMainThread,
+) -> (Result<(), GraphCompilerError>, Vec<u64>) {
+    let start_time = Instant::now();
+
+    loop {
+        let (events, _) = engine.on_timer();
+        for event in events {
+            if let OnIdleEvent::GraphCompiled { status, rolled_back_requests, .. } = event {
+                return (status, rolled_back_requests);
+            }
+        }
+
+        assert!(start_time.elapsed() < COMPILE_TIMEOUT, "Timed out while waiting for compile");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn failed_compile_rolls_back_modification() {
+    let sample_rate = 44_100;
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![Box::new(ThruPlugFactory)],
+    );
+    let thru_plug_key = internal_plugins_res[0].clone().unwrap();
+
+    let (_, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames: 256,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![
+                PluginHostSaveState::new_with_default_state(thru_plug_key.clone()),
+                PluginHostSaveState::new_with_default_state(thru_plug_key.clone()),
+            ],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![connect_mono_edge(
+                PluginIDReq::Added(0),
+                PluginIDReq::Added(1),
+                true,
+            )],
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    assert_eq!(res.new_edges.len(), 1);
+    wait_for_compile(&mut engine).unwrap();
+
+    let plug_a = res.new_plugins[0].plugin_id.clone();
+    let plug_b = res.new_plugins[1].plugin_id.clone();
+    let a_to_b = res.new_edges[0].id;
+
+    let save_state_before = engine.collect_graph_save_state().unwrap();
+
+    // Remove one plugin, disconnect the existing edge, and add a new plugin
+    // which forms a cycle with the remaining one.
+    let res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![PluginHostSaveState::new_with_default_state(thru_plug_key)],
+            remove_plugin_instances: vec![plug_a.clone()],
+            connect_new_edges: vec![
+                connect_mono_edge(
+                    PluginIDReq::Existing(plug_b.clone()),
+                    PluginIDReq::Added(0),
+                    false,
+                ),
+                connect_mono_edge(PluginIDReq::Added(0), PluginIDReq::Existing(plug_b), false),
+            ],
+            disconnect_edges: vec![a_to_b],
+        })
+        .unwrap();
+    assert_eq!(res.removed_plugins, vec![plug_a.clone()]);
+    assert_eq!(res.new_edges.len(), 2);
+
+    assert!(wait_for_compile(&mut engine).is_err());
+
+    // Everything is back to how it was before the modification.
+    let save_state_after = engine.collect_graph_save_state().unwrap();
+    assert_eq!(save_state_after.plugins.len(), save_state_before.plugins.len());
+    assert_eq!(save_state_after.edges, save_state_before.edges);
+    assert!(engine.plugin_host(&plug_a).is_some());
+
+    // The rolled back graph is compiled again so the new plugin gets dropped.
+    wait_for_compile(&mut engine).unwrap();
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
+
+#[test]
+fn failed_compile_keeps_independent_modifications() {
+    let sample_rate = 44_100;
+
+    // A long idle interval, so that modifications can be made while a compile
+    // is in progress.
+    let main_idle_interval = Duration::from_millis(200);
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings {
+            main_idle_interval_ms: main_idle_interval.as_millis() as u32,
+            plugin_scan_cache_path: None,
+            ..Default::default()
+        },
+        vec![Box::new(ThruPlugFactory)],
+    );
+    let thru_plug_key = internal_plugins_res[0].clone().unwrap();
+
+    let (_, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames: 256,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![
+                PluginHostSaveState::new_with_default_state(thru_plug_key.clone()),
+                PluginHostSaveState::new_with_default_state(thru_plug_key.clone()),
+            ],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![connect_mono_edge(
+                PluginIDReq::Added(0),
+                PluginIDReq::Added(1),
+                true,
+            )],
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    wait_for_compile(&mut engine).unwrap();
+
+    let plug_b = res.new_plugins[1].plugin_id.clone();
+
+    let save_state_before = engine.collect_graph_save_state().unwrap();
+
+    // Add a plugin which forms a cycle with an existing one.
+    let failed_res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![PluginHostSaveState::new_with_default_state(
+                thru_plug_key.clone(),
+            )],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![
+                connect_mono_edge(
+                    PluginIDReq::Existing(plug_b.clone()),
+                    PluginIDReq::Added(0),
+                    false,
+                ),
+                connect_mono_edge(PluginIDReq::Added(0), PluginIDReq::Existing(plug_b), false),
+            ],
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    let plug_x = failed_res.new_plugins[0].plugin_id.clone();
+
+    // The compile results were just collected on an idle tick, so this waits
+    // for exactly one more idle tick, which starts compiling the modification.
+    std::thread::sleep(main_idle_interval * 3 / 2);
+    let _ = engine.on_timer();
+
+    // Make a modification which doesn't depend on the failed one, and one
+    // which does, while the failed one is still being compiled.
+    let independent_res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![PluginHostSaveState::new_with_default_state(thru_plug_key)],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![],
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    let plug_y = independent_res.new_plugins[0].plugin_id.clone();
+
+    let dependent_res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![connect_mono_edge(
+                PluginIDReq::Existing(plug_x),
+                PluginIDReq::Existing(plug_y.clone()),
+                false,
+            )],
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    assert_eq!(dependent_res.new_edges.len(), 1);
+
+    let (status, rolled_back_requests) = wait_for_compile_result(&mut engine);
+    assert!(status.is_err());
+    assert_eq!(rolled_back_requests, vec![failed_res.request_id, dependent_res.request_id]);
+
+    // The independent modification is compiled with the next schedule.
+    wait_for_compile(&mut engine).unwrap();
+
+    assert!(engine.plugin_host(&plug_y).is_some());
+    let save_state_after = engine.collect_graph_save_state().unwrap();
+    assert_eq!(save_state_after.plugins.len(), save_state_before.plugins.len() + 1);
+    assert_eq!(save_state_after.edges, save_state_before.edges);
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
+
+#[test]
+fn graph_save_state_round_trips_through_serde() {
+    let settings = ActivateEngineSettings {
+        sample_rate: 48_000,
+        max_frames: 256,
+        num_audio_in_channels: 2,
+        num_audio_out_channels: 2,
+        num_worker_threads: 0,
+        ..Default::default()
+    };
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![Box::new(ThruPlugFactory)],
+    );
+    let thru_plug_key = internal_plugins_res[0].clone().unwrap();
+
+    let (engine_info, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, settings.sample_rate)),
+            settings,
+        )
+        .unwrap();
+
+    let mut to_graph_out = connect_mono_edge(
+        PluginIDReq::Added(1),
+        PluginIDReq::Existing(engine_info.graph_out_id.clone()),
+        true,
+    );
+    to_graph_out.dst_port_channel = 1;
+
+    let mut save_state_plugin = PluginHostSaveState::new_with_default_state(thru_plug_key.clone());
+    save_state_plugin.bypassed = true;
+
+    engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![
+                PluginHostSaveState::new_with_default_state(thru_plug_key),
+                save_state_plugin,
+            ],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![
+                connect_mono_edge(
+                    PluginIDReq::Existing(engine_info.graph_in_id.clone()),
+                    PluginIDReq::Added(0),
+                    true,
+                ),
+                connect_mono_edge(PluginIDReq::Added(0), PluginIDReq::Added(1), true),
+                to_graph_out,
+            ],
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    wait_for_compile(&mut engine).unwrap();
+
+    let save_state = engine.collect_graph_save_state().unwrap();
+    assert_eq!(save_state.edges.len(), 3);
+
+    let text = ron::to_string(&save_state).unwrap();
+    let loaded: AudioGraphSaveState = ron::from_str(&text).unwrap();
+
+    assert_eq!(loaded.settings, save_state.settings);
+    assert_eq!(loaded.edges, save_state.edges);
+    assert_eq!(loaded.plugins.len(), save_state.plugins.len());
+    for (loaded_plugin, plugin) in loaded.plugins.iter().zip(save_state.plugins.iter()) {
+        assert_eq!(loaded_plugin.key, plugin.key);
+        assert_eq!(loaded_plugin.bypassed, plugin.bypassed);
+        assert_eq!(loaded_plugin.backup_audio_ports_ext, plugin.backup_audio_ports_ext);
+    }
+
+    // Rebuild the graph in a freshly activated engine from the loaded save
+    // state.
+    drop(audio_thread);
+    engine.deactivate_engine();
+
+    let (_, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, loaded.settings.sample_rate)),
+            loaded.settings,
+        )
+        .unwrap();
+
+    let res = engine.restore_graph(loaded).unwrap();
+    assert_eq!(res.new_plugins.len(), 2);
+    wait_for_compile(&mut engine).unwrap();
+
+    let restored = engine.collect_graph_save_state().unwrap();
+    assert_eq!(restored.settings, save_state.settings);
+    assert_eq!(restored.edges, save_state.edges);
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
@@ -0,0 +1,752 @@
+use basedrop::Shared;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use meadowlark_engine::engine::error::OfflineRenderError;
+use meadowlark_engine::engine::modify_request::{
+    ConnectEdgeReq, EdgeReqPortID, ModifyGraphRequest, PluginIDReq,
+};
+use meadowlark_engine::engine::{
+    ActivateEngineSettings, DefaultTempoMap, EngineMainThread, EngineSettings,
+    OfflineRenderSettings,
+};
+use meadowlark_engine::graph::PortType;
+use meadowlark_engine::plugin_host::PluginHostSaveState;
+use meadowlark_plugin_api::ext::audio_ports::{
+    AudioPortInfo, MainPortsLayout, PluginAudioPortsExt, PORT_TYPE_STEREO,
+};
+use meadowlark_plugin_api::transport::LoopState;
+use meadowlark_plugin_api::{
+    buffer::EventBuffer, ext, HostInfo, HostRequestChannelSender, PluginActivatedInfo,
+    PluginDescriptor, PluginFactory, PluginInstanceID, PluginMainThread, PluginProcessor,
+    ProcBuffers, ProcInfo, ProcessStatus, TailLength,
+};
+
+static DC_PLUG_RDN: &str = "app.meadowlark.test-dc";
+static SINK_PLUG_RDN: &str = "app.meadowlark.test-sink";
+
+static DC_LEFT: f32 = 0.25;
+static DC_RIGHT: f32 = -0.5;
+
+/// A plugin which outputs a constant value on each of its two output
+/// channels.
+struct DcPlugFactory {
+    latency: i64,
+    tail: TailLength,
+    status: ProcessStatus,
+}
+
+impl DcPlugFactory {
+    fn new(latency: i64) -> Self {
+        Self { latency, tail: TailLength::Frames(0), status: ProcessStatus::Continue }
+    }
+}
+
+impl PluginFactory for DcPlugFactory {
+    fn description(&self) -> PluginDescriptor {
+        PluginDescriptor {
+            id: DC_PLUG_RDN.into(),
+            version: "0.1".into(),
+            name: "DC".into(),
+            vendor: "Meadowlark".into(),
+            description: String::new(),
+            url: String::new(),
+            manual_url: String::new(),
+            support_url: String::new(),
+            features: String::new(),
+        }
+    }
+
+    fn instantiate(
+        &mut self,
+        _host_request_channel: HostRequestChannelSender,
+        _host_info: Shared<HostInfo>,
+        _plugin_id: PluginInstanceID,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<Box<dyn PluginMainThread>, String> {
+        Ok(Box::new(DcPlugMainThread {
+            latency: self.latency,
+            tail: self.tail,
+            status: self.status,
+        }))
+    }
+}
+
+struct DcPlugMainThread {
+    latency: i64,
+    tail: TailLength,
+    status: ProcessStatus,
+}
+
+impl PluginMainThread for DcPlugMainThread {
+    fn activate(
+        &mut self,
+        _sample_rate: u32,
+        _min_frames: u32,
+        _max_frames: u32,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<PluginActivatedInfo, String> {
+        Ok(PluginActivatedInfo {
+            processor: Box::new(DcPlugProcessor { tail: self.tail, status: self.status }),
+            internal_handle: None,
+        })
+    }
+
+    fn audio_ports_ext(&mut self) -> Result<ext::audio_ports::PluginAudioPortsExt, String> {
+        Ok(ext::audio_ports::PluginAudioPortsExt::stereo_out())
+    }
+
+    fn latency(&self) -> i64 {
+        self.latency
+    }
+}
+
+struct DcPlugProcessor {
+    tail: TailLength,
+    status: ProcessStatus,
+}
+
+impl PluginProcessor for DcPlugProcessor {
+    fn process(
+        &mut self,
+        proc_info: &ProcInfo,
+        buffers: &mut ProcBuffers,
+        _in_events: &EventBuffer,
+        _out_events: &mut EventBuffer,
+    ) -> ProcessStatus {
+        let (mut buf_l, mut buf_r) = buffers.audio_out[0].stereo_f32_mut().unwrap();
+
+        buf_l.data[0..proc_info.frames].fill(DC_LEFT);
+        buf_r.data[0..proc_info.frames].fill(DC_RIGHT);
+        buf_l.is_constant = true;
+        buf_r.is_constant = true;
+
+        self.status
+    }
+
+    fn tail(&mut self) -> TailLength {
+        self.tail
+    }
+}
+
+/// A plugin with a stereo input and no outputs, which counts the number of
+/// frames it has received from the DC plugin.
+struct SinkPlugFactory {
+    dc_frames_received: Arc<AtomicU64>,
+    status: ProcessStatus,
+}
+
+impl PluginFactory for SinkPlugFactory {
+    fn description(&self) -> PluginDescriptor {
+        PluginDescriptor {
+            id: SINK_PLUG_RDN.into(),
+            version: "0.1".into(),
+            name: "Sink".into(),
+            vendor: "Meadowlark".into(),
+            description: String::new(),
+            url: String::new(),
+            manual_url: String::new(),
+            support_url: String::new(),
+            features: String::new(),
+        }
+    }
+
+    fn instantiate(
+        &mut self,
+        _host_request_channel: HostRequestChannelSender,
+        _host_info: Shared<HostInfo>,
+        _plugin_id: PluginInstanceID,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<Box<dyn PluginMainThread>, String> {
+        Ok(Box::new(SinkPlugMainThread {
+            dc_frames_received: Arc::clone(&self.dc_frames_received),
+            status: self.status,
+        }))
+    }
+}
+
+struct SinkPlugMainThread {
+    dc_frames_received: Arc<AtomicU64>,
+    status: ProcessStatus,
+}
+
+impl PluginMainThread for SinkPlugMainThread {
+    fn activate(
+        &mut self,
+        _sample_rate: u32,
+        _min_frames: u32,
+        _max_frames: u32,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<PluginActivatedInfo, String> {
+        Ok(PluginActivatedInfo {
+            processor: Box::new(SinkPlugProcessor {
+                dc_frames_received: Arc::clone(&self.dc_frames_received),
+                status: self.status,
+            }),
+            internal_handle: None,
+        })
+    }
+
+    fn audio_ports_ext(&mut self) -> Result<PluginAudioPortsExt, String> {
+        Ok(PluginAudioPortsExt {
+            inputs: vec![AudioPortInfo {
+                stable_id: 0,
+                channels: 2,
+                port_type: Some(PORT_TYPE_STEREO.into()),
+                display_name: None,
+            }],
+            outputs: vec![],
+            main_ports_layout: MainPortsLayout::InOnly,
+        })
+    }
+}
+
+struct SinkPlugProcessor {
+    dc_frames_received: Arc<AtomicU64>,
+    status: ProcessStatus,
+}
+
+impl PluginProcessor for SinkPlugProcessor {
+    fn process(
+        &mut self,
+        proc_info: &ProcInfo,
+        buffers: &mut ProcBuffers,
+        _in_events: &EventBuffer,
+        _out_events: &mut EventBuffer,
+    ) -> ProcessStatus {
+        let (buf_l, buf_r) = buffers.audio_in[0].stereo_f32().unwrap();
+
+        let num_dc_frames = buf_l.data[0..proc_info.frames]
+            .iter()
+            .zip(buf_r.data[0..proc_info.frames].iter())
+            .filter(|(l, r)| **l == DC_LEFT && **r == DC_RIGHT)
+            .count();
+        self.dc_frames_received.fetch_add(num_dc_frames as u64, Ordering::Relaxed);
+
+        self.status
+    }
+}
+
+fn connect_stereo_edges(
+    src_plugin_id: PluginIDReq,
+    dst_plugin_id: PluginIDReq,
+) -> Vec<ConnectEdgeReq> {
+    (0..2)
+        .map(|channel| ConnectEdgeReq {
+            edge_type: PortType::Audio,
+            src_plugin_id: src_plugin_id.clone(),
+            dst_plugin_id: dst_plugin_id.clone(),
+            src_port_id: EdgeReqPortID::Main,
+            src_port_channel: channel,
+            dst_port_id: EdgeReqPortID::Main,
+            dst_port_channel: channel,
+            check_for_cycles: true,
+            log_error_on_fail: true,
+        })
+        .collect()
+}
+
+#[test]
+fn render_dc_plugin_to_graph_output() {
+    let sample_rate = 44_100;
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![Box::new(DcPlugFactory::new(0))],
+    );
+    let dc_plug_key = internal_plugins_res[0].clone().unwrap();
+
+    let (engine_info, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames: 256,
+                num_audio_out_channels: 2,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![PluginHostSaveState::new_with_default_state(dc_plug_key)],
+            remove_plugin_instances: vec![],
+            connect_new_edges: connect_stereo_edges(
+                PluginIDReq::Added(0),
+                PluginIDReq::Existing(engine_info.graph_out_id.clone()),
+            ),
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    assert_eq!(res.new_edges.len(), 2);
+
+    // Use a length which is not a multiple of `max_frames` so the last block
+    // is a partial one.
+    let num_frames = 1_000;
+    let (output, info) = engine
+        .render_offline_to_vec(OfflineRenderSettings {
+            start_frame: 0,
+            end_frame: num_frames,
+            max_tail_ms: 0,
+            ..Default::default()
+        })
+        .unwrap();
+
+    assert_eq!(info.num_frames, num_frames);
+    assert_eq!(info.num_audio_out_channels, 2);
+    assert!(!info.cancelled);
+    assert_eq!(output.len(), num_frames as usize * 2);
+    for frame in output.chunks_exact(2) {
+        assert_eq!(frame, &[DC_LEFT, DC_RIGHT]);
+    }
+
+    // Every block of the render is metered, including the partial one.
+    let report = engine.performance_report().unwrap();
+    assert_eq!(report.offline_render.num_cycles, 4);
+    assert!(report.offline_render_dsp_load.max > 0.0);
+
+    // Rendering the same range again gives the exact same output.
+    let (output_2, _) = engine
+        .render_offline_to_vec(OfflineRenderSettings {
+            start_frame: 0,
+            end_frame: num_frames,
+            max_tail_ms: 0,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(output, output_2);
+
+    // Let the process thread exit before deactivating the engine.
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
+
+#[test]
+fn plugin_not_connected_to_graph_output_is_processed() {
+    let sample_rate = 44_100;
+    let dc_latency = 64;
+
+    let dc_frames_received = Arc::new(AtomicU64::new(0));
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![
+            Box::new(DcPlugFactory::new(dc_latency)),
+            Box::new(SinkPlugFactory {
+                dc_frames_received: Arc::clone(&dc_frames_received),
+                status: ProcessStatus::Continue,
+            }),
+        ],
+    );
+    let dc_plug_key = internal_plugins_res[0].clone().unwrap();
+    let sink_plug_key = internal_plugins_res[1].clone().unwrap();
+
+    let (engine_info, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames: 256,
+                num_audio_out_channels: 2,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    // The sink plugin has no path to the graph output, just like the
+    // recorder plugin.
+    let mut connect_new_edges = connect_stereo_edges(
+        PluginIDReq::Added(0),
+        PluginIDReq::Existing(engine_info.graph_out_id.clone()),
+    );
+    connect_new_edges
+        .append(&mut connect_stereo_edges(PluginIDReq::Added(0), PluginIDReq::Added(1)));
+
+    let res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![
+                PluginHostSaveState::new_with_default_state(dc_plug_key),
+                PluginHostSaveState::new_with_default_state(sink_plug_key),
+            ],
+            remove_plugin_instances: vec![],
+            connect_new_edges,
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    assert_eq!(res.new_edges.len(), 4);
+
+    assert_eq!(engine.graph_out_latency(), dc_latency as u64);
+
+    let num_frames = 1_000;
+    engine
+        .render_offline_to_vec(OfflineRenderSettings {
+            start_frame: 0,
+            end_frame: num_frames,
+            max_tail_ms: 0,
+            ..Default::default()
+        })
+        .unwrap();
+
+    assert_eq!(dc_frames_received.load(Ordering::Relaxed), num_frames);
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
+
+#[test]
+fn render_offline_in_steps() {
+    let sample_rate = 44_100;
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![Box::new(DcPlugFactory::new(0))],
+    );
+    let dc_plug_key = internal_plugins_res[0].clone().unwrap();
+
+    let (engine_info, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames: 256,
+                num_audio_out_channels: 2,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![PluginHostSaveState::new_with_default_state(dc_plug_key)],
+            remove_plugin_instances: vec![],
+            connect_new_edges: connect_stereo_edges(
+                PluginIDReq::Added(0),
+                PluginIDReq::Existing(engine_info.graph_out_id.clone()),
+            ),
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+
+    let num_frames = 1_000;
+    let settings = OfflineRenderSettings {
+        start_frame: 0,
+        end_frame: num_frames,
+        max_tail_ms: 0,
+        ..Default::default()
+    };
+
+    engine.begin_offline_render(settings.clone()).unwrap();
+    assert!(engine.is_rendering_offline());
+    assert_eq!(
+        engine.begin_offline_render(settings.clone()).unwrap_err(),
+        OfflineRenderError::AlreadyRendering
+    );
+
+    // Without any time to spare, every step processes a single block.
+    let mut output = Vec::new();
+    let mut num_steps = 0;
+    loop {
+        let progress = engine
+            .continue_offline_render(Duration::ZERO, |block| {
+                output.extend_from_slice(block);
+                true
+            })
+            .unwrap();
+        num_steps += 1;
+
+        assert_eq!(progress.total_frames, num_frames);
+        if progress.finished {
+            assert_eq!(progress.frames_rendered, num_frames);
+            break;
+        }
+    }
+    assert_eq!(num_steps, 4);
+
+    let info = engine.end_offline_render().unwrap();
+    assert!(!engine.is_rendering_offline());
+    assert_eq!(info.num_frames, num_frames);
+    assert!(!info.cancelled);
+    assert_eq!(output.len(), num_frames as usize * 2);
+    for frame in output.chunks_exact(2) {
+        assert_eq!(frame, &[DC_LEFT, DC_RIGHT]);
+    }
+
+    // Ending a render before it has finished cancels it.
+    engine.begin_offline_render(settings.clone()).unwrap();
+    engine.continue_offline_render(Duration::ZERO, |_| true).unwrap();
+    let info = engine.end_offline_render().unwrap();
+    assert!(info.cancelled);
+    assert_eq!(info.num_frames, 256);
+
+    assert!(engine.end_offline_render().is_none());
+    assert_eq!(
+        engine.continue_offline_render(Duration::ZERO, |_| true).unwrap_err(),
+        OfflineRenderError::NotRendering
+    );
+
+    // The schedule is handed back once the render has ended, so the same
+    // range can be rendered again in one go.
+    let (output_2, _) = engine.render_offline_to_vec(settings).unwrap();
+    assert_eq!(output, output_2);
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
+
+#[test]
+fn render_is_extended_by_tail_of_upstream_plugins() {
+    let sample_rate = 44_100;
+    let dc_tail = 300;
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![
+            Box::new(DcPlugFactory { tail: TailLength::Frames(dc_tail), ..DcPlugFactory::new(0) }),
+            Box::new(DcPlugFactory { tail: TailLength::Infinite, ..DcPlugFactory::new(0) }),
+            Box::new(SinkPlugFactory {
+                dc_frames_received: Arc::new(AtomicU64::new(0)),
+                status: ProcessStatus::Continue,
+            }),
+        ],
+    );
+    let dc_plug_key = internal_plugins_res[0].clone().unwrap();
+    let infinite_dc_plug_key = internal_plugins_res[1].clone().unwrap();
+    let sink_plug_key = internal_plugins_res[2].clone().unwrap();
+
+    let (engine_info, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames: 256,
+                num_audio_out_channels: 2,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    // The plugin with the infinite tail does not feed into the graph output,
+    // so it must not extend the render.
+    let mut connect_new_edges = connect_stereo_edges(
+        PluginIDReq::Added(0),
+        PluginIDReq::Existing(engine_info.graph_out_id.clone()),
+    );
+    connect_new_edges
+        .append(&mut connect_stereo_edges(PluginIDReq::Added(1), PluginIDReq::Added(2)));
+
+    engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![
+                PluginHostSaveState::new_with_default_state(dc_plug_key),
+                PluginHostSaveState::new_with_default_state(infinite_dc_plug_key),
+                PluginHostSaveState::new_with_default_state(sink_plug_key),
+            ],
+            remove_plugin_instances: vec![],
+            connect_new_edges,
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+
+    let num_frames = 1_000;
+    let (output, info) = engine
+        .render_offline_to_vec(OfflineRenderSettings {
+            start_frame: 0,
+            end_frame: num_frames,
+            max_tail_ms: 1_000,
+            ..Default::default()
+        })
+        .unwrap();
+
+    assert_eq!(info.num_frames, num_frames + u64::from(dc_tail));
+    assert!(!info.cancelled);
+    assert_eq!(output.len(), info.num_frames as usize * 2);
+
+    // The tail is clamped to `max_tail_ms`.
+    let (_, info) = engine
+        .render_offline_to_vec(OfflineRenderSettings {
+            start_frame: 0,
+            end_frame: num_frames,
+            max_tail_ms: 5,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(info.num_frames, num_frames + 5 * u64::from(sample_rate) / 1_000);
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
+
+#[test]
+fn plugin_sleeps_once_its_tail_has_rung_out() {
+    let sample_rate = 44_100;
+    let max_frames = 256;
+    let dc_tail = 600;
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![Box::new(DcPlugFactory {
+            tail: TailLength::Frames(dc_tail),
+            status: ProcessStatus::Tail,
+            ..DcPlugFactory::new(0)
+        })],
+    );
+    let dc_plug_key = internal_plugins_res[0].clone().unwrap();
+
+    let (engine_info, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames,
+                num_audio_out_channels: 2,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![PluginHostSaveState::new_with_default_state(dc_plug_key)],
+            remove_plugin_instances: vec![],
+            connect_new_edges: connect_stereo_edges(
+                PluginIDReq::Added(0),
+                PluginIDReq::Existing(engine_info.graph_out_id.clone()),
+            ),
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+
+    let num_frames = 1_000;
+    let (output, _) = engine
+        .render_offline_to_vec(OfflineRenderSettings {
+            start_frame: 0,
+            end_frame: num_frames,
+            max_tail_ms: 0,
+            ..Default::default()
+        })
+        .unwrap();
+
+    // The plugin has no inputs, so it keeps processing until the end of the
+    // block in which its tail of 600 frames has rung out (the third block of
+    // 256 frames), and is then put to sleep.
+    let frames_until_sleep = 768;
+    for (i, frame) in output.chunks_exact(2).enumerate() {
+        if i < frames_until_sleep {
+            assert_eq!(frame, &[DC_LEFT, DC_RIGHT], "frame {}", i);
+        } else {
+            assert_eq!(frame, &[0.0, 0.0], "frame {}", i);
+        }
+    }
+
+    let report = engine.performance_report().unwrap();
+    assert!(report.plugins.iter().any(|(_, stats)| stats.num_skipped_cycles > 0));
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
+
+#[test]
+fn silence_propagates_through_large_session() {
+    let sample_rate = 44_100;
+    let num_sinks = 32;
+
+    // Both sources output one block and then go to sleep, like tracks whose
+    // clips have ended.
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![
+            Box::new(DcPlugFactory { status: ProcessStatus::Sleep, ..DcPlugFactory::new(0) }),
+            Box::new(SinkPlugFactory {
+                dc_frames_received: Arc::new(AtomicU64::new(0)),
+                status: ProcessStatus::ContinueIfNotQuiet,
+            }),
+        ],
+    );
+    let dc_plug_key = internal_plugins_res[0].clone().unwrap();
+    let sink_plug_key = internal_plugins_res[1].clone().unwrap();
+
+    let (_, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames: 256,
+                num_audio_out_channels: 2,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    // Every sink sums the outputs of both sources.
+    let mut add_plugin_instances = vec![
+        PluginHostSaveState::new_with_default_state(dc_plug_key.clone()),
+        PluginHostSaveState::new_with_default_state(dc_plug_key),
+    ];
+    let mut connect_new_edges = Vec::new();
+    for i in 0..num_sinks {
+        add_plugin_instances
+            .push(PluginHostSaveState::new_with_default_state(sink_plug_key.clone()));
+        for src in 0..2 {
+            connect_new_edges.append(&mut connect_stereo_edges(
+                PluginIDReq::Added(src),
+                PluginIDReq::Added(2 + i),
+            ));
+        }
+    }
+
+    engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances,
+            remove_plugin_instances: vec![],
+            connect_new_edges,
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+
+    let num_frames = 1_024;
+    engine
+        .render_offline_to_vec(OfflineRenderSettings {
+            start_frame: 0,
+            end_frame: num_frames,
+            max_tail_ms: 0,
+            ..Default::default()
+        })
+        .unwrap();
+
+    // After the first of the four blocks, every source and sink in the
+    // session is asleep and gets skipped, since the silent hints propagate
+    // through the sum tasks. This is the work saved by the silent hints.
+    let report = engine.performance_report().unwrap();
+    let num_skipped_cycles: u64 =
+        report.plugins.iter().map(|(_, stats)| stats.num_skipped_cycles).sum();
+    assert!(num_skipped_cycles >= (num_sinks as u64 + 2) * 3);
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
@@ -0,0 +1,331 @@
+use basedrop::Shared;
+use clack_host::events::spaces::CoreEventSpace;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use meadowlark_engine::engine::modify_request::{
+    ConnectEdgeReq, EdgeReqPortID, ModifyGraphRequest, PluginIDReq,
+};
+use meadowlark_engine::engine::{
+    ActivateEngineSettings, DefaultTempoMap, EngineMainThread, EngineSettings,
+    OfflineRenderSettings,
+};
+use meadowlark_engine::graph::PortType;
+use meadowlark_engine::plugin_host::error::SetParamValueError;
+use meadowlark_engine::plugin_host::PluginHostSaveState;
+use meadowlark_plugin_api::automation::AutomationIoEvent;
+use meadowlark_plugin_api::ext::params::{ParamInfo, ParamInfoFlags};
+use meadowlark_plugin_api::transport::LoopState;
+use meadowlark_plugin_api::ParamID;
+use meadowlark_plugin_api::{
+    buffer::EventBuffer, HostInfo, HostRequestChannelSender, PluginActivatedInfo, PluginDescriptor,
+    PluginFactory, PluginInstanceID, PluginMainThread, PluginProcessor, ProcBuffers, ProcInfo,
+    ProcessStatus,
+};
+
+static MOD_PLUG_RDN: &str = "app.meadowlark.test-mod";
+static TARGET_PLUG_RDN: &str = "app.meadowlark.test-mod-target";
+
+const MODULATABLE_PARAM: u32 = 0;
+const NOT_MODULATABLE_PARAM: u32 = 1;
+const UNKNOWN_PARAM: u32 = 99;
+
+static EDGE_MOD_AMOUNT: f64 = 0.25;
+
+fn descriptor(id: &str, name: &str) -> PluginDescriptor {
+    PluginDescriptor {
+        id: id.into(),
+        version: "0.1".into(),
+        name: name.into(),
+        vendor: "Meadowlark".into(),
+        description: String::new(),
+        url: String::new(),
+        manual_url: String::new(),
+        support_url: String::new(),
+        features: String::new(),
+    }
+}
+
+/// An internal modulator plugin which modulates every parameter ID of the
+/// target plugin (including one which doesn't exist) in each block.
+struct ModPlugFactory {
+    target_instance_id: Arc<AtomicU64>,
+}
+
+impl PluginFactory for ModPlugFactory {
+    fn description(&self) -> PluginDescriptor {
+        descriptor(MOD_PLUG_RDN, "Mod")
+    }
+
+    fn instantiate(
+        &mut self,
+        _host_request_channel: HostRequestChannelSender,
+        _host_info: Shared<HostInfo>,
+        _plugin_id: PluginInstanceID,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<Box<dyn PluginMainThread>, String> {
+        Ok(Box::new(ModPlugMainThread { target_instance_id: Arc::clone(&self.target_instance_id) }))
+    }
+}
+
+struct ModPlugMainThread {
+    target_instance_id: Arc<AtomicU64>,
+}
+
+impl PluginMainThread for ModPlugMainThread {
+    fn activate(
+        &mut self,
+        _sample_rate: u32,
+        _min_frames: u32,
+        _max_frames: u32,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<PluginActivatedInfo, String> {
+        Ok(PluginActivatedInfo {
+            processor: Box::new(ModPlugProcessor {
+                target_instance_id: Arc::clone(&self.target_instance_id),
+            }),
+            internal_handle: None,
+        })
+    }
+
+    fn has_automation_out_port(&self) -> bool {
+        true
+    }
+}
+
+struct ModPlugProcessor {
+    target_instance_id: Arc<AtomicU64>,
+}
+
+impl PluginProcessor for ModPlugProcessor {
+    fn process(
+        &mut self,
+        _proc_info: &ProcInfo,
+        _buffers: &mut ProcBuffers,
+        _in_events: &EventBuffer,
+        _out_events: &mut EventBuffer,
+    ) -> ProcessStatus {
+        ProcessStatus::Error
+    }
+
+    fn process_with_automation_out(
+        &mut self,
+        _proc_info: &ProcInfo,
+        _buffers: &mut ProcBuffers,
+        _in_events: &EventBuffer,
+        _out_events: &mut EventBuffer,
+        automation_out: &mut Vec<AutomationIoEvent>,
+    ) -> ProcessStatus {
+        let target_instance_id = self.target_instance_id.load(Ordering::Relaxed);
+
+        for param_id in [MODULATABLE_PARAM, NOT_MODULATABLE_PARAM, UNKNOWN_PARAM] {
+            automation_out.push(AutomationIoEvent::modulation(
+                target_instance_id,
+                param_id,
+                0,
+                EDGE_MOD_AMOUNT,
+            ));
+        }
+
+        ProcessStatus::Continue
+    }
+}
+
+/// A plugin with a modulatable and a non-modulatable parameter, which
+/// records the modulation events it receives.
+struct TargetPlugFactory {
+    received_mod_events: Arc<Mutex<Vec<(u32, f64)>>>,
+}
+
+impl PluginFactory for TargetPlugFactory {
+    fn description(&self) -> PluginDescriptor {
+        descriptor(TARGET_PLUG_RDN, "Mod Target")
+    }
+
+    fn instantiate(
+        &mut self,
+        _host_request_channel: HostRequestChannelSender,
+        _host_info: Shared<HostInfo>,
+        _plugin_id: PluginInstanceID,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<Box<dyn PluginMainThread>, String> {
+        Ok(Box::new(TargetPlugMainThread {
+            received_mod_events: Arc::clone(&self.received_mod_events),
+        }))
+    }
+}
+
+struct TargetPlugMainThread {
+    received_mod_events: Arc<Mutex<Vec<(u32, f64)>>>,
+}
+
+impl PluginMainThread for TargetPlugMainThread {
+    fn activate(
+        &mut self,
+        _sample_rate: u32,
+        _min_frames: u32,
+        _max_frames: u32,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<PluginActivatedInfo, String> {
+        Ok(PluginActivatedInfo {
+            processor: Box::new(TargetPlugProcessor {
+                received_mod_events: Arc::clone(&self.received_mod_events),
+            }),
+            internal_handle: None,
+        })
+    }
+
+    fn num_params(&mut self) -> u32 {
+        2
+    }
+
+    fn param_info(&mut self, param_index: usize) -> Result<ParamInfo, Box<dyn Error>> {
+        let (stable_id, flags) = match param_index {
+            0 => (MODULATABLE_PARAM, ParamInfoFlags::default_float()),
+            1 => (NOT_MODULATABLE_PARAM, ParamInfoFlags::IS_AUTOMATABLE),
+            _ => return Err(format!("Param at index {} does not exist", param_index).into()),
+        };
+
+        Ok(ParamInfo::new(
+            ParamID::new(stable_id),
+            flags,
+            format!("Param {}", stable_id),
+            String::new(),
+            0.0,
+            1.0,
+            0.0,
+        ))
+    }
+
+    fn param_value(&self, _param_id: ParamID) -> Result<f64, Box<dyn Error>> {
+        Ok(0.0)
+    }
+}
+
+struct TargetPlugProcessor {
+    received_mod_events: Arc<Mutex<Vec<(u32, f64)>>>,
+}
+
+impl PluginProcessor for TargetPlugProcessor {
+    fn process(
+        &mut self,
+        _proc_info: &ProcInfo,
+        _buffers: &mut ProcBuffers,
+        in_events: &EventBuffer,
+        _out_events: &mut EventBuffer,
+    ) -> ProcessStatus {
+        let mut received_mod_events = self.received_mod_events.lock().unwrap();
+        for event in in_events.iter() {
+            if let Some(CoreEventSpace::ParamMod(e)) = event.as_core_event() {
+                received_mod_events.push((e.param_id(), e.value()));
+            }
+        }
+
+        ProcessStatus::Continue
+    }
+}
+
+#[test]
+fn modulation_from_main_thread_and_edges_is_summed() {
+    let sample_rate = 44_100;
+    let max_frames = 256;
+
+    let target_instance_id = Arc::new(AtomicU64::new(0));
+    let received_mod_events = Arc::new(Mutex::new(Vec::new()));
+
+    let (mut engine, _, internal_plugins_res) = EngineMainThread::new(
+        HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+        EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+        vec![
+            Box::new(ModPlugFactory { target_instance_id: Arc::clone(&target_instance_id) }),
+            Box::new(TargetPlugFactory { received_mod_events: Arc::clone(&received_mod_events) }),
+        ],
+    );
+    let mod_plug_key = internal_plugins_res[0].clone().unwrap();
+    let target_plug_key = internal_plugins_res[1].clone().unwrap();
+
+    let (_, audio_thread) = engine
+        .activate_engine(
+            0,
+            LoopState::Inactive,
+            Box::new(DefaultTempoMap::new(120.0, 4, 4, sample_rate)),
+            ActivateEngineSettings {
+                sample_rate,
+                max_frames,
+                num_audio_out_channels: 2,
+                num_worker_threads: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let res = engine
+        .modify_graph(ModifyGraphRequest {
+            add_plugin_instances: vec![
+                PluginHostSaveState::new_with_default_state(mod_plug_key),
+                PluginHostSaveState::new_with_default_state(target_plug_key),
+            ],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![ConnectEdgeReq {
+                edge_type: PortType::Automation,
+                src_plugin_id: PluginIDReq::Added(0),
+                dst_plugin_id: PluginIDReq::Added(1),
+                src_port_id: EdgeReqPortID::Main,
+                src_port_channel: 0,
+                dst_port_id: EdgeReqPortID::Main,
+                dst_port_channel: 0,
+                check_for_cycles: true,
+                log_error_on_fail: true,
+            }],
+            disconnect_edges: vec![],
+        })
+        .unwrap();
+    assert_eq!(res.new_edges.len(), 1);
+
+    let target_id = res.new_plugins[1].plugin_id.clone();
+    target_instance_id.store(target_id.unique_id(), Ordering::Relaxed);
+
+    let target_host = engine.plugin_host_mut(&target_id).unwrap();
+
+    // Only modulatable parameters can be modulated, and the amount is
+    // clamped to the range of the parameter.
+    assert!(matches!(
+        target_host.set_param_mod_amount(ParamID::new(NOT_MODULATABLE_PARAM), 0.5),
+        Err(SetParamValueError::ParamIsNotModulatable(_))
+    ));
+    assert!(matches!(
+        target_host.set_param_mod_amount(ParamID::new(UNKNOWN_PARAM), 0.5),
+        Err(SetParamValueError::ParamDoesNotExist(_))
+    ));
+    assert_eq!(
+        target_host.set_param_mod_amount(ParamID::new(MODULATABLE_PARAM), 2.0).unwrap(),
+        1.0
+    );
+    assert_eq!(
+        target_host.set_param_mod_amount(ParamID::new(MODULATABLE_PARAM), 0.5).unwrap(),
+        0.5
+    );
+
+    // Render a single block.
+    engine
+        .render_offline_to_vec(OfflineRenderSettings {
+            start_frame: 0,
+            end_frame: u64::from(max_frames),
+            max_tail_ms: 0,
+            ..Default::default()
+        })
+        .unwrap();
+
+    // The amount from the main thread is sent at the start of the block, and
+    // then the amount from the modulator plugin is added on top of it. The
+    // events for the parameters which aren't modulatable or don't exist are
+    // discarded.
+    assert_eq!(
+        *received_mod_events.lock().unwrap(),
+        vec![(MODULATABLE_PARAM, 0.5), (MODULATABLE_PARAM, 0.5 + EDGE_MOD_AMOUNT)]
+    );
+
+    drop(audio_thread);
+    engine.deactivate_engine();
+}
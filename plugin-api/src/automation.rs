@@ -7,9 +7,25 @@ pub struct AutomationIoEvent {
     pub parameter_id: u32,
     pub event_type: AutomationIoEventType,
     pub plugin_instance_id: u64,
+    /// The cookie of the target parameter, if known. Internal plugins such as
+    /// modulators may leave this as `None`.
     pub cookie: Option<Cookie>,
 }
 
+impl AutomationIoEvent {
+    /// A modulation event targeting the given parameter on the given plugin
+    /// instance, for use by internal modulator plugins (e.g. LFOs or envelopes).
+    pub fn modulation(plugin_instance_id: u64, parameter_id: u32, time: u32, amount: f64) -> Self {
+        Self {
+            header: IoEventHeader { time },
+            parameter_id,
+            event_type: AutomationIoEventType::Modulation(amount),
+            plugin_instance_id,
+            cookie: None,
+        }
+    }
+}
+
 // Contains common data
 #[derive(Copy, Clone)]
 pub struct IoEventHeader {
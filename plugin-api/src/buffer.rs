@@ -40,17 +40,26 @@ impl Debug for DebugBufferID {
 pub struct BufferInner<T: Clone + Copy + Send + Sync + 'static> {
     pub data: Vec<T>,
     pub is_constant: bool,
+
+    /// An arena for variable-sized event data (i.e. MIDI SysEx messages)
+    /// which the events in `data` refer to by offset. This is only used by
+    /// note buffers, and it is cleared along with `data`.
+    pub bytes: Vec<u8>,
 }
 
 impl<T: Clone + Copy + Send + Sync + 'static> BufferInner<T> {
-    fn with_capacity(capacity: usize) -> Self {
-        Self { data: Vec::with_capacity(capacity), is_constant: false }
+    fn with_capacity(capacity: usize, bytes_capacity: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(capacity),
+            is_constant: false,
+            bytes: Vec::with_capacity(bytes_capacity),
+        }
     }
 }
 
 impl<T: Clone + Copy + Send + Sync + Default + 'static> BufferInner<T> {
     fn new(max_frames: usize) -> Self {
-        Self { data: vec![T::default(); max_frames], is_constant: false }
+        Self { data: vec![T::default(); max_frames], is_constant: false, bytes: Vec::new() }
     }
 }
 
@@ -66,8 +75,11 @@ pub struct SharedBuffer<T: Clone + Copy + Send + Sync + 'static> {
 }
 
 impl<T: Clone + Copy + Send + Sync + 'static> SharedBuffer<T> {
+    /// `bytes_capacity` is the capacity of the arena for variable-sized event
+    /// data (see `BufferInner::bytes`).
     pub fn with_capacity(
         capacity: usize,
+        bytes_capacity: usize,
         debug_info: DebugBufferID,
         coll_handle: &basedrop::Handle,
     ) -> Self {
@@ -75,7 +87,7 @@ impl<T: Clone + Copy + Send + Sync + 'static> SharedBuffer<T> {
             buffer: Shared::new(
                 coll_handle,
                 Buffer {
-                    data: AtomicRefCell::new(BufferInner::with_capacity(capacity)),
+                    data: AtomicRefCell::new(BufferInner::with_capacity(capacity, bytes_capacity)),
                     debug_info,
                 },
             ),
@@ -98,7 +110,9 @@ impl<T: Clone + Copy + Send + Sync + 'static> SharedBuffer<T> {
     }
 
     pub fn truncate(&self) {
-        self.borrow_mut().data.truncate(0)
+        let mut buf_ref = self.borrow_mut();
+        buf_ref.data.truncate(0);
+        buf_ref.bytes.clear();
     }
 }
 
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 pub const PORT_TYPE_MONO: &str = "mono";
 pub const PORT_TYPE_STEREO: &str = "stereo";
 
@@ -9,7 +11,7 @@ pub(crate) static EMPTY_AUDIO_PORTS_CONFIG: PluginAudioPortsExt = PluginAudioPor
 ///
 /// By default this returns a configuration with a main stereo
 /// input port and a main stereo output port.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PluginAudioPortsExt {
     /// The list of input audio ports, in order.
     pub inputs: Vec<AudioPortInfo>,
@@ -325,7 +327,7 @@ impl PluginAudioPortsExt {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// Information about a custom audio port.
 pub struct AudioPortInfo {
     /// Stable identifier, it must never change.
@@ -354,7 +356,7 @@ pub struct AudioPortInfo {
     pub display_name: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 /// Specifies which audio ports are "main" ports.
 pub enum MainPortsLayout {
     /// Both the first input port and the first output port are main ports.
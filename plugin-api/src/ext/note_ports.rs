@@ -3,10 +3,11 @@
 //! The plugin is only allowed to change its note ports configuration while it is deactivated.
 
 use clack_extensions::note_ports::{NoteDialect, NoteDialects};
+use serde::{Deserialize, Serialize};
 
 pub(crate) static EMPTY_NOTE_PORTS_CONFIG: PluginNotePortsExt = PluginNotePortsExt::empty();
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// The layout of the audio ports of a plugin.
 pub struct PluginNotePortsExt {
     /// The list of input note ports.
@@ -22,17 +23,75 @@ impl PluginNotePortsExt {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NotePortInfo {
     /// stable identifier
     pub stable_id: u32,
 
     /// bitfield, see `NoteDialect`
+    #[serde(with = "serde_note_dialects")]
     pub supported_dialects: NoteDialects,
 
     /// one value of `NoteDialect`
+    #[serde(with = "serde_preferred_dialect")]
     pub preferred_dialect: Option<NoteDialect>,
 
     /// displayable name
     pub display_name: Option<String>,
 }
+
+/// `NoteDialects` is defined by `clack_extensions`, so it is (de)serialized
+/// as its raw bitfield.
+mod serde_note_dialects {
+    use clack_extensions::note_ports::NoteDialects;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(dialects: &NoteDialects, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u32(dialects.bits())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NoteDialects, D::Error> {
+        Ok(NoteDialects::from_bits_truncate(u32::deserialize(d)?))
+    }
+}
+
+/// `NoteDialect` is defined by `clack_extensions`, so it is (de)serialized as
+/// the bit of that dialect in `NoteDialects`.
+mod serde_preferred_dialect {
+    use clack_extensions::note_ports::{NoteDialect, NoteDialects};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        dialect: &Option<NoteDialect>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let bits = dialect.map(|dialect| match dialect {
+            NoteDialect::Clap => NoteDialects::CLAP.bits(),
+            NoteDialect::Midi => NoteDialects::MIDI.bits(),
+            NoteDialect::MidiMpe => NoteDialects::MIDI_MPE.bits(),
+            NoteDialect::Midi2 => NoteDialects::MIDI2.bits(),
+        });
+
+        bits.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<NoteDialect>, D::Error> {
+        let bits = match Option::<u32>::deserialize(d)? {
+            Some(bits) => NoteDialects::from_bits_truncate(bits),
+            None => return Ok(None),
+        };
+
+        // An unknown dialect is the same as no preferred dialect.
+        Ok(if bits == NoteDialects::CLAP {
+            Some(NoteDialect::Clap)
+        } else if bits == NoteDialects::MIDI {
+            Some(NoteDialect::Midi)
+        } else if bits == NoteDialects::MIDI_MPE {
+            Some(NoteDialect::MidiMpe)
+        } else if bits == NoteDialects::MIDI2 {
+            Some(NoteDialect::Midi2)
+        } else {
+            None
+        })
+    }
+}
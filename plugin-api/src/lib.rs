@@ -23,7 +23,7 @@ pub use host_info::HostInfo;
 pub use host_request_channel::*;
 pub use instance_id::*;
 pub use main_thread::{PluginActivatedInfo, PluginMainThread};
-pub use process_info::{ProcBuffers, ProcInfo, ProcessStatus};
+pub use process_info::{ProcBuffers, ProcInfo, ProcessStatus, TailLength};
 pub use processor::PluginProcessor;
 
 pub use clack_host::events::event_types as event;
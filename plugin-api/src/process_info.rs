@@ -26,6 +26,17 @@ pub enum ProcessStatus {
     Sleep = 4,
 }
 
+/// The length of a plugin's tail, i.e. how long the plugin keeps outputting
+/// sound after its inputs have gone silent (e.g. a reverb or a delay).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TailLength {
+    /// The tail is this many frames long.
+    Frames(u32),
+
+    /// The plugin may keep outputting sound forever (e.g. an oscillator).
+    Infinite,
+}
+
 pub struct ProcInfo {
     /// A steady sample time counter.
     ///
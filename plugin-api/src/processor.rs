@@ -2,7 +2,7 @@ use std::error::Error;
 
 use super::automation::AutomationIoEvent;
 use super::buffer::EventBuffer;
-use super::process_info::{ProcBuffers, ProcInfo, ProcessStatus};
+use super::process_info::{ProcBuffers, ProcInfo, ProcessStatus, TailLength};
 
 /// The methods of an audio plugin instance which run in the "process" thread.
 pub trait PluginProcessor: Send + 'static {
@@ -39,6 +39,19 @@ pub trait PluginProcessor: Send + 'static {
         out_events: &mut EventBuffer,
     ) -> ProcessStatus;
 
+    /// The length of the plugin's tail.
+    ///
+    /// The host uses this to decide when to put the plugin to sleep after
+    /// `process()` returned `ProcessStatus::Tail`, and to know how far past
+    /// the end of an offline render it should keep rendering.
+    ///
+    /// By default this returns `TailLength::Frames(0)`.
+    ///
+    /// `[process-thread & active_state & processing_state]`
+    fn tail(&mut self) -> TailLength {
+        TailLength::Frames(0)
+    }
+
     /// Flushes a set of parameter changes.
     ///
     /// This will only be called while the plugin is active.
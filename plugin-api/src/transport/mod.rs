@@ -12,7 +12,7 @@ pub struct TransportInfo {
     loop_back_info: Option<LoopBackInfo>,
     seek_info: Option<SeekInfo>,
     range_checker: RangeChecker,
-    event: Option<TransportEvent>,
+    event: TransportEvent,
     declick: DeclickInfo,
 }
 
@@ -40,7 +40,7 @@ impl TransportInfo {
         loop_back_info: Option<LoopBackInfo>,
         seek_info: Option<SeekInfo>,
         range_checker: RangeChecker,
-        event: Option<TransportEvent>,
+        event: TransportEvent,
         declick: DeclickInfo,
     ) -> Self {
         Self {
@@ -103,8 +103,11 @@ impl TransportInfo {
         self.range_checker.is_frame_active(self.playhead_frame, frame)
     }
 
-    pub fn event(&self) -> Option<&TransportEvent> {
-        self.event.as_ref()
+    /// The state of the transport at the start of this process block (tempo,
+    /// time signature, bar start, loop range, and whether or not it is playing)
+    /// in the form of a CLAP transport event.
+    pub fn event(&self) -> &TransportEvent {
+        &self.event
     }
 
     pub fn declick_info(&self) -> &DeclickInfo {
@@ -0,0 +1,459 @@
+//! Saving and loading the user's configuration (the `AppState`) to/from the
+//! platform's config directory.
+//!
+//! Like project files, the config file is stored as a RON (Rusty Object
+//! Notation) text file so it can be edited by hand. Any setting which is
+//! missing from the file falls back to its default value, so older config
+//! files can still be loaded after new settings are added.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use meadowlark_engine::utils::fs::write_atomic;
+
+use crate::state_system::source_state::{
+    AppState, AudioSettings, BrowserPanelState, BrowserPanelTab as StateBrowserPanelTab,
+    SnapMode as StateSnapMode, TimelineTool as StateTimelineTool,
+};
+use crate::util::fmt_newer_version_error;
+
+/// The version of the config file format written by this version of
+/// Meadowlark.
+pub static CONFIG_FILE_VERSION: u32 = 1;
+
+/// The name of the config file inside the config directory.
+pub static CONFIG_FILE_NAME: &str = "config.ron";
+
+#[derive(Debug)]
+pub enum ConfigFileError {
+    Io(std::io::Error),
+    Serialize(String),
+    Parse(String),
+    /// The file was written by a newer version of Meadowlark.
+    NewerVersion(u32),
+    /// The platform has no config directory.
+    NoConfigDirectory,
+}
+
+impl Error for ConfigFileError {}
+
+impl std::fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigFileError::Io(e) => write!(f, "Config file error: {}", e),
+            ConfigFileError::Serialize(e) => {
+                write!(f, "Config file error: failed to serialize config: {}", e)
+            }
+            ConfigFileError::Parse(e) => {
+                write!(f, "Config file error: failed to parse config file: {}", e)
+            }
+            ConfigFileError::NewerVersion(v) => {
+                write!(f, "Config file error: ")?;
+                fmt_newer_version_error(f, "config", *v, CONFIG_FILE_VERSION)
+            }
+            ConfigFileError::NoConfigDirectory => {
+                write!(f, "Config file error: could not find the user's config directory")
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigFileError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigFileError::Io(e)
+    }
+}
+
+/// The path to the config file in the platform's config directory.
+pub fn config_file_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("meadowlark").join(CONFIG_FILE_NAME))
+}
+
+/// Load the app state from the config file.
+///
+/// This returns `Ok(None)` if no config file exists yet.
+pub fn load_app_state() -> Result<Option<AppState>, ConfigFileError> {
+    let path = config_file_path().ok_or(ConfigFileError::NoConfigDirectory)?;
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    load_app_state_from(&path).map(Some)
+}
+
+/// Load the app state from the config file, falling back to the default
+/// app state if the file does not exist or could not be loaded.
+pub fn load_app_state_or_default() -> AppState {
+    match load_app_state() {
+        Ok(Some(app_state)) => app_state,
+        Ok(None) => AppState::new(),
+        Err(e) => {
+            log::error!("Could not load config file, using the default config: {}", e);
+            AppState::new()
+        }
+    }
+}
+
+/// Save the app state to the config file.
+pub fn save_app_state(app_state: &AppState) -> Result<(), ConfigFileError> {
+    let path = config_file_path().ok_or(ConfigFileError::NoConfigDirectory)?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    save_app_state_to(app_state, &path)
+}
+
+fn load_app_state_from(path: &Path) -> Result<AppState, ConfigFileError> {
+    let text = std::fs::read_to_string(path)?;
+
+    let file: ConfigFile =
+        ron::from_str(&text).map_err(|e| ConfigFileError::Parse(e.to_string()))?;
+
+    if file.version > CONFIG_FILE_VERSION {
+        return Err(ConfigFileError::NewerVersion(file.version));
+    }
+
+    Ok(file.into_app_state())
+}
+
+fn save_app_state_to(app_state: &AppState, path: &Path) -> Result<(), ConfigFileError> {
+    let file = ConfigFile::from_app_state(app_state);
+
+    let text = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default())
+        .map_err(|e| ConfigFileError::Serialize(e.to_string()))?;
+
+    write_atomic(path, text)?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    version: u32,
+
+    browser_panel: BrowserPanel,
+
+    selected_timeline_tool: TimelineTool,
+    timeline_snap_active: bool,
+    timeline_snap_mode: SnapMode,
+
+    record_input_channels: [u16; 2],
+    recordings_directory: PathBuf,
+
+    audio: Audio,
+
+    clap_scan_directories: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct BrowserPanel {
+    panel_shown: bool,
+    current_tab: BrowserPanelTab,
+    panel_width: f32,
+    volume_normalized: f32,
+    playback_on_select: bool,
+    root_sample_directories: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct Audio {
+    out_device_name: Option<String>,
+    in_device_name: Option<String>,
+    min_frames: u32,
+    max_frames: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+enum BrowserPanelTab {
+    Samples,
+    Multisamples,
+    Synths,
+    Effects,
+    PianoRollClips,
+    AutomationClips,
+    Projects,
+    Files,
+}
+
+#[derive(Serialize, Deserialize)]
+enum TimelineTool {
+    Pointer,
+    Pencil,
+    Slicer,
+    Eraser,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SnapMode {
+    Line,
+    Bar,
+    Beat,
+    HalfBeat,
+    ThirdBeat,
+    QuarterBeat,
+    SixthBeat,
+    EigthBeat,
+    TwelfthBeat,
+    SixteenthBeat,
+    _32ndBeat,
+    DottedBeat,
+    DottedHalfBeat,
+    DottedQuarterBeat,
+}
+
+impl ConfigFile {
+    fn from_app_state(app_state: &AppState) -> Self {
+        let browser_panel = &app_state.browser_panel;
+
+        Self {
+            version: CONFIG_FILE_VERSION,
+            browser_panel: BrowserPanel {
+                panel_shown: browser_panel.panel_shown,
+                current_tab: browser_panel.current_tab.into(),
+                panel_width: browser_panel.panel_width,
+                volume_normalized: browser_panel.volume_normalized,
+                playback_on_select: browser_panel.playback_on_select,
+                root_sample_directories: browser_panel.root_sample_directories.clone(),
+            },
+            selected_timeline_tool: app_state.selected_timeline_tool.into(),
+            timeline_snap_active: app_state.timeline_snap_active,
+            timeline_snap_mode: app_state.timeline_snap_mode.into(),
+            record_input_channels: app_state.record_input_channels,
+            recordings_directory: app_state.recordings_directory.clone(),
+            audio: Audio {
+                out_device_name: app_state.audio.out_device_name.clone(),
+                in_device_name: app_state.audio.in_device_name.clone(),
+                min_frames: app_state.audio.min_frames,
+                max_frames: app_state.audio.max_frames,
+            },
+            clap_scan_directories: app_state.clap_scan_directories.clone(),
+        }
+    }
+
+    fn into_app_state(self) -> AppState {
+        let default_state = AppState::new();
+
+        let min_frames = self.audio.min_frames.max(1);
+        let max_frames = self.audio.max_frames.max(min_frames);
+
+        AppState {
+            browser_panel: BrowserPanelState {
+                panel_shown: self.browser_panel.panel_shown,
+                current_tab: self.browser_panel.current_tab.into(),
+                panel_width: self.browser_panel.panel_width,
+                volume_normalized: self.browser_panel.volume_normalized.clamp(0.0, 1.0),
+                volume_default_normalized: default_state.browser_panel.volume_default_normalized,
+                playback_on_select: self.browser_panel.playback_on_select,
+                root_sample_directories: self.browser_panel.root_sample_directories,
+            },
+            selected_timeline_tool: self.selected_timeline_tool.into(),
+            timeline_snap_active: self.timeline_snap_active,
+            timeline_snap_mode: self.timeline_snap_mode.into(),
+            record_input_channels: self.record_input_channels,
+            recordings_directory: self.recordings_directory,
+            audio: AudioSettings {
+                out_device_name: self.audio.out_device_name,
+                in_device_name: self.audio.in_device_name,
+                min_frames,
+                max_frames,
+            },
+            clap_scan_directories: self.clap_scan_directories,
+        }
+    }
+}
+
+// Missing settings fall back to the values of the default `AppState`.
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile::from_app_state(&AppState::new())
+    }
+}
+
+impl Default for BrowserPanel {
+    fn default() -> Self {
+        ConfigFile::default().browser_panel
+    }
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        ConfigFile::default().audio
+    }
+}
+
+impl From<StateBrowserPanelTab> for BrowserPanelTab {
+    fn from(t: StateBrowserPanelTab) -> Self {
+        match t {
+            StateBrowserPanelTab::Samples => BrowserPanelTab::Samples,
+            StateBrowserPanelTab::Multisamples => BrowserPanelTab::Multisamples,
+            StateBrowserPanelTab::Synths => BrowserPanelTab::Synths,
+            StateBrowserPanelTab::Effects => BrowserPanelTab::Effects,
+            StateBrowserPanelTab::PianoRollClips => BrowserPanelTab::PianoRollClips,
+            StateBrowserPanelTab::AutomationClips => BrowserPanelTab::AutomationClips,
+            StateBrowserPanelTab::Projects => BrowserPanelTab::Projects,
+            StateBrowserPanelTab::Files => BrowserPanelTab::Files,
+        }
+    }
+}
+
+impl From<BrowserPanelTab> for StateBrowserPanelTab {
+    fn from(t: BrowserPanelTab) -> Self {
+        match t {
+            BrowserPanelTab::Samples => StateBrowserPanelTab::Samples,
+            BrowserPanelTab::Multisamples => StateBrowserPanelTab::Multisamples,
+            BrowserPanelTab::Synths => StateBrowserPanelTab::Synths,
+            BrowserPanelTab::Effects => StateBrowserPanelTab::Effects,
+            BrowserPanelTab::PianoRollClips => StateBrowserPanelTab::PianoRollClips,
+            BrowserPanelTab::AutomationClips => StateBrowserPanelTab::AutomationClips,
+            BrowserPanelTab::Projects => StateBrowserPanelTab::Projects,
+            BrowserPanelTab::Files => StateBrowserPanelTab::Files,
+        }
+    }
+}
+
+impl From<StateTimelineTool> for TimelineTool {
+    fn from(t: StateTimelineTool) -> Self {
+        match t {
+            StateTimelineTool::Pointer => TimelineTool::Pointer,
+            StateTimelineTool::Pencil => TimelineTool::Pencil,
+            StateTimelineTool::Slicer => TimelineTool::Slicer,
+            StateTimelineTool::Eraser => TimelineTool::Eraser,
+        }
+    }
+}
+
+impl From<TimelineTool> for StateTimelineTool {
+    fn from(t: TimelineTool) -> Self {
+        match t {
+            TimelineTool::Pointer => StateTimelineTool::Pointer,
+            TimelineTool::Pencil => StateTimelineTool::Pencil,
+            TimelineTool::Slicer => StateTimelineTool::Slicer,
+            TimelineTool::Eraser => StateTimelineTool::Eraser,
+        }
+    }
+}
+
+impl From<StateSnapMode> for SnapMode {
+    fn from(m: StateSnapMode) -> Self {
+        match m {
+            StateSnapMode::Line => SnapMode::Line,
+            StateSnapMode::Bar => SnapMode::Bar,
+            StateSnapMode::Beat => SnapMode::Beat,
+            StateSnapMode::HalfBeat => SnapMode::HalfBeat,
+            StateSnapMode::ThirdBeat => SnapMode::ThirdBeat,
+            StateSnapMode::QuarterBeat => SnapMode::QuarterBeat,
+            StateSnapMode::SixthBeat => SnapMode::SixthBeat,
+            StateSnapMode::EigthBeat => SnapMode::EigthBeat,
+            StateSnapMode::TwelfthBeat => SnapMode::TwelfthBeat,
+            StateSnapMode::SixteenthBeat => SnapMode::SixteenthBeat,
+            StateSnapMode::_32ndBeat => SnapMode::_32ndBeat,
+            StateSnapMode::DottedBeat => SnapMode::DottedBeat,
+            StateSnapMode::DottedHalfBeat => SnapMode::DottedHalfBeat,
+            StateSnapMode::DottedQuarterBeat => SnapMode::DottedQuarterBeat,
+        }
+    }
+}
+
+impl From<SnapMode> for StateSnapMode {
+    fn from(m: SnapMode) -> Self {
+        match m {
+            SnapMode::Line => StateSnapMode::Line,
+            SnapMode::Bar => StateSnapMode::Bar,
+            SnapMode::Beat => StateSnapMode::Beat,
+            SnapMode::HalfBeat => StateSnapMode::HalfBeat,
+            SnapMode::ThirdBeat => StateSnapMode::ThirdBeat,
+            SnapMode::QuarterBeat => StateSnapMode::QuarterBeat,
+            SnapMode::SixthBeat => StateSnapMode::SixthBeat,
+            SnapMode::EigthBeat => StateSnapMode::EigthBeat,
+            SnapMode::TwelfthBeat => StateSnapMode::TwelfthBeat,
+            SnapMode::SixteenthBeat => StateSnapMode::SixteenthBeat,
+            SnapMode::_32ndBeat => StateSnapMode::_32ndBeat,
+            SnapMode::DottedBeat => StateSnapMode::DottedBeat,
+            SnapMode::DottedHalfBeat => StateSnapMode::DottedHalfBeat,
+            SnapMode::DottedQuarterBeat => StateSnapMode::DottedQuarterBeat,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::unique_temp_dir;
+
+    /// A path in the temporary directory which is unique to this test.
+    fn temp_config_path(name: &str) -> PathBuf {
+        unique_temp_dir(&format!("config-file-test-{}", name)).join(CONFIG_FILE_NAME)
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_config_path("round-trip");
+
+        let mut app_state = AppState::new();
+        app_state.browser_panel.current_tab = StateBrowserPanelTab::Effects;
+        app_state.browser_panel.panel_width = 321.0;
+        app_state.browser_panel.volume_normalized = 0.25;
+        app_state.browser_panel.root_sample_directories = vec!["/samples".into()];
+        app_state.selected_timeline_tool = StateTimelineTool::Slicer;
+        app_state.timeline_snap_mode = StateSnapMode::DottedQuarterBeat;
+        app_state.audio.out_device_name = Some("Speakers".into());
+        app_state.audio.max_frames = 1024;
+        app_state.clap_scan_directories = vec!["/plugins/clap".into()];
+
+        save_app_state_to(&app_state, &path).unwrap();
+        let loaded = load_app_state_from(&path).unwrap();
+
+        assert_eq!(loaded.browser_panel.current_tab, StateBrowserPanelTab::Effects);
+        assert_eq!(loaded.browser_panel.panel_width, 321.0);
+        assert_eq!(loaded.browser_panel.volume_normalized, 0.25);
+        assert_eq!(loaded.browser_panel.root_sample_directories, vec![PathBuf::from("/samples")]);
+        assert_eq!(loaded.selected_timeline_tool, StateTimelineTool::Slicer);
+        assert_eq!(loaded.timeline_snap_mode, StateSnapMode::DottedQuarterBeat);
+        assert_eq!(loaded.audio, app_state.audio);
+        assert_eq!(loaded.clap_scan_directories, vec![PathBuf::from("/plugins/clap")]);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_settings_fall_back_to_defaults() {
+        let path = temp_config_path("missing-settings");
+
+        std::fs::write(
+            &path,
+            "ConfigFile(version: 1, browser_panel: (panel_width: 300.0), audio: (min_frames: 0, max_frames: 0))",
+        )
+        .unwrap();
+        let loaded = load_app_state_from(&path).unwrap();
+        let default_state = AppState::new();
+
+        assert_eq!(loaded.browser_panel.panel_width, 300.0);
+        assert_eq!(loaded.browser_panel.current_tab, default_state.browser_panel.current_tab);
+        assert_eq!(loaded.timeline_snap_mode, default_state.timeline_snap_mode);
+        assert_eq!(loaded.recordings_directory, default_state.recordings_directory);
+
+        // Invalid buffer sizes are corrected.
+        assert_eq!((loaded.audio.min_frames, loaded.audio.max_frames), (1, 1));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let path = temp_config_path("newer-version");
+
+        std::fs::write(&path, format!("ConfigFile(version: {})", CONFIG_FILE_VERSION + 1)).unwrap();
+
+        assert!(matches!(load_app_state_from(&path), Err(ConfigFileError::NewerVersion(_))));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -20,6 +20,7 @@ use crate::resource::ResourceLoader;
 use crate::state_system::time::{FrameTime, TempoMap};
 use crate::state_system::SourceState;
 
+use crate::plugins::recorder_plug::{RecorderPlugFactory, RecorderPlugHandle, RECORDER_PLUG_RDN};
 use crate::plugins::sample_browser_plug::{
     SampleBrowserPlugFactory, SampleBrowserPlugHandle, SAMPLE_BROWSER_PLUG_RDN,
 };
@@ -31,9 +32,6 @@ pub mod system_io;
 
 use system_io::SystemIOStreamHandle;
 
-// TODO: Have these be configurable.
-const MIN_FRAMES: u32 = 1;
-const MAX_FRAMES: u32 = 512;
 const GRAPH_IN_CHANNELS: u16 = 2;
 const GRAPH_OUT_CHANNELS: u16 = 2;
 
@@ -52,10 +50,18 @@ pub struct EngineHandle {
 impl EngineHandle {
     pub fn new(state: &SourceState) -> Self {
         // TODO: Use rainout instead of cpal once it's ready.
-        // TODO: Load settings from a save file rather than spawning
-        // a stream with default settings.
-        let mut system_io_stream_handle =
-            crate::engine_handle::system_io::temp_spawn_cpal_default_output_only().unwrap();
+        let audio_settings = &state.app.audio;
+        let mut system_io_stream_handle = match system_io::temp_spawn_cpal_duplex(
+            audio_settings.out_device_name.as_deref(),
+            audio_settings.in_device_name.as_deref(),
+        ) {
+            Ok(handle) => handle,
+            Err(e) => {
+                log::warn!("Could not open audio input, falling back to output only: {}", e);
+                system_io::temp_spawn_cpal_output_only(audio_settings.out_device_name.as_deref())
+                    .unwrap()
+            }
+        };
 
         let (mut ds_engine, first_timer_instant, internal_plugins_scan_res) = EngineMainThread::new(
             HostInfo::new(
@@ -65,11 +71,21 @@ impl EngineHandle {
                 Some("https://meadowlark.app".into()), // url
             ),
             EngineSettings::default(),
-            vec![Box::new(SampleBrowserPlugFactory), Box::new(TimelineTrackPlugFactory)], // list of internal plugins
+            vec![
+                Box::new(SampleBrowserPlugFactory),
+                Box::new(TimelineTrackPlugFactory),
+                Box::new(RecorderPlugFactory),
+            ], // list of internal plugins
         );
 
         log::info!("{:?}", &internal_plugins_scan_res);
 
+        for dir in state.app.clap_scan_directories.iter() {
+            if !ds_engine.add_clap_scan_directory(dir.clone()) {
+                log::warn!("Could not add CLAP scan directory {:?}", dir);
+            }
+        }
+
         let (seek_to_frame, loop_state, tempo_map) = if let Some(project_state) = &state.project {
             let seek_to_frame = project_state
                 .tempo_map
@@ -102,8 +118,8 @@ impl EngineHandle {
                 tempo_map,
                 ActivateEngineSettings {
                     sample_rate: system_io_stream_handle.sample_rate(),
-                    min_frames: MIN_FRAMES,
-                    max_frames: MAX_FRAMES,
+                    min_frames: audio_settings.min_frames,
+                    max_frames: audio_settings.max_frames,
                     num_audio_in_channels: GRAPH_IN_CHANNELS,
                     num_audio_out_channels: GRAPH_OUT_CHANNELS,
                     hard_clip_outputs: true,
@@ -116,18 +132,23 @@ impl EngineHandle {
 
         let mut sample_browser_plug_key = None;
         let mut timeline_track_plug_key = None;
+        let mut recorder_plug_key = None;
         for res in internal_plugins_scan_res.iter() {
             if let Ok(res) = res {
                 if res.rdn == SAMPLE_BROWSER_PLUG_RDN {
                     sample_browser_plug_key = Some(res.clone());
                 } else if res.rdn == TIMELINE_TRACK_PLUG_RDN {
                     timeline_track_plug_key = Some(res.clone());
+                } else if res.rdn == RECORDER_PLUG_RDN {
+                    recorder_plug_key = Some(res.clone());
                 }
             }
         }
         let sample_browser_plug_key = sample_browser_plug_key.unwrap();
         let timeline_track_plug_key = timeline_track_plug_key.unwrap();
+        let recorder_plug_key = recorder_plug_key.unwrap();
 
+        let graph_in_id = engine_info.graph_in_id.clone();
         let graph_out_id = engine_info.graph_out_id.clone();
 
         // Add a sample browser plugin to the graph, and connect it directly
@@ -166,6 +187,8 @@ impl EngineHandle {
             })
             .unwrap();
 
+        let mut graph_requests = vec![(res.request_id, GraphRequest::SampleBrowserPlug)];
+
         let sample_browser_plug_res = res.new_plugins.remove(0);
         let sample_browser_plug_id = sample_browser_plug_res.plugin_id;
         let sample_browser_plug_host = ds_engine.plugin_host_mut(&sample_browser_plug_id).unwrap();
@@ -183,11 +206,56 @@ impl EngineHandle {
             )
             .unwrap();
 
+        // Add a recorder plugin to the graph, and connect the selected graph
+        // inputs to it.
+        let mut res = ds_engine
+            .modify_graph(ModifyGraphRequest {
+                add_plugin_instances: vec![PluginHostSaveState::new_with_default_state(
+                    recorder_plug_key,
+                )],
+                remove_plugin_instances: vec![],
+                connect_new_edges: state
+                    .app
+                    .record_input_channels
+                    .iter()
+                    .enumerate()
+                    .map(|(dst_port_channel, src_port_channel)| ConnectEdgeReq {
+                        edge_type: PortType::Audio,
+                        src_plugin_id: PluginIDReq::Existing(graph_in_id.clone()),
+                        dst_plugin_id: PluginIDReq::Added(0),
+                        src_port_id: EdgeReqPortID::Main,
+                        src_port_channel: (*src_port_channel).min(GRAPH_IN_CHANNELS - 1),
+                        dst_port_id: EdgeReqPortID::Main,
+                        dst_port_channel: dst_port_channel as u16,
+                        check_for_cycles: false,
+                        log_error_on_fail: true,
+                    })
+                    .collect(),
+                disconnect_edges: vec![],
+            })
+            .unwrap();
+
+        graph_requests.push((res.request_id, GraphRequest::RecorderPlug));
+
+        let recorder_plug_res = res.new_plugins.remove(0);
+        let recorder_plug_id = recorder_plug_res.plugin_id;
+        let mut recorder_plug_handle =
+            if let PluginStatus::Activated(status) = recorder_plug_res.status {
+                *(status.internal_handle.unwrap().downcast::<RecorderPlugHandle>().unwrap())
+            } else {
+                panic!("Recorder plugin failed to activate");
+            };
+        recorder_plug_handle.set_recordings_directory(state.app.recordings_directory.clone());
+        if let Some(project_state) = &state.project {
+            recorder_plug_handle.set_armed_tracks(project_state.record_armed_tracks());
+        }
+
         let mut resource_loader = ResourceLoader::new(system_io_stream_handle.sample_rate());
 
+        let mut timeline_track_plug_ids: Vec<PluginInstanceID> = Vec::new();
         let mut timeline_track_plug_handles: Vec<TimelineTrackPlugHandle> = Vec::new();
         if let Some(project_state) = &state.project {
-            for track_state in project_state.tracks.iter() {
+            for (track_index, track_state) in project_state.tracks.iter().enumerate() {
                 // Create a timeline track plugin and add it to the graph.
 
                 // TODO: Tracks that don't have stereo outputs.
@@ -225,6 +293,9 @@ impl EngineHandle {
                     })
                     .unwrap();
 
+                graph_requests
+                    .push((res.request_id, GraphRequest::TimelineTrackPlug { track_index }));
+
                 let timeline_track_plug_res = res.new_plugins.remove(0);
                 let timeline_track_plug_id = timeline_track_plug_res.plugin_id;
                 let timeline_track_plug_host =
@@ -247,17 +318,32 @@ impl EngineHandle {
                     &mut resource_loader,
                 );
 
+                timeline_track_plug_ids.push(timeline_track_plug_id);
                 timeline_track_plug_handles.push(timeline_track_plug_handle);
             }
         }
 
+        // TODO: Connect the external plugins to their tracks once tracks can
+        // host plugins.
+        if let Some(project_state) = &state.project {
+            if let Some((request_id, _)) =
+                add_saved_plugins(&mut ds_engine, &project_state.plugin_states)
+            {
+                graph_requests.push((request_id, GraphRequest::SavedPlugins));
+            }
+        }
+
         let activated_handles = ActivatedEngineHandles {
             engine_info,
             sample_browser_plug_id,
             sample_browser_plug_params,
             sample_browser_plug_handle,
+            timeline_track_plug_ids,
             timeline_track_plug_handles,
+            recorder_plug_id,
+            recorder_plug_handle,
             resource_loader,
+            graph_requests,
         };
 
         Self {
@@ -268,6 +354,76 @@ impl EngineHandle {
             system_io_stream_handle,
         }
     }
+
+    /// The latency (in frames) between when audio on the timeline is
+    /// processed and when audio performed against it arrives at the recorder.
+    ///
+    /// This is the latency of the graph up to the graph output (including
+    /// delay compensation) plus the round-trip latency of the system audio
+    /// streams. Recorded takes are shifted earlier on the timeline by this
+    /// amount.
+    pub fn recording_latency_frames(&self) -> u64 {
+        self.ds_engine.graph_out_latency()
+            + self.system_io_stream_handle.round_trip_latency_frames()
+    }
+}
+
+/// Add the plugins with the given save states to the graph, restoring the
+/// saved state of each plugin.
+///
+/// Plugins which fail to load are still added to the graph as placeholders,
+/// so their save states are kept the next time the project is saved.
+///
+/// Returns the ID of the request to modify the graph along with the IDs of
+/// the new plugins (in the same order as `plugin_states`), or `None` if no
+/// plugins were added.
+fn add_saved_plugins(
+    ds_engine: &mut EngineMainThread,
+    plugin_states: &[PluginHostSaveState],
+) -> Option<(u64, Vec<PluginInstanceID>)> {
+    if plugin_states.is_empty() {
+        return None;
+    }
+
+    let res = match ds_engine.modify_graph(ModifyGraphRequest {
+        add_plugin_instances: plugin_states.to_vec(),
+        remove_plugin_instances: vec![],
+        connect_new_edges: vec![],
+        disconnect_edges: vec![],
+    }) {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("Failed to add the plugins in the project to the graph: {}", e);
+            return None;
+        }
+    };
+
+    for (new_plugin, save_state) in res.new_plugins.iter().zip(plugin_states.iter()) {
+        match &new_plugin.status {
+            PluginStatus::LoadError(e) => {
+                log::error!("Failed to load plugin {:?}: {}", &save_state.key, e);
+            }
+            PluginStatus::ActivationError(e) => {
+                log::error!("Failed to activate plugin {:?}: {}", &save_state.key, e);
+            }
+            _ => {}
+        }
+    }
+
+    Some((
+        res.request_id,
+        res.new_plugins.into_iter().map(|new_plugin| new_plugin.plugin_id).collect(),
+    ))
+}
+
+/// The part of the project that a request to modify the audio graph was made
+/// for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphRequest {
+    SampleBrowserPlug,
+    RecorderPlug,
+    TimelineTrackPlug { track_index: usize },
+    SavedPlugins,
 }
 
 pub enum EnginePollStatus {
@@ -284,5 +440,179 @@ pub struct ActivatedEngineHandles {
     pub sample_browser_plug_params: Vec<ParamID>,
     pub sample_browser_plug_handle: SampleBrowserPlugHandle,
 
+    /// The IDs of the timeline track plugins, in the same order as the tracks
+    /// in the project.
+    pub timeline_track_plug_ids: Vec<PluginInstanceID>,
     pub timeline_track_plug_handles: Vec<TimelineTrackPlugHandle>,
+
+    pub recorder_plug_id: PluginInstanceID,
+    pub recorder_plug_handle: RecorderPlugHandle,
+
+    /// The IDs of the requests this handle made to modify the audio graph,
+    /// used to find out which parts of the project were rolled back when the
+    /// audio graph fails to compile.
+    pub graph_requests: Vec<(u64, GraphRequest)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Shared;
+    use meadowlark_engine::engine::{DefaultTempoMap, EngineAudioThread};
+    use meadowlark_engine::plugin_scanner::ScannedPluginKey;
+    use meadowlark_plugin_api::{
+        buffer::EventBuffer, HostRequestChannelSender, PluginActivatedInfo, PluginDescriptor,
+        PluginFactory, PluginMainThread, PluginProcessor, ProcBuffers, ProcInfo, ProcessStatus,
+    };
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::project_file;
+    use crate::state_system::source_state::ProjectState;
+    use crate::util::unique_temp_dir;
+
+    static STATEFUL_PLUG_RDN: &str = "app.meadowlark.test-stateful";
+
+    /// A plugin whose state is a list of bytes. Every state it loads is
+    /// recorded in `loaded_states`.
+    struct StatefulPlugFactory {
+        loaded_states: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl PluginFactory for StatefulPlugFactory {
+        fn description(&self) -> PluginDescriptor {
+            PluginDescriptor {
+                id: STATEFUL_PLUG_RDN.into(),
+                version: "0.1".into(),
+                name: "Stateful".into(),
+                vendor: "Meadowlark".into(),
+                description: String::new(),
+                url: String::new(),
+                manual_url: String::new(),
+                support_url: String::new(),
+                features: String::new(),
+            }
+        }
+
+        fn instantiate(
+            &mut self,
+            _host_request_channel: HostRequestChannelSender,
+            _host_info: Shared<HostInfo>,
+            _plugin_id: PluginInstanceID,
+            _coll_handle: &basedrop::Handle,
+        ) -> Result<Box<dyn PluginMainThread>, String> {
+            Ok(Box::new(StatefulPlugMainThread {
+                state: Vec::new(),
+                loaded_states: Arc::clone(&self.loaded_states),
+            }))
+        }
+    }
+
+    struct StatefulPlugMainThread {
+        state: Vec<u8>,
+        loaded_states: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl PluginMainThread for StatefulPlugMainThread {
+        fn activate(
+            &mut self,
+            _sample_rate: u32,
+            _min_frames: u32,
+            _max_frames: u32,
+            _coll_handle: &basedrop::Handle,
+        ) -> Result<PluginActivatedInfo, String> {
+            Ok(PluginActivatedInfo {
+                processor: Box::new(StatefulPlugProcessor),
+                internal_handle: None,
+            })
+        }
+
+        fn collect_save_state(&mut self) -> Result<Option<Vec<u8>>, String> {
+            Ok(Some(self.state.clone()))
+        }
+
+        fn load_save_state(&mut self, state: Vec<u8>) -> Result<(), String> {
+            self.loaded_states.lock().unwrap().push(state.clone());
+            self.state = state;
+            Ok(())
+        }
+    }
+
+    struct StatefulPlugProcessor;
+
+    impl PluginProcessor for StatefulPlugProcessor {
+        fn process(
+            &mut self,
+            _proc_info: &ProcInfo,
+            _buffers: &mut ProcBuffers,
+            _in_events: &EventBuffer,
+            _out_events: &mut EventBuffer,
+        ) -> ProcessStatus {
+            ProcessStatus::Continue
+        }
+    }
+
+    fn new_activated_engine(
+        loaded_states: &Arc<Mutex<Vec<Vec<u8>>>>,
+    ) -> (EngineMainThread, EngineAudioThread, ScannedPluginKey) {
+        let (mut ds_engine, _, internal_plugins_res) = EngineMainThread::new(
+            HostInfo::new("Meadowlark Test".into(), "0.1".into(), None, None),
+            EngineSettings { plugin_scan_cache_path: None, ..Default::default() },
+            vec![Box::new(StatefulPlugFactory { loaded_states: Arc::clone(loaded_states) })],
+        );
+        let stateful_plug_key = internal_plugins_res[0].clone().unwrap();
+
+        let (_, audio_thread) = ds_engine
+            .activate_engine(
+                0,
+                LoopState::Inactive,
+                Box::new(DefaultTempoMap::new(120.0, 4, 4, 44_100)),
+                ActivateEngineSettings {
+                    sample_rate: 44_100,
+                    max_frames: 256,
+                    num_worker_threads: 0,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        (ds_engine, audio_thread, stateful_plug_key)
+    }
+
+    #[test]
+    fn plugin_states_are_restored_from_project_file() {
+        let dir = unique_temp_dir("engine-handle-test-plugin-states");
+        let path = dir.join(format!("project.{}", project_file::PROJECT_FILE_EXTENSION));
+
+        // Add a plugin and change its state, then save its state in a project.
+        let loaded_states = Arc::new(Mutex::new(Vec::new()));
+        let (mut ds_engine, audio_thread, stateful_plug_key) = new_activated_engine(&loaded_states);
+
+        let plugin_ids = add_saved_plugins(
+            &mut ds_engine,
+            &[PluginHostSaveState::new_with_default_state(stateful_plug_key)],
+        );
+        ds_engine.plugin_host_mut(&plugin_ids[0]).unwrap().load_save_state(vec![1, 2, 3]).unwrap();
+
+        let mut project = ProjectState::test_project();
+        project.plugin_states = ds_engine.collect_graph_save_state().unwrap().plugins;
+        project_file::save_project(&project, &path).unwrap();
+
+        drop(audio_thread);
+        ds_engine.deactivate_engine();
+
+        // Open the project in a new engine.
+        let loaded_states = Arc::new(Mutex::new(Vec::new()));
+        let (mut ds_engine, audio_thread, _) = new_activated_engine(&loaded_states);
+
+        let loaded = project_file::load_project(&path).unwrap();
+        let (_, plugin_ids) = add_saved_plugins(&mut ds_engine, &loaded.plugin_states).unwrap();
+
+        assert_eq!(plugin_ids.len(), 1);
+        assert_eq!(*loaded_states.lock().unwrap(), vec![vec![1, 2, 3]]);
+
+        drop(audio_thread);
+        ds_engine.deactivate_engine();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
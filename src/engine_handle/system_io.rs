@@ -1,11 +1,33 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::Stream;
+use cpal::{Device, Host, SampleFormat, Stream, StreamConfig};
 use meadowlark_engine::engine::EngineAudioThread;
 use rtrb::{Producer, RingBuffer};
 use std::error::Error;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 const HANDLE_TO_STREAM_MSG_SIZE: usize = 8;
 
+/// The maximum number of frames the input stream can get ahead of the
+/// output stream before the oldest input frames are discarded.
+const MAX_INPUT_FRAMES_AHEAD: usize = 8_192;
+
+/// The latency of the system streams, measured from the timestamps given by
+/// the audio backend in each callback.
+#[derive(Default)]
+struct StreamLatency {
+    /// The number of frames between the input device capturing a frame and
+    /// the input callback receiving it.
+    input_frames: AtomicU32,
+    /// The number of input frames waiting in the ring buffer when the output
+    /// callback reads from it.
+    buffered_input_frames: AtomicU32,
+    /// The number of frames between the output callback and the output
+    /// device playing the first frame of that callback.
+    output_frames: AtomicU32,
+}
+
 #[derive(Debug)]
 enum HandleToStreamMsg {
     NewEngineAudioThread(EngineAudioThread),
@@ -14,8 +36,10 @@ enum HandleToStreamMsg {
 
 pub struct SystemIOStreamHandle {
     cpal_stream: Stream,
+    cpal_in_stream: Option<Stream>,
     to_stream_tx: Producer<HandleToStreamMsg>,
     sample_rate: u32,
+    latency: Arc<StreamLatency>,
 }
 
 impl SystemIOStreamHandle {
@@ -23,6 +47,22 @@ impl SystemIOStreamHandle {
         self.sample_rate
     }
 
+    /// Returns `true` if this stream is capturing audio from an input device.
+    pub fn has_input(&self) -> bool {
+        self.cpal_in_stream.is_some()
+    }
+
+    /// The latency (in frames) between the input device capturing a frame
+    /// and the output device playing a frame which was processed at the same
+    /// time, not including the latency of the audio graph itself.
+    ///
+    /// This is `0` until the streams have run for at least one callback.
+    pub fn round_trip_latency_frames(&self) -> u64 {
+        u64::from(self.latency.input_frames.load(Ordering::Relaxed))
+            + u64::from(self.latency.buffered_input_frames.load(Ordering::Relaxed))
+            + u64::from(self.latency.output_frames.load(Ordering::Relaxed))
+    }
+
     pub fn on_engine_activated(&mut self, engine_audio_thread: EngineAudioThread) {
         self.to_stream_tx
             .push(HandleToStreamMsg::NewEngineAudioThread(engine_audio_thread))
@@ -36,17 +76,20 @@ impl SystemIOStreamHandle {
 
 /// This is temporary. Eventually we will have a more sophisticated and
 /// configurable system using `rainout`.
-pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box<dyn Error>> {
+///
+/// If `out_device_name` is `None` or no device with that name exists, then
+/// the default output device is used.
+pub fn temp_spawn_cpal_output_only(
+    out_device_name: Option<&str>,
+) -> Result<SystemIOStreamHandle, Box<dyn Error>> {
     let (to_stream_tx, mut from_handle_rx) =
         RingBuffer::<HandleToStreamMsg>::new(HANDLE_TO_STREAM_MSG_SIZE);
 
     let cpal_host = cpal::default_host();
 
-    let device = cpal_host
-        .default_output_device()
-        .ok_or("CPAL: no default audio out device found".to_string())?;
+    let device = find_output_device(&cpal_host, out_device_name)?;
 
-    log::info!("Selected default CPAL output device: {:?}", &device.name());
+    log::info!("Selected CPAL output device: {:?}", &device.name());
 
     let config = device.default_output_config()?;
 
@@ -55,11 +98,21 @@ pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box
 
     let mut engine_audio_thread: Option<EngineAudioThread> = None;
 
+    let latency = Arc::new(StreamLatency::default());
+    let out_latency = Arc::clone(&latency);
+
     log::info!("Starting CPAL stream with config {:?}...", &config);
 
     let cpal_stream = device.build_output_stream(
         &config.into(),
-        move |audio_buffer: &mut [f32], _: &cpal::OutputCallbackInfo| {
+        move |audio_buffer: &mut [f32], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                out_latency
+                    .output_frames
+                    .store(duration_to_frames(latency, sample_rate), Ordering::Relaxed);
+            }
+
             while let Ok(msg) = from_handle_rx.pop() {
                 match msg {
                     HandleToStreamMsg::NewEngineAudioThread(new_engine_audio_thread) => {
@@ -86,5 +139,220 @@ pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box
 
     log::info!("Successfully started CPAL stream");
 
-    Ok(SystemIOStreamHandle { cpal_stream, to_stream_tx, sample_rate })
+    Ok(SystemIOStreamHandle {
+        cpal_stream,
+        cpal_in_stream: None,
+        to_stream_tx,
+        sample_rate,
+        latency,
+    })
+}
+
+/// This is temporary. Eventually we will have a more sophisticated and
+/// configurable system using `rainout`.
+///
+/// This opens the input device alongside the output device at the same
+/// sample rate. If a device name is `None` or no device with that name
+/// exists, then the default device is used.
+pub fn temp_spawn_cpal_duplex(
+    out_device_name: Option<&str>,
+    in_device_name: Option<&str>,
+) -> Result<SystemIOStreamHandle, Box<dyn Error>> {
+    let (to_stream_tx, mut from_handle_rx) =
+        RingBuffer::<HandleToStreamMsg>::new(HANDLE_TO_STREAM_MSG_SIZE);
+
+    let cpal_host = cpal::default_host();
+
+    let out_device = find_output_device(&cpal_host, out_device_name)?;
+    let in_device = find_input_device(&cpal_host, in_device_name)?;
+
+    log::info!("Selected CPAL output device: {:?}", &out_device.name());
+    log::info!("Selected CPAL input device: {:?}", &in_device.name());
+
+    let out_config = out_device.default_output_config()?;
+    let sample_rate = out_config.sample_rate();
+
+    // The input stream must run at the same sample rate as the output stream.
+    let in_config: StreamConfig = in_device
+        .supported_input_configs()?
+        .find(|c| {
+            c.sample_format() == SampleFormat::F32
+                && c.min_sample_rate() <= sample_rate
+                && c.max_sample_rate() >= sample_rate
+        })
+        .ok_or(format!(
+            "CPAL: default audio in device does not support a sample rate of {}",
+            sample_rate.0
+        ))?
+        .with_sample_rate(sample_rate)
+        .into();
+
+    let num_out_channels = usize::from(out_config.channels());
+    let num_in_channels = usize::from(in_config.channels);
+    let sample_rate: u32 = sample_rate.0;
+
+    let (mut input_tx, mut input_rx) =
+        RingBuffer::<f32>::new(num_in_channels * MAX_INPUT_FRAMES_AHEAD * 2);
+
+    let mut input_buffer: Vec<f32> = Vec::with_capacity(num_in_channels * MAX_INPUT_FRAMES_AHEAD);
+
+    let mut engine_audio_thread: Option<EngineAudioThread> = None;
+
+    let latency = Arc::new(StreamLatency::default());
+    let in_latency = Arc::clone(&latency);
+    let out_latency = Arc::clone(&latency);
+
+    log::info!("Starting CPAL input stream with config {:?}...", &in_config);
+
+    let cpal_in_stream = in_device.build_input_stream(
+        &in_config,
+        move |data: &[f32], info: &cpal::InputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(latency) = timestamp.callback.duration_since(&timestamp.capture) {
+                in_latency
+                    .input_frames
+                    .store(duration_to_frames(latency, sample_rate), Ordering::Relaxed);
+            }
+
+            // Only ever write whole frames so the channels stay aligned. If
+            // the ring buffer is full, the rest of the input is dropped.
+            let num_samples = whole_frames(data.len().min(input_tx.slots()), num_in_channels);
+
+            if let Ok(mut chunk) = input_tx.write_chunk(num_samples) {
+                let (slice_1, slice_2) = chunk.as_mut_slices();
+                slice_1.copy_from_slice(&data[0..slice_1.len()]);
+                slice_2.copy_from_slice(&data[slice_1.len()..num_samples]);
+                chunk.commit_all();
+            }
+        },
+        |e| {
+            // TODO: Better handling of the system IO stream crashing.
+            panic!("{}", e);
+        },
+    )?;
+
+    log::info!("Starting CPAL output stream with config {:?}...", &out_config);
+
+    let cpal_stream = out_device.build_output_stream(
+        &out_config.into(),
+        move |audio_buffer: &mut [f32], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                out_latency
+                    .output_frames
+                    .store(duration_to_frames(latency, sample_rate), Ordering::Relaxed);
+            }
+
+            while let Ok(msg) = from_handle_rx.pop() {
+                match msg {
+                    HandleToStreamMsg::NewEngineAudioThread(new_engine_audio_thread) => {
+                        engine_audio_thread = Some(new_engine_audio_thread);
+                    }
+                    HandleToStreamMsg::DropEngineAudioThread => {
+                        engine_audio_thread = None;
+                    }
+                }
+            }
+
+            let num_frames = audio_buffer.len() / num_out_channels;
+            let num_in_samples = whole_frames(
+                (num_frames * num_in_channels).min(input_buffer.capacity()),
+                num_in_channels,
+            );
+
+            // Keep the latency between the input and output streams bounded.
+            let max_samples_ahead = num_in_channels * MAX_INPUT_FRAMES_AHEAD;
+            if input_rx.slots() > num_in_samples + max_samples_ahead {
+                let num_discard = whole_frames(input_rx.slots() - num_in_samples, num_in_channels);
+                if let Ok(chunk) = input_rx.read_chunk(num_discard) {
+                    chunk.commit_all();
+                }
+            }
+
+            out_latency
+                .buffered_input_frames
+                .store((input_rx.slots() / num_in_channels.max(1)) as u32, Ordering::Relaxed);
+
+            input_buffer.clear();
+            if let Ok(chunk) = input_rx
+                .read_chunk(whole_frames(num_in_samples.min(input_rx.slots()), num_in_channels))
+            {
+                let (slice_1, slice_2) = chunk.as_slices();
+                input_buffer.extend_from_slice(slice_1);
+                input_buffer.extend_from_slice(slice_2);
+                chunk.commit_all();
+            }
+
+            if let Some(engine_audio_thread) = &mut engine_audio_thread {
+                engine_audio_thread.process_cpal_interleaved_duplex(
+                    num_in_channels,
+                    &input_buffer,
+                    num_out_channels,
+                    audio_buffer,
+                );
+            }
+        },
+        |e| {
+            // TODO: Better handling of the system IO stream crashing.
+            panic!("{}", e);
+        },
+    )?;
+
+    cpal_in_stream.play()?;
+    cpal_stream.play()?;
+
+    log::info!("Successfully started CPAL streams");
+
+    Ok(SystemIOStreamHandle {
+        cpal_stream,
+        cpal_in_stream: Some(cpal_in_stream),
+        to_stream_tx,
+        sample_rate,
+        latency,
+    })
+}
+
+fn find_output_device(cpal_host: &Host, name: Option<&str>) -> Result<Device, Box<dyn Error>> {
+    if let Some(name) = name {
+        if let Some(device) =
+            cpal_host.output_devices()?.find(|d| d.name().map(|n| n == name).unwrap_or(false))
+        {
+            return Ok(device);
+        }
+
+        log::warn!("CPAL: audio out device {:?} not found, using the default device", name);
+    }
+
+    Ok(cpal_host
+        .default_output_device()
+        .ok_or("CPAL: no default audio out device found".to_string())?)
+}
+
+fn find_input_device(cpal_host: &Host, name: Option<&str>) -> Result<Device, Box<dyn Error>> {
+    if let Some(name) = name {
+        if let Some(device) =
+            cpal_host.input_devices()?.find(|d| d.name().map(|n| n == name).unwrap_or(false))
+        {
+            return Ok(device);
+        }
+
+        log::warn!("CPAL: audio in device {:?} not found, using the default device", name);
+    }
+
+    Ok(cpal_host
+        .default_input_device()
+        .ok_or("CPAL: no default audio in device found".to_string())?)
+}
+
+/// Round the number of interleaved samples down to a whole number of frames.
+fn whole_frames(num_samples: usize, num_channels: usize) -> usize {
+    if num_channels == 0 {
+        return 0;
+    }
+
+    num_samples - (num_samples % num_channels)
+}
+
+fn duration_to_frames(duration: Duration, sample_rate: u32) -> u32 {
+    (duration.as_secs_f64() * f64::from(sample_rate)).round() as u32
 }
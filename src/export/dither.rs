@@ -0,0 +1,92 @@
+/// Triangular probability density function (TPDF) dither.
+///
+/// This adds the sum of two uniformly distributed random values in the range
+/// `[-0.5, 0.5)` LSB (least significant bit) to each sample before it is
+/// quantized.
+pub struct TpdfDither {
+    state: u32,
+}
+
+impl TpdfDither {
+    pub fn new(seed: u32) -> Self {
+        // The xorshift state must never be zero.
+        Self { state: if seed == 0 { 0x9E37_79B9 } else { seed } }
+    }
+
+    /// Quantize a sample in the range `[-1.0, 1.0]` to a signed integer with
+    /// the given bit depth.
+    pub fn quantize(&mut self, sample: f32, bits_per_sample: u16, dither: bool) -> i32 {
+        let max = ((1_i64 << (bits_per_sample - 1)) - 1) as f64;
+        let min = -(1_i64 << (bits_per_sample - 1)) as f64;
+
+        let mut s = f64::from(sample) * max;
+        if dither {
+            s += self.next_uniform() + self.next_uniform();
+        }
+
+        s.round().clamp(min, max) as i32
+    }
+
+    /// Returns a uniformly distributed random value in the range `[-0.5, 0.5)`.
+    fn next_uniform(&mut self) -> f64 {
+        // xorshift32
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        (f64::from(self.state) / 4_294_967_296.0) - 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_without_dither_rounds_and_clamps() {
+        let mut ditherer = TpdfDither::new(1);
+
+        assert_eq!(ditherer.quantize(0.0, 16, false), 0);
+        assert_eq!(ditherer.quantize(1.0, 16, false), 32_767);
+        assert_eq!(ditherer.quantize(-1.0, 16, false), -32_767);
+        assert_eq!(ditherer.quantize(0.5, 24, false), 4_194_304);
+
+        // Out of range samples are clamped to the range of the bit depth.
+        assert_eq!(ditherer.quantize(2.0, 16, false), 32_767);
+        assert_eq!(ditherer.quantize(-2.0, 16, false), -32_768);
+    }
+
+    #[test]
+    fn dither_stays_within_one_lsb() {
+        let mut ditherer = TpdfDither::new(1);
+
+        let mut sum = 0i64;
+        for _ in 0..10_000 {
+            let s = ditherer.quantize(0.0, 16, true);
+            assert!((-1..=1).contains(&s));
+            sum += i64::from(s);
+        }
+
+        // TPDF dither has a mean of zero.
+        assert!(sum.abs() < 500);
+    }
+
+    #[test]
+    fn dither_is_reproducible() {
+        let mut a = TpdfDither::new(1234);
+        let mut b = TpdfDither::new(1234);
+
+        for i in 0..1_000 {
+            let s = (i as f32 / 1_000.0) - 0.5;
+            assert_eq!(a.quantize(s, 16, true), b.quantize(s, 16, true));
+        }
+    }
+
+    #[test]
+    fn zero_seed_produces_noise() {
+        let mut ditherer = TpdfDither::new(0);
+
+        let first = ditherer.next_uniform();
+        assert!((0..100).any(|_| ditherer.next_uniform() != first));
+    }
+}
@@ -0,0 +1,295 @@
+use flacenc::component::BitRepr;
+use flacenc::error::SourceError;
+use flacenc::source::{Fill, Source};
+use hound::{SampleFormat, WavIntoSamples, WavReader, WavSpec, WavWriter};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::dither::TpdfDither;
+use super::{ExportError, ExportFormat};
+
+/// The seed used for the dither noise, so exports are reproducible.
+const DITHER_SEED: u32 = 0x4D45_4144;
+
+/// How many samples are encoded between updates of the `EncodeProgress`.
+const PROGRESS_INTERVAL: u64 = 16_384;
+
+/// The progress of an encode, shared between the thread doing the encoding
+/// and the thread waiting on it.
+#[derive(Debug, Default)]
+pub struct EncodeProgress {
+    /// The number of samples that have been read from the source file.
+    pub samples_done: AtomicU64,
+    /// The total number of samples in the source file.
+    pub total_samples: AtomicU64,
+    /// Set this to `true` to stop the encode. `encode_file()` then returns
+    /// `ExportError::Cancelled`.
+    pub cancel: AtomicBool,
+}
+
+impl EncodeProgress {
+    /// Returns `true` if the encode was cancelled.
+    fn update(&self, samples_done: u64) -> bool {
+        self.samples_done.store(samples_done, Ordering::Relaxed);
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// Encode the samples of a 32 bit float WAV file into a file on disk,
+/// multiplying every sample by `gain`.
+///
+/// The samples are read from `src` one at a time. WAV files are written out
+/// as they go, but the FLAC encoder builds the whole (compressed) stream in
+/// memory before it is written to `dst`.
+///
+/// If the encode fails or is cancelled, then `dst` is removed.
+pub fn encode_file(
+    src: &Path,
+    dst: &Path,
+    format: ExportFormat,
+    dither: bool,
+    gain: f32,
+    progress: &EncodeProgress,
+) -> Result<(), ExportError> {
+    let res = encode(src, dst, format, dither, gain, progress);
+
+    if res.is_err() {
+        if let Err(e) = std::fs::remove_file(dst) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Could not remove unfinished export {:?}: {}", dst, e);
+            }
+        }
+    }
+
+    res
+}
+
+fn encode(
+    src: &Path,
+    dst: &Path,
+    format: ExportFormat,
+    dither: bool,
+    gain: f32,
+    progress: &EncodeProgress,
+) -> Result<(), ExportError> {
+    let reader = WavReader::open(src)?;
+    let num_channels = reader.spec().channels;
+    let sample_rate = reader.spec().sample_rate;
+
+    progress.total_samples.store(u64::from(reader.len()), Ordering::Relaxed);
+
+    let samples = reader.into_samples::<f32>();
+
+    match format {
+        ExportFormat::WavF32 => {
+            write_wav_f32(dst, samples, num_channels, sample_rate, gain, progress)
+        }
+        ExportFormat::Wav16 | ExportFormat::Wav24 => write_wav_int(
+            dst,
+            samples,
+            num_channels,
+            sample_rate,
+            format.bits_per_sample(),
+            dither,
+            gain,
+            progress,
+        ),
+        ExportFormat::Flac16 | ExportFormat::Flac24 => write_flac(
+            dst,
+            samples,
+            num_channels,
+            sample_rate,
+            format.bits_per_sample(),
+            dither,
+            gain,
+            progress,
+        ),
+    }
+}
+
+fn write_wav_f32(
+    path: &Path,
+    samples: WavIntoSamples<BufReader<File>, f32>,
+    num_channels: u16,
+    sample_rate: u32,
+    gain: f32,
+    progress: &EncodeProgress,
+) -> Result<(), ExportError> {
+    let spec = WavSpec {
+        channels: num_channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+
+    let mut writer = WavWriter::create(path, spec)?;
+    for (i, s) in samples.enumerate() {
+        if i as u64 % PROGRESS_INTERVAL == 0 && progress.update(i as u64) {
+            return Err(ExportError::Cancelled);
+        }
+
+        writer.write_sample(s? * gain)?;
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_wav_int(
+    path: &Path,
+    samples: WavIntoSamples<BufReader<File>, f32>,
+    num_channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+    dither: bool,
+    gain: f32,
+    progress: &EncodeProgress,
+) -> Result<(), ExportError> {
+    let spec = WavSpec {
+        channels: num_channels,
+        sample_rate,
+        bits_per_sample,
+        sample_format: SampleFormat::Int,
+    };
+
+    let mut ditherer = TpdfDither::new(DITHER_SEED);
+
+    let mut writer = WavWriter::create(path, spec)?;
+    for (i, s) in samples.enumerate() {
+        if i as u64 % PROGRESS_INTERVAL == 0 && progress.update(i as u64) {
+            return Err(ExportError::Cancelled);
+        }
+
+        writer.write_sample(ditherer.quantize(s? * gain, bits_per_sample, dither))?;
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_flac(
+    path: &Path,
+    samples: WavIntoSamples<BufReader<File>, f32>,
+    num_channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+    dither: bool,
+    gain: f32,
+    progress: &EncodeProgress,
+) -> Result<(), ExportError> {
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|e| ExportError::Encode(format!("{:?}", e)))?;
+
+    let read_error = Rc::new(RefCell::new(None));
+
+    let source = QuantizedSource {
+        samples,
+        num_channels: usize::from(num_channels),
+        sample_rate: sample_rate as usize,
+        bits_per_sample,
+        dither,
+        gain,
+        ditherer: TpdfDither::new(DITHER_SEED),
+        block: Vec::new(),
+        read_error: Rc::clone(&read_error),
+        samples_done: 0,
+        progress,
+    };
+
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| ExportError::Encode(format!("{:?}", e)))?;
+
+    if let Some(e) = read_error.borrow_mut().take() {
+        return Err(e.into());
+    }
+
+    if progress.cancel.load(Ordering::Relaxed) {
+        return Err(ExportError::Cancelled);
+    }
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream.write(&mut sink).map_err(|e| ExportError::Encode(format!("{:?}", e)))?;
+
+    std::fs::write(path, sink.as_slice())?;
+
+    Ok(())
+}
+
+/// Feeds the encoder with quantized samples read from a WAV file one block
+/// at a time.
+struct QuantizedSource<'a, R: Read> {
+    samples: WavIntoSamples<R, f32>,
+    num_channels: usize,
+    sample_rate: usize,
+    bits_per_sample: u16,
+    dither: bool,
+    gain: f32,
+    ditherer: TpdfDither,
+    block: Vec<i32>,
+
+    /// An error while reading the WAV file ends the stream early, so it is
+    /// stored here to be reported once the encoder returns.
+    read_error: Rc<RefCell<Option<hound::Error>>>,
+
+    samples_done: u64,
+    progress: &'a EncodeProgress,
+}
+
+impl<'a, R: Read> Source for QuantizedSource<'a, R> {
+    fn channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn bits_per_sample(&self) -> usize {
+        usize::from(self.bits_per_sample)
+    }
+
+    fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn read_samples<F: Fill>(
+        &mut self,
+        block_size: usize,
+        dest: &mut F,
+    ) -> Result<usize, SourceError> {
+        self.block.clear();
+
+        // Cancelling ends the stream early.
+        let num_samples = if self.progress.update(self.samples_done) {
+            0
+        } else {
+            block_size * self.num_channels
+        };
+
+        for s in self.samples.by_ref().take(num_samples) {
+            match s {
+                Ok(s) => self.block.push(self.ditherer.quantize(
+                    s * self.gain,
+                    self.bits_per_sample,
+                    self.dither,
+                )),
+                Err(e) => {
+                    *self.read_error.borrow_mut() = Some(e);
+                    break;
+                }
+            }
+        }
+
+        // Only pass whole frames to the encoder.
+        self.block.truncate(self.block.len() - (self.block.len() % self.num_channels));
+
+        self.samples_done += self.block.len() as u64;
+
+        dest.fill_interleaved(&self.block)?;
+
+        Ok(self.block.len() / self.num_channels)
+    }
+}
@@ -0,0 +1,629 @@
+//! Rendering the project to audio files on disk.
+
+use meadowlark_engine::engine::error::OfflineRenderError;
+use meadowlark_engine::engine::modify_request::EdgeReqPortID;
+use meadowlark_engine::engine::{OfflineRenderSettings, OfflineRenderSource};
+use meadowlark_plugin_api::decibel::db_to_coeff_f32;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use vizia::prelude::Data;
+
+use crate::engine_handle::EngineHandle;
+use crate::state_system::source_state::{ProjectState, TrackType};
+use crate::state_system::time::{FrameTime, Timestamp};
+
+mod dither;
+mod encoder;
+
+use encoder::EncodeProgress;
+
+/// The extension of the temporary file a render is streamed into before it
+/// is encoded.
+static RENDER_FILE_EXTENSION: &str = "render.tmp";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data)]
+pub enum ExportFormat {
+    /// 16 bit integer WAV
+    Wav16,
+    /// 24 bit integer WAV
+    Wav24,
+    /// 32 bit floating point WAV
+    WavF32,
+    /// 16 bit FLAC
+    Flac16,
+    /// 24 bit FLAC
+    Flac24,
+}
+
+impl ExportFormat {
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            ExportFormat::Wav16 | ExportFormat::Flac16 => 16,
+            ExportFormat::Wav24 | ExportFormat::Flac24 => 24,
+            ExportFormat::WavF32 => 32,
+        }
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Wav16 | ExportFormat::Wav24 | ExportFormat::WavF32 => "wav",
+            ExportFormat::Flac16 | ExportFormat::Flac24 => "flac",
+        }
+    }
+
+    /// Whether or not this format stores samples as integers, and thus can
+    /// benefit from dithering.
+    pub fn is_integer(&self) -> bool {
+        !matches!(self, ExportFormat::WavF32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Data)]
+pub struct ExportSettings {
+    pub format: ExportFormat,
+
+    /// The sample rate of the exported file. If this is `None`, then the
+    /// sample rate of the project is used.
+    pub sample_rate: Option<u32>,
+
+    /// Whether or not to apply TPDF dither when converting to an integer
+    /// format.
+    pub dither: bool,
+
+    /// If this is `Some`, then the exported audio will be normalized so that
+    /// its peak lies at this level (in dB).
+    pub normalize_peak_db: Option<f32>,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Wav24,
+            sample_rate: None,
+            dither: true,
+            normalize_peak_db: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    EngineDeactivated,
+    TrackDoesNotExist(usize),
+    Render(OfflineRenderError),
+    Resample(String),
+    Encode(String),
+    Io(std::io::Error),
+    Cancelled,
+}
+
+impl Error for ExportError {}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::EngineDeactivated => write!(f, "Failed to export: engine is deactivated"),
+            ExportError::TrackDoesNotExist(i) => {
+                write!(f, "Failed to export: track with index {} does not exist", i)
+            }
+            ExportError::Render(e) => write!(f, "Failed to export: {}", e),
+            ExportError::Resample(e) => write!(f, "Failed to export: resampler error: {}", e),
+            ExportError::Encode(e) => write!(f, "Failed to export: encoder error: {}", e),
+            ExportError::Io(e) => write!(f, "Failed to export: {}", e),
+            ExportError::Cancelled => write!(f, "Export was cancelled"),
+        }
+    }
+}
+
+impl From<OfflineRenderError> for ExportError {
+    fn from(e: OfflineRenderError) -> Self {
+        ExportError::Render(e)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<hound::Error> for ExportError {
+    fn from(e: hound::Error) -> Self {
+        match e {
+            hound::Error::IoError(e) => ExportError::Io(e),
+            e => ExportError::Encode(e.to_string()),
+        }
+    }
+}
+
+/// How long the UI thread spends rendering in each call to
+/// `ExportJob::poll()`.
+static RENDER_STEP_DURATION: Duration = Duration::from_millis(8);
+
+/// The minimum time between two render steps, so the UI thread has time to
+/// handle events and draw in between.
+static RENDER_STEP_INTERVAL: Duration = Duration::from_millis(16);
+
+/// What an `ExportJob` is doing at the moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data)]
+pub enum ExportPhase {
+    Rendering,
+    Encoding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Data)]
+pub struct ExportProgress {
+    /// The index of the file currently being exported.
+    pub file_index: usize,
+    /// The total number of files in the export.
+    pub num_files: usize,
+    pub phase: ExportPhase,
+    /// The progress of the current phase of the current file in the range
+    /// `[0.0, 1.0]`.
+    pub fraction: f32,
+}
+
+/// A single file to export.
+struct ExportTarget {
+    source: OfflineRenderSource,
+    start_frame: FrameTime,
+    end_frame: FrameTime,
+    path: PathBuf,
+}
+
+impl ExportTarget {
+    /// The temporary file the render is streamed into before it is encoded.
+    fn render_path(&self) -> PathBuf {
+        self.path.with_extension(RENDER_FILE_EXTENSION)
+    }
+}
+
+enum ExportStage {
+    /// Waiting to start on the next target.
+    Idle,
+    Rendering {
+        target: ExportTarget,
+        writer: RenderWriter,
+        fraction: f32,
+    },
+    Encoding {
+        target: ExportTarget,
+        progress: Arc<EncodeProgress>,
+        result_rx: mpsc::Receiver<Result<(), ExportError>>,
+    },
+}
+
+/// Renders one or more sources in the audio graph to files on disk.
+///
+/// Each render is streamed block by block (through the resampler if needed)
+/// into a temporary 32 bit float WAV file next to the final file, while
+/// keeping track of the peak. The render is done in small steps on the UI
+/// thread by calling `ExportJob::poll()` periodically, since the engine can
+/// only be used from there.
+///
+/// That file is then encoded into the final format in a separate thread,
+/// applying normalization and dithering along the way. This way the raw
+/// render never has to fit in memory (only the FLAC encoder keeps its
+/// compressed output in memory), and the UI stays responsive.
+pub struct ExportJob {
+    settings: ExportSettings,
+    targets: VecDeque<ExportTarget>,
+    stage: ExportStage,
+    num_files: usize,
+    next_step_instant: Instant,
+
+    /// The path or the error for each file that has been exported so far,
+    /// in order.
+    results: Vec<Result<PathBuf, ExportError>>,
+}
+
+impl ExportJob {
+    /// Render the whole project (the output of the master track) to a file.
+    pub fn master(project_state: &ProjectState, settings: ExportSettings, path: PathBuf) -> Self {
+        let end_frame = project_end_frame(project_state);
+
+        Self::new(
+            settings,
+            vec![ExportTarget {
+                source: OfflineRenderSource::GraphOutput,
+                start_frame: FrameTime(0),
+                end_frame,
+                path,
+            }],
+        )
+    }
+
+    /// Render the output of each track in the project to a separate file in
+    /// `directory`, named after the track.
+    ///
+    /// Every stem spans the whole project so that they line up when imported
+    /// elsewhere.
+    pub fn track_stems(
+        engine_handle: &EngineHandle,
+        project_state: &ProjectState,
+        settings: ExportSettings,
+        directory: &Path,
+    ) -> Result<Self, ExportError> {
+        let end_frame = project_end_frame(project_state);
+
+        let plugin_ids = &engine_handle
+            .activated_handles
+            .as_ref()
+            .ok_or(ExportError::EngineDeactivated)?
+            .timeline_track_plug_ids;
+
+        let targets = (0..project_state.tracks.len())
+            .map(|track_index| {
+                let plugin_id = plugin_ids
+                    .get(track_index)
+                    .ok_or(ExportError::TrackDoesNotExist(track_index))?
+                    .clone();
+
+                Ok(ExportTarget {
+                    source: OfflineRenderSource::PluginAudioOutPort {
+                        plugin_id,
+                        port_id: EdgeReqPortID::Main,
+                    },
+                    start_frame: FrameTime(0),
+                    end_frame,
+                    path: directory.join(stem_file_name(
+                        project_state,
+                        track_index,
+                        settings.format,
+                    )),
+                })
+            })
+            .collect::<Result<Vec<_>, ExportError>>()?;
+
+        Ok(Self::new(settings, targets))
+    }
+
+    fn new(settings: ExportSettings, targets: Vec<ExportTarget>) -> Self {
+        Self {
+            settings,
+            num_files: targets.len(),
+            targets: targets.into(),
+            stage: ExportStage::Idle,
+            next_step_instant: Instant::now(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Continue the export. This must be called periodically from the UI
+    /// thread.
+    ///
+    /// Once every file has been exported, this returns the path or the error
+    /// for each file, in order.
+    pub fn poll(
+        &mut self,
+        engine_handle: &mut EngineHandle,
+    ) -> Option<Vec<Result<PathBuf, ExportError>>> {
+        let now = Instant::now();
+        if now < self.next_step_instant {
+            return None;
+        }
+
+        match std::mem::replace(&mut self.stage, ExportStage::Idle) {
+            ExportStage::Idle => match self.targets.pop_front() {
+                Some(target) => self.begin_render(engine_handle, target),
+                None => return Some(std::mem::take(&mut self.results)),
+            },
+            ExportStage::Rendering { target, writer, .. } => {
+                self.continue_render(engine_handle, target, writer);
+                self.next_step_instant = now + RENDER_STEP_INTERVAL;
+            }
+            ExportStage::Encoding { target, progress, result_rx } => match result_rx.try_recv() {
+                Ok(res) => self.finish_target(target, res),
+                Err(mpsc::TryRecvError::Empty) => {
+                    self.stage = ExportStage::Encoding { target, progress, result_rx };
+                }
+                Err(mpsc::TryRecvError::Disconnected) => self.finish_target(
+                    target,
+                    Err(ExportError::Encode("encoder thread has unexpectedly exited".into())),
+                ),
+            },
+        }
+
+        None
+    }
+
+    /// Stop the export, and remove the file that is currently being exported.
+    ///
+    /// This returns the results of the files that were already exported.
+    pub fn cancel(mut self, engine_handle: &mut EngineHandle) -> Vec<Result<PathBuf, ExportError>> {
+        match std::mem::replace(&mut self.stage, ExportStage::Idle) {
+            ExportStage::Idle => {}
+            ExportStage::Rendering { target, writer, .. } => {
+                engine_handle.ds_engine.end_offline_render();
+                drop(writer);
+                self.finish_target(target, Err(ExportError::Cancelled));
+            }
+            ExportStage::Encoding { target, progress, result_rx } => {
+                progress.cancel.store(true, Ordering::Relaxed);
+
+                // The encoder checks for cancellation often, so this does not
+                // block for long.
+                let res = result_rx.recv().unwrap_or(Err(ExportError::Cancelled));
+                self.finish_target(target, res);
+            }
+        }
+
+        self.results
+    }
+
+    pub fn progress(&self) -> ExportProgress {
+        let file_index = self.results.len().min(self.num_files.saturating_sub(1));
+
+        let (phase, fraction) = match &self.stage {
+            ExportStage::Idle => (ExportPhase::Rendering, 0.0),
+            ExportStage::Rendering { fraction, .. } => (ExportPhase::Rendering, *fraction),
+            ExportStage::Encoding { progress, .. } => {
+                let total_samples = progress.total_samples.load(Ordering::Relaxed);
+                let fraction = if total_samples == 0 {
+                    0.0
+                } else {
+                    progress.samples_done.load(Ordering::Relaxed) as f32 / total_samples as f32
+                };
+
+                (ExportPhase::Encoding, fraction)
+            }
+        };
+
+        ExportProgress { file_index, num_files: self.num_files, phase, fraction }
+    }
+
+    fn begin_render(&mut self, engine_handle: &mut EngineHandle, target: ExportTarget) {
+        log::info!("Exporting to {:?}...", &target.path);
+
+        let res = RenderWriter::create(engine_handle, &target, &self.settings).and_then(|writer| {
+            engine_handle
+                .ds_engine
+                .begin_offline_render(OfflineRenderSettings {
+                    source: target.source.clone(),
+                    start_frame: target.start_frame.0,
+                    end_frame: target.end_frame.0,
+                    hard_clip_outputs: false,
+                    ..Default::default()
+                })
+                .map(|()| writer)
+                .map_err(ExportError::from)
+        });
+
+        match res {
+            Ok(writer) => {
+                self.stage = ExportStage::Rendering { target, writer, fraction: 0.0 };
+            }
+            Err(e) => self.finish_target(target, Err(e)),
+        }
+    }
+
+    fn continue_render(
+        &mut self,
+        engine_handle: &mut EngineHandle,
+        target: ExportTarget,
+        mut writer: RenderWriter,
+    ) {
+        let mut block_error: Option<ExportError> = None;
+        let res = engine_handle.ds_engine.continue_offline_render(RENDER_STEP_DURATION, |block| {
+            if let Err(e) = writer.write_block(block) {
+                // Cancel the render.
+                block_error = Some(e);
+                false
+            } else {
+                true
+            }
+        });
+
+        let progress = match res {
+            Ok(progress) => progress,
+            Err(e) => {
+                self.finish_target(target, Err(e.into()));
+                return;
+            }
+        };
+
+        if !progress.finished {
+            let fraction = progress.frames_rendered as f32 / progress.total_frames.max(1) as f32;
+            self.stage = ExportStage::Rendering { target, writer, fraction };
+            return;
+        }
+
+        engine_handle.ds_engine.end_offline_render();
+
+        if let Some(e) = block_error {
+            self.finish_target(target, Err(e));
+            return;
+        }
+
+        let peak = match writer.finish() {
+            Ok(peak) => peak,
+            Err(e) => {
+                self.finish_target(target, Err(e));
+                return;
+            }
+        };
+
+        let gain = match self.settings.normalize_peak_db {
+            Some(peak_db) => normalize_gain(peak, db_to_coeff_f32(peak_db)),
+            None => 1.0,
+        };
+
+        let progress = Arc::new(EncodeProgress::default());
+        let (result_tx, result_rx) = mpsc::channel();
+
+        let render_path = target.render_path();
+        let path = target.path.clone();
+        let format = self.settings.format;
+        let dither = self.settings.dither && format.is_integer();
+        let thread_progress = Arc::clone(&progress);
+        let spawn_res =
+            std::thread::Builder::new().name("export_encoder".into()).spawn(move || {
+                let _ = result_tx.send(encoder::encode_file(
+                    &render_path,
+                    &path,
+                    format,
+                    dither,
+                    gain,
+                    &thread_progress,
+                ));
+            });
+
+        match spawn_res {
+            Ok(_) => self.stage = ExportStage::Encoding { target, progress, result_rx },
+            Err(e) => self.finish_target(target, Err(e.into())),
+        }
+    }
+
+    fn finish_target(&mut self, target: ExportTarget, res: Result<(), ExportError>) {
+        let render_path = target.render_path();
+        if let Err(e) = std::fs::remove_file(&render_path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Could not remove temporary render file {:?}: {}", &render_path, e);
+            }
+        }
+
+        match &res {
+            Ok(()) => log::info!("Successfully exported to {:?}", &target.path),
+            Err(e) => log::error!("{}", e),
+        }
+
+        self.results.push(res.map(|()| target.path));
+    }
+}
+
+/// Streams the blocks of an offline render into a 32 bit float WAV file,
+/// resampling them to the sample rate in the export settings, while keeping
+/// track of the absolute peak of all the samples that were written.
+struct RenderWriter {
+    writer: hound::WavWriter<BufWriter<File>>,
+    resampler: Option<samplerate::Samplerate>,
+    peak: f32,
+}
+
+impl RenderWriter {
+    fn create(
+        engine_handle: &EngineHandle,
+        target: &ExportTarget,
+        settings: &ExportSettings,
+    ) -> Result<Self, ExportError> {
+        let project_sample_rate = engine_handle.system_io_stream_handle.sample_rate();
+        let sample_rate = settings.sample_rate.unwrap_or(project_sample_rate);
+
+        let num_channels = engine_handle.ds_engine.offline_render_num_channels(&target.source)?;
+
+        let writer = hound::WavWriter::create(
+            target.render_path(),
+            hound::WavSpec {
+                channels: num_channels,
+                sample_rate,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            },
+        )?;
+
+        let resampler = if sample_rate != project_sample_rate {
+            Some(
+                samplerate::Samplerate::new(
+                    samplerate::ConverterType::SincBestQuality,
+                    project_sample_rate,
+                    sample_rate,
+                    usize::from(num_channels),
+                )
+                .map_err(|e| ExportError::Resample(e.to_string()))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Self { writer, resampler, peak: 0.0 })
+    }
+
+    fn write_block(&mut self, block: &[f32]) -> Result<(), ExportError> {
+        match &self.resampler {
+            Some(resampler) => {
+                let resampled =
+                    resampler.process(block).map_err(|e| ExportError::Resample(e.to_string()))?;
+                self.write_samples(&resampled)
+            }
+            None => self.write_samples(block),
+        }
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), ExportError> {
+        for s in samples.iter() {
+            self.peak = self.peak.max(s.abs());
+            self.writer.write_sample(*s)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the samples still buffered in the resampler and finish the
+    /// file. This returns the absolute peak of all the samples.
+    fn finish(mut self) -> Result<f32, ExportError> {
+        if let Some(resampler) = self.resampler.take() {
+            let remaining =
+                resampler.process_last(&[]).map_err(|e| ExportError::Resample(e.to_string()))?;
+            self.write_samples(&remaining)?;
+        }
+
+        self.writer.finalize()?;
+
+        Ok(self.peak)
+    }
+}
+
+/// The gain needed to bring the absolute peak `current_peak` to `peak`.
+fn normalize_gain(current_peak: f32, peak: f32) -> f32 {
+    // Don't amplify silence.
+    if current_peak <= f32::EPSILON {
+        return 1.0;
+    }
+
+    peak / current_peak
+}
+
+/// The frame at which the last clip in the project ends.
+fn project_end_frame(project_state: &ProjectState) -> FrameTime {
+    let tempo_map = &project_state.tempo_map;
+
+    let mut end_frame = FrameTime(0);
+    for track in project_state.tracks.iter() {
+        if let TrackType::Audio(audio_track) = &track.type_ {
+            for clip in audio_track.clips.iter() {
+                let timeline_start = match clip.copyable.timeline_start {
+                    Timestamp::Musical(t) => tempo_map.musical_to_nearest_frame_round(t),
+                    Timestamp::Superclock(t) => t.to_nearest_frame_round(tempo_map.sample_rate()),
+                };
+
+                let timeline_end = timeline_start
+                    + clip.copyable.clip_length.to_nearest_frame_round(tempo_map.sample_rate());
+
+                if timeline_end.0 > end_frame.0 {
+                    end_frame = timeline_end;
+                }
+            }
+        }
+    }
+
+    end_frame
+}
+
+fn stem_file_name(
+    project_state: &ProjectState,
+    track_index: usize,
+    format: ExportFormat,
+) -> String {
+    let name: String = project_state.tracks[track_index]
+        .name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+
+    format!("{:02} {}.{}", track_index + 1, name.trim(), format.file_extension())
+}
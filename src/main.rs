@@ -5,8 +5,11 @@
 use log::LevelFilter;
 use std::error::Error;
 
+mod config_file;
 mod engine_handle;
+mod export;
 mod plugins;
+mod project_file;
 mod resource;
 mod state_system;
 mod ui;
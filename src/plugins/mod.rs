@@ -1,2 +1,3 @@
+pub mod recorder_plug;
 pub mod sample_browser_plug;
 pub mod timeline_track_plug;
@@ -0,0 +1,593 @@
+use basedrop::{Owned, Shared};
+use meadowlark_plugin_api::ext::audio_ports::{
+    AudioPortInfo, MainPortsLayout, PluginAudioPortsExt, PORT_TYPE_STEREO,
+};
+use meadowlark_plugin_api::{
+    buffer::EventBuffer, HostInfo, HostRequestChannelSender, HostRequestFlags, PluginActivatedInfo,
+    PluginDescriptor, PluginFactory, PluginInstanceID, PluginMainThread, PluginProcessor,
+    ProcBuffers, ProcInfo, ProcessStatus,
+};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::state_system::source_state::default_recordings_directory;
+
+pub static RECORDER_PLUG_RDN: &str = "app.meadowlark.recorder";
+
+/// The number of seconds of audio that can be buffered between the process
+/// thread and the disk writer thread.
+const SAMPLE_BUFFER_SECONDS: usize = 4;
+const EVENT_BUFFER_SIZE: usize = 256;
+
+static WRITER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// TODO: Have the recorder support a variable number of channels.
+const NUM_CHANNELS: usize = 2;
+
+pub struct RecorderPlugFactory;
+
+impl PluginFactory for RecorderPlugFactory {
+    fn description(&self) -> PluginDescriptor {
+        PluginDescriptor {
+            id: RECORDER_PLUG_RDN.into(),
+            version: "0.1".into(),
+            name: "Recorder".into(),
+            vendor: "Meadowlark".into(),
+            description: String::new(),
+            url: String::new(),
+            manual_url: String::new(),
+            support_url: String::new(),
+            features: String::new(),
+        }
+    }
+
+    fn instantiate(
+        &mut self,
+        host_request_channel: HostRequestChannelSender,
+        _host_info: Shared<HostInfo>,
+        _plugin_id: PluginInstanceID,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<Box<dyn PluginMainThread>, String> {
+        Ok(Box::new(RecorderPlugMainThread::new(host_request_channel)))
+    }
+}
+
+/// A take that has been fully written to disk.
+#[derive(Debug, Clone)]
+pub struct RecordedTake {
+    /// The path to the WAV file containing the take.
+    pub path: PathBuf,
+
+    /// The frame on the timeline where the transport was when the first
+    /// frame of this take was captured.
+    ///
+    /// This has not been compensated for latency.
+    pub timeline_start_frame: u64,
+
+    /// The length of this take in frames.
+    pub num_frames: u64,
+
+    /// Whether or not this take was started because the transport looped
+    /// back to the start of the loop.
+    pub is_loop_pass: bool,
+
+    /// The indices of the tracks which were armed when this take started.
+    pub armed_tracks: Vec<usize>,
+}
+
+pub struct RecorderPlugHandle {
+    armed: Arc<AtomicBool>,
+    overrun: Arc<AtomicBool>,
+    /// The ID of the latest set of armed tracks. The processor tags each
+    /// take with the ID that was current when the take started.
+    arm_id: Arc<AtomicU32>,
+    /// The sets of armed tracks which takes that haven't been polled yet
+    /// may have started with, oldest first.
+    armed_track_sets: VecDeque<(u32, Vec<usize>)>,
+    to_writer_tx: mpsc::Sender<WriterMsg>,
+    from_writer_rx: mpsc::Receiver<(u32, RecordedTake)>,
+    host_request: HostRequestChannelSender,
+}
+
+impl RecorderPlugHandle {
+    /// Set the indices of the tracks which new takes will be recorded to.
+    ///
+    /// Audio arriving at the recorder's inputs is only captured while the
+    /// transport is playing and at least one track is armed.
+    pub fn set_armed_tracks(&mut self, armed_tracks: Vec<usize>) {
+        let armed = !armed_tracks.is_empty();
+
+        let arm_id = self.arm_id.load(Ordering::Relaxed).wrapping_add(1);
+        self.armed_track_sets.push_back((arm_id, armed_tracks));
+        self.arm_id.store(arm_id, Ordering::Relaxed);
+
+        self.armed.store(armed, Ordering::Relaxed);
+
+        if armed {
+            self.host_request.request(HostRequestFlags::PROCESS);
+        }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed.load(Ordering::Relaxed)
+    }
+
+    /// Set the directory where new takes will be written to.
+    pub fn set_recordings_directory(&mut self, directory: PathBuf) {
+        if let Err(e) = self.to_writer_tx.send(WriterMsg::SetDirectory(directory)) {
+            log::error!("Recorder plugin failed to send message to disk writer: {}", e);
+        }
+    }
+
+    /// Returns all takes which have been fully written to disk since the last
+    /// call to this method.
+    pub fn poll_recorded_takes(&mut self) -> Vec<RecordedTake> {
+        if self.overrun.swap(false, Ordering::Relaxed) {
+            log::warn!("Recorder plugin overran its buffer, some audio was not recorded");
+        }
+
+        let mut takes = Vec::new();
+        while let Ok((arm_id, mut take)) = self.from_writer_rx.try_recv() {
+            // Takes arrive in the order they were started, so no later take
+            // can have started with an older set of armed tracks.
+            if let Some(i) = self.armed_track_sets.iter().position(|(id, _)| *id == arm_id) {
+                self.armed_track_sets.drain(0..i);
+                take.armed_tracks = self.armed_track_sets[0].1.clone();
+            }
+
+            takes.push(take);
+        }
+
+        takes
+    }
+}
+
+impl Drop for RecorderPlugHandle {
+    fn drop(&mut self) {
+        self.armed.store(false, Ordering::Relaxed);
+    }
+}
+
+enum WriterMsg {
+    SetDirectory(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RecorderEvent {
+    TakeStarted {
+        /// The frame on the timeline where this take starts.
+        timeline_frame: u64,
+        /// The total number of frames sent to the writer before this take.
+        stream_frame: u64,
+        is_loop_pass: bool,
+        /// The ID of the set of armed tracks when this take started.
+        arm_id: u32,
+    },
+    TakeEnded {
+        /// The total number of frames sent to the writer at the end of this take.
+        stream_frame: u64,
+    },
+}
+
+impl RecorderEvent {
+    fn stream_frame(&self) -> u64 {
+        match self {
+            RecorderEvent::TakeStarted { stream_frame, .. } => *stream_frame,
+            RecorderEvent::TakeEnded { stream_frame } => *stream_frame,
+        }
+    }
+}
+
+pub struct RecorderPlugMainThread {
+    host_request: HostRequestChannelSender,
+    recordings_directory: PathBuf,
+}
+
+impl RecorderPlugMainThread {
+    fn new(host_request: HostRequestChannelSender) -> Self {
+        Self { host_request, recordings_directory: default_recordings_directory() }
+    }
+}
+
+impl PluginMainThread for RecorderPlugMainThread {
+    fn activate(
+        &mut self,
+        sample_rate: u32,
+        _min_frames: u32,
+        _max_frames: u32,
+        coll_handle: &basedrop::Handle,
+    ) -> Result<PluginActivatedInfo, String> {
+        let (sample_tx, sample_rx) =
+            RingBuffer::<f32>::new(sample_rate as usize * SAMPLE_BUFFER_SECONDS * NUM_CHANNELS);
+        let (event_tx, event_rx) = RingBuffer::<RecorderEvent>::new(EVENT_BUFFER_SIZE);
+
+        let (to_writer_tx, from_handle_rx) = mpsc::channel();
+        let (to_handle_tx, from_writer_rx) = mpsc::channel();
+
+        let armed = Arc::new(AtomicBool::new(false));
+        let overrun = Arc::new(AtomicBool::new(false));
+        let arm_id = Arc::new(AtomicU32::new(0));
+
+        let writer = DiskWriter {
+            sample_rx,
+            event_rx,
+            from_handle_rx,
+            to_handle_tx,
+            sample_rate,
+            directory: self.recordings_directory.clone(),
+            current_take: None,
+            frames_read: 0,
+            next_take_index: 0,
+            read_buffer: Vec::with_capacity(sample_rate as usize * NUM_CHANNELS),
+        };
+        // The disk writer thread exits on its own once the processor has been
+        // dropped and it has finished flushing the remaining audio to disk.
+        std::thread::Builder::new()
+            .name("recorder-disk-writer".into())
+            .spawn(move || writer.run())
+            .map_err(|e| format!("Failed to spawn recorder disk writer thread: {}", e))?;
+
+        Ok(PluginActivatedInfo {
+            processor: Box::new(RecorderPlugProcessor {
+                armed: Arc::clone(&armed),
+                overrun: Arc::clone(&overrun),
+                arm_id: Arc::clone(&arm_id),
+                sample_tx: Owned::new(coll_handle, sample_tx),
+                event_tx: Owned::new(coll_handle, event_tx),
+                in_take: false,
+                frames_sent: 0,
+            }),
+            internal_handle: Some(Box::new(RecorderPlugHandle {
+                armed,
+                overrun,
+                arm_id,
+                armed_track_sets: VecDeque::from(vec![(0, Vec::new())]),
+                to_writer_tx,
+                from_writer_rx,
+                host_request: self.host_request.clone(),
+            })),
+        })
+    }
+
+    fn audio_ports_ext(&mut self) -> Result<PluginAudioPortsExt, String> {
+        Ok(PluginAudioPortsExt {
+            inputs: vec![AudioPortInfo {
+                stable_id: 0,
+                channels: NUM_CHANNELS as u16,
+                port_type: Some(PORT_TYPE_STEREO.into()),
+                display_name: None,
+            }],
+            outputs: vec![],
+            main_ports_layout: MainPortsLayout::InOnly,
+        })
+    }
+}
+
+pub struct RecorderPlugProcessor {
+    armed: Arc<AtomicBool>,
+    overrun: Arc<AtomicBool>,
+    arm_id: Arc<AtomicU32>,
+
+    sample_tx: Owned<Producer<f32>>,
+    event_tx: Owned<Producer<RecorderEvent>>,
+
+    in_take: bool,
+    frames_sent: u64,
+}
+
+impl RecorderPlugProcessor {
+    fn start_take(&mut self, timeline_frame: u64, is_loop_pass: bool) {
+        self.send_event(RecorderEvent::TakeStarted {
+            timeline_frame,
+            stream_frame: self.frames_sent,
+            is_loop_pass,
+            arm_id: self.arm_id.load(Ordering::Relaxed),
+        });
+        self.in_take = true;
+    }
+
+    fn end_take(&mut self) {
+        if self.in_take {
+            self.send_event(RecorderEvent::TakeEnded { stream_frame: self.frames_sent });
+            self.in_take = false;
+        }
+    }
+
+    fn send_event(&mut self, event: RecorderEvent) {
+        if self.event_tx.push(event).is_err() {
+            self.overrun.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn send_frames(&mut self, buffers: &ProcBuffers, start: usize, end: usize) {
+        let num_frames = end - start;
+        if num_frames == 0 {
+            return;
+        }
+
+        let (in_l, in_r) = buffers.audio_in[0].stereo_f32().unwrap();
+        let in_l = &in_l.data[start..end];
+        let in_r = &in_r.data[start..end];
+
+        if let Ok(mut chunk) = self.sample_tx.write_chunk(num_frames * NUM_CHANNELS) {
+            let (slice_1, slice_2) = chunk.as_mut_slices();
+            for (s, (l, r)) in slice_1
+                .chunks_exact_mut(NUM_CHANNELS)
+                .chain(slice_2.chunks_exact_mut(NUM_CHANNELS))
+                .zip(in_l.iter().zip(in_r.iter()))
+            {
+                s[0] = *l;
+                s[1] = *r;
+            }
+            chunk.commit_all();
+
+            self.frames_sent += num_frames as u64;
+        } else {
+            self.overrun.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl PluginProcessor for RecorderPlugProcessor {
+    fn start_processing(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn stop_processing(&mut self) {
+        self.end_take();
+    }
+
+    fn process(
+        &mut self,
+        proc_info: &ProcInfo,
+        buffers: &mut ProcBuffers,
+        _in_events: &EventBuffer,
+        _out_events: &mut EventBuffer,
+    ) -> ProcessStatus {
+        let transport = &proc_info.transport;
+
+        if !self.armed.load(Ordering::Relaxed) {
+            self.end_take();
+            return ProcessStatus::Sleep;
+        }
+
+        if !transport.is_playing() {
+            // Stay awake while armed. A sleeping plugin is only woken up by
+            // note events or non-silent audio on its inputs, so if it slept
+            // here the start of a take with a silent input would be missed.
+            self.end_take();
+            return ProcessStatus::Continue;
+        }
+
+        if transport.did_seek().is_some() {
+            // The user seeked to a new position while recording, so start a
+            // new take at the new position.
+            self.end_take();
+        }
+
+        if !self.in_take {
+            self.start_take(transport.playhead_frame(), false);
+        }
+
+        if let Some(loop_back) = transport.do_loop_back() {
+            // Each pass of the loop gets recorded into its own take.
+            let frames_before_loop_end =
+                (loop_back.loop_end.saturating_sub(transport.playhead_frame()) as usize)
+                    .min(proc_info.frames);
+
+            self.send_frames(buffers, 0, frames_before_loop_end);
+            self.end_take();
+
+            self.start_take(loop_back.loop_start, true);
+            self.send_frames(buffers, frames_before_loop_end, proc_info.frames);
+        } else {
+            self.send_frames(buffers, 0, proc_info.frames);
+        }
+
+        ProcessStatus::Continue
+    }
+
+    fn param_flush(&mut self, _in_events: &EventBuffer, _out_events: &mut EventBuffer) {}
+}
+
+struct TakeWriter {
+    writer: hound::WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    timeline_start_frame: u64,
+    num_frames: u64,
+    is_loop_pass: bool,
+    arm_id: u32,
+}
+
+/// Streams the recorded audio to disk on a non-realtime thread.
+struct DiskWriter {
+    sample_rx: Consumer<f32>,
+    event_rx: Consumer<RecorderEvent>,
+    from_handle_rx: mpsc::Receiver<WriterMsg>,
+    to_handle_tx: mpsc::Sender<(u32, RecordedTake)>,
+
+    sample_rate: u32,
+    directory: PathBuf,
+
+    current_take: Option<TakeWriter>,
+    frames_read: u64,
+    next_take_index: u64,
+
+    read_buffer: Vec<f32>,
+}
+
+impl DiskWriter {
+    fn run(mut self) {
+        loop {
+            while let Ok(msg) = self.from_handle_rx.try_recv() {
+                match msg {
+                    WriterMsg::SetDirectory(directory) => self.directory = directory,
+                }
+            }
+
+            // Check for abandonment *before* reading so that no audio pushed
+            // before the processor was dropped is missed.
+            let abandoned = self.sample_rx.is_abandoned();
+
+            self.poll();
+
+            if abandoned {
+                self.finish_take();
+                break;
+            }
+
+            std::thread::sleep(WRITER_POLL_INTERVAL);
+        }
+    }
+
+    fn poll(&mut self) {
+        // The number of available frames must be read *before* peeking at the
+        // events. The process thread always pushes the frames preceding an
+        // event before pushing the event itself, so this guarantees that we
+        // never read frames belonging to a take whose start event we haven't
+        // seen yet.
+        let mut frames_available = (self.sample_rx.slots() / NUM_CHANNELS) as u64;
+
+        loop {
+            let next_event = self.event_rx.peek().ok().copied();
+
+            let frames_until_event = match next_event {
+                Some(event) => event.stream_frame().saturating_sub(self.frames_read),
+                None => frames_available,
+            };
+
+            let frames_to_read = frames_until_event.min(frames_available);
+            let frames_written = self.write_frames(frames_to_read as usize);
+            frames_available -= frames_written;
+
+            if frames_written < frames_to_read {
+                // Try again on the next poll.
+                break;
+            }
+
+            match next_event {
+                Some(event) if event.stream_frame() <= self.frames_read => {
+                    let _ = self.event_rx.pop();
+
+                    match event {
+                        RecorderEvent::TakeStarted {
+                            timeline_frame, is_loop_pass, arm_id, ..
+                        } => {
+                            self.finish_take();
+                            self.start_take(timeline_frame, is_loop_pass, arm_id);
+                        }
+                        RecorderEvent::TakeEnded { .. } => self.finish_take(),
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Returns the number of frames that were actually read from the
+    /// buffer.
+    fn write_frames(&mut self, num_frames: usize) -> u64 {
+        if num_frames == 0 {
+            return 0;
+        }
+
+        self.read_buffer.clear();
+        match self.sample_rx.read_chunk(num_frames * NUM_CHANNELS) {
+            Ok(chunk) => {
+                let (slice_1, slice_2) = chunk.as_slices();
+                self.read_buffer.extend_from_slice(slice_1);
+                self.read_buffer.extend_from_slice(slice_2);
+                chunk.commit_all();
+            }
+            Err(e) => {
+                log::warn!("Failed to read recorded audio: {}", e);
+                return 0;
+            }
+        }
+
+        let num_frames = (self.read_buffer.len() / NUM_CHANNELS) as u64;
+        self.frames_read += num_frames;
+
+        if let Some(take) = &mut self.current_take {
+            for s in self.read_buffer.iter() {
+                if let Err(e) = take.writer.write_sample(*s) {
+                    log::error!("Failed to write recorded audio to {:?}: {}", &take.path, e);
+                    break;
+                }
+            }
+
+            take.num_frames += num_frames;
+        }
+
+        num_frames
+    }
+
+    fn start_take(&mut self, timeline_start_frame: u64, is_loop_pass: bool, arm_id: u32) {
+        if let Err(e) = std::fs::create_dir_all(&self.directory) {
+            log::error!("Failed to create recordings directory {:?}: {}", &self.directory, e);
+            return;
+        }
+
+        let timestamp =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let path = self.directory.join(format!("take_{}_{}.wav", timestamp, self.next_take_index));
+        self.next_take_index += 1;
+
+        let spec = hound::WavSpec {
+            channels: NUM_CHANNELS as u16,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+
+        match hound::WavWriter::create(&path, spec) {
+            Ok(writer) => {
+                self.current_take = Some(TakeWriter {
+                    writer,
+                    path,
+                    timeline_start_frame,
+                    num_frames: 0,
+                    is_loop_pass,
+                    arm_id,
+                });
+            }
+            Err(e) => {
+                log::error!("Failed to create recording file {:?}: {}", &path, e);
+            }
+        }
+    }
+
+    fn finish_take(&mut self) {
+        if let Some(take) = self.current_take.take() {
+            if let Err(e) = take.writer.finalize() {
+                log::error!("Failed to finalize recording file {:?}: {}", &take.path, e);
+                return;
+            }
+
+            if take.num_frames == 0 {
+                let _ = std::fs::remove_file(&take.path);
+                return;
+            }
+
+            let _ = self.to_handle_tx.send((
+                take.arm_id,
+                RecordedTake {
+                    path: take.path,
+                    timeline_start_frame: take.timeline_start_frame,
+                    num_frames: take.num_frames,
+                    is_loop_pass: take.is_loop_pass,
+                    // This is filled in by the handle.
+                    armed_tracks: Vec::new(),
+                },
+            ));
+        }
+    }
+}
@@ -329,8 +329,8 @@ impl PluginProcessor for SampleBrowserPlugProcessor {
         self.poll(in_events);
 
         let (mut buf_l, mut buf_r) = buffers.audio_out[0].stereo_f32_mut().unwrap();
-        let BufferInner { data: buf_l_data, is_constant: buf_l_is_constant } = &mut *buf_l;
-        let BufferInner { data: buf_r_data, is_constant: buf_r_is_constant } = &mut *buf_r;
+        let BufferInner { data: buf_l_data, is_constant: buf_l_is_constant, .. } = &mut *buf_l;
+        let BufferInner { data: buf_r_data, is_constant: buf_r_is_constant, .. } = &mut *buf_r;
         let buf_l_part = &mut buf_l_data[0..proc_info.frames];
         let buf_r_part = &mut buf_r_data[0..proc_info.frames];
 
@@ -0,0 +1,284 @@
+//! Saving and loading projects to/from files on disk.
+//!
+//! Projects are stored as RON (Rusty Object Notation) text files, so they
+//! can be read and diffed by humans. Every file starts with the version of
+//! the format it was written with.
+//!
+//! When the format changes, the structs of the previous version are kept in
+//! their own module (i.e. `v1`), and a new module is added for the new
+//! version. Files written with an older version are then migrated forward
+//! by converting them into the next version with `From`, one version at a
+//! time, until they reach the current version.
+
+use serde::Deserialize;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use meadowlark_engine::utils::fs::write_atomic;
+
+use crate::state_system::source_state::ProjectState;
+use crate::util::fmt_newer_version_error;
+
+mod v1;
+mod v2;
+
+/// The version of the project file format written by this version of
+/// Meadowlark.
+pub static PROJECT_FILE_VERSION: u32 = 2;
+
+/// The file extension of Meadowlark project files.
+pub static PROJECT_FILE_EXTENSION: &str = "mdlk";
+
+#[derive(Debug)]
+pub enum ProjectFileError {
+    Io(std::io::Error),
+    Serialize(String),
+    Parse(String),
+    /// The file was written by a newer version of Meadowlark.
+    NewerVersion(u32),
+    UnknownVersion(u32),
+}
+
+impl Error for ProjectFileError {}
+
+impl std::fmt::Display for ProjectFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectFileError::Io(e) => write!(f, "Project file error: {}", e),
+            ProjectFileError::Serialize(e) => {
+                write!(f, "Project file error: failed to serialize project: {}", e)
+            }
+            ProjectFileError::Parse(e) => {
+                write!(f, "Project file error: failed to parse project file: {}", e)
+            }
+            ProjectFileError::NewerVersion(v) => {
+                write!(f, "Project file error: ")?;
+                fmt_newer_version_error(f, "project", *v, PROJECT_FILE_VERSION)
+            }
+            ProjectFileError::UnknownVersion(v) => {
+                write!(f, "Project file error: unknown file format version {}", v)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for ProjectFileError {
+    fn from(e: std::io::Error) -> Self {
+        ProjectFileError::Io(e)
+    }
+}
+
+/// Only the version of a project file, used to select which version of the
+/// format to parse the rest of the file with.
+#[derive(Deserialize)]
+#[serde(rename = "ProjectFile")]
+struct ProjectFileHeader {
+    version: u32,
+}
+
+/// Save the project to the file at the given path.
+pub fn save_project(project: &ProjectState, path: &Path) -> Result<(), ProjectFileError> {
+    let project_dir = project_dir(path)?;
+
+    let file = v2::ProjectFile::from_project_state(project, &project_dir);
+
+    let text = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default())
+        .map_err(|e| ProjectFileError::Serialize(e.to_string()))?;
+
+    write_atomic(path, text)?;
+
+    Ok(())
+}
+
+/// Load the project from the file at the given path, migrating it from an
+/// older version of the format if needed.
+pub fn load_project(path: &Path) -> Result<ProjectState, ProjectFileError> {
+    let text = std::fs::read_to_string(path)?;
+    let project_dir = project_dir(path)?;
+
+    let header: ProjectFileHeader =
+        ron::from_str(&text).map_err(|e| ProjectFileError::Parse(e.to_string()))?;
+
+    match header.version {
+        1 => {
+            let file: v1::ProjectFile =
+                ron::from_str(&text).map_err(|e| ProjectFileError::Parse(e.to_string()))?;
+
+            Ok(v2::ProjectFile::from(file).into_project_state(&project_dir))
+        }
+        2 => {
+            let file: v2::ProjectFile =
+                ron::from_str(&text).map_err(|e| ProjectFileError::Parse(e.to_string()))?;
+
+            Ok(file.into_project_state(&project_dir))
+        }
+        v if v > PROJECT_FILE_VERSION => Err(ProjectFileError::NewerVersion(v)),
+        v => Err(ProjectFileError::UnknownVersion(v)),
+    }
+}
+
+/// The absolute path of the directory containing the project file.
+fn project_dir(path: &Path) -> Result<PathBuf, ProjectFileError> {
+    let path = if path.is_relative() { std::env::current_dir()?.join(path) } else { path.into() };
+
+    Ok(path.parent().map(|p| p.to_path_buf()).unwrap_or(path))
+}
+
+/// Convert a path to a media file into the path that is stored in the
+/// project file.
+///
+/// Paths inside the project's directory are stored relative to the project
+/// file, so that a project can be moved along with its media.
+fn path_to_file(path: &Path, project_dir: &Path) -> PathBuf {
+    let path = if path.is_relative() {
+        std::env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.into())
+    } else {
+        path.into()
+    };
+
+    path.strip_prefix(project_dir).map(|p| p.to_path_buf()).unwrap_or(path)
+}
+
+/// Convert a path stored in the project file back into a path to the media
+/// file.
+fn path_from_file(path: &Path, project_dir: &Path) -> PathBuf {
+    if path.is_relative() {
+        project_dir.join(path)
+    } else {
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_system::source_state::TrackType;
+    use crate::state_system::time::{MusicalTime, Timestamp};
+    use crate::util::unique_temp_dir;
+
+    /// A directory in the temporary directory which is unique to this test.
+    fn temp_project_dir(name: &str) -> PathBuf {
+        unique_temp_dir(&format!("project-file-test-{}", name))
+    }
+
+    fn clip_paths(project: &ProjectState) -> Vec<PathBuf> {
+        project
+            .tracks
+            .iter()
+            .flat_map(|track| match &track.type_ {
+                TrackType::Audio(audio) => {
+                    audio.clips.iter().map(|c| c.pcm_key.path.clone()).collect()
+                }
+                TrackType::Synth => Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = temp_project_dir("round-trip");
+        let path = dir.join(format!("project.{}", PROJECT_FILE_EXTENSION));
+
+        let mut project = ProjectState::test_project();
+        if let TrackType::Audio(audio) = &mut project.tracks[0].type_ {
+            audio.clips[0].pcm_key.path = dir.join("media").join("synth.wav");
+        }
+
+        save_project(&project, &path).unwrap();
+
+        // Media inside the project's directory is stored relative to it.
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("version: 2"));
+        assert!(!text.contains(dir.to_str().unwrap()));
+
+        let loaded = load_project(&path).unwrap();
+
+        assert_eq!(loaded.tracks.len(), project.tracks.len());
+        for (loaded, track) in loaded.tracks.iter().zip(project.tracks.iter()) {
+            assert_eq!(loaded.name, track.name);
+            assert_eq!(loaded.color, track.color);
+        }
+        assert_eq!(clip_paths(&loaded)[0], dir.join("media").join("synth.wav"));
+        assert_eq!(loaded.loop_start, project.loop_start);
+        assert_eq!(loaded.loop_end, project.loop_end);
+        assert_eq!(loaded.loop_active, project.loop_active);
+        assert_eq!(loaded.tempo_map.tempo_points(), project.tempo_map.tempo_points());
+        assert_eq!(loaded.tempo_map.meter_changes(), project.tempo_map.meter_changes());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn version_1_is_migrated() {
+        let dir = temp_project_dir("version-1");
+        let path = dir.join(format!("project.{}", PROJECT_FILE_EXTENSION));
+
+        std::fs::write(
+            &path,
+            r#"ProjectFile(
+    version: 1,
+    master_track: (color: Color2, lane_height: 40.0, volume_normalized: 1.0, pan_normalized: 0.5),
+    tracks: [
+        (
+            name: "Drums",
+            color: Color0,
+            lane_height: 40.0,
+            volume_normalized: 0.8,
+            pan_normalized: 0.5,
+            routed_to: ToMaster,
+            record_armed: false,
+            type_: Audio(clips: [
+                (
+                    name: "Kick",
+                    path: "media/kick.wav",
+                    resample_to_project_sr: true,
+                    resample_quality: Default,
+                    timeline_start: Musical(beats: 4, ticks: 0),
+                    clip_length: (seconds: 2, ticks: 0),
+                    gain_db: 0.0,
+                    clip_to_pcm_offset: (seconds: 0, ticks: 0),
+                    clip_to_pcm_offset_is_negative: false,
+                    incrossfade_type: Linear,
+                    incrossfade_time: (seconds: 0, ticks: 0),
+                    outcrossfade_type: Linear,
+                    outcrossfade_time: (seconds: 0, ticks: 0),
+                ),
+            ]),
+        ),
+    ],
+    timeline_horizontal_zoom: 0.25,
+    timeline_scroll_beats_x: 0.0,
+    loop_start: Musical(beats: 8, ticks: 0),
+    loop_end: Musical(beats: 16, ticks: 0),
+    loop_active: true,
+    playhead_last_seeked: Musical(beats: 0, ticks: 0),
+    tempo_map: (bpm: 140.0, tsig_num: 3, tsig_denom: 4),
+    plugins: [],
+)"#,
+        )
+        .unwrap();
+
+        let project = load_project(&path).unwrap();
+
+        assert_eq!(project.tracks[0].name, "Drums");
+        assert_eq!(clip_paths(&project), vec![dir.join("media").join("kick.wav")]);
+        assert_eq!(project.loop_end, Timestamp::Musical(MusicalTime::from_beats(16)));
+        assert_eq!(project.tempo_map.bpm_at_musical_time(MusicalTime::from_beats(8)), 140.0);
+        assert_eq!(project.tempo_map.tsig_at_musical_time(MusicalTime::from_beats(8)), (3, 4));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let dir = temp_project_dir("newer-version");
+        let path = dir.join(format!("project.{}", PROJECT_FILE_EXTENSION));
+
+        std::fs::write(&path, format!("ProjectFile(version: {})", PROJECT_FILE_VERSION + 1))
+            .unwrap();
+
+        assert!(matches!(load_project(&path), Err(ProjectFileError::NewerVersion(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
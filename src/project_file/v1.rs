@@ -0,0 +1,359 @@
+//! Version 1 of the project file format.
+//!
+//! Files in this version are migrated to version 2 when loaded. The rest of
+//! the structs are shared with version 2.
+
+use meadowlark_engine::plugin_host::PluginHostSaveState;
+use meadowlark_engine::plugin_scanner::ScannedPluginKey;
+use meadowlark_plugin_api::ext::gui::GuiSize;
+use meadowlark_plugin_api::PluginFormat as EnginePluginFormat;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::resource::PcmKey;
+use crate::state_system::source_state::{
+    AudioClipCopyableState, AudioClipState, CrossfadeType as StateCrossfadeType,
+    PaletteColor as StatePaletteColor,
+};
+use crate::state_system::time::{
+    MusicalTime, SuperclockTime as StateSuperclockTime, Timestamp as StateTimestamp,
+};
+
+use super::{path_from_file, path_to_file};
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectFile {
+    pub version: u32,
+
+    pub master_track: MasterTrack,
+    pub tracks: Vec<Track>,
+
+    pub timeline_horizontal_zoom: f64,
+    pub timeline_scroll_beats_x: f64,
+
+    pub loop_start: Timestamp,
+    pub loop_end: Timestamp,
+    pub loop_active: bool,
+
+    pub playhead_last_seeked: Timestamp,
+
+    pub tempo_map: TempoMap,
+
+    pub plugins: Vec<Plugin>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MasterTrack {
+    pub color: PaletteColor,
+    pub lane_height: f32,
+    pub volume_normalized: f32,
+    pub pan_normalized: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Track {
+    pub name: String,
+    pub color: PaletteColor,
+    pub lane_height: f32,
+    pub volume_normalized: f32,
+    pub pan_normalized: f32,
+    pub routed_to: TrackRoute,
+    pub record_armed: bool,
+    pub type_: TrackType,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum TrackRoute {
+    ToMaster,
+    ToTrackAtIndex(usize),
+    None,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum TrackType {
+    Audio { clips: Vec<AudioClip> },
+    Synth,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AudioClip {
+    pub name: String,
+
+    /// The path to the audio file. This is relative to the project file if
+    /// the audio file is inside the project's directory.
+    pub path: PathBuf,
+    pub resample_to_project_sr: bool,
+    pub resample_quality: ResampleQuality,
+
+    pub timeline_start: Timestamp,
+    pub clip_length: SuperclockTime,
+    pub gain_db: f32,
+
+    pub clip_to_pcm_offset: SuperclockTime,
+    pub clip_to_pcm_offset_is_negative: bool,
+
+    pub incrossfade_type: CrossfadeType,
+    pub incrossfade_time: SuperclockTime,
+    pub outcrossfade_type: CrossfadeType,
+    pub outcrossfade_time: SuperclockTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ResampleQuality {
+    Default,
+    Linear,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum CrossfadeType {
+    ConstantPower,
+    Linear,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PaletteColor {
+    Unassigned,
+    Color0,
+    Color1,
+    Color2,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Timestamp {
+    Musical { beats: u32, ticks: u32 },
+    Superclock { seconds: u32, ticks: u32 },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SuperclockTime {
+    pub seconds: u32,
+    pub ticks: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TempoMap {
+    pub bpm: f64,
+    pub tsig_num: u16,
+    pub tsig_denom: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Plugin {
+    pub rdn: String,
+    pub format: PluginFormat,
+
+    /// The path to the binary the plugin was loaded from. This is `None`
+    /// for internal plugins.
+    pub binary_path: Option<PathBuf>,
+    pub plugin_version: Option<String>,
+
+    pub active: bool,
+    pub bypassed: bool,
+
+    pub gui_size: Option<(u32, u32)>,
+
+    /// The plugin's state as a hex string.
+    pub state: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PluginFormat {
+    Internal,
+    Clap,
+}
+
+impl AudioClip {
+    pub(super) fn from_state(clip: &AudioClipState, project_dir: &Path) -> Self {
+        let c = &clip.copyable;
+
+        Self {
+            name: clip.name.clone(),
+            path: path_to_file(&clip.pcm_key.path, project_dir),
+            resample_to_project_sr: clip.pcm_key.resample_to_project_sr,
+            resample_quality: if clip.pcm_key.resample_quality
+                == pcm_loader::ResampleQuality::Linear
+            {
+                ResampleQuality::Linear
+            } else {
+                ResampleQuality::Default
+            },
+            timeline_start: c.timeline_start.into(),
+            clip_length: c.clip_length.into(),
+            gain_db: c.gain_db,
+            clip_to_pcm_offset: c.clip_to_pcm_offset.into(),
+            clip_to_pcm_offset_is_negative: c.clip_to_pcm_offset_is_negative,
+            incrossfade_type: c.incrossfade_type.into(),
+            incrossfade_time: c.incrossfade_time.into(),
+            outcrossfade_type: c.outcrossfade_type.into(),
+            outcrossfade_time: c.outcrossfade_time.into(),
+        }
+    }
+
+    pub(super) fn into_state(self, project_dir: &Path) -> AudioClipState {
+        AudioClipState {
+            name: self.name,
+            pcm_key: PcmKey {
+                path: path_from_file(&self.path, project_dir),
+                resample_to_project_sr: self.resample_to_project_sr,
+                resample_quality: match self.resample_quality {
+                    ResampleQuality::Default => pcm_loader::ResampleQuality::default(),
+                    ResampleQuality::Linear => pcm_loader::ResampleQuality::Linear,
+                },
+            },
+            copyable: AudioClipCopyableState {
+                timeline_start: self.timeline_start.into(),
+                clip_length: self.clip_length.into(),
+                gain_db: self.gain_db,
+                clip_to_pcm_offset: self.clip_to_pcm_offset.into(),
+                clip_to_pcm_offset_is_negative: self.clip_to_pcm_offset_is_negative,
+                incrossfade_type: self.incrossfade_type.into(),
+                incrossfade_time: self.incrossfade_time.into(),
+                outcrossfade_type: self.outcrossfade_type.into(),
+                outcrossfade_time: self.outcrossfade_time.into(),
+            },
+        }
+    }
+}
+
+impl Plugin {
+    pub(super) fn from_state(state: &PluginHostSaveState, project_dir: &Path) -> Self {
+        Self {
+            rdn: state.key.rdn.clone(),
+            format: match state.key.format {
+                EnginePluginFormat::Internal => PluginFormat::Internal,
+                EnginePluginFormat::Clap => PluginFormat::Clap,
+            },
+            binary_path: state.binary_path.as_ref().map(|p| path_to_file(p, project_dir)),
+            plugin_version: state.plugin_version.clone(),
+            active: state.active,
+            bypassed: state.bypassed,
+            gui_size: state.gui_size.map(|s| (s.width, s.height)),
+            state: state.raw_state.as_ref().map(|s| bytes_to_hex(s)),
+        }
+    }
+
+    pub(super) fn into_state(self, project_dir: &Path) -> PluginHostSaveState {
+        let raw_state = self.state.and_then(|s| {
+            let bytes = hex_to_bytes(&s);
+            if bytes.is_none() {
+                log::error!(
+                    "The saved state of plugin {} is corrupt, loading its default state instead",
+                    &self.rdn
+                );
+            }
+            bytes
+        });
+
+        PluginHostSaveState {
+            key: ScannedPluginKey {
+                rdn: self.rdn,
+                format: match self.format {
+                    PluginFormat::Internal => EnginePluginFormat::Internal,
+                    PluginFormat::Clap => EnginePluginFormat::Clap,
+                },
+            },
+            binary_path: self.binary_path.map(|p| path_from_file(&p, project_dir)),
+            plugin_version: self.plugin_version,
+            active: self.active,
+            bypassed: self.bypassed,
+            backup_audio_ports_ext: None,
+            backup_note_ports_ext: None,
+            gui_size: self.gui_size.map(|(width, height)| GuiSize { width, height }),
+            raw_state,
+        }
+    }
+}
+
+impl From<StateTimestamp> for Timestamp {
+    fn from(t: StateTimestamp) -> Self {
+        match t {
+            StateTimestamp::Musical(t) => Timestamp::Musical { beats: t.beats(), ticks: t.ticks() },
+            StateTimestamp::Superclock(t) => {
+                Timestamp::Superclock { seconds: t.seconds(), ticks: t.ticks() }
+            }
+        }
+    }
+}
+
+impl From<Timestamp> for StateTimestamp {
+    fn from(t: Timestamp) -> Self {
+        match t {
+            Timestamp::Musical { beats, ticks } => {
+                StateTimestamp::Musical(MusicalTime::new(beats, ticks))
+            }
+            Timestamp::Superclock { seconds, ticks } => {
+                StateTimestamp::Superclock(StateSuperclockTime::new(seconds, ticks))
+            }
+        }
+    }
+}
+
+impl From<StateSuperclockTime> for SuperclockTime {
+    fn from(t: StateSuperclockTime) -> Self {
+        SuperclockTime { seconds: t.seconds(), ticks: t.ticks() }
+    }
+}
+
+impl From<SuperclockTime> for StateSuperclockTime {
+    fn from(t: SuperclockTime) -> Self {
+        StateSuperclockTime::new(t.seconds, t.ticks)
+    }
+}
+
+impl From<StateCrossfadeType> for CrossfadeType {
+    fn from(t: StateCrossfadeType) -> Self {
+        match t {
+            StateCrossfadeType::ConstantPower => CrossfadeType::ConstantPower,
+            StateCrossfadeType::Linear => CrossfadeType::Linear,
+        }
+    }
+}
+
+impl From<CrossfadeType> for StateCrossfadeType {
+    fn from(t: CrossfadeType) -> Self {
+        match t {
+            CrossfadeType::ConstantPower => StateCrossfadeType::ConstantPower,
+            CrossfadeType::Linear => StateCrossfadeType::Linear,
+        }
+    }
+}
+
+impl From<StatePaletteColor> for PaletteColor {
+    fn from(c: StatePaletteColor) -> Self {
+        match c {
+            StatePaletteColor::Unassigned => PaletteColor::Unassigned,
+            StatePaletteColor::Color0 => PaletteColor::Color0,
+            StatePaletteColor::Color1 => PaletteColor::Color1,
+            StatePaletteColor::Color2 => PaletteColor::Color2,
+        }
+    }
+}
+
+impl From<PaletteColor> for StatePaletteColor {
+    fn from(c: PaletteColor) -> Self {
+        match c {
+            PaletteColor::Unassigned => StatePaletteColor::Unassigned,
+            PaletteColor::Color0 => StatePaletteColor::Color0,
+            PaletteColor::Color1 => StatePaletteColor::Color1,
+            PaletteColor::Color2 => StatePaletteColor::Color2,
+        }
+    }
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes.iter() {
+        let _ = write!(s, "{:02x}", b);
+    }
+    s
+}
+
+fn hex_to_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
@@ -0,0 +1,274 @@
+//! Version 2 of the project file format.
+//!
+//! This replaces the single tempo and time signature of version 1 with a
+//! list of tempo points and meter changes. All other structs are unchanged
+//! from version 1.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::state_system::source_state::{
+    ProjectAudioTrackState, ProjectState, ProjectTrackState, TrackRouteType,
+    TrackType as StateTrackType,
+};
+use crate::state_system::time::{
+    MeterChange as StateMeterChange, MusicalTime, TempoMap as StateTempoMap,
+    TempoPoint as StateTempoPoint, TempoRamp as StateTempoRamp,
+};
+
+use super::v1::{self, AudioClip, MasterTrack, Plugin, Timestamp, Track, TrackRoute, TrackType};
+use super::PROJECT_FILE_VERSION;
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectFile {
+    pub version: u32,
+
+    pub master_track: MasterTrack,
+    pub tracks: Vec<Track>,
+
+    pub timeline_horizontal_zoom: f64,
+    pub timeline_scroll_beats_x: f64,
+
+    pub loop_start: Timestamp,
+    pub loop_end: Timestamp,
+    pub loop_active: bool,
+
+    pub playhead_last_seeked: Timestamp,
+
+    pub tempo_map: TempoMap,
+
+    pub plugins: Vec<Plugin>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TempoMap {
+    pub tempo_points: Vec<TempoPoint>,
+    pub meter_changes: Vec<MeterChange>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TempoPoint {
+    pub beats: u32,
+    pub ticks: u32,
+    pub bpm: f64,
+    pub ramp: TempoRamp,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum TempoRamp {
+    Step,
+    Linear,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MeterChange {
+    pub beats: u32,
+    pub ticks: u32,
+    pub tsig_num: u16,
+    pub tsig_denom: u16,
+}
+
+impl ProjectFile {
+    pub fn from_project_state(project: &ProjectState, project_dir: &Path) -> Self {
+        Self {
+            version: PROJECT_FILE_VERSION,
+            master_track: MasterTrack {
+                color: project.master_track_color.into(),
+                lane_height: project.master_track_lane_height,
+                volume_normalized: project.master_track_volume_normalized,
+                pan_normalized: project.master_track_pan_normalized,
+            },
+            tracks: project
+                .tracks
+                .iter()
+                .map(|track| Track {
+                    name: track.name.clone(),
+                    color: track.color.into(),
+                    lane_height: track.lane_height,
+                    volume_normalized: track.volume_normalized,
+                    pan_normalized: track.pan_normalized,
+                    routed_to: match track.routed_to {
+                        TrackRouteType::ToMaster => TrackRoute::ToMaster,
+                        TrackRouteType::ToTrackAtIndex(i) => TrackRoute::ToTrackAtIndex(i),
+                        TrackRouteType::None => TrackRoute::None,
+                    },
+                    record_armed: track.record_armed,
+                    type_: match &track.type_ {
+                        StateTrackType::Audio(audio_track) => TrackType::Audio {
+                            clips: audio_track
+                                .clips
+                                .iter()
+                                .map(|clip| AudioClip::from_state(clip, project_dir))
+                                .collect(),
+                        },
+                        StateTrackType::Synth => TrackType::Synth,
+                    },
+                })
+                .collect(),
+            timeline_horizontal_zoom: project.timeline_horizontal_zoom,
+            timeline_scroll_beats_x: project.timeline_scroll_beats_x,
+            loop_start: project.loop_start.into(),
+            loop_end: project.loop_end.into(),
+            loop_active: project.loop_active,
+            playhead_last_seeked: project.playhead_last_seeked.into(),
+            tempo_map: TempoMap::from_state(&project.tempo_map),
+            plugins: project
+                .plugin_states
+                .iter()
+                .map(|state| Plugin::from_state(state, project_dir))
+                .collect(),
+        }
+    }
+
+    pub fn into_project_state(self, project_dir: &Path) -> ProjectState {
+        let tempo_map = self.tempo_map.into_state();
+
+        ProjectState {
+            master_track_color: self.master_track.color.into(),
+            master_track_lane_height: self.master_track.lane_height,
+            master_track_volume_normalized: self.master_track.volume_normalized,
+            master_track_pan_normalized: self.master_track.pan_normalized,
+            tracks: self
+                .tracks
+                .into_iter()
+                .map(|track| ProjectTrackState {
+                    name: track.name,
+                    color: track.color.into(),
+                    lane_height: track.lane_height,
+                    volume_normalized: track.volume_normalized,
+                    pan_normalized: track.pan_normalized,
+                    routed_to: match track.routed_to {
+                        TrackRoute::ToMaster => TrackRouteType::ToMaster,
+                        TrackRoute::ToTrackAtIndex(i) => TrackRouteType::ToTrackAtIndex(i),
+                        TrackRoute::None => TrackRouteType::None,
+                    },
+                    record_armed: track.record_armed,
+                    type_: match track.type_ {
+                        TrackType::Audio { clips } => {
+                            StateTrackType::Audio(ProjectAudioTrackState {
+                                clips: clips
+                                    .into_iter()
+                                    .map(|clip| clip.into_state(project_dir))
+                                    .collect(),
+                            })
+                        }
+                        TrackType::Synth => StateTrackType::Synth,
+                    },
+                })
+                .collect(),
+            timeline_horizontal_zoom: self.timeline_horizontal_zoom,
+            timeline_scroll_beats_x: self.timeline_scroll_beats_x,
+            loop_start: self.loop_start.into(),
+            loop_end: self.loop_end.into(),
+            loop_active: self.loop_active,
+            playhead_last_seeked: self.playhead_last_seeked.into(),
+            tempo_map,
+            plugin_states: self
+                .plugins
+                .into_iter()
+                .map(|plugin| plugin.into_state(project_dir))
+                .collect(),
+        }
+    }
+}
+
+impl TempoMap {
+    fn from_state(tempo_map: &StateTempoMap) -> Self {
+        Self {
+            tempo_points: tempo_map
+                .tempo_points()
+                .iter()
+                .map(|p| TempoPoint {
+                    beats: p.time.beats(),
+                    ticks: p.time.ticks(),
+                    bpm: p.bpm,
+                    ramp: match p.ramp {
+                        StateTempoRamp::Step => TempoRamp::Step,
+                        StateTempoRamp::Linear => TempoRamp::Linear,
+                    },
+                })
+                .collect(),
+            meter_changes: tempo_map
+                .meter_changes()
+                .iter()
+                .map(|m| MeterChange {
+                    beats: m.time.beats(),
+                    ticks: m.time.ticks(),
+                    tsig_num: m.tsig_num,
+                    tsig_denom: m.tsig_denom,
+                })
+                .collect(),
+        }
+    }
+
+    fn into_state(self) -> StateTempoMap {
+        let default_tempo_map = StateTempoMap::default();
+
+        // Skip invalid points instead of failing to load the whole project.
+        let mut tempo_points: Vec<StateTempoPoint> = self
+            .tempo_points
+            .into_iter()
+            .filter(|p| p.bpm > 0.0 && p.bpm.is_finite())
+            .map(|p| StateTempoPoint {
+                time: MusicalTime::new(p.beats, p.ticks),
+                bpm: p.bpm,
+                ramp: match p.ramp {
+                    TempoRamp::Step => StateTempoRamp::Step,
+                    TempoRamp::Linear => StateTempoRamp::Linear,
+                },
+            })
+            .collect();
+        if tempo_points.is_empty() {
+            tempo_points = default_tempo_map.tempo_points().to_vec();
+        }
+
+        let mut meter_changes: Vec<StateMeterChange> = self
+            .meter_changes
+            .into_iter()
+            .filter(|m| m.tsig_num != 0 && m.tsig_denom != 0)
+            .map(|m| StateMeterChange {
+                time: MusicalTime::new(m.beats, m.ticks),
+                tsig_num: m.tsig_num,
+                tsig_denom: m.tsig_denom,
+            })
+            .collect();
+        if meter_changes.is_empty() {
+            meter_changes = default_tempo_map.meter_changes().to_vec();
+        }
+
+        StateTempoMap::from_points(tempo_points, meter_changes, default_tempo_map.sample_rate())
+    }
+}
+
+impl From<v1::ProjectFile> for ProjectFile {
+    fn from(file: v1::ProjectFile) -> Self {
+        let tempo_map = TempoMap {
+            tempo_points: vec![TempoPoint {
+                beats: 0,
+                ticks: 0,
+                bpm: file.tempo_map.bpm,
+                ramp: TempoRamp::Step,
+            }],
+            meter_changes: vec![MeterChange {
+                beats: 0,
+                ticks: 0,
+                tsig_num: file.tempo_map.tsig_num,
+                tsig_denom: file.tempo_map.tsig_denom,
+            }],
+        };
+
+        Self {
+            version: 2,
+            master_track: file.master_track,
+            tracks: file.tracks,
+            timeline_horizontal_zoom: file.timeline_horizontal_zoom,
+            timeline_scroll_beats_x: file.timeline_scroll_beats_x,
+            loop_start: file.loop_start,
+            loop_end: file.loop_end,
+            loop_active: file.loop_active,
+            playhead_last_seeked: file.playhead_last_seeked,
+            tempo_map,
+            plugins: file.plugins,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use vizia::prelude::*;
+
+use crate::state_system::actions::ExportAction;
+use crate::state_system::{EngineHandle, SourceState, WorkingState};
+
+pub fn handle_export_action(
+    action: &ExportAction,
+    _cx: &mut EventContext,
+    _source_state: &mut SourceState,
+    working_state: &mut WorkingState,
+    engine_handle: &mut EngineHandle,
+) {
+    match action {
+        ExportAction::ShowDialog(shown) => {
+            working_state.export_dialog_shown = *shown;
+        }
+        ExportAction::SetFormat(format) => {
+            working_state.export_settings.format = *format;
+        }
+        ExportAction::SetSampleRate(sample_rate) => {
+            working_state.export_settings.sample_rate = *sample_rate;
+        }
+        ExportAction::SetDither(dither) => {
+            working_state.export_settings.dither = *dither;
+        }
+        ExportAction::SetNormalizePeakDb(peak_db) => {
+            working_state.export_settings.normalize_peak_db = *peak_db;
+        }
+        ExportAction::Cancel => {
+            if let Some(job) = working_state.export_job.take() {
+                let results = job.cancel(engine_handle);
+                log::info!("Export cancelled after exporting {} file(s)", count_exported(&results));
+            }
+            working_state.export_progress = None;
+        }
+    }
+}
+
+/// Continue the export in progress, if any.
+pub fn poll_export(working_state: &mut WorkingState, engine_handle: &mut EngineHandle) {
+    if let Some(job) = &mut working_state.export_job {
+        if let Some(results) = job.poll(engine_handle) {
+            log::info!(
+                "Export finished: {} of {} file(s) exported",
+                count_exported(&results),
+                results.len()
+            );
+
+            working_state.export_job = None;
+            working_state.export_progress = None;
+        } else {
+            let progress = job.progress();
+            if working_state.export_progress != Some(progress) {
+                working_state.export_progress = Some(progress);
+            }
+        }
+    }
+}
+
+fn count_exported<T, E>(results: &[Result<T, E>]) -> usize {
+    results.iter().filter(|res| res.is_ok()).count()
+}
@@ -1,11 +1,15 @@
 mod browser_panel_action_handler;
+mod export_action_handler;
 mod internal_action_handler;
 mod poll_engine_handler;
+mod project_action_handler;
 mod timeline_action_handler;
 mod track_action_handler;
 
 pub use browser_panel_action_handler::handle_browser_panel_action;
+pub use export_action_handler::{handle_export_action, poll_export};
 pub use internal_action_handler::handle_internal_action;
 pub use poll_engine_handler::poll_engine;
+pub use project_action_handler::{handle_project_action, open_project};
 pub use timeline_action_handler::handle_timeline_action;
 pub use track_action_handler::handle_track_action;
@@ -3,10 +3,18 @@
 
 use meadowlark_engine::engine::error::EngineCrashError;
 use meadowlark_engine::engine::{EngineDeactivatedStatus, OnIdleEvent};
+use meadowlark_engine::graph::error::GraphCompilerError;
+use pcm_loader::ResampleQuality;
 use std::time::Instant;
 use vizia::prelude::*;
 
-use crate::engine_handle::GARBAGE_COLLECT_INTERVAL;
+use crate::engine_handle::{GraphRequest, GARBAGE_COLLECT_INTERVAL};
+use crate::plugins::recorder_plug::RecordedTake;
+use crate::resource::PcmKey;
+use crate::state_system::source_state::{
+    AudioClipCopyableState, AudioClipState, CrossfadeType, TrackType,
+};
+use crate::state_system::time::{FrameTime, SuperclockTime, TempoMap, Timestamp};
 use crate::state_system::{EngineHandle, SourceState, WorkingState};
 use crate::ui::panels::timeline_panel::TimelineViewEvent;
 
@@ -24,7 +32,7 @@ pub fn poll_engine(
         let mut status = EnginePollStatus::Ok;
 
         for event in events.drain(..) {
-            match on_engine_event(engine_handle, event) {
+            match on_engine_event(working_state, engine_handle, event) {
                 EnginePollStatus::Ok => continue,
                 s => {
                     status = s;
@@ -36,6 +44,7 @@ pub fn poll_engine(
         match status {
             EnginePollStatus::Ok => {
                 poll_plugins(cx, source_state, working_state, engine_handle);
+                poll_recorder(cx, source_state, working_state, engine_handle);
             }
             EnginePollStatus::EngineDeactivatedGracefully => {
                 log::info!("Engine deactivated gracefully");
@@ -43,9 +52,24 @@ pub fn poll_engine(
             EnginePollStatus::EngineCrashed(error_msg) => {
                 log::error!("Engine crashed: {}", error_msg);
             }
+            EnginePollStatus::GraphCompileFailed {
+                schedule_version,
+                error,
+                rolled_back_requests,
+            } => {
+                on_graph_compile_failed(
+                    working_state,
+                    engine_handle,
+                    schedule_version,
+                    error,
+                    rolled_back_requests,
+                );
+            }
         }
     }
 
+    super::poll_export(working_state, engine_handle);
+
     if now >= engine_handle.next_garbage_collect_instant {
         if let Some(activated_handles) = &mut engine_handle.activated_handles {
             activated_handles.resource_loader.collect();
@@ -87,7 +111,133 @@ fn poll_plugins(
     }
 }
 
-fn on_engine_event(engine_handle: &mut EngineHandle, event: OnIdleEvent) -> EnginePollStatus {
+/// Add any takes which have finished recording to the tracks which were armed
+/// when each take started.
+fn poll_recorder(
+    cx: &mut EventContext,
+    source_state: &mut SourceState,
+    working_state: &mut WorkingState,
+    engine_handle: &mut EngineHandle,
+) {
+    let project_state = if let Some(project_state) = &mut source_state.project {
+        project_state
+    } else {
+        return;
+    };
+    let takes = if let Some(activated_handles) = &mut engine_handle.activated_handles {
+        activated_handles.recorder_plug_handle.poll_recorded_takes()
+    } else {
+        return;
+    };
+    if takes.is_empty() {
+        return;
+    }
+
+    let latency_frames = engine_handle.recording_latency_frames();
+
+    let activated_handles = engine_handle.activated_handles.as_mut().unwrap();
+
+    for take in takes.iter() {
+        let clip_state = if let Some(clip_state) =
+            recorded_take_to_clip_state(take, latency_frames, &project_state.tempo_map)
+        {
+            clip_state
+        } else {
+            continue;
+        };
+
+        for &track_index in take.armed_tracks.iter() {
+            let track_state = if let Some(track_state) = project_state.tracks.get_mut(track_index) {
+                track_state
+            } else {
+                continue;
+            };
+
+            if let TrackType::Audio(audio_track_state) = &mut track_state.type_ {
+                let mut clip_state = clip_state.clone();
+                clip_state.name =
+                    format!("{} Take #{}", &track_state.name, audio_track_state.clips.len() + 1);
+
+                activated_handles.timeline_track_plug_handles[track_index].insert_audio_clip(
+                    &clip_state,
+                    &project_state.tempo_map,
+                    &mut activated_handles.resource_loader,
+                );
+
+                {
+                    working_state.shared_timeline_view_state.borrow_mut().insert_audio_clip(
+                        track_index,
+                        clip_state.clone(),
+                        &project_state.tempo_map,
+                    );
+                }
+
+                audio_track_state.clips.push(clip_state);
+
+                cx.emit_to(
+                    working_state.timeline_view_id.unwrap(),
+                    TimelineViewEvent::ClipInserted {
+                        track_index,
+                        clip_index: audio_track_state.clips.len() - 1,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Create the state of an audio clip for a take, compensating for the given
+/// latency.
+///
+/// This will return `None` if the take is shorter than the latency.
+fn recorded_take_to_clip_state(
+    take: &RecordedTake,
+    latency_frames: u64,
+    tempo_map: &TempoMap,
+) -> Option<AudioClipState> {
+    let sample_rate = tempo_map.sample_rate();
+
+    // The audio captured at the start of the take was performed against playback
+    // that was `latency_frames` behind the transport, so shift the take earlier.
+    // If that would place the start of the take before the start of the timeline,
+    // trim the beginning of the clip instead.
+    let trimmed_frames = latency_frames.saturating_sub(take.timeline_start_frame);
+    if trimmed_frames >= take.num_frames {
+        return None;
+    }
+    let timeline_start_frame = take.timeline_start_frame.saturating_sub(latency_frames);
+
+    Some(AudioClipState {
+        name: String::new(),
+        pcm_key: PcmKey {
+            path: take.path.clone(),
+            resample_to_project_sr: true,
+            resample_quality: ResampleQuality::default(),
+        },
+        copyable: AudioClipCopyableState {
+            timeline_start: Timestamp::Musical(
+                tempo_map.frame_to_musical(FrameTime(timeline_start_frame)),
+            ),
+            clip_length: SuperclockTime::from_frame(
+                FrameTime(take.num_frames - trimmed_frames),
+                sample_rate,
+            ),
+            gain_db: 0.0,
+            clip_to_pcm_offset: SuperclockTime::from_frame(FrameTime(trimmed_frames), sample_rate),
+            clip_to_pcm_offset_is_negative: false,
+            incrossfade_type: CrossfadeType::Linear,
+            incrossfade_time: SuperclockTime::new(0, 0),
+            outcrossfade_type: CrossfadeType::Linear,
+            outcrossfade_time: SuperclockTime::new(0, 0),
+        },
+    })
+}
+
+fn on_engine_event(
+    working_state: &mut WorkingState,
+    engine_handle: &mut EngineHandle,
+    event: OnIdleEvent,
+) -> EnginePollStatus {
     match event {
         // The plugin's parameters have been modified via the plugin's custom
         // GUI.
@@ -149,6 +299,30 @@ fn on_engine_event(engine_handle: &mut EngineHandle, event: OnIdleEvent) -> Engi
         // reactivated.
         OnIdleEvent::PluginDeactivated { plugin_id, status } => {}
 
+        // Sent when the audio graph has finished compiling a new schedule in
+        // the background.
+        //
+        // If `status` is an error, then the engine keeps running the last
+        // schedule that compiled successfully, and the requests to modify the
+        // graph in `rolled_back_requests` have been undone.
+        OnIdleEvent::GraphCompiled { schedule_version, status, rolled_back_requests } => {
+            match status {
+                Ok(()) => working_state.engine_error = None,
+                Err(error) => {
+                    return EnginePollStatus::GraphCompileFailed {
+                        schedule_version,
+                        error,
+                        rolled_back_requests,
+                    };
+                }
+            }
+        }
+
+        // Sent when a scan of external plugins has finished. This contains
+        // the new list of all external plugins, as well as the plugins that
+        // failed to load or were skipped because they are in the blocklist.
+        OnIdleEvent::ExternalPluginsScanned(res) => {}
+
         // Sent whenever the engine has been deactivated, whether gracefully or
         // because of a crash.
         OnIdleEvent::EngineDeactivated(status) => {
@@ -169,8 +343,63 @@ fn on_engine_event(engine_handle: &mut EngineHandle, event: OnIdleEvent) -> Engi
     EnginePollStatus::Ok
 }
 
+/// The engine keeps running the last schedule that compiled successfully and
+/// rolls back the requests to modify the graph that failed to compile (along
+/// with any later requests that depend on them). Report which parts of the
+/// project were rolled back, and show the error to the user.
+fn on_graph_compile_failed(
+    working_state: &mut WorkingState,
+    engine_handle: &mut EngineHandle,
+    schedule_version: u64,
+    error: GraphCompilerError,
+    rolled_back_requests: Vec<u64>,
+) {
+    log::error!("Audio graph failed to compile (schedule version {}): {}", schedule_version, error);
+
+    let mut rolled_back = Vec::new();
+    if let Some(activated_handles) = &mut engine_handle.activated_handles {
+        activated_handles.graph_requests.retain(|(request_id, request)| {
+            if rolled_back_requests.contains(request_id) {
+                rolled_back.push(*request);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    let rolled_back: Vec<String> = rolled_back
+        .iter()
+        .map(|request| match request {
+            GraphRequest::SampleBrowserPlug => String::from("the sample browser"),
+            GraphRequest::RecorderPlug => String::from("the recorder"),
+            GraphRequest::TimelineTrackPlug { track_index } => {
+                format!("track #{}", track_index + 1)
+            }
+            GraphRequest::SavedPlugins => String::from("the plugins in the project"),
+        })
+        .collect();
+
+    working_state.engine_error = Some(if rolled_back.is_empty() {
+        format!("Audio graph failed to compile: {}", error)
+    } else {
+        log::warn!("Removed from the audio graph: {}", rolled_back.join(", "));
+
+        format!(
+            "Audio graph failed to compile, removed {} from the graph: {}",
+            rolled_back.join(", "),
+            error
+        )
+    });
+}
+
 enum EnginePollStatus {
     Ok,
     EngineDeactivatedGracefully,
     EngineCrashed(Box<EngineCrashError>),
+    GraphCompileFailed {
+        schedule_version: u64,
+        error: GraphCompilerError,
+        rolled_back_requests: Vec<u64>,
+    },
 }
@@ -0,0 +1,137 @@
+use meadowlark_plugin_api::PluginFormat;
+use std::path::Path;
+use std::rc::Rc;
+use vizia::prelude::*;
+
+use crate::export::{ExportError, ExportJob};
+use crate::project_file::{self, ProjectFileError};
+use crate::state_system::{EngineHandle, ProjectAction, SourceState, WorkingState};
+use crate::ui::panels::timeline_panel::TimelineViewEvent;
+
+pub fn handle_project_action(
+    action: &ProjectAction,
+    cx: &mut EventContext,
+    source_state: &mut SourceState,
+    working_state: &mut WorkingState,
+    engine_handle: &mut EngineHandle,
+) {
+    match action {
+        ProjectAction::Save => {
+            if let Some(path) = working_state.project_file_path.clone() {
+                save_project(&path, source_state, working_state, engine_handle);
+            } else {
+                log::error!("Could not save project: the project has not been saved to a file yet");
+            }
+        }
+        ProjectAction::SaveAs(path) => {
+            save_project(path, source_state, working_state, engine_handle);
+        }
+        ProjectAction::Open(path) => {
+            if let Err(e) = open_project(path, cx, source_state, working_state, engine_handle) {
+                log::error!("Could not open project {:?}: {}", path, e);
+            }
+        }
+        ProjectAction::Export { path, settings } => {
+            if let Some(project_state) = &source_state.project {
+                start_export(
+                    working_state,
+                    Ok(ExportJob::master(project_state, *settings, path.clone())),
+                );
+            }
+        }
+        ProjectAction::ExportStems { directory, settings } => {
+            if let Some(project_state) = &source_state.project {
+                start_export(
+                    working_state,
+                    ExportJob::track_stems(engine_handle, project_state, *settings, directory),
+                );
+            }
+        }
+    }
+}
+
+/// Open the project from the given file, replacing the current project.
+///
+/// If the file could not be loaded, then the current project is left as is.
+pub fn open_project(
+    path: &Path,
+    cx: &mut EventContext,
+    source_state: &mut SourceState,
+    working_state: &mut WorkingState,
+    engine_handle: &mut EngineHandle,
+) -> Result<(), ProjectFileError> {
+    let mut project_state = project_file::load_project(path)?;
+
+    project_state.tempo_map.set_sample_rate(engine_handle.system_io_stream_handle.sample_rate());
+
+    source_state.project = Some(project_state);
+
+    // Rebuild the engine and all of the working state from the new
+    // project.
+    engine_handle.ds_engine.deactivate_engine();
+    engine_handle.system_io_stream_handle.on_engine_deactivated();
+    *engine_handle = EngineHandle::new(source_state);
+
+    let timeline_view_id = working_state.timeline_view_id;
+    *working_state =
+        WorkingState::new(source_state, Rc::clone(&working_state.shared_timeline_view_state));
+    working_state.timeline_view_id = timeline_view_id;
+    working_state.project_file_path = Some(path.into());
+
+    if let (Some(project_state), Some(timeline_view_id)) =
+        (&source_state.project, working_state.timeline_view_id)
+    {
+        {
+            working_state
+                .shared_timeline_view_state
+                .borrow_mut()
+                .sync_from_project_state(&source_state.app, project_state);
+        }
+        cx.emit_to(timeline_view_id, TimelineViewEvent::SyncedFromProjectState);
+    }
+
+    Ok(())
+}
+
+fn start_export(working_state: &mut WorkingState, job: Result<ExportJob, ExportError>) {
+    if working_state.export_job.is_some() {
+        log::error!("Could not start export: another export is already in progress");
+        return;
+    }
+
+    match job {
+        Ok(job) => {
+            working_state.export_progress = Some(job.progress());
+            working_state.export_job = Some(job);
+            working_state.export_dialog_shown = false;
+        }
+        Err(e) => log::error!("Could not start export: {}", e),
+    }
+}
+
+fn save_project(
+    path: &Path,
+    source_state: &mut SourceState,
+    working_state: &mut WorkingState,
+    engine_handle: &mut EngineHandle,
+) {
+    if let Some(project_state) = &mut source_state.project {
+        // Internal plugins are constructed from the rest of the project
+        // state, so only the states of external plugins need to be saved.
+        if let Some(graph_save_state) = engine_handle.ds_engine.collect_graph_save_state() {
+            project_state.plugin_states = graph_save_state
+                .plugins
+                .into_iter()
+                .filter(|save_state| !matches!(save_state.key.format, PluginFormat::Internal))
+                .collect();
+        }
+
+        match project_file::save_project(project_state, path) {
+            Ok(()) => {
+                log::info!("Saved project to {:?}", path);
+                working_state.project_file_path = Some(path.into());
+            }
+            Err(e) => log::error!("Could not save project to {:?}: {}", path, e),
+        }
+    }
+}
@@ -112,5 +112,18 @@ pub fn handle_track_action(
                 }
             }
         }
+        TrackAction::SetTrackRecordArmed { index, armed } => {
+            if let Some(project_state) = &mut source_state.project {
+                if let Some(track_state) = project_state.tracks.get_mut(*index) {
+                    track_state.record_armed = *armed;
+
+                    if let Some(activated_handles) = &mut engine_handle.activated_handles {
+                        activated_handles
+                            .recorder_plug_handle
+                            .set_armed_tracks(project_state.record_armed_tracks());
+                    }
+                }
+            }
+        }
     }
 }
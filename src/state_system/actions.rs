@@ -1,17 +1,65 @@
 use std::path::PathBuf;
 use vizia::prelude::Entity;
 
+use crate::export::{ExportFormat, ExportSettings};
+
 use super::source_state::{AudioClipCopyableState, BrowserPanelTab, SnapMode, TimelineTool};
 
 #[derive(Debug, Clone)]
 pub enum AppAction {
     _PollEngine,
+    History(HistoryAction),
+    Project(ProjectAction),
     BrowserPanel(BrowserPanelAction),
     Track(TrackAction),
     Timeline(TimelineAction),
+    Export(ExportAction),
     _Internal(InternalAction),
 }
 
+#[derive(Debug, Clone)]
+pub enum HistoryAction {
+    Undo,
+    Redo,
+    /// Group all undoable actions sent until the matching `EndGroup` into a
+    /// single entry in the undo history. Groups may be nested.
+    BeginGroup,
+    EndGroup,
+    /// The user has finished adjusting a value (i.e. let go of a knob), so
+    /// the next change to it gets its own entry in the undo history.
+    EndGesture,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProjectAction {
+    /// Save the project to the file it was last saved to or opened from.
+    Save,
+    /// Save the project to a new file.
+    SaveAs(PathBuf),
+    /// Open the project from the given file, replacing the current project.
+    Open(PathBuf),
+    /// Render the whole project (the output of the master track) to an audio
+    /// file.
+    Export { path: PathBuf, settings: ExportSettings },
+    /// Render the output of each track to a separate audio file in the given
+    /// directory.
+    ExportStems { directory: PathBuf, settings: ExportSettings },
+}
+
+#[derive(Debug, Clone)]
+pub enum ExportAction {
+    ShowDialog(bool),
+    SetFormat(ExportFormat),
+    /// `None` exports at the sample rate of the project.
+    SetSampleRate(Option<u32>),
+    SetDither(bool),
+    /// `None` disables normalization.
+    SetNormalizePeakDb(Option<f32>),
+    /// Stop the export that is in progress, removing the file it was
+    /// exporting.
+    Cancel,
+}
+
 #[derive(Debug, Clone)]
 pub enum BrowserPanelAction {
     SetPanelShown(bool),
@@ -38,6 +86,7 @@ pub enum TrackAction {
     SetTrackHeight { index: usize, height: f32 },
     SetTrackVolumeNormalized { index: usize, volume_normalized: f32 },
     SetTrackPanNormalized { index: usize, pan_normalized: f32 },
+    SetTrackRecordArmed { index: usize, armed: bool },
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,446 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::actions::{AppAction, TimelineAction, TrackAction};
+use super::source_state::{SourceState, TrackType};
+
+/// The maximum number of entries in the undo history. Once this is reached,
+/// the oldest entries are forgotten.
+pub static DEFAULT_MAX_UNDO_ENTRIES: usize = 256;
+
+/// Actions which are further apart than this are never coalesced, even if
+/// they modify the same value (i.e. scrolling the mouse wheel over a knob
+/// twice).
+pub static COALESCE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Identifies the value an undoable action modifies.
+///
+/// Consecutive actions which modify the same value (i.e. the user dragging
+/// a volume knob) are coalesced into a single entry in the undo history,
+/// until the gesture ends, another action is sent, or `COALESCE_TIMEOUT`
+/// has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoalesceKey {
+    MasterTrackVolume,
+    MasterTrackPan,
+    TrackVolume(usize),
+    TrackPan(usize),
+}
+
+/// A single step in the undo history.
+///
+/// An entry can contain multiple actions when edits were grouped together
+/// with `HistoryAction::BeginGroup` and `HistoryAction::EndGroup`.
+#[derive(Debug, Default)]
+pub struct HistoryEntry {
+    /// The actions to re-apply when redoing this entry, in order.
+    pub redo: Vec<AppAction>,
+    /// The actions which undo this entry. These must be applied in
+    /// reverse order.
+    pub undo: Vec<AppAction>,
+}
+
+/// The history of undoable actions applied to the `SourceState`.
+pub struct UndoHistory {
+    undo_stack: VecDeque<HistoryEntry>,
+    redo_stack: Vec<HistoryEntry>,
+
+    max_entries: usize,
+
+    open_group: Option<HistoryEntry>,
+    group_depth: usize,
+
+    last_coalesce_key: Option<CoalesceKey>,
+    last_record_instant: Option<Instant>,
+}
+
+impl UndoHistory {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            max_entries: max_entries.max(1),
+            open_group: None,
+            group_depth: 0,
+            last_coalesce_key: None,
+            last_record_instant: None,
+        }
+    }
+
+    /// Record an action which was just applied, along with the action that
+    /// reverts it.
+    pub fn record(
+        &mut self,
+        action: AppAction,
+        inverse: AppAction,
+        coalesce_key: Option<CoalesceKey>,
+    ) {
+        self.record_at(action, inverse, coalesce_key, Instant::now());
+    }
+
+    fn record_at(
+        &mut self,
+        action: AppAction,
+        inverse: AppAction,
+        coalesce_key: Option<CoalesceKey>,
+        now: Instant,
+    ) {
+        self.redo_stack.clear();
+
+        let timed_out = self
+            .last_record_instant
+            .map(|last| now.saturating_duration_since(last) >= COALESCE_TIMEOUT)
+            .unwrap_or(true);
+        let coalesce =
+            coalesce_key.is_some() && coalesce_key == self.last_coalesce_key && !timed_out;
+        self.last_coalesce_key = coalesce_key;
+        self.last_record_instant = Some(now);
+
+        let entry = if let Some(group) = &mut self.open_group {
+            Some(group)
+        } else if coalesce {
+            self.undo_stack.back_mut()
+        } else {
+            None
+        };
+
+        if let Some(entry) = entry {
+            if coalesce {
+                // Keep the original inverse so that undoing reverts back
+                // to the value from before the first action.
+                if let Some(last) = entry.redo.last_mut() {
+                    *last = action;
+                    return;
+                }
+            }
+
+            entry.redo.push(action);
+            entry.undo.push(inverse);
+        } else {
+            self.push_undo(HistoryEntry { redo: vec![action], undo: vec![inverse] });
+        }
+    }
+
+    /// Stop coalescing actions into the last entry, so that the next action
+    /// gets its own entry (i.e. when the user has let go of a knob).
+    pub fn end_coalescing(&mut self) {
+        self.last_coalesce_key = None;
+    }
+
+    /// Start grouping all recorded actions into a single entry until the
+    /// matching call to `end_group()`. Groups may be nested.
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.open_group = Some(HistoryEntry::default());
+        }
+        self.group_depth += 1;
+        self.last_coalesce_key = None;
+    }
+
+    pub fn end_group(&mut self) {
+        if self.group_depth == 0 {
+            log::warn!("Ignored request to end an undo group: no group is open");
+            return;
+        }
+
+        self.group_depth -= 1;
+        if self.group_depth == 0 {
+            self.close_group();
+            self.last_coalesce_key = None;
+        }
+    }
+
+    /// Take the next entry to undo. Once its actions have been applied,
+    /// hand it back with `push_redo()`.
+    pub fn pop_undo(&mut self) -> Option<HistoryEntry> {
+        // Undoing in the middle of a group closes the group.
+        self.group_depth = 0;
+        self.close_group();
+
+        self.last_coalesce_key = None;
+        self.undo_stack.pop_back()
+    }
+
+    pub fn push_redo(&mut self, entry: HistoryEntry) {
+        self.redo_stack.push(entry);
+    }
+
+    /// Take the next entry to redo. Once its actions have been applied,
+    /// hand it back with `push_undo()`.
+    pub fn pop_redo(&mut self) -> Option<HistoryEntry> {
+        self.last_coalesce_key = None;
+        self.redo_stack.pop()
+    }
+
+    pub fn push_undo(&mut self, entry: HistoryEntry) {
+        if self.undo_stack.len() >= self.max_entries {
+            self.undo_stack.pop_front();
+        }
+        self.undo_stack.push_back(entry);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+            || self.open_group.as_ref().map(|g| !g.redo.is_empty()).unwrap_or(false)
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Forget the whole history (i.e. when opening a different project).
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.open_group = None;
+        self.group_depth = 0;
+        self.last_coalesce_key = None;
+    }
+
+    fn close_group(&mut self) {
+        if let Some(group) = self.open_group.take() {
+            if !group.redo.is_empty() {
+                self.push_undo(group);
+            }
+        }
+    }
+}
+
+/// If the given action is undoable, returns the action which reverts it
+/// (computed from the current state, so this must be called before the
+/// action is applied).
+pub fn inverse_action(
+    action: &AppAction,
+    source_state: &SourceState,
+) -> Option<(AppAction, Option<CoalesceKey>)> {
+    let project_state = source_state.project.as_ref()?;
+
+    match action {
+        AppAction::Track(action) => match action {
+            TrackAction::SetMasterTrackVolumeNormalized(_) => Some((
+                AppAction::Track(TrackAction::SetMasterTrackVolumeNormalized(
+                    project_state.master_track_volume_normalized,
+                )),
+                Some(CoalesceKey::MasterTrackVolume),
+            )),
+            TrackAction::SetMasterTrackPanNormalized(_) => Some((
+                AppAction::Track(TrackAction::SetMasterTrackPanNormalized(
+                    project_state.master_track_pan_normalized,
+                )),
+                Some(CoalesceKey::MasterTrackPan),
+            )),
+            TrackAction::SetTrackVolumeNormalized { index, .. } => {
+                let track_state = project_state.tracks.get(*index)?;
+
+                Some((
+                    AppAction::Track(TrackAction::SetTrackVolumeNormalized {
+                        index: *index,
+                        volume_normalized: track_state.volume_normalized,
+                    }),
+                    Some(CoalesceKey::TrackVolume(*index)),
+                ))
+            }
+            TrackAction::SetTrackPanNormalized { index, .. } => {
+                let track_state = project_state.tracks.get(*index)?;
+
+                Some((
+                    AppAction::Track(TrackAction::SetTrackPanNormalized {
+                        index: *index,
+                        pan_normalized: track_state.pan_normalized,
+                    }),
+                    Some(CoalesceKey::TrackPan(*index)),
+                ))
+            }
+            _ => None,
+        },
+        AppAction::Timeline(action) => match action {
+            TimelineAction::SetLoopActive(_) => Some((
+                AppAction::Timeline(TimelineAction::SetLoopActive(project_state.loop_active)),
+                None,
+            )),
+            // Note that `GestureAudioClipCopyableStates` is not undoable. Only
+            // the final state at the end of the gesture is recorded.
+            TimelineAction::SetAudioClipCopyableStates { track_index, changed_clips } => {
+                let track_state = project_state.tracks.get(*track_index)?;
+
+                if let TrackType::Audio(audio_track_state) = &track_state.type_ {
+                    let old_clips = changed_clips
+                        .iter()
+                        .filter_map(|(clip_index, _)| {
+                            audio_track_state
+                                .clips
+                                .get(*clip_index)
+                                .map(|clip_state| (*clip_index, clip_state.copyable))
+                        })
+                        .collect();
+
+                    Some((
+                        AppAction::Timeline(TimelineAction::SetAudioClipCopyableStates {
+                            track_index: *track_index,
+                            changed_clips: old_clips,
+                        }),
+                        None,
+                    ))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master_volume(volume: f32) -> AppAction {
+        AppAction::Track(TrackAction::SetMasterTrackVolumeNormalized(volume))
+    }
+
+    fn track_pan(index: usize, pan_normalized: f32) -> AppAction {
+        AppAction::Track(TrackAction::SetTrackPanNormalized { index, pan_normalized })
+    }
+
+    /// The volumes set by the master volume actions in the given list.
+    fn volumes(actions: &[AppAction]) -> Vec<f32> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                AppAction::Track(TrackAction::SetMasterTrackVolumeNormalized(v)) => Some(*v),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn undo_and_redo_move_entries_between_stacks() {
+        let mut history = UndoHistory::new(DEFAULT_MAX_UNDO_ENTRIES);
+        assert!(!history.can_undo());
+
+        history.record(master_volume(0.5), master_volume(1.0), None);
+        history.record(master_volume(0.25), master_volume(0.5), None);
+
+        let entry = history.pop_undo().unwrap();
+        assert_eq!(volumes(&entry.undo), vec![0.5]);
+        history.push_redo(entry);
+        assert!(history.can_redo());
+
+        let entry = history.pop_redo().unwrap();
+        assert_eq!(volumes(&entry.redo), vec![0.25]);
+        history.push_undo(entry);
+
+        // Recording a new action discards everything that could be redone.
+        let entry = history.pop_undo().unwrap();
+        history.push_redo(entry);
+        history.record(master_volume(0.75), master_volume(0.5), None);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn actions_on_the_same_value_are_coalesced() {
+        let mut history = UndoHistory::new(DEFAULT_MAX_UNDO_ENTRIES);
+
+        let key = Some(CoalesceKey::MasterTrackVolume);
+        history.record(master_volume(0.9), master_volume(1.0), key);
+        history.record(master_volume(0.8), master_volume(0.9), key);
+        history.record(master_volume(0.7), master_volume(0.8), key);
+
+        // Undoing reverts back to the value from before the first action,
+        // and redoing applies the last value.
+        let entry = history.pop_undo().unwrap();
+        assert_eq!(volumes(&entry.undo), vec![1.0]);
+        assert_eq!(volumes(&entry.redo), vec![0.7]);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn actions_on_different_values_are_not_coalesced() {
+        let mut history = UndoHistory::new(DEFAULT_MAX_UNDO_ENTRIES);
+
+        history.record(track_pan(0, 0.25), track_pan(0, 0.5), Some(CoalesceKey::TrackPan(0)));
+        history.record(track_pan(1, 0.25), track_pan(1, 0.5), Some(CoalesceKey::TrackPan(1)));
+
+        assert_eq!(history.pop_undo().unwrap().redo.len(), 1);
+        assert_eq!(history.pop_undo().unwrap().redo.len(), 1);
+        assert!(history.pop_undo().is_none());
+    }
+
+    #[test]
+    fn groups_are_a_single_entry() {
+        let mut history = UndoHistory::new(DEFAULT_MAX_UNDO_ENTRIES);
+
+        history.begin_group();
+        history.record(master_volume(0.5), master_volume(1.0), None);
+        history.begin_group();
+        history.record(master_volume(0.25), master_volume(0.5), None);
+        history.end_group();
+        assert!(history.can_undo());
+        history.record(master_volume(0.125), master_volume(0.25), None);
+        history.end_group();
+
+        let entry = history.pop_undo().unwrap();
+        assert_eq!(volumes(&entry.redo), vec![0.5, 0.25, 0.125]);
+        assert_eq!(volumes(&entry.undo), vec![1.0, 0.5, 0.25]);
+        assert!(history.pop_undo().is_none());
+
+        // Empty groups don't add an entry.
+        history.begin_group();
+        history.end_group();
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn oldest_entries_are_forgotten() {
+        let mut history = UndoHistory::new(2);
+
+        history.record(master_volume(0.5), master_volume(1.0), None);
+        history.record(master_volume(0.25), master_volume(0.5), None);
+        history.record(master_volume(0.125), master_volume(0.25), None);
+
+        assert_eq!(volumes(&history.pop_undo().unwrap().undo), vec![0.25]);
+        assert_eq!(volumes(&history.pop_undo().unwrap().undo), vec![0.5]);
+        assert!(history.pop_undo().is_none());
+    }
+
+    #[test]
+    fn coalescing_ends_with_the_gesture() {
+        let mut history = UndoHistory::new(DEFAULT_MAX_UNDO_ENTRIES);
+
+        let key = Some(CoalesceKey::MasterTrackVolume);
+        history.record(master_volume(0.9), master_volume(1.0), key);
+        history.record(master_volume(0.8), master_volume(0.9), key);
+        history.end_coalescing();
+        history.record(master_volume(0.7), master_volume(0.8), key);
+
+        assert_eq!(volumes(&history.pop_undo().unwrap().undo), vec![0.8]);
+        assert_eq!(volumes(&history.pop_undo().unwrap().undo), vec![1.0]);
+        assert!(history.pop_undo().is_none());
+    }
+
+    #[test]
+    fn coalescing_ends_after_timeout() {
+        let mut history = UndoHistory::new(DEFAULT_MAX_UNDO_ENTRIES);
+
+        let key = Some(CoalesceKey::MasterTrackVolume);
+        let start = Instant::now();
+        history.record_at(master_volume(0.9), master_volume(1.0), key, start);
+        history.record_at(
+            master_volume(0.8),
+            master_volume(0.9),
+            key,
+            start + COALESCE_TIMEOUT / 2,
+        );
+        history.record_at(
+            master_volume(0.7),
+            master_volume(0.8),
+            key,
+            start + COALESCE_TIMEOUT / 2 + COALESCE_TIMEOUT,
+        );
+
+        assert_eq!(volumes(&history.pop_undo().unwrap().undo), vec![0.8]);
+        assert_eq!(volumes(&history.pop_undo().unwrap().undo), vec![1.0]);
+        assert!(history.pop_undo().is_none());
+    }
+}
@@ -2,20 +2,27 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use vizia::prelude::*;
 
+use crate::config_file;
 use crate::engine_handle::EngineHandle;
 use crate::ui::panels::timeline_panel::TimelineViewWorkingState;
 
 mod action_handler;
 pub mod actions;
+pub mod history;
 pub mod source_state;
 pub mod time;
 pub mod working_state;
 
-pub use actions::{AppAction, BrowserPanelAction, TimelineAction, TrackAction};
+pub use actions::{
+    AppAction, BrowserPanelAction, ExportAction, HistoryAction, ProjectAction, TimelineAction,
+    TrackAction,
+};
+pub use history::UndoHistory;
 pub use source_state::SourceState;
 pub use working_state::WorkingState;
 
 use self::actions::InternalAction;
+use self::history::DEFAULT_MAX_UNDO_ENTRIES;
 
 /// The `StateSystem` struct is in charge of listening to `Action`s sent from sources
 /// such as UI views and scripts, and then mutating state and manipulating the backend
@@ -39,22 +46,45 @@ pub struct StateSystem {
     #[lens(ignore)]
     pub engine_handle: EngineHandle,
 
+    #[lens(ignore)]
+    pub history: UndoHistory,
+
     pub working_state: WorkingState,
 }
 
 impl StateSystem {
     pub fn new(shared_timeline_view_state: Rc<RefCell<TimelineViewWorkingState>>) -> Self {
-        let source_state = SourceState::test_project();
+        let mut source_state = SourceState::test_project();
+        source_state.app = config_file::load_app_state_or_default();
 
         let engine_handle = EngineHandle::new(&source_state);
         let working_state = WorkingState::new(&source_state, shared_timeline_view_state);
 
-        Self { source_state, working_state, engine_handle }
+        Self {
+            source_state,
+            working_state,
+            engine_handle,
+            history: UndoHistory::new(DEFAULT_MAX_UNDO_ENTRIES),
+        }
     }
 }
 
 impl Model for StateSystem {
     fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, _| match window_event {
+            WindowEvent::WindowClose => {
+                if let Err(e) = config_file::save_app_state(&self.source_state.app) {
+                    log::error!("Could not save config file: {}", e);
+                }
+            }
+            WindowEvent::KeyDown(code, _) => {
+                if let Some(action) = history_shortcut(*code, cx.modifiers) {
+                    cx.emit(AppAction::History(action));
+                }
+            }
+            _ => {}
+        });
+
         event.map(|action, _| {
             handle_action(
                 action,
@@ -62,22 +92,133 @@ impl Model for StateSystem {
                 &mut self.source_state,
                 &mut self.working_state,
                 &mut self.engine_handle,
+                &mut self.history,
             )
         });
     }
 }
 
+/// The keyboard shortcuts for undo (Ctrl+Z) and redo (Ctrl+Shift+Z or
+/// Ctrl+Y). Cmd is used instead of Ctrl on macOS.
+fn history_shortcut(code: Code, modifiers: Modifiers) -> Option<HistoryAction> {
+    #[cfg(target_os = "macos")]
+    let command = Modifiers::LOGO;
+    #[cfg(not(target_os = "macos"))]
+    let command = Modifiers::CTRL;
+
+    if !modifiers.contains(command) {
+        return None;
+    }
+
+    match code {
+        Code::KeyZ if modifiers.contains(Modifiers::SHIFT) => Some(HistoryAction::Redo),
+        Code::KeyZ => Some(HistoryAction::Undo),
+        Code::KeyY => Some(HistoryAction::Redo),
+        _ => None,
+    }
+}
+
 pub fn handle_action(
     action: &AppAction,
     cx: &mut EventContext,
     source_state: &mut SourceState,
     working_state: &mut WorkingState,
     engine_handle: &mut EngineHandle,
+    history: &mut UndoHistory,
 ) {
+    // The engine is busy rendering while an export is in progress, so only
+    // allow actions that don't modify the project until it is done.
+    if working_state.export_job.is_some()
+        && !matches!(
+            action,
+            AppAction::_PollEngine
+                | AppAction::Export(_)
+                | AppAction::_Internal(_)
+                | AppAction::Timeline(TimelineAction::Navigate { .. })
+        )
+    {
+        log::debug!("Ignored action while exporting: {:?}", action);
+        return;
+    }
+
     match action {
+        AppAction::History(action) => match action {
+            HistoryAction::Undo => {
+                if let Some(entry) = history.pop_undo() {
+                    for action in entry.undo.iter().rev() {
+                        apply_action(action, cx, source_state, working_state, engine_handle);
+                    }
+                    history.push_redo(entry);
+                }
+            }
+            HistoryAction::Redo => {
+                if let Some(entry) = history.pop_redo() {
+                    for action in entry.redo.iter() {
+                        apply_action(action, cx, source_state, working_state, engine_handle);
+                    }
+                    history.push_undo(entry);
+                }
+            }
+            HistoryAction::BeginGroup => history.begin_group(),
+            HistoryAction::EndGroup => history.end_group(),
+            HistoryAction::EndGesture => history.end_coalescing(),
+        },
+        AppAction::Project(ProjectAction::Open(path)) => {
+            match action_handler::open_project(path, cx, source_state, working_state, engine_handle)
+            {
+                // The history belongs to the previous project.
+                Ok(()) => history.clear(),
+                Err(e) => log::error!("Could not open project {:?}: {}", path, e),
+            }
+        }
+        action => {
+            // The inverse must be computed before the action modifies the state.
+            let inverse = history::inverse_action(action, source_state);
+
+            apply_action(action, cx, source_state, working_state, engine_handle);
+
+            if let Some((inverse, coalesce_key)) = inverse {
+                history.record(action.clone(), inverse, coalesce_key);
+            } else if !matches!(action, AppAction::_PollEngine) {
+                // Any other action (i.e. selecting a track) ends the previous
+                // edit, so the next edit gets its own entry.
+                history.end_coalescing();
+            }
+        }
+    }
+}
+
+/// Apply the action without recording it in the undo history.
+fn apply_action(
+    action: &AppAction,
+    cx: &mut EventContext,
+    source_state: &mut SourceState,
+    working_state: &mut WorkingState,
+    engine_handle: &mut EngineHandle,
+) {
+    match action {
+        AppAction::History(_) => {}
         AppAction::_PollEngine => {
             action_handler::poll_engine(cx, source_state, working_state, engine_handle);
         }
+        AppAction::Project(action) => {
+            action_handler::handle_project_action(
+                action,
+                cx,
+                source_state,
+                working_state,
+                engine_handle,
+            );
+        }
+        AppAction::Export(action) => {
+            action_handler::handle_export_action(
+                action,
+                cx,
+                source_state,
+                working_state,
+                engine_handle,
+            );
+        }
         AppAction::BrowserPanel(action) => {
             action_handler::handle_browser_panel_action(
                 action,
@@ -1,5 +1,21 @@
+use std::path::PathBuf;
 use vizia::prelude::Data;
 
+use crate::state_system::time::{MusicalTime, TempoMap};
+
+/// The default minimum number of frames processed in a single process cycle.
+pub static DEFAULT_MIN_FRAMES: u32 = 1;
+/// The default maximum number of frames processed in a single process cycle.
+pub static DEFAULT_MAX_FRAMES: u32 = 512;
+
+/// The directory where recorded takes are written to by default, inside the
+/// platform's data directory.
+pub fn default_recordings_directory() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("meadowlark").join("recordings"))
+        .unwrap_or_else(|| PathBuf::from("./recordings"))
+}
+
 /// This struct contains all of the non-project-related state such as
 /// panel sizes, which panels are open, etc.
 ///
@@ -12,6 +28,18 @@ pub struct AppState {
     pub selected_timeline_tool: TimelineTool,
     pub timeline_snap_active: bool,
     pub timeline_snap_mode: SnapMode,
+
+    /// The graph input channels that get recorded onto armed tracks, in
+    /// the order (left, right).
+    pub record_input_channels: [u16; 2],
+    /// The directory where recorded takes are written to.
+    pub recordings_directory: PathBuf,
+
+    pub audio: AudioSettings,
+
+    /// Extra directories to scan for CLAP plugins, in addition to the
+    /// standard CLAP directories of the platform.
+    pub clap_scan_directories: Vec<PathBuf>,
 }
 
 impl AppState {
@@ -24,10 +52,20 @@ impl AppState {
                 volume_normalized: 1.0,
                 volume_default_normalized: 1.0,
                 playback_on_select: true,
+                root_sample_directories: vec!["./assets/test_files".into()],
             },
             selected_timeline_tool: TimelineTool::Pointer,
             timeline_snap_active: true,
             timeline_snap_mode: SnapMode::Line,
+            record_input_channels: [0, 1],
+            recordings_directory: default_recordings_directory(),
+            audio: AudioSettings {
+                out_device_name: None,
+                in_device_name: None,
+                min_frames: DEFAULT_MIN_FRAMES,
+                max_frames: DEFAULT_MAX_FRAMES,
+            },
+            clap_scan_directories: Vec::new(),
         }
     }
 }
@@ -52,6 +90,25 @@ pub struct BrowserPanelState {
     pub volume_normalized: f32,
     pub volume_default_normalized: f32,
     pub playback_on_select: bool,
+    /// The directories shown at the root of the "Samples" tab.
+    pub root_sample_directories: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioSettings {
+    /// The name of the audio output device. If this is `None` or the device
+    /// could not be found, then the default output device is used.
+    pub out_device_name: Option<String>,
+    /// The name of the audio input device. If this is `None` or the device
+    /// could not be found, then the default input device is used.
+    pub in_device_name: Option<String>,
+
+    /// The minimum number of frames (samples in a single audio channel)
+    /// that can be processed in a single process cycle.
+    pub min_frames: u32,
+    /// The maximum number of frames (samples in a single audio channel)
+    /// that can be processed in a single process cycle.
+    pub max_frames: u32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Data)]
@@ -64,28 +121,108 @@ pub enum TimelineTool {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Data)]
 pub enum SnapMode {
+    /// Snap to the finest grid line currently drawn in the timeline.
     Line,
+    Bar,
     Beat,
     HalfBeat,
     ThirdBeat,
     QuarterBeat,
+    SixthBeat,
     EigthBeat,
+    TwelfthBeat,
     SixteenthBeat,
     _32ndBeat,
-    // TODO: More
+    DottedBeat,
+    DottedHalfBeat,
+    DottedQuarterBeat,
 }
 
 impl SnapMode {
     pub fn to_text(&self) -> &'static str {
         match self {
             SnapMode::Line => "Line",
+            SnapMode::Bar => "Bar",
             SnapMode::Beat => "Beat",
             SnapMode::HalfBeat => "1/2 Beat",
             SnapMode::ThirdBeat => "1/3 Beat",
             SnapMode::QuarterBeat => "1/4 Beat",
+            SnapMode::SixthBeat => "1/6 Beat",
             SnapMode::EigthBeat => "1/8 Beat",
+            SnapMode::TwelfthBeat => "1/12 Beat",
             SnapMode::SixteenthBeat => "1/16 Beat",
             SnapMode::_32ndBeat => "1/32 Beat",
+            SnapMode::DottedBeat => "Dotted Beat",
+            SnapMode::DottedHalfBeat => "Dotted 1/2 Beat",
+            SnapMode::DottedQuarterBeat => "Dotted 1/4 Beat",
         }
     }
+
+    /// The length of a single step of this snap mode as a fraction
+    /// `(numerator, denominator)` of a beat, where a "beat" is the beat of
+    /// the time signature (i.e. an eighth note in 6/8).
+    ///
+    /// This returns `None` for `SnapMode::Line` and `SnapMode::Bar`.
+    pub fn beat_fraction(&self) -> Option<(u32, u32)> {
+        match self {
+            SnapMode::Line | SnapMode::Bar => None,
+            SnapMode::Beat => Some((1, 1)),
+            SnapMode::HalfBeat => Some((1, 2)),
+            SnapMode::ThirdBeat => Some((1, 3)),
+            SnapMode::QuarterBeat => Some((1, 4)),
+            SnapMode::SixthBeat => Some((1, 6)),
+            SnapMode::EigthBeat => Some((1, 8)),
+            SnapMode::TwelfthBeat => Some((1, 12)),
+            SnapMode::SixteenthBeat => Some((1, 16)),
+            SnapMode::_32ndBeat => Some((1, 32)),
+            SnapMode::DottedBeat => Some((3, 2)),
+            SnapMode::DottedHalfBeat => Some((3, 4)),
+            SnapMode::DottedQuarterBeat => Some((3, 8)),
+        }
+    }
+
+    /// Snap the given musical time to the grid of this snap mode.
+    ///
+    /// `SnapMode::Line` must be resolved into a different mode first (see
+    /// `TimelineViewWorkingState::snap_musical_time`). If it is not, then it
+    /// snaps to the nearest beat.
+    pub fn snap(&self, musical_time: MusicalTime, tempo_map: &TempoMap) -> MusicalTime {
+        match self {
+            SnapMode::Bar => tempo_map.snap_to_nearest_bar(musical_time),
+            _ => {
+                let (numerator, denominator) = self.beat_fraction().unwrap_or((1, 1));
+                tempo_map.snap_to_nearest_beat_fraction(musical_time, numerator, denominator)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapping_follows_the_time_signature() {
+        let tempo_map = TempoMap::new(120.0, 7, 8, 48_000);
+
+        // A dotted beat in 7/8 is three sixteenth notes long.
+        let snap =
+            |beats: f64| SnapMode::DottedBeat.snap(MusicalTime::from_beats_f64(beats), &tempo_map);
+        assert_eq!(snap(0.8), MusicalTime::from_quarter_beats(0, 3));
+
+        // The last step of a bar is cut short by the end of the bar.
+        assert_eq!(snap(3.4), MusicalTime::from_half_beats(3, 1));
+
+        // The grid starts over at the start of every bar.
+        assert_eq!(snap(4.1), MusicalTime::from_quarter_beats(4, 1));
+
+        assert_eq!(
+            SnapMode::Bar.snap(MusicalTime::from_beats(5), &tempo_map),
+            MusicalTime::from_half_beats(3, 1)
+        );
+        assert_eq!(
+            SnapMode::SixteenthBeat.snap(MusicalTime::from_beats_f64(0.51), &tempo_map),
+            MusicalTime::from_half_beats(0, 1)
+        );
+    }
 }
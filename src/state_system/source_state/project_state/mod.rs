@@ -2,6 +2,7 @@ use crate::state_system::time::{MusicalTime, SuperclockTime, TempoMap, Timestamp
 use crate::{
     resource::PcmKey, ui::panels::timeline_panel::track_header_view::DEFAULT_TRACK_HEADER_HEIGHT,
 };
+use meadowlark_engine::plugin_host::PluginHostSaveState;
 
 pub mod palette;
 pub mod project_track_state;
@@ -43,9 +44,26 @@ pub struct ProjectState {
     pub playhead_last_seeked: Timestamp,
 
     pub tempo_map: TempoMap,
+
+    /// The save states of the external plugins in this project.
+    ///
+    /// This is refreshed from the engine every time the project is saved.
+    /// (Internal plugins such as the timeline tracks are constructed from
+    /// the rest of the project state instead.)
+    pub plugin_states: Vec<PluginHostSaveState>,
 }
 
 impl ProjectState {
+    /// The indices of the tracks which will receive new takes when recording.
+    pub fn record_armed_tracks(&self) -> Vec<usize> {
+        self.tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, track_state)| track_state.record_armed)
+            .map(|(track_index, _)| track_index)
+            .collect()
+    }
+
     pub fn test_project() -> Self {
         Self {
             master_track_color: PaletteColor::Unassigned,
@@ -61,6 +79,7 @@ impl ProjectState {
                     volume_normalized: 1.0,
                     pan_normalized: 0.5,
                     routed_to: TrackRouteType::ToMaster,
+                    record_armed: false,
                     type_: TrackType::Audio(ProjectAudioTrackState {
                         clips: vec![AudioClipState {
                             name: "Spicy Synth #1".into(),
@@ -91,6 +110,7 @@ impl ProjectState {
                     volume_normalized: 1.0,
                     pan_normalized: 0.5,
                     routed_to: TrackRouteType::ToMaster,
+                    record_armed: false,
                     type_: TrackType::Audio(ProjectAudioTrackState {
                         clips: vec![
                             AudioClipState {
@@ -148,6 +168,8 @@ impl ProjectState {
             playhead_last_seeked: Timestamp::Musical(MusicalTime::from_beats(0)),
 
             tempo_map: TempoMap::default(),
+
+            plugin_states: Vec::new(),
         }
     }
 }
@@ -21,6 +21,8 @@ pub struct ProjectTrackState {
     pub pan_normalized: f32,
 
     pub routed_to: TrackRouteType,
+    /// Whether or not this track will receive new takes when recording.
+    pub record_armed: bool,
     //pub parent_track_index: Option<usize>, // TODO
     pub type_: TrackType,
 }
@@ -44,7 +46,7 @@ pub struct AudioClipState {
     pub copyable: AudioClipCopyableState,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AudioClipCopyableState {
     pub timeline_start: Timestamp,
 
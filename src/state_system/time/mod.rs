@@ -9,7 +9,7 @@ pub use frame_time::FrameTime;
 pub use musical_time::{MusicalTime, SUPER_BEAT_TICKS_PER_BEAT};
 pub use seconds::SecondsF64;
 pub use superclock_time::{SuperclockTime, SUPER_SAMPLE_TICKS_PER_SECOND};
-pub use tempo_map::TempoMap;
+pub use tempo_map::{Bar, BarIter, MeterChange, TempoMap, TempoPoint, TempoRamp};
 //pub use video_timecode::{VideoFpsFormat, VideoTimecode};
 
 /// A reliable timestamp for events on the timeline.
@@ -73,6 +73,24 @@ impl MusicalTime {
         (u64::from(self.beats) * u64::from(SUPER_BEAT_TICKS_PER_BEAT)) + u64::from(self.ticks)
     }
 
+    /// Get the musical time from the total number of ticks (the inverse of
+    /// `MusicalTime::total_ticks()`).
+    ///
+    /// If the number of beats does not fit in a `u32`, then this will be
+    /// clamped to the latest representable time.
+    pub fn from_total_ticks(total_ticks: u64) -> Self {
+        let beats = total_ticks / u64::from(SUPER_BEAT_TICKS_PER_BEAT);
+
+        if beats > u64::from(u32::MAX) {
+            Self { beats: u32::MAX, ticks: SUPER_BEAT_TICKS_PER_BEAT - 1 }
+        } else {
+            Self {
+                beats: beats as u32,
+                ticks: (total_ticks % u64::from(SUPER_BEAT_TICKS_PER_BEAT)) as u32,
+            }
+        }
+    }
+
     /// * `beats` - The time in musical beats.
     pub fn from_beats(beats: u32) -> Self {
         Self { beats, ticks: 0 }
@@ -1,53 +1,139 @@
-use super::{FrameTime, MusicalTime, SecondsF64, Timestamp};
+use super::{
+    FrameTime, MusicalTime, SecondsF64, SuperclockTime, Timestamp, SUPER_BEAT_TICKS_PER_BEAT,
+};
 use meadowlark_engine::engine::{EngineTempoMap, TransportInfoAtFrame};
 use meadowlark_plugin_api::{BeatTime, SecondsTime};
 
-// TODO: Make tempo map work like series of automation lines/curves between points in time.
+/// How the tempo moves from a `TempoPoint` to the next tempo point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempoRamp {
+    /// The tempo stays constant until the next tempo point, where it jumps
+    /// to the new tempo.
+    Step,
+    /// The tempo changes linearly over time until it reaches the tempo of
+    /// the next tempo point.
+    ///
+    /// If this is the last tempo point, then this behaves the same as
+    /// `TempoRamp::Step`.
+    Linear,
+}
+
+/// A change in tempo at a point in musical time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoPoint {
+    pub time: MusicalTime,
+    pub bpm: f64,
+    pub ramp: TempoRamp,
+}
+
+/// A change in time signature at a point in musical time.
+///
+/// Meter changes should land on the start of a bar. If one does not, then
+/// the bar it lands in is cut short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeterChange {
+    pub time: MusicalTime,
+    pub tsig_num: u16,
+    pub tsig_denom: u16,
+}
 
 /// A map of all tempo changes in the current project. Used to convert timestamps
 /// to/from time in frames.
+///
+/// The tempo map is made of a list of `TempoPoint`s and a list of
+/// `MeterChange`s. Both lists always start at musical time `0`.
+///
+/// Conversions are calculated in closed form from the start of the tempo
+/// segment they land in, so errors do not accumulate over the length of the
+/// project:
+/// * Between two tempo points with `TempoRamp::Step`, the tempo is constant.
+/// * Between two tempo points with `TempoRamp::Linear`, the tempo changes
+/// linearly over time (in seconds). This means the number of beats elapsed
+/// is a quadratic function of time.
 #[derive(Debug, Clone)]
 pub struct TempoMap {
-    /// Temporary static tempo
-    bpm: f64,
-    beats_per_second: f64,
-    seconds_per_beat: f64,
+    tempo_points: Vec<TempoPoint>,
+    meter_changes: Vec<MeterChange>,
 
-    /// Temporary static time signature
-    tsig_num: u16,
-    tsig_denom: u16,
+    tempo_segments: Vec<TempoSegment>,
+    meter_segments: Vec<MeterSegment>,
 
     sample_rate: u32,
     sample_rate_u64: u64,
     sample_rate_recip: f64,
 }
 
+/// The cached state at the start of a tempo point.
+#[derive(Debug, Clone, Copy)]
+struct TempoSegment {
+    start_ticks: u64,
+    start_seconds: f64,
+    start_bpm: f64,
+    /// The rate of change of the tempo, in BPM per second.
+    bpm_per_second: f64,
+}
+
+/// The cached state at the start of a meter change.
+#[derive(Debug, Clone, Copy)]
+struct MeterSegment {
+    start_ticks: u64,
+    first_bar_number: u64,
+    ticks_per_bar: u64,
+    tsig_num: u16,
+    tsig_denom: u16,
+}
+
 impl TempoMap {
-    /// Temporary static tempo and time signature
+    /// Create a tempo map with a single static tempo and time signature.
     pub fn new(bpm: f64, tsig_num: u16, tsig_denom: u16, sample_rate: u32) -> Self {
+        Self::from_points(
+            vec![TempoPoint { time: MusicalTime::default(), bpm, ramp: TempoRamp::Step }],
+            vec![MeterChange { time: MusicalTime::default(), tsig_num, tsig_denom }],
+            sample_rate,
+        )
+    }
+
+    /// Create a tempo map from a list of tempo points and a list of meter
+    /// changes.
+    ///
+    /// The lists do not need to be sorted. If a list does not contain a point
+    /// at musical time `0`, then its earliest point is moved to the start.
+    /// If multiple points land on the same time, only the last one is kept.
+    ///
+    /// # Panics
+    /// This will panic if either list is empty, if any tempo is not greater
+    /// than `0.0`, or if any time signature has a `0` in it.
+    pub fn from_points(
+        tempo_points: Vec<TempoPoint>,
+        meter_changes: Vec<MeterChange>,
+        sample_rate: u32,
+    ) -> Self {
         assert_ne!(sample_rate, 0);
-        assert_ne!(bpm, 0.0);
-        assert_ne!(tsig_num, 0);
-        assert_ne!(tsig_denom, 0);
-
-        TempoMap {
-            bpm,
-            beats_per_second: bpm / 60.0,
-            seconds_per_beat: 60.0 / bpm,
-            tsig_num,
-            tsig_denom,
-            sample_rate: sample_rate.into(),
+
+        let mut new_self = Self {
+            tempo_points: Vec::new(),
+            meter_changes: Vec::new(),
+            tempo_segments: Vec::new(),
+            meter_segments: Vec::new(),
+            sample_rate,
             sample_rate_u64: u64::from(sample_rate),
             sample_rate_recip: 1.0 / f64::from(sample_rate),
-        }
+        };
+
+        new_self.set_tempo_points(tempo_points);
+        new_self.set_meter_changes(meter_changes);
+
+        new_self
     }
 
-    pub fn bpm(&self) -> f64 {
-        self.bpm
+    /// The tempo points in this map, sorted by time.
+    pub fn tempo_points(&self) -> &[TempoPoint] {
+        &self.tempo_points
     }
 
-    pub fn beats_per_second(&self) -> f64 {
-        self.beats_per_second
+    /// The meter changes in this map, sorted by time.
+    pub fn meter_changes(&self) -> &[MeterChange] {
+        &self.meter_changes
     }
 
     pub fn sample_rate(&self) -> u32 {
@@ -58,28 +144,194 @@ impl TempoMap {
         self.sample_rate_recip
     }
 
-    pub fn set_bpm(&mut self, bpm: f64) {
-        assert_ne!(bpm, 0.0);
+    /// Replace all of the tempo points in this map.
+    ///
+    /// See `TempoMap::from_points()` for how the list is sanitized.
+    pub fn set_tempo_points(&mut self, mut tempo_points: Vec<TempoPoint>) {
+        assert!(!tempo_points.is_empty());
+        for point in tempo_points.iter() {
+            assert!(point.bpm > 0.0);
+        }
+
+        // A stable sort keeps points on the same time in the order they were
+        // given, so the last one wins below.
+        tempo_points.sort_by_key(|p| p.time);
+        tempo_points.reverse();
+        tempo_points.dedup_by_key(|p| p.time);
+        tempo_points.reverse();
+        tempo_points[0].time = MusicalTime::default();
+
+        self.tempo_points = tempo_points;
+        self.compute_tempo_segments();
+    }
+
+    /// Replace all of the meter changes in this map.
+    ///
+    /// See `TempoMap::from_points()` for how the list is sanitized.
+    pub fn set_meter_changes(&mut self, mut meter_changes: Vec<MeterChange>) {
+        assert!(!meter_changes.is_empty());
+        for meter in meter_changes.iter() {
+            assert_ne!(meter.tsig_num, 0);
+            assert_ne!(meter.tsig_denom, 0);
+        }
+
+        meter_changes.sort_by_key(|m| m.time);
+        meter_changes.reverse();
+        meter_changes.dedup_by_key(|m| m.time);
+        meter_changes.reverse();
+        meter_changes[0].time = MusicalTime::default();
+
+        self.meter_changes = meter_changes;
+        self.compute_meter_segments();
+    }
+
+    /// Add a tempo point, replacing any existing tempo point at the same time.
+    pub fn insert_tempo_point(&mut self, point: TempoPoint) {
+        let mut tempo_points = self.tempo_points.clone();
+        tempo_points.push(point);
+        self.set_tempo_points(tempo_points);
+    }
+
+    /// Remove the tempo point at the given index.
+    ///
+    /// The first tempo point cannot be removed. This returns `false` if the
+    /// tempo point was not removed.
+    pub fn remove_tempo_point(&mut self, index: usize) -> bool {
+        if index == 0 || index >= self.tempo_points.len() {
+            return false;
+        }
+
+        self.tempo_points.remove(index);
+        self.compute_tempo_segments();
+        true
+    }
+
+    /// Add a meter change, replacing any existing meter change at the same
+    /// time.
+    pub fn insert_meter_change(&mut self, meter: MeterChange) {
+        let mut meter_changes = self.meter_changes.clone();
+        meter_changes.push(meter);
+        self.set_meter_changes(meter_changes);
+    }
+
+    /// Remove the meter change at the given index.
+    ///
+    /// The first meter change cannot be removed. This returns `false` if the
+    /// meter change was not removed.
+    pub fn remove_meter_change(&mut self, index: usize) -> bool {
+        if index == 0 || index >= self.meter_changes.len() {
+            return false;
+        }
 
-        self.bpm = bpm;
-        self.beats_per_second = bpm / 60.0;
-        self.seconds_per_beat = 60.0 / bpm;
+        self.meter_changes.remove(index);
+        self.compute_meter_segments();
+        true
     }
 
     pub fn set_sample_rate(&mut self, sample_rate: u32) {
         assert_ne!(sample_rate, 0);
 
-        self.sample_rate = sample_rate.into();
+        self.sample_rate = sample_rate;
         self.sample_rate_u64 = u64::from(sample_rate);
         self.sample_rate_recip = 1.0 / f64::from(sample_rate);
     }
 
-    pub fn set_tsig(&mut self, tsig_num: u16, tsig_denom: u16) {
-        assert_ne!(tsig_num, 0);
-        assert_ne!(tsig_denom, 0);
+    /// The tempo (in beats per minute) at the given musical time.
+    pub fn bpm_at_musical_time(&self, musical_time: MusicalTime) -> f64 {
+        self.bpm_at_seconds(self.musical_to_seconds(musical_time))
+    }
+
+    /// The tempo (in beats per minute) at the given frame.
+    pub fn bpm_at_frame(&self, frame: FrameTime) -> f64 {
+        self.bpm_at_seconds(self.frame_to_seconds_f64(frame))
+    }
+
+    /// The time signature as (numerator, denominator) at the given musical
+    /// time.
+    pub fn tsig_at_musical_time(&self, musical_time: MusicalTime) -> (u16, u16) {
+        let segment = &self.meter_segments[self.meter_segment_index(musical_time.total_ticks())];
+        (segment.tsig_num, segment.tsig_denom)
+    }
+
+    /// The (zero-based) number of the bar the given musical time lands in,
+    /// along with the musical time at the start of that bar.
+    pub fn bar_at_musical_time(&self, musical_time: MusicalTime) -> (u64, MusicalTime) {
+        let ticks = musical_time.total_ticks();
+        let segment = &self.meter_segments[self.meter_segment_index(ticks)];
+
+        let bars_into_segment = (ticks - segment.start_ticks) / segment.ticks_per_bar;
+
+        (
+            segment.first_bar_number + bars_into_segment,
+            MusicalTime::from_total_ticks(
+                segment.start_ticks + (bars_into_segment * segment.ticks_per_bar),
+            ),
+        )
+    }
+
+    /// Iterate over all bars on the timeline, starting with the bar with the
+    /// given (zero-based) number.
+    ///
+    /// The returned iterator never ends.
+    pub fn bars_from(&self, first_bar_number: u64) -> BarIter<'_> {
+        let segment_index =
+            self.meter_segments.partition_point(|s| s.first_bar_number <= first_bar_number) - 1;
+        let segment = &self.meter_segments[segment_index];
+
+        BarIter {
+            tempo_map: self,
+            segment_index,
+            bar_number: first_bar_number,
+            bar_start_ticks: segment.start_ticks
+                + ((first_bar_number - segment.first_bar_number) * segment.ticks_per_bar),
+        }
+    }
+
+    /// Snap the given musical time to the start of the nearest bar.
+    pub fn snap_to_nearest_bar(&self, musical_time: MusicalTime) -> MusicalTime {
+        let (bar_number, _) = self.bar_at_musical_time(musical_time);
+        let bar = self.bars_from(bar_number).next().unwrap();
+
+        if musical_time.total_ticks() - bar.start.total_ticks()
+            < bar.end.total_ticks() - musical_time.total_ticks()
+        {
+            bar.start
+        } else {
+            bar.end
+        }
+    }
 
-        self.tsig_num = tsig_num;
-        self.tsig_denom = tsig_denom;
+    /// Snap the given musical time to the nearest multiple of
+    /// `numerator / denominator` beats, where a "beat" is the beat of the
+    /// time signature at that time (i.e. an eighth note in 6/8).
+    ///
+    /// The grid starts over at the start of every bar, so the grid always
+    /// lines up with bars that have an odd length (i.e. 7/8).
+    pub fn snap_to_nearest_beat_fraction(
+        &self,
+        musical_time: MusicalTime,
+        numerator: u32,
+        denominator: u32,
+    ) -> MusicalTime {
+        let (bar_number, _) = self.bar_at_musical_time(musical_time);
+        let bar = self.bars_from(bar_number).next().unwrap();
+
+        let step_ticks =
+            (bar.beat_ticks() * u64::from(numerator) / u64::from(denominator.max(1))).max(1);
+
+        let ticks_into_bar = musical_time.total_ticks() - bar.start.total_ticks();
+        let bar_length_ticks = bar.end.total_ticks() - bar.start.total_ticks();
+
+        let prev_ticks = (ticks_into_bar / step_ticks) * step_ticks;
+        let next_ticks = (prev_ticks + step_ticks).min(bar_length_ticks);
+
+        let snapped_ticks = if ticks_into_bar - prev_ticks < next_ticks - ticks_into_bar {
+            prev_ticks
+        } else {
+            next_ticks
+        };
+
+        MusicalTime::from_total_ticks(bar.start.total_ticks() + snapped_ticks)
     }
 
     pub fn timestamp_to_nearest_frame_round(&self, timestamp: Timestamp) -> FrameTime {
@@ -92,42 +344,53 @@ impl TempoMap {
     /// Convert the given `MusicalTime` into the corresponding time in `SecondsF64`.
     ///
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
-    #[inline]
     pub fn musical_to_seconds(&self, musical_time: MusicalTime) -> SecondsF64 {
-        // temporary static tempo
-        SecondsF64(musical_time.as_beats_f64() * self.seconds_per_beat)
+        let ticks = musical_time.total_ticks();
+        let segment = &self.tempo_segments[self.tempo_segment_index(ticks)];
+
+        // Use the exact number of ticks since the start of the segment to
+        // avoid losing precision late in the project.
+        let beats_into_segment =
+            (ticks - segment.start_ticks) as f64 / f64::from(SUPER_BEAT_TICKS_PER_BEAT);
+
+        SecondsF64(segment.start_seconds + segment.beats_to_seconds(beats_into_segment))
     }
 
     /// Convert the given `SecondsF64` into the corresponding `MusicalTime`.
     ///
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
-    #[inline]
     pub fn seconds_to_musical(&self, seconds: SecondsF64) -> MusicalTime {
-        // temporary static tempo
-        MusicalTime::from_beats_f64(seconds.0 * self.beats_per_second)
+        let seconds = seconds.0.max(0.0);
+        let segment = &self.tempo_segments[self.tempo_segment_index_at_seconds(seconds)];
+
+        let ticks_into_segment = (segment.seconds_to_beats(seconds - segment.start_seconds)
+            * f64::from(SUPER_BEAT_TICKS_PER_BEAT))
+        .round() as u64;
+
+        MusicalTime::from_total_ticks(segment.start_ticks + ticks_into_segment)
     }
 
-    /*
-    /// Convert the given `FrameTime` time into the corresponding `MusicalTime`.
+    /// Convert the given `MusicalTime` into the corresponding `SuperclockTime`.
+    /// This will be rounded to the nearest tick.
     ///
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
-    #[inline]
-    pub fn frame_to_musical(&self, frame: FrameTime) -> MusicalTime {
-        // temporary static tempo
-        MusicalTime::from_beats_f64(
-            frame.to_seconds_f64(self.sample_rate).0 * self.beats_per_second,
-        )
+    pub fn musical_to_superclock(&self, musical_time: MusicalTime) -> SuperclockTime {
+        SuperclockTime::from_seconds_f64(self.musical_to_seconds(musical_time))
     }
 
-    /// Convert the given `FrameTime` time into the corresponding time in `SecondsF64`.
+    /// Convert the given `SuperclockTime` into the corresponding `MusicalTime`.
     ///
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
-    #[inline]
-    pub fn frame_to_seconds(&self, frame: FrameTime) -> SecondsF64 {
-        // temporary static tempo
-        SecondsF64::from_frame(frame, self.sample_rate)
+    pub fn superclock_to_musical(&self, superclock_time: SuperclockTime) -> MusicalTime {
+        self.seconds_to_musical(superclock_time.to_seconds_f64())
+    }
+
+    /// Convert the given `FrameTime` time into the corresponding `MusicalTime`.
+    ///
+    /// Note that this must be re-calculated after recieving a new `TempoMap`.
+    pub fn frame_to_musical(&self, frame: FrameTime) -> MusicalTime {
+        self.seconds_to_musical(SecondsF64(self.frame_to_seconds_f64(frame)))
     }
-    */
 
     /// Convert the given `MusicalTime` into the corresponding discrete `FrameTime` time.
     /// This will be rounded to the nearest frame.
@@ -135,7 +398,6 @@ impl TempoMap {
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
     #[inline]
     pub fn musical_to_nearest_frame_round(&self, musical_time: MusicalTime) -> FrameTime {
-        // temporary static tempo
         self.musical_to_seconds(musical_time).to_nearest_frame_round(self.sample_rate)
     }
 
@@ -145,7 +407,6 @@ impl TempoMap {
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
     #[inline]
     pub fn seconds_to_nearest_frame_round(&self, seconds: SecondsF64) -> FrameTime {
-        // temporary static tempo
         seconds.to_nearest_frame_round(self.sample_rate)
     }
 
@@ -155,7 +416,6 @@ impl TempoMap {
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
     #[inline]
     pub fn musical_to_nearest_frame_floor(&self, musical_time: MusicalTime) -> FrameTime {
-        // temporary static tempo
         self.musical_to_seconds(musical_time).to_nearest_frame_floor(self.sample_rate)
     }
 
@@ -165,7 +425,6 @@ impl TempoMap {
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
     #[inline]
     pub fn seconds_to_nearest_frame_floor(&self, seconds: SecondsF64) -> FrameTime {
-        // temporary static tempo
         seconds.to_nearest_frame_floor(self.sample_rate)
     }
 
@@ -175,7 +434,6 @@ impl TempoMap {
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
     #[inline]
     pub fn musical_to_nearest_frame_ceil(&self, musical_time: MusicalTime) -> FrameTime {
-        // temporary static tempo
         self.musical_to_seconds(musical_time).to_nearest_frame_ceil(self.sample_rate)
     }
 
@@ -185,7 +443,6 @@ impl TempoMap {
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
     #[inline]
     pub fn seconds_to_nearest_frame_ceil(&self, seconds: SecondsF64) -> FrameTime {
-        // temporary static tempo
         seconds.to_nearest_frame_ceil(self.sample_rate)
     }
 
@@ -195,7 +452,6 @@ impl TempoMap {
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
     #[inline]
     pub fn musical_to_sub_frame(&self, musical_time: MusicalTime) -> (FrameTime, f64) {
-        // temporary static tempo
         self.musical_to_seconds(musical_time).to_sub_frame(self.sample_rate)
     }
 
@@ -205,48 +461,232 @@ impl TempoMap {
     /// Note that this must be re-calculated after recieving a new `TempoMap`.
     #[inline]
     pub fn seconds_to_sub_frame(&self, seconds: SecondsF64) -> (FrameTime, f64) {
-        // temporary static tempo
         seconds.to_sub_frame(self.sample_rate)
     }
+
+    fn frame_to_seconds_f64(&self, frame: FrameTime) -> f64 {
+        let whole_seconds = frame.0 / self.sample_rate_u64;
+        let fract_frames = frame.0 % self.sample_rate_u64;
+
+        whole_seconds as f64 + (fract_frames as f64 * self.sample_rate_recip)
+    }
+
+    fn seconds_to_beats_f64(&self, seconds: f64) -> f64 {
+        let seconds = seconds.max(0.0);
+        let segment = &self.tempo_segments[self.tempo_segment_index_at_seconds(seconds)];
+
+        (segment.start_ticks as f64 / f64::from(SUPER_BEAT_TICKS_PER_BEAT))
+            + segment.seconds_to_beats(seconds - segment.start_seconds)
+    }
+
+    fn bpm_at_seconds(&self, seconds: SecondsF64) -> f64 {
+        let seconds = seconds.0.max(0.0);
+        let segment = &self.tempo_segments[self.tempo_segment_index_at_seconds(seconds)];
+
+        segment.bpm_at(seconds - segment.start_seconds)
+    }
+
+    fn tempo_segment_index(&self, ticks: u64) -> usize {
+        // The first segment always starts at `0`.
+        self.tempo_segments.partition_point(|s| s.start_ticks <= ticks) - 1
+    }
+
+    fn tempo_segment_index_at_seconds(&self, seconds: f64) -> usize {
+        self.tempo_segments.partition_point(|s| s.start_seconds <= seconds).max(1) - 1
+    }
+
+    fn meter_segment_index(&self, ticks: u64) -> usize {
+        self.meter_segments.partition_point(|s| s.start_ticks <= ticks) - 1
+    }
+
+    fn compute_tempo_segments(&mut self) {
+        self.tempo_segments.clear();
+
+        let mut start_seconds = 0.0;
+        for (i, point) in self.tempo_points.iter().enumerate() {
+            let start_ticks = point.time.total_ticks();
+
+            let mut segment = TempoSegment {
+                start_ticks,
+                start_seconds,
+                start_bpm: point.bpm,
+                bpm_per_second: 0.0,
+            };
+
+            if let Some(next_point) = self.tempo_points.get(i + 1) {
+                let beats = (next_point.time.total_ticks() - start_ticks) as f64
+                    / f64::from(SUPER_BEAT_TICKS_PER_BEAT);
+
+                let seconds = match point.ramp {
+                    TempoRamp::Step => beats * 60.0 / point.bpm,
+                    TempoRamp::Linear => {
+                        // The tempo changes linearly over time, so the average
+                        // tempo over the segment is the mean of both tempos.
+                        let seconds = beats * 120.0 / (point.bpm + next_point.bpm);
+                        segment.bpm_per_second = (next_point.bpm - point.bpm) / seconds;
+                        seconds
+                    }
+                };
+
+                start_seconds += seconds;
+            }
+
+            self.tempo_segments.push(segment);
+        }
+    }
+
+    fn compute_meter_segments(&mut self) {
+        self.meter_segments.clear();
+
+        let mut first_bar_number = 0;
+        for (i, meter) in self.meter_changes.iter().enumerate() {
+            let start_ticks = meter.time.total_ticks();
+
+            // The length of a bar in (quarter note) beats.
+            let ticks_per_bar =
+                (u64::from(meter.tsig_num) * 4 * u64::from(SUPER_BEAT_TICKS_PER_BEAT)
+                    / u64::from(meter.tsig_denom))
+                .max(1);
+
+            self.meter_segments.push(MeterSegment {
+                start_ticks,
+                first_bar_number,
+                ticks_per_bar,
+                tsig_num: meter.tsig_num,
+                tsig_denom: meter.tsig_denom,
+            });
+
+            if let Some(next_meter) = self.meter_changes.get(i + 1) {
+                // A bar that is cut short by the next meter change still
+                // counts as a bar.
+                let ticks = next_meter.time.total_ticks() - start_ticks;
+                first_bar_number += (ticks + ticks_per_bar - 1) / ticks_per_bar;
+            }
+        }
+    }
 }
 
-impl EngineTempoMap for TempoMap {
-    fn frame_to_beat(&self, frame: u64) -> BeatTime {
-        let whole_seconds = frame / self.sample_rate_u64;
-        let fract_frames = frame % self.sample_rate_u64;
+/// A bar on the timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bar {
+    /// The (zero-based) number of this bar.
+    pub number: u64,
+    pub start: MusicalTime,
+    /// The end of this bar. This is earlier than the start of the bar plus
+    /// the length of the time signature if the bar is cut short by a meter
+    /// change.
+    pub end: MusicalTime,
+    pub tsig_num: u16,
+    pub tsig_denom: u16,
+}
+
+impl Bar {
+    /// The length of a single beat of this bar's time signature in ticks
+    /// (i.e. the length of an eighth note in 6/8).
+    pub fn beat_ticks(&self) -> u64 {
+        (4 * u64::from(SUPER_BEAT_TICKS_PER_BEAT) / u64::from(self.tsig_denom)).max(1)
+    }
+
+    /// The length of a single beat of this bar's time signature in (quarter
+    /// note) beats.
+    pub fn beat_length_beats_f64(&self) -> f64 {
+        4.0 / f64::from(self.tsig_denom)
+    }
+}
+
+/// An iterator over the bars of a `TempoMap`, created with
+/// `TempoMap::bars_from()`.
+pub struct BarIter<'a> {
+    tempo_map: &'a TempoMap,
+    segment_index: usize,
+    bar_number: u64,
+    bar_start_ticks: u64,
+}
+
+impl<'a> Iterator for BarIter<'a> {
+    type Item = Bar;
 
-        let whole_beats = whole_seconds as f64 * self.beats_per_second;
+    fn next(&mut self) -> Option<Bar> {
+        let segments = &self.tempo_map.meter_segments;
+        let segment = &segments[self.segment_index];
+        let next_segment = segments.get(self.segment_index + 1);
 
-        let fract_seconds = fract_frames as f64 * self.sample_rate_recip;
-        let fract_beats = fract_seconds * self.beats_per_second;
+        let mut bar_end_ticks = self.bar_start_ticks + segment.ticks_per_bar;
+        if let Some(next_segment) = next_segment {
+            bar_end_ticks = bar_end_ticks.min(next_segment.start_ticks);
+        }
+
+        let bar = Bar {
+            number: self.bar_number,
+            start: MusicalTime::from_total_ticks(self.bar_start_ticks),
+            end: MusicalTime::from_total_ticks(bar_end_ticks),
+            tsig_num: segment.tsig_num,
+            tsig_denom: segment.tsig_denom,
+        };
+
+        self.bar_number += 1;
+        self.bar_start_ticks = bar_end_ticks;
+        if let Some(next_segment) = next_segment {
+            if self.bar_start_ticks >= next_segment.start_ticks {
+                self.segment_index += 1;
+            }
+        }
 
-        BeatTime::from_float(whole_beats + fract_beats)
+        Some(bar)
     }
+}
 
-    fn frame_to_seconds(&self, frame: u64) -> SecondsTime {
-        let whole_seconds = frame / self.sample_rate_u64;
-        let fract_frames = frame % self.sample_rate_u64;
+impl TempoSegment {
+    fn bpm_at(&self, seconds_into_segment: f64) -> f64 {
+        self.start_bpm + (self.bpm_per_second * seconds_into_segment)
+    }
 
-        let fract_seconds = fract_frames as f64 * self.sample_rate_recip;
+    fn beats_to_seconds(&self, beats_into_segment: f64) -> f64 {
+        if self.bpm_per_second == 0.0 {
+            beats_into_segment * 60.0 / self.start_bpm
+        } else {
+            // Solve `(a/2)t^2 + (bpm)t - 60(beats) = 0` for `t`. This form
+            // avoids the cancellation error of the usual quadratic formula
+            // when `a` is small.
+            let discriminant = (self.start_bpm * self.start_bpm)
+                + (120.0 * self.bpm_per_second * beats_into_segment);
+
+            beats_into_segment * 120.0 / (self.start_bpm + discriminant.max(0.0).sqrt())
+        }
+    }
 
-        SecondsTime::from_float(whole_seconds as f64 + fract_seconds)
+    fn seconds_to_beats(&self, seconds_into_segment: f64) -> f64 {
+        ((self.start_bpm * seconds_into_segment)
+            + (0.5 * self.bpm_per_second * seconds_into_segment * seconds_into_segment))
+            / 60.0
     }
+}
 
-    fn transport_info_at_frame(&self, frame: u64) -> TransportInfoAtFrame {
-        let current_beat = self.frame_to_beat(frame).to_int() as u64;
+impl EngineTempoMap for TempoMap {
+    fn frame_to_beat(&self, frame: u64) -> BeatTime {
+        BeatTime::from_float(self.seconds_to_beats_f64(self.frame_to_seconds_f64(FrameTime(frame))))
+    }
+
+    fn frame_to_seconds(&self, frame: u64) -> SecondsTime {
+        SecondsTime::from_float(self.frame_to_seconds_f64(FrameTime(frame)))
+    }
 
-        let current_bar_number = current_beat / u64::from(self.tsig_num);
+    fn transport_info_at_frame(&self, frame: u64) -> TransportInfoAtFrame {
+        let seconds = self.frame_to_seconds_f64(FrameTime(frame));
+        let tempo_segment = &self.tempo_segments[self.tempo_segment_index_at_seconds(seconds)];
 
-        let current_bar_start =
-            BeatTime::from_int((current_bar_number * u64::from(self.tsig_num)) as i64);
+        let musical_time = self.seconds_to_musical(SecondsF64(seconds));
+        let (tsig_num, tsig_denom) = self.tsig_at_musical_time(musical_time);
+        let (current_bar_number, current_bar_start) = self.bar_at_musical_time(musical_time);
 
         TransportInfoAtFrame {
-            tempo: self.bpm,
-            tempo_inc: 0.0,
-            tsig_num: self.tsig_num,
-            tsig_denom: self.tsig_denom,
-            current_bar_number: current_bar_number as i32,
-            current_bar_start,
+            tempo: tempo_segment.bpm_at(seconds - tempo_segment.start_seconds),
+            // The tempo increment is per frame.
+            tempo_inc: tempo_segment.bpm_per_second * self.sample_rate_recip,
+            tsig_num,
+            tsig_denom,
+            current_bar_number: current_bar_number.min(i32::MAX as u64) as i32,
+            current_bar_start: BeatTime::from_float(current_bar_start.as_beats_f64()),
         }
     }
 }
@@ -256,3 +696,160 @@ impl Default for TempoMap {
         TempoMap::new(110.0, 4, 4, 44_100)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(beats: u32, bpm: f64) -> TempoPoint {
+        TempoPoint { time: MusicalTime::from_beats(beats), bpm, ramp: TempoRamp::Step }
+    }
+
+    fn linear(beats: u32, bpm: f64) -> TempoPoint {
+        TempoPoint { time: MusicalTime::from_beats(beats), bpm, ramp: TempoRamp::Linear }
+    }
+
+    fn meter(time: MusicalTime, tsig_num: u16, tsig_denom: u16) -> MeterChange {
+        MeterChange { time, tsig_num, tsig_denom }
+    }
+
+    fn assert_seconds(tempo_map: &TempoMap, musical_time: MusicalTime, seconds: f64) {
+        let actual = tempo_map.musical_to_seconds(musical_time).0;
+        assert!((actual - seconds).abs() < 1e-9, "{:?}: {} != {}", musical_time, actual, seconds);
+    }
+
+    #[test]
+    fn static_tempo_conversions() {
+        let tempo_map = TempoMap::new(120.0, 4, 4, 48_000);
+
+        assert_seconds(&tempo_map, MusicalTime::from_beats(2), 1.0);
+        assert_eq!(tempo_map.musical_to_nearest_frame_round(MusicalTime::from_beats(2)).0, 48_000);
+        assert_eq!(tempo_map.frame_to_musical(FrameTime(48_000)), MusicalTime::from_beats(2));
+        assert_eq!(
+            tempo_map.superclock_to_musical(SuperclockTime::from_seconds_f64(SecondsF64(0.25))),
+            MusicalTime::from_half_beats(0, 1)
+        );
+    }
+
+    #[test]
+    fn step_tempo_changes() {
+        let tempo_map = TempoMap::from_points(
+            vec![step(0, 120.0), step(4, 60.0)],
+            vec![meter(MusicalTime::default(), 4, 4)],
+            48_000,
+        );
+
+        assert_seconds(&tempo_map, MusicalTime::from_beats(4), 2.0);
+        assert_seconds(&tempo_map, MusicalTime::from_beats(6), 4.0);
+        assert_eq!(tempo_map.bpm_at_musical_time(MusicalTime::from_beats(3)), 120.0);
+        assert_eq!(tempo_map.bpm_at_musical_time(MusicalTime::from_beats(5)), 60.0);
+        assert_eq!(tempo_map.frame_to_musical(FrameTime(4 * 48_000)), MusicalTime::from_beats(6));
+    }
+
+    #[test]
+    fn linear_tempo_ramps() {
+        // Ramping from 60 to 120 BPM over 3 beats takes 2 seconds.
+        let tempo_map = TempoMap::from_points(
+            vec![linear(0, 60.0), step(3, 120.0)],
+            vec![meter(MusicalTime::default(), 4, 4)],
+            48_000,
+        );
+
+        assert_seconds(&tempo_map, MusicalTime::from_beats(3), 2.0);
+        assert_seconds(&tempo_map, MusicalTime::from_beats(5), 3.0);
+
+        // After 1 second the tempo is 90 BPM, and 1.25 beats have passed.
+        assert_seconds(&tempo_map, MusicalTime::from_quarter_beats(1, 1), 1.0);
+        assert!((tempo_map.bpm_at_frame(FrameTime(48_000)) - 90.0).abs() < 1e-9);
+        assert_eq!(
+            tempo_map.seconds_to_musical(SecondsF64(1.0)),
+            MusicalTime::from_quarter_beats(1, 1)
+        );
+
+        let info = tempo_map.transport_info_at_frame(48_000);
+        assert!((info.tempo - 90.0).abs() < 1e-9);
+        assert!((info.tempo_inc - (30.0 / 48_000.0)).abs() < 1e-12);
+
+        let info = tempo_map.transport_info_at_frame(3 * 48_000);
+        assert_eq!(info.tempo, 120.0);
+        assert_eq!(info.tempo_inc, 0.0);
+    }
+
+    #[test]
+    fn meter_changes() {
+        // Two bars of 4/4, then 7/8.
+        let tempo_map = TempoMap::from_points(
+            vec![step(0, 120.0)],
+            vec![meter(MusicalTime::default(), 4, 4), meter(MusicalTime::from_beats(8), 7, 8)],
+            48_000,
+        );
+
+        assert_eq!(tempo_map.tsig_at_musical_time(MusicalTime::from_beats(7)), (4, 4));
+        assert_eq!(tempo_map.tsig_at_musical_time(MusicalTime::from_beats(9)), (7, 8));
+        assert_eq!(
+            tempo_map.bar_at_musical_time(MusicalTime::from_beats(12)),
+            (3, MusicalTime::from_half_beats(11, 1))
+        );
+
+        let bars: Vec<(u64, MusicalTime)> =
+            tempo_map.bars_from(1).take(3).map(|bar| (bar.number, bar.start)).collect();
+        assert_eq!(
+            bars,
+            vec![
+                (1, MusicalTime::from_beats(4)),
+                (2, MusicalTime::from_beats(8)),
+                (3, MusicalTime::from_half_beats(11, 1)),
+            ]
+        );
+
+        let info = tempo_map.transport_info_at_frame(5 * 48_000);
+        assert_eq!((info.tsig_num, info.tsig_denom), (7, 8));
+        assert_eq!(info.current_bar_number, 2);
+    }
+
+    #[test]
+    fn meter_changes_within_a_bar_cut_it_short() {
+        let tempo_map = TempoMap::from_points(
+            vec![step(0, 120.0)],
+            vec![meter(MusicalTime::default(), 4, 4), meter(MusicalTime::from_beats(6), 3, 4)],
+            48_000,
+        );
+
+        let bars: Vec<Bar> = tempo_map.bars_from(0).take(3).collect();
+        assert_eq!(bars[1].start, MusicalTime::from_beats(4));
+        assert_eq!(bars[1].end, MusicalTime::from_beats(6));
+        assert_eq!((bars[2].number, bars[2].start), (2, MusicalTime::from_beats(6)));
+        assert_eq!(bars[2].end, MusicalTime::from_beats(9));
+    }
+
+    #[test]
+    fn snapping_follows_the_time_signature() {
+        // In 6/8 a beat is an eighth note.
+        let tempo_map = TempoMap::new(120.0, 6, 8, 48_000);
+
+        assert_eq!(
+            tempo_map.snap_to_nearest_beat_fraction(MusicalTime::from_eighth_beats(1, 3), 1, 1),
+            MusicalTime::from_half_beats(1, 1)
+        );
+        assert_eq!(
+            tempo_map.snap_to_nearest_bar(MusicalTime::from_beats(2)),
+            MusicalTime::from_beats(3)
+        );
+        assert_eq!(
+            tempo_map.snap_to_nearest_bar(MusicalTime::from_beats(1)),
+            MusicalTime::from_beats(0)
+        );
+    }
+
+    #[test]
+    fn points_are_sorted_and_start_at_zero() {
+        let tempo_map = TempoMap::from_points(
+            vec![step(8, 90.0), step(2, 100.0), step(8, 140.0)],
+            vec![meter(MusicalTime::from_beats(4), 3, 4)],
+            48_000,
+        );
+
+        assert_eq!(tempo_map.tempo_points(), &[step(0, 100.0), step(8, 140.0)]);
+        assert_eq!(tempo_map.meter_changes(), &[meter(MusicalTime::default(), 3, 4)]);
+    }
+}
@@ -1,7 +1,9 @@
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 use vizia::prelude::*;
 
+use crate::export::{ExportJob, ExportProgress, ExportSettings};
 use crate::ui::panels::browser_panel::BrowserPanelLens;
 use crate::ui::panels::timeline_panel::track_headers_panel::TrackHeadersPanelLens;
 use crate::ui::panels::timeline_panel::TimelineViewWorkingState;
@@ -29,9 +31,23 @@ pub struct WorkingState {
     pub timeline_snap_mode: SnapMode,
     pub timeline_snap_choices: Vec<SnapMode>,
 
+    pub export_dialog_shown: bool,
+    pub export_settings: ExportSettings,
+    /// The progress of the export in progress, if any.
+    pub export_progress: Option<ExportProgress>,
+    /// The last error from the engine to show to the user, if any.
+    pub engine_error: Option<String>,
+
     #[lens(ignore)]
     pub timeline_view_id: Option<Entity>,
 
+    #[lens(ignore)]
+    pub export_job: Option<ExportJob>,
+
+    /// The file the current project was last saved to or opened from.
+    #[lens(ignore)]
+    pub project_file_path: Option<PathBuf>,
+
     /// This is only allowed to be borrowed mutably within the
     /// `state_system::handle_action` method.
     #[lens(ignore)]
@@ -53,6 +69,7 @@ impl WorkingState {
             timeline_snap_mode: state.app.timeline_snap_mode,
             timeline_snap_choices: vec![
                 SnapMode::Line,
+                SnapMode::Bar,
                 SnapMode::Beat,
                 SnapMode::HalfBeat,
                 SnapMode::QuarterBeat,
@@ -60,8 +77,19 @@ impl WorkingState {
                 SnapMode::SixteenthBeat,
                 SnapMode::_32ndBeat,
                 SnapMode::ThirdBeat,
+                SnapMode::SixthBeat,
+                SnapMode::TwelfthBeat,
+                SnapMode::DottedBeat,
+                SnapMode::DottedHalfBeat,
+                SnapMode::DottedQuarterBeat,
             ],
+            export_dialog_shown: false,
+            export_settings: ExportSettings::default(),
+            export_progress: None,
+            engine_error: None,
             timeline_view_id: None,
+            export_job: None,
+            project_file_path: None,
             shared_timeline_view_state,
         }
     }
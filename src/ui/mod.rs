@@ -11,7 +11,9 @@ use std::{error::Error, time::Duration};
 use vizia::prelude::*;
 
 use crate::state_system::{AppAction, StateSystem};
-use crate::ui::panels::{bottom_bar, browser_panel, side_tab_bar, timeline_panel, top_bar};
+use crate::ui::panels::{
+    bottom_bar, browser_panel, export_dialog, side_tab_bar, timeline_panel, top_bar,
+};
 
 use self::panels::timeline_panel::TimelineViewWorkingState;
 
@@ -59,6 +61,8 @@ pub fn run_ui() -> Result<(), Box<dyn Error>> {
             .width(Stretch(2.0));
 
             bottom_bar::bottom_bar(cx);
+
+            export_dialog::export_dialog(cx);
         })
         .background_color(Color::from("#171717"))
         .row_between(Pixels(1.0));
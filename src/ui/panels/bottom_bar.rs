@@ -1,11 +1,21 @@
 use vizia::prelude::*;
 
+use crate::state_system::{StateSystem, WorkingState};
 use crate::ui::generic_views::{Icon, IconCode};
 
 pub fn bottom_bar(cx: &mut Context) {
     HStack::new(cx, |cx| {
         Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Home, 22.0, 20.0)).class("icon_btn");
 
+        Label::new(
+            cx,
+            StateSystem::working_state
+                .then(WorkingState::engine_error)
+                .map(|e| e.clone().unwrap_or_default()),
+        )
+        .class("bottom_bar_error")
+        .left(Pixels(8.0));
+
         Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Terminal, 22.0, 20.0))
             .class("icon_btn")
             .left(Stretch(1.0));
@@ -64,7 +64,7 @@ impl BrowserPanelLens {
             current_directory_text: String::new(),
             list_entries: Vec::new(),
             selected_entry_index: None,
-            root_sample_directories: vec!["./assets/test_files".into()],
+            root_sample_directories: state.app.browser_panel.root_sample_directories.clone(),
             parent_subdirectories: Vec::new(),
         };
 
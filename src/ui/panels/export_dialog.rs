@@ -0,0 +1,234 @@
+use vizia::prelude::*;
+
+use crate::export::{ExportFormat, ExportPhase, ExportProgress, ExportSettings};
+use crate::state_system::{AppAction, ExportAction, ProjectAction, StateSystem, WorkingState};
+
+const DIALOG_WIDTH: f32 = 460.0;
+const ROW_HEIGHT: f32 = 26.0;
+const ROW_LABEL_WIDTH: f32 = 110.0;
+const LABEL_LR_PADDING: f32 = 7.0;
+const PROGRESS_BAR_HEIGHT: f32 = 6.0;
+
+static FORMAT_CHOICES: [(&str, ExportFormat); 5] = [
+    ("WAV 16", ExportFormat::Wav16),
+    ("WAV 24", ExportFormat::Wav24),
+    ("WAV 32f", ExportFormat::WavF32),
+    ("FLAC 16", ExportFormat::Flac16),
+    ("FLAC 24", ExportFormat::Flac24),
+];
+
+static SAMPLE_RATE_CHOICES: [(&str, Option<u32>); 5] = [
+    ("Project", None),
+    ("44.1k", Some(44_100)),
+    ("48k", Some(48_000)),
+    ("88.2k", Some(88_200)),
+    ("96k", Some(96_000)),
+];
+
+static NORMALIZE_CHOICES: [(&str, Option<f32>); 4] =
+    [("Off", None), ("-0.1 dB", Some(-0.1)), ("-1 dB", Some(-1.0)), ("-3 dB", Some(-3.0))];
+
+/// The dialog with the settings of an export, as well as the progress of the
+/// export once it has started.
+pub fn export_dialog(cx: &mut Context) {
+    let settings = StateSystem::working_state.then(WorkingState::export_settings);
+    let progress = StateSystem::working_state.then(WorkingState::export_progress);
+
+    VStack::new(cx, |cx| {
+        Label::new(cx, "Export").class("export_dialog_title");
+
+        settings_row(cx, "Format", |cx| {
+            for (name, format) in FORMAT_CHOICES.iter().copied() {
+                choice_button(
+                    cx,
+                    name,
+                    settings.clone().map(move |s| s.format == format),
+                    ExportAction::SetFormat(format),
+                );
+            }
+        });
+
+        settings_row(cx, "Sample Rate", |cx| {
+            for (name, sample_rate) in SAMPLE_RATE_CHOICES.iter().copied() {
+                choice_button(
+                    cx,
+                    name,
+                    settings.clone().map(move |s| s.sample_rate == sample_rate),
+                    ExportAction::SetSampleRate(sample_rate),
+                );
+            }
+        });
+
+        settings_row(cx, "Dither", |cx| {
+            choice_button(
+                cx,
+                "Off",
+                settings.clone().map(|s| !s.dither),
+                ExportAction::SetDither(false),
+            );
+            choice_button(
+                cx,
+                "On",
+                settings.clone().map(|s| s.dither),
+                ExportAction::SetDither(true),
+            );
+        });
+
+        settings_row(cx, "Normalize", |cx| {
+            for (name, peak_db) in NORMALIZE_CHOICES.iter().copied() {
+                choice_button(
+                    cx,
+                    name,
+                    settings.clone().map(move |s| s.normalize_peak_db == peak_db),
+                    ExportAction::SetNormalizePeakDb(peak_db),
+                );
+            }
+        });
+
+        HStack::new(cx, |cx| {
+            Button::new(
+                cx,
+                |cx| cx.emit(AppAction::Export(ExportAction::ShowDialog(false))),
+                |cx| padded_label(cx, "Close"),
+            )
+            .class("icon_btn");
+
+            Button::new(
+                cx,
+                |cx| {
+                    let settings =
+                        StateSystem::working_state.then(WorkingState::export_settings).get(cx);
+                    if let Some(path) = pick_export_file(&settings) {
+                        cx.emit(AppAction::Project(ProjectAction::Export { path, settings }));
+                    }
+                },
+                |cx| padded_label(cx, "Export..."),
+            )
+            .class("icon_btn")
+            .left(Stretch(1.0));
+
+            Button::new(
+                cx,
+                |cx| {
+                    let settings =
+                        StateSystem::working_state.then(WorkingState::export_settings).get(cx);
+                    if let Some(directory) = rfd::FileDialog::new().pick_folder() {
+                        cx.emit(AppAction::Project(ProjectAction::ExportStems {
+                            directory,
+                            settings,
+                        }));
+                    }
+                },
+                |cx| padded_label(cx, "Export Stems..."),
+            )
+            .class("icon_btn");
+        })
+        .col_between(Pixels(6.0))
+        .height(Pixels(ROW_HEIGHT))
+        .top(Pixels(8.0));
+    })
+    .class("export_dialog")
+    .row_between(Pixels(6.0))
+    .position_type(PositionType::SelfDirected)
+    .z_order(10)
+    .width(Pixels(DIALOG_WIDTH))
+    .height(Auto)
+    .space(Stretch(1.0))
+    .display(StateSystem::working_state.then(WorkingState::export_dialog_shown));
+
+    VStack::new(cx, |cx| {
+        Label::new(cx, progress.clone().map(progress_text));
+
+        HStack::new(cx, |cx| {
+            Element::new(cx).class("export_progress_bar_fill").width(
+                progress.clone().map(|p| Percentage(p.map(|p| p.fraction).unwrap_or(0.0) * 100.0)),
+            );
+        })
+        .class("export_progress_bar")
+        .height(Pixels(PROGRESS_BAR_HEIGHT));
+
+        Button::new(
+            cx,
+            |cx| cx.emit(AppAction::Export(ExportAction::Cancel)),
+            |cx| padded_label(cx, "Cancel"),
+        )
+        .class("icon_btn")
+        .height(Pixels(ROW_HEIGHT))
+        .left(Stretch(1.0));
+    })
+    .class("export_dialog")
+    .row_between(Pixels(8.0))
+    .position_type(PositionType::SelfDirected)
+    .z_order(10)
+    .width(Pixels(DIALOG_WIDTH))
+    .height(Auto)
+    .space(Stretch(1.0))
+    .display(progress.map(|p| p.is_some()));
+}
+
+fn settings_row(cx: &mut Context, name: &str, content: impl FnOnce(&mut Context)) {
+    HStack::new(cx, |cx| {
+        Label::new(cx, name)
+            .class("toolbar_group_dimmed_label")
+            .width(Pixels(ROW_LABEL_WIDTH))
+            .top(Stretch(1.0))
+            .bottom(Stretch(1.0));
+
+        HStack::new(cx, content).class("toolbar_group").width(Auto).height(Pixels(ROW_HEIGHT));
+    })
+    .height(Pixels(ROW_HEIGHT));
+}
+
+fn choice_button(
+    cx: &mut Context,
+    name: &'static str,
+    selected: impl Lens<Target = bool>,
+    action: ExportAction,
+) {
+    Button::new(
+        cx,
+        move |cx| cx.emit(AppAction::Export(action.clone())),
+        |cx| padded_label(cx, name),
+    )
+    .class("icon_btn")
+    .toggle_class("icon_btn_toggled", selected);
+}
+
+fn padded_label<'a>(cx: &'a mut Context, text: &str) -> Handle<'a, Label> {
+    Label::new(cx, text)
+        .top(Stretch(1.0))
+        .bottom(Stretch(1.0))
+        .left(Pixels(LABEL_LR_PADDING))
+        .right(Pixels(LABEL_LR_PADDING))
+}
+
+fn pick_export_file(settings: &ExportSettings) -> Option<std::path::PathBuf> {
+    let extension = settings.format.file_extension();
+
+    rfd::FileDialog::new().add_filter("Audio", &[extension]).save_file().map(|mut path| {
+        if path.extension().is_none() {
+            path.set_extension(extension);
+        }
+        path
+    })
+}
+
+fn progress_text(progress: &Option<ExportProgress>) -> String {
+    match progress {
+        Some(p) => {
+            let phase = match p.phase {
+                ExportPhase::Rendering => "Rendering",
+                ExportPhase::Encoding => "Encoding",
+            };
+
+            format!(
+                "{} file {} of {}... {:.0}%",
+                phase,
+                p.file_index + 1,
+                p.num_files,
+                p.fraction * 100.0
+            )
+        }
+        None => String::new(),
+    }
+}
@@ -1,5 +1,6 @@
 pub mod bottom_bar;
 pub mod browser_panel;
+pub mod export_dialog;
 pub mod side_tab_bar;
 pub mod timeline_panel;
 pub mod top_bar;
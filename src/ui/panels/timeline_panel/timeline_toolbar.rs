@@ -17,6 +17,23 @@ pub fn timeline_toolbar(cx: &mut Context) {
     const SMALL_ICON_FRAME_SIZE: f32 = 20.0;
     const SMALL_ICON_SIZE: f32 = 18.0;
 
+    const SNAP_MODE_MENU: [SnapMode; 14] = [
+        SnapMode::Line,
+        SnapMode::Bar,
+        SnapMode::Beat,
+        SnapMode::HalfBeat,
+        SnapMode::ThirdBeat,
+        SnapMode::QuarterBeat,
+        SnapMode::SixthBeat,
+        SnapMode::EigthBeat,
+        SnapMode::TwelfthBeat,
+        SnapMode::SixteenthBeat,
+        SnapMode::_32ndBeat,
+        SnapMode::DottedBeat,
+        SnapMode::DottedHalfBeat,
+        SnapMode::DottedQuarterBeat,
+    ];
+
     HStack::new(cx, |cx| {
         HStack::new(cx, |cx| {
             Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Menu, ICON_FRAME_SIZE, ICON_SIZE))
@@ -152,46 +169,11 @@ pub fn timeline_toolbar(cx: &mut Context) {
                             .child_right(Pixels(LABEL_LR_PADDING))
                         },
                         |cx| {
-                            MenuButton::new_simple(cx, "Line", |cx| {
-                                cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(
-                                    SnapMode::Line,
-                                )))
-                            });
-                            MenuButton::new_simple(cx, "Beat", |cx| {
-                                cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(
-                                    SnapMode::Beat,
-                                )))
-                            });
-                            MenuButton::new_simple(cx, "1/2 Beat", |cx| {
-                                cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(
-                                    SnapMode::HalfBeat,
-                                )))
-                            });
-                            MenuButton::new_simple(cx, "1/3 Beat", |cx| {
-                                cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(
-                                    SnapMode::ThirdBeat,
-                                )))
-                            });
-                            MenuButton::new_simple(cx, "1/4 Beat", |cx| {
-                                cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(
-                                    SnapMode::QuarterBeat,
-                                )))
-                            });
-                            MenuButton::new_simple(cx, "1/8 Beat", |cx| {
-                                cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(
-                                    SnapMode::EigthBeat,
-                                )))
-                            });
-                            MenuButton::new_simple(cx, "1/16 Beat", |cx| {
-                                cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(
-                                    SnapMode::SixteenthBeat,
-                                )))
-                            });
-                            MenuButton::new_simple(cx, "1/32 Beat", |cx| {
-                                cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(
-                                    SnapMode::_32ndBeat,
-                                )))
-                            });
+                            for mode in SNAP_MODE_MENU {
+                                MenuButton::new_simple(cx, mode.to_text(), move |cx| {
+                                    cx.emit(AppAction::Timeline(TimelineAction::SetSnapMode(mode)))
+                                });
+                            }
                         },
                     );
                 });
@@ -78,6 +78,8 @@ struct DraggingClip {
     region: ClipRegion,
 
     drag_start_units_x: f64,
+    /// The state of the clip when the drag started.
+    drag_start_state: AudioClipCopyableState,
 
     passed_drag_threshold: bool,
 }
@@ -330,11 +332,11 @@ impl View for TimelineView {
                             }));
                         }
 
-                        let drag_start_units_x =
+                        let (drag_start_units_x, drag_start_state) =
                             match &shared_state.lane_states[hovered_clip.lane_index].type_ {
                                 TimelineLaneType::Audio(audio_lane_state) => {
-                                    audio_lane_state.clips[hovered_clip.clip_index]
-                                        .timeline_start_beats_x
+                                    let clip = &audio_lane_state.clips[hovered_clip.clip_index];
+                                    (clip.timeline_start_beats_x, clip.clip_state.copyable)
                                 }
                             };
 
@@ -345,6 +347,7 @@ impl View for TimelineView {
                             selected: hovered_clip.selected,
                             region: hovered_clip.region,
                             drag_start_units_x,
+                            drag_start_state,
                             passed_drag_threshold: false,
                         });
 
@@ -389,7 +392,11 @@ impl View for TimelineView {
 
                     meta.consume();
 
-                    if let Some(dragged_clip) = self.dragging_clip.take() {
+                    // Only commit the final state of the clip (which also
+                    // records it in the undo history) if it was actually moved.
+                    if let Some(dragged_clip) =
+                        self.dragging_clip.take().filter(|d| d.passed_drag_threshold)
+                    {
                         let shared_state = self.shared_state.borrow();
 
                         match &shared_state.lane_states[dragged_clip.lane_index].type_ {
@@ -399,15 +406,17 @@ impl View for TimelineView {
                                     .clip_state
                                     .copyable;
 
-                                cx.emit(AppAction::Timeline(
-                                    TimelineAction::SetAudioClipCopyableStates {
-                                        track_index: dragged_clip.track_index,
-                                        changed_clips: vec![(
-                                            dragged_clip.clip_index,
-                                            cloned_state,
-                                        )],
-                                    },
-                                ));
+                                if cloned_state != dragged_clip.drag_start_state {
+                                    cx.emit(AppAction::Timeline(
+                                        TimelineAction::SetAudioClipCopyableStates {
+                                            track_index: dragged_clip.track_index,
+                                            changed_clips: vec![(
+                                                dragged_clip.clip_index,
+                                                cloned_state,
+                                            )],
+                                        },
+                                    ));
+                                }
                             }
                         }
                     }
@@ -468,9 +477,11 @@ impl View for TimelineView {
                             ClipRegion::TopPart => {
                                 let new_start_beats_x =
                                     dragged_clip.drag_start_units_x + offset_x_beats;
-                                let new_timestamp = Timestamp::Musical(
-                                    MusicalTime::from_beats_f64(new_start_beats_x),
-                                );
+                                let mut new_start = MusicalTime::from_beats_f64(new_start_beats_x);
+                                if shared_state.snap_active {
+                                    new_start = shared_state.snap_musical_time(new_start);
+                                }
+                                let new_timestamp = Timestamp::Musical(new_start);
 
                                 match &shared_state.lane_states[dragged_clip.lane_index].type_ {
                                     TimelineLaneType::Audio(audio_lane_state) => {
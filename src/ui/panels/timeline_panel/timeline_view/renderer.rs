@@ -2,6 +2,7 @@ use vizia::resource::FontOrId;
 use vizia::vg::Paint;
 use vizia::{prelude::*, vg::Color};
 
+use crate::state_system::time::MusicalTime;
 use crate::ui::panels::timeline_panel::timeline_view::state::TimelineLaneType;
 
 use super::culler::TimelineViewCuller;
@@ -122,163 +123,107 @@ pub(super) fn render_timeline_view(
     let line_marker_label_y = (bounds.y + (LINE_MARKER_LABEL_TOP_OFFSET * scale_factor)).round();
 
     let beat_delta_x = (POINTS_PER_BEAT * state.horizontal_zoom) as f32 * scale_factor;
-    let first_beat_x = bounds.x
-        - ((state.scroll_beats_x.fract() * POINTS_PER_BEAT * state.horizontal_zoom) as f32
-            * scale_factor);
-    let first_beat = state.scroll_beats_x.floor() as i64;
-
-    enum MajorValueDeltaType {
-        WholeUnits(i64),
-        Fractional(i64),
-    }
+    let beats_to_x =
+        |beats: f64| -> f32 { bounds.x + ((beats - state.scroll_beats_x) as f32 * beat_delta_x) };
+    let view_end_x = bounds.x + bounds.width();
+
+    let draw_minor_line = |canvas: &mut Canvas, x: f32| {
+        // Round to the nearest pixel so lines are sharp.
+        let line_x = x.round();
+
+        // We draw rectangles instead of lines because those are more
+        // efficient to draw.
+        let mut minor_line_path = Path::new();
+        minor_line_path.rect(
+            line_x - minor_line_width_offset,
+            minor_line_start_y,
+            minor_line_width,
+            minor_line_height,
+        );
 
-    let draw_vertical_gridlines = |canvas: &mut Canvas,
-                                   first_major_value: i64,
-                                   first_major_x: f32,
-                                   major_value_delta: MajorValueDeltaType,
-                                   mut major_value_fraction_count: i64,
-                                   major_delta_x: f32,
-                                   num_minor_subdivisions: usize,
-                                   view_end_x: f32| {
-        let minor_delta_x = major_delta_x / num_minor_subdivisions as f32;
-
-        let mut current_major_value = first_major_value;
-        let mut current_major_x = first_major_x;
-        while current_major_x <= view_end_x {
-            // Draw the minor line subdivisions.
-            for i in 1..num_minor_subdivisions {
-                let line_x = (current_major_x + (minor_delta_x * i as f32)).round();
-
-                // We draw rectangles instead of lines because those are more
-                // efficient to draw.
-                let mut minor_line_path = Path::new();
-                minor_line_path.rect(
-                    line_x - minor_line_width_offset,
-                    minor_line_start_y,
-                    minor_line_width,
-                    minor_line_height,
-                );
+        canvas.fill_path(&mut minor_line_path, &minor_line_paint);
+    };
 
-                canvas.fill_path(&mut minor_line_path, &minor_line_paint);
-            }
+    let draw_major_line = |canvas: &mut Canvas, x: f32, is_primary: bool, text: String| {
+        // Round to the nearest pixel so lines are sharp.
+        let line_x = x.round();
 
-            // Round to the nearest pixel so lines are sharp.
-            let line_x = current_major_x.round();
+        let (line_width_offset, line_width, line_paint) = if is_primary {
+            (major_line_width_offset, major_line_width, &major_line_paint)
+        } else {
+            (major_line_width_2_offset, major_line_width_2, &major_line_paint_2)
+        };
 
-            let (line_width_offset, line_width, line_paint) = match major_value_delta {
-                MajorValueDeltaType::WholeUnits(_) => {
-                    (major_line_width_offset, major_line_width, &major_line_paint)
-                }
-                MajorValueDeltaType::Fractional(_) => {
-                    if major_value_fraction_count == 0 {
-                        (major_line_width_offset, major_line_width, &major_line_paint)
-                    } else {
-                        (major_line_width_2_offset, major_line_width_2, &major_line_paint_2)
-                    }
-                }
-            };
+        // We draw rectangles instead of lines because those are more
+        // efficient to draw.
+        let mut major_line_path = Path::new();
+        major_line_path.rect(
+            line_x - line_width_offset,
+            major_line_start_y,
+            line_width,
+            major_line_height,
+        );
 
-            // We draw rectangles instead of lines because those are more
-            // efficient to draw.
-            let mut major_line_path = Path::new();
-            major_line_path.rect(
-                line_x - line_width_offset,
-                major_line_start_y,
-                line_width,
-                major_line_height,
-            );
-
-            canvas.fill_path(&mut major_line_path, &line_paint);
-
-            let text = match major_value_delta {
-                MajorValueDeltaType::WholeUnits(_) => format!("{}", current_major_value),
-                MajorValueDeltaType::Fractional(_) => {
-                    format!("{}.{}", current_major_value, major_value_fraction_count)
-                }
-            };
-
-            canvas
-                .fill_text(
-                    current_major_x + LINE_MARKER_LABEL_LEFT_OFFSET,
-                    line_marker_label_y,
-                    text,
-                    &line_marker_label_paint,
-                )
-                .unwrap();
-
-            match major_value_delta {
-                MajorValueDeltaType::WholeUnits(delta) => current_major_value += delta,
-                MajorValueDeltaType::Fractional(num_fractions) => {
-                    major_value_fraction_count += 1;
-                    if major_value_fraction_count == num_fractions {
-                        major_value_fraction_count = 0;
-                        current_major_value += 1;
-                    }
-                }
-            }
+        canvas.fill_path(&mut major_line_path, line_paint);
 
-            current_major_x += major_delta_x;
-        }
+        canvas
+            .fill_text(
+                x + LINE_MARKER_LABEL_LEFT_OFFSET,
+                line_marker_label_y,
+                text,
+                &line_marker_label_paint,
+            )
+            .unwrap();
     };
 
-    // TODO: Account for different time signatures.
-    // TODO: Account for time signature changes.
-    let beats_per_bar: i64 = 4;
-    let bars_per_measure: i64 = 4;
-    let beats_per_measure: i64 = beats_per_bar * bars_per_measure;
+    // Bars are numbered starting from 1 in the UI.
+    let (first_bar_number, _) =
+        state.tempo_map.bar_at_musical_time(MusicalTime::from_beats_f64(state.scroll_beats_x));
 
     if state.horizontal_zoom < ZOOM_THRESHOLD_BARS {
-        // The zoom threshold at which major lines represent measures and minor lines
-        // represent bars.
-
-        let measure_delta_x = beat_delta_x * beats_per_measure as f32;
-
-        let num_bars = first_beat / beats_per_bar;
-        let num_measures = first_beat / beats_per_measure;
-        let first_measure_beat = num_measures * beats_per_measure;
-        let first_measure_beat_x =
-            first_beat_x - (((first_beat - first_measure_beat) as f32) * beat_delta_x);
-
-        // Draw one extra to make sure that the text of the last marker is rendered.
-        let view_end_x = bounds.x + bounds.width() + measure_delta_x;
-
-        draw_vertical_gridlines(
-            canvas,
-            (num_measures * bars_per_measure) + 1,
-            first_measure_beat_x,
-            MajorValueDeltaType::WholeUnits(bars_per_measure),
-            0,
-            measure_delta_x,
-            (beats_per_measure / beats_per_bar) as usize,
-            view_end_x,
-        );
+        // The zoom threshold at which major lines represent measures (groups of
+        // `BARS_PER_MEASURE` bars) and minor lines represent bars.
+
+        const BARS_PER_MEASURE: u64 = 4;
+
+        let first_measure_bar_number = first_bar_number - (first_bar_number % BARS_PER_MEASURE);
+
+        for bar in state.tempo_map.bars_from(first_measure_bar_number) {
+            let x = beats_to_x(bar.start.as_beats_f64());
+            if x > view_end_x {
+                break;
+            }
+
+            if bar.number % BARS_PER_MEASURE == 0 {
+                draw_major_line(canvas, x, true, format!("{}", bar.number + 1));
+            } else {
+                draw_minor_line(canvas, x);
+            }
+        }
     } else if state.horizontal_zoom < ZOOM_THRESHOLD_BEATS {
         // The zoom threshold at which major lines represent bars and minor lines represent
-        // beats.
-
-        let bar_delta_x = beat_delta_x * beats_per_bar as f32;
-
-        let num_bars = first_beat / beats_per_bar;
-        let first_bar_beat = num_bars * beats_per_bar;
-        let first_bar_beat_x =
-            first_beat_x - (((first_beat - first_bar_beat) as f32) * beat_delta_x);
-
-        // Draw one extra to make sure that the text of the last marker is rendered.
-        let view_end_x = bounds.x + bounds.width() + bar_delta_x;
-
-        draw_vertical_gridlines(
-            canvas,
-            num_bars + 1,
-            first_bar_beat_x,
-            MajorValueDeltaType::WholeUnits(1),
-            0,
-            bar_delta_x,
-            beats_per_bar as usize,
-            view_end_x,
-        );
+        // the beats of the time signature.
+
+        for bar in state.tempo_map.bars_from(first_bar_number) {
+            let bar_start_beats = bar.start.as_beats_f64();
+            let bar_end_beats = bar.end.as_beats_f64();
+            let x = beats_to_x(bar_start_beats);
+            if x > view_end_x {
+                break;
+            }
+
+            let beat_length = bar.beat_length_beats_f64();
+            let mut beat_beats = bar_start_beats + beat_length;
+            while beat_beats < bar_end_beats {
+                draw_minor_line(canvas, beats_to_x(beat_beats));
+                beat_beats += beat_length;
+            }
+
+            draw_major_line(canvas, x, true, format!("{}", bar.number + 1));
+        }
     } else {
-        // The zoom threshold at which major lines represent beats and minor lines represent
-        // beat subdivisions.
+        // The zoom threshold at which major lines represent the beats of the time signature
+        // and minor lines represent beat subdivisions.
 
         let num_subbeat_divisions = if state.horizontal_zoom < ZOOM_THRESHOLD_QUARTER_BEATS {
             4
@@ -288,25 +233,39 @@ pub(super) fn render_timeline_view(
             16
         }; // TODO: More subdivisions?
 
-        // Draw one extra to make sure that the text of the last marker is rendered.
-        let view_end_x = bounds.x + bounds.width() + beat_delta_x;
-
-        let num_bars = first_beat / beats_per_bar;
-        let first_bar_beat = num_bars * beats_per_bar;
-        let first_bar_beat_x =
-            first_beat_x - (((first_beat - first_bar_beat) as f32) * beat_delta_x);
-        let bar_fraction_count = first_beat - first_bar_beat;
-
-        draw_vertical_gridlines(
-            canvas,
-            num_bars + 1,
-            first_beat_x,
-            MajorValueDeltaType::Fractional(beats_per_bar),
-            bar_fraction_count,
-            beat_delta_x,
-            num_subbeat_divisions,
-            view_end_x,
-        );
+        'bars: for bar in state.tempo_map.bars_from(first_bar_number) {
+            let bar_end_beats = bar.end.as_beats_f64();
+            let beat_length = bar.beat_length_beats_f64();
+            let subbeat_length = beat_length / f64::from(num_subbeat_divisions);
+
+            let mut beat_index: u32 = 0;
+            let mut beat_beats = bar.start.as_beats_f64();
+            while beat_beats < bar_end_beats {
+                let x = beats_to_x(beat_beats);
+                if x > view_end_x {
+                    break 'bars;
+                }
+
+                for i in 1..num_subbeat_divisions {
+                    let subbeat_beats = beat_beats + (subbeat_length * f64::from(i));
+                    if subbeat_beats >= bar_end_beats {
+                        break;
+                    }
+
+                    draw_minor_line(canvas, beats_to_x(subbeat_beats));
+                }
+
+                draw_major_line(
+                    canvas,
+                    x,
+                    beat_index == 0,
+                    format!("{}.{}", bar.number + 1, beat_index),
+                );
+
+                beat_index += 1;
+                beat_beats += beat_length;
+            }
+        }
     }
 
     // -- Draw the loop markers ---------------------------------------------------
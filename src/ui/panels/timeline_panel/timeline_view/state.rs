@@ -5,9 +5,12 @@ use crate::state_system::source_state::{
     AppState, AudioClipCopyableState, PaletteColor, ProjectState, SnapMode, TimelineTool,
     TrackType, DEFAULT_TIMELINE_ZOOM,
 };
-use crate::state_system::time::{TempoMap, Timestamp};
+use crate::state_system::time::{MusicalTime, TempoMap, Timestamp};
 
-use super::zoom_value_to_normal;
+use super::{
+    zoom_value_to_normal, ZOOM_THRESHOLD_BARS, ZOOM_THRESHOLD_BEATS, ZOOM_THRESHOLD_EIGTH_BEATS,
+    ZOOM_THRESHOLD_QUARTER_BEATS,
+};
 
 //#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 //pub(super) struct ClipID(pub u64);
@@ -36,6 +39,10 @@ pub struct TimelineViewWorkingState {
     pub snap_active: bool,
     pub snap_mode: SnapMode,
 
+    /// A copy of the project's tempo map, used to draw the bar/beat lines and
+    /// to snap to them.
+    pub(super) tempo_map: TempoMap,
+
     pub(super) track_index_to_lane_index: Vec<usize>,
 
     pub(super) any_clips_selected: bool,
@@ -62,6 +69,7 @@ impl TimelineViewWorkingState {
             selected_tool: TimelineTool::Pointer,
             snap_active: true,
             snap_mode: SnapMode::Line,
+            tempo_map: TempoMap::default(),
             any_clips_selected: false,
         }
     }
@@ -72,6 +80,7 @@ impl TimelineViewWorkingState {
         self.selected_tool = app_state.selected_timeline_tool;
         self.snap_active = app_state.timeline_snap_active;
         self.snap_mode = app_state.timeline_snap_mode;
+        self.tempo_map = project_state.tempo_map.clone();
 
         self.navigate(
             project_state.timeline_horizontal_zoom,
@@ -123,6 +132,31 @@ impl TimelineViewWorkingState {
         self.set_playhead_seek_pos(project_state.playhead_last_seeked);
     }
 
+    /// The snap mode with `SnapMode::Line` resolved into the mode matching
+    /// the finest lines currently drawn in the timeline.
+    pub(super) fn resolved_snap_mode(&self) -> SnapMode {
+        if let SnapMode::Line = self.snap_mode {
+            if self.horizontal_zoom < ZOOM_THRESHOLD_BARS {
+                SnapMode::Bar
+            } else if self.horizontal_zoom < ZOOM_THRESHOLD_BEATS {
+                SnapMode::Beat
+            } else if self.horizontal_zoom < ZOOM_THRESHOLD_QUARTER_BEATS {
+                SnapMode::QuarterBeat
+            } else if self.horizontal_zoom < ZOOM_THRESHOLD_EIGTH_BEATS {
+                SnapMode::EigthBeat
+            } else {
+                SnapMode::SixteenthBeat
+            }
+        } else {
+            self.snap_mode
+        }
+    }
+
+    /// Snap the given musical time to the current snap grid.
+    pub(super) fn snap_musical_time(&self, musical_time: MusicalTime) -> MusicalTime {
+        self.resolved_snap_mode().snap(musical_time, &self.tempo_map)
+    }
+
     pub fn insert_audio_clip(
         &mut self,
         track_index: usize,
@@ -195,6 +195,9 @@ where
                                         value_normalized,
                                     ));
                                 }
+                                VirtualSliderEvent::GestureFinished => {
+                                    cx.emit(InternalTrackHeaderEvent::KnobGestureFinished);
+                                }
                                 _ => {}
                             },
                         )
@@ -220,6 +223,9 @@ where
                                         value_normalized,
                                     ));
                                 }
+                                VirtualSliderEvent::GestureFinished => {
+                                    cx.emit(InternalTrackHeaderEvent::KnobGestureFinished);
+                                }
                                 _ => {}
                             },
                         )
@@ -252,6 +258,7 @@ enum InternalTrackHeaderEvent {
     StopResizeDrag,
     SetVolumeNormalized(f32),
     SetPanNormalized(f32),
+    KnobGestureFinished,
 }
 
 impl<L> View for TrackHeaderView<L>
@@ -284,6 +291,9 @@ where
             InternalTrackHeaderEvent::SetPanNormalized(pan_normalized) => {
                 (self.on_event)(cx, TrackHeaderEvent::SetPanNormalized(*pan_normalized));
             }
+            InternalTrackHeaderEvent::KnobGestureFinished => {
+                (self.on_event)(cx, TrackHeaderEvent::KnobGestureFinished);
+            }
         });
 
         event.map(|window_event, meta| match window_event {
@@ -344,4 +354,6 @@ pub enum TrackHeaderEvent {
     Selected,
     SetVolumeNormalized(f32),
     SetPanNormalized(f32),
+    /// The user let go of the volume or pan knob.
+    KnobGestureFinished,
 }
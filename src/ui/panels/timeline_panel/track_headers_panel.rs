@@ -7,7 +7,7 @@ use super::track_header_view::{
 use crate::{
     state_system::{
         source_state::{PaletteColor, TrackType},
-        AppAction, SourceState, StateSystem, TrackAction, WorkingState,
+        AppAction, HistoryAction, SourceState, StateSystem, TrackAction, WorkingState,
     },
     ui::generic_views::virtual_slider::VirtualSliderLens,
 };
@@ -154,6 +154,9 @@ pub fn track_headers_panel(cx: &mut Context) {
                                 pan_normalized,
                             }));
                         }
+                        TrackHeaderEvent::KnobGestureFinished => {
+                            cx.emit(AppAction::History(HistoryAction::EndGesture));
+                        }
                     });
                 },
             )
@@ -191,6 +194,9 @@ pub fn track_headers_panel(cx: &mut Context) {
                         pan_normalized,
                     )));
                 }
+                TrackHeaderEvent::KnobGestureFinished => {
+                    cx.emit(AppAction::History(HistoryAction::EndGesture));
+                }
             },
         );
     })
@@ -1,6 +1,10 @@
 use vizia::prelude::*;
 
-use crate::state_system::{actions::TimelineAction, AppAction, StateSystem, WorkingState};
+use crate::project_file::PROJECT_FILE_EXTENSION;
+use crate::state_system::{
+    actions::TimelineAction, AppAction, ExportAction, HistoryAction, ProjectAction, StateSystem,
+    WorkingState,
+};
 use crate::ui::generic_views::{Icon, IconCode};
 
 pub fn top_bar(cx: &mut Context) {
@@ -24,9 +28,12 @@ pub fn top_bar(cx: &mut Context) {
                     cx,
                     |cx| Label::new(cx, "File"),
                     |cx| {
-                        MenuButton::new_simple(cx, "Open Project", |_| {});
-                        MenuButton::new_simple(cx, "Save", |_| {});
-                        MenuButton::new_simple(cx, "Save As", |_| {});
+                        MenuButton::new_simple(cx, "Open Project", open_project);
+                        MenuButton::new_simple(cx, "Save", save_project);
+                        MenuButton::new_simple(cx, "Save As", save_project_as);
+                        MenuButton::new_simple(cx, "Export", |cx| {
+                            cx.emit(AppAction::Export(ExportAction::ShowDialog(true)));
+                        });
                     },
                 );
 
@@ -39,7 +46,12 @@ pub fn top_bar(cx: &mut Context) {
                     cx,
                     |cx| Label::new(cx, "Edit").class("menu_bar_label"),
                     |cx| {
-                        MenuButton::new_simple(cx, "TODO", |_| {});
+                        MenuButton::new_simple(cx, "Undo", |cx| {
+                            cx.emit(AppAction::History(HistoryAction::Undo));
+                        });
+                        MenuButton::new_simple(cx, "Redo", |cx| {
+                            cx.emit(AppAction::History(HistoryAction::Redo));
+                        });
                     },
                 );
 
@@ -79,21 +91,31 @@ pub fn top_bar(cx: &mut Context) {
             .class("top_bar_separator");
 
         HStack::new(cx, |cx| {
-            Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Undo, ICON_FRAME_SIZE, ICON_SIZE))
-                .class("icon_btn");
+            Button::new(
+                cx,
+                |cx| cx.emit(AppAction::History(HistoryAction::Undo)),
+                |cx| Icon::new(cx, IconCode::Undo, ICON_FRAME_SIZE, ICON_SIZE),
+            )
+            .class("icon_btn");
 
             Element::new(cx).class("toolbar_group_separator");
 
-            Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Redo, ICON_FRAME_SIZE, ICON_SIZE))
-                .class("icon_btn");
+            Button::new(
+                cx,
+                |cx| cx.emit(AppAction::History(HistoryAction::Redo)),
+                |cx| Icon::new(cx, IconCode::Redo, ICON_FRAME_SIZE, ICON_SIZE),
+            )
+            .class("icon_btn");
         })
         .class("toolbar_group")
         .height(Pixels(TOOLBAR_GROUP_HEIGHT))
         .width(Auto);
 
         HStack::new(cx, |cx| {
-            Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Save, ICON_FRAME_SIZE, ICON_SIZE))
-                .class("icon_btn");
+            Button::new(cx, save_project, |cx| {
+                Icon::new(cx, IconCode::Save, ICON_FRAME_SIZE, ICON_SIZE)
+            })
+            .class("icon_btn");
         })
         .class("toolbar_group")
         .left(Pixels(SEPARATOR_PADDING))
@@ -277,3 +299,37 @@ pub fn top_bar(cx: &mut Context) {
     .child_space(Pixels(TOP_BAR_CHILD_SPACE))
     .class("top_bar");
 }
+
+fn open_project(cx: &mut EventContext) {
+    if let Some(path) = rfd::FileDialog::new()
+        .add_filter("Meadowlark Project", &[PROJECT_FILE_EXTENSION])
+        .pick_file()
+    {
+        cx.emit(AppAction::Project(ProjectAction::Open(path)));
+    }
+}
+
+/// Save the project to the file it was last saved to or opened from, or ask
+/// for a new file if it hasn't been saved yet.
+fn save_project(cx: &mut EventContext) {
+    let has_file =
+        StateSystem::working_state.map(|state| state.project_file_path.is_some()).get(cx);
+
+    if has_file {
+        cx.emit(AppAction::Project(ProjectAction::Save));
+    } else {
+        save_project_as(cx);
+    }
+}
+
+fn save_project_as(cx: &mut EventContext) {
+    if let Some(mut path) = rfd::FileDialog::new()
+        .add_filter("Meadowlark Project", &[PROJECT_FILE_EXTENSION])
+        .save_file()
+    {
+        if path.extension().is_none() {
+            path.set_extension(PROJECT_FILE_EXTENSION);
+        }
+        cx.emit(AppAction::Project(ProjectAction::SaveAs(path)));
+    }
+}
@@ -1,3 +1,27 @@
 mod twox_hash_map;
 
 pub use twox_hash_map::TwoXHashMap;
+
+/// Write the description of the error when a file was saved with a newer
+/// version of Meadowlark. `what` is the kind of file (i.e. "project").
+pub fn fmt_newer_version_error(
+    f: &mut std::fmt::Formatter<'_>,
+    what: &str,
+    version: u32,
+    supported_version: u32,
+) -> std::fmt::Result {
+    write!(
+        f,
+        "the {} was saved with a newer version of Meadowlark (file format version {}, this version of Meadowlark supports up to version {})",
+        what, version, supported_version
+    )
+}
+
+/// Create a directory in the platform's temporary directory which is unique
+/// to this process and `name`, for use by tests which read and write files.
+#[cfg(test)]
+pub fn unique_temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("meadowlark-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}